@@ -1,9 +1,15 @@
 mod client;
+mod openai;
 pub(crate) mod shared;
 mod streaming_client;
 
 pub use client::Client;
+pub use openai::{
+    OpenAiClient,
+    OpenAiConfig,
+};
 pub use streaming_client::{
+    MockResponse,
     SendMessageOutput,
     StreamingClient,
 };
@@ -0,0 +1,679 @@
+//! Client for OpenAI-compatible chat completion endpoints, e.g. a local vLLM or Ollama server.
+//! Referencing https://platform.openai.com/docs/api-reference/chat/create
+//!
+//! Conversations are sent to `{endpoint}/chat/completions` with `stream` enabled, and the streamed
+//! chunks are translated back into [ChatResponseStream] events.
+use std::collections::VecDeque;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use reqwest::header::AUTHORIZATION;
+use reqwest::{
+    Client as HttpClient,
+    Response,
+};
+use serde::Deserialize;
+use serde_json::{
+    Value,
+    json,
+};
+use tracing::error;
+
+use crate::api_client::ApiClientError;
+use crate::api_client::model::{
+    AssistantResponseMessage,
+    ChatMessage,
+    ChatResponseStream,
+    ConversationState,
+    FigDocument,
+    ImageBlock,
+    ImageFormat,
+    ImageSource,
+    Tool,
+    ToolResult,
+    ToolResultContentBlock,
+    ToolResultStatus,
+    UserInputMessage,
+};
+use crate::database::Database;
+use crate::database::settings::Setting;
+use crate::mcp_client::transport::http::SseParser;
+use crate::platform::Env;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const DONE_DATA: &str = "[DONE]";
+
+/// Configuration of an OpenAI-compatible endpoint, read from the `api.openai.service` setting:
+///
+/// ```json
+/// { "endpoint": "http://localhost:11434/v1", "model": "qwen2.5-coder", "apiKeyEnv": "OPENAI_API_KEY" }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenAiConfig {
+    /// Base url of the api, the chat completions path is appended to it.
+    pub endpoint: String,
+    /// The model to use, required since the models selected in the chat are only available from
+    /// Amazon Q.
+    pub model: Option<String>,
+    /// Name of the environment variable holding the api key, if the endpoint requires one.
+    pub api_key_env: Option<String>,
+}
+
+impl OpenAiConfig {
+    /// Returns the configured endpoint, if the user has set one.
+    pub fn load(database: &Database) -> Option<Self> {
+        let Some(Value::Object(o)) = database.settings.get(Setting::ApiOpenAiService) else {
+            return None;
+        };
+        let string = |key: &str| o.get(key).and_then(|v| v.as_str()).map(|v| v.to_owned());
+        match string("endpoint") {
+            Some(endpoint) => Some(Self {
+                endpoint,
+                model: string("model"),
+                api_key_env: string("apiKeyEnv"),
+            }),
+            None => {
+                error!("{} is missing an endpoint", Setting::ApiOpenAiService);
+                None
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OpenAiClient {
+    http_client: HttpClient,
+    endpoint: String,
+    model: String,
+    /// The api key read from [OpenAiConfig::api_key_env].
+    api_key: Option<String>,
+}
+
+impl OpenAiClient {
+    /// Creates a client for the configured endpoint, failing if no model is configured.
+    pub fn new(config: OpenAiConfig, env: &Env) -> Result<Self, ApiClientError> {
+        let Some(model) = config.model else {
+            return Err(ApiClientError::OpenAiResponse {
+                status: None,
+                message: format!("{} is missing the model to use", Setting::ApiOpenAiService),
+            });
+        };
+        let http_client = crate::request::new_client().map_err(|err| ApiClientError::OpenAiResponse {
+            status: None,
+            message: err.to_string(),
+        })?;
+        Ok(Self {
+            http_client,
+            endpoint: config.endpoint,
+            model,
+            api_key: config.api_key_env.and_then(|var| env.get(var).ok()),
+        })
+    }
+
+    pub async fn send_message(&self, conversation_state: ConversationState) -> Result<OpenAiResponse, ApiClientError> {
+        let url = format!("{}/chat/completions", self.endpoint.trim_end_matches('/'));
+        let body = request_body(conversation_state, &self.model);
+
+        let mut request = self.http_client.post(url).json(&body);
+        if let Some(api_key) = &self.api_key {
+            request = request.header(AUTHORIZATION, format!("Bearer {api_key}"));
+        }
+        let response = request.send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let error = serde_json::from_str::<ErrorResponse>(&body).ok().map(|r| r.error);
+            let is_context_window_overflow = error.as_ref().is_some_and(|e| {
+                e.code.as_deref() == Some("context_length_exceeded") || e.message.contains("context length")
+            });
+            return Err(if status.as_u16() == 429 {
+                ApiClientError::QuotaBreach("quota has reached its limit")
+            } else if is_context_window_overflow {
+                ApiClientError::ContextWindowOverflow
+            } else {
+                ApiClientError::OpenAiResponse {
+                    status: Some(status.as_u16()),
+                    message: error.map_or(body, |e| e.message),
+                }
+            });
+        }
+
+        Ok(OpenAiResponse {
+            request_id: response
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_owned()),
+            response,
+            parser: SseParser::default(),
+            events: VecDeque::new(),
+            tool_call: None,
+            done: false,
+        })
+    }
+}
+
+/// The streamed response to a chat completion request.
+#[derive(Debug)]
+pub struct OpenAiResponse {
+    request_id: Option<String>,
+    response: Response,
+    parser: SseParser,
+    /// Events translated from the chunks received so far.
+    events: VecDeque<ChatResponseStream>,
+    /// The index, id, and name of the tool call being received.
+    tool_call: Option<(usize, String, String)>,
+    done: bool,
+}
+
+impl OpenAiResponse {
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub async fn recv(&mut self) -> Result<Option<ChatResponseStream>, ApiClientError> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => {
+                    for event in self.parser.feed(&chunk) {
+                        self.handle_data(&event.data)?;
+                    }
+                },
+                None => self.finish(),
+            }
+        }
+    }
+
+    fn handle_data(&mut self, data: &str) -> Result<(), ApiClientError> {
+        if data.is_empty() || self.done {
+            return Ok(());
+        }
+        if data == DONE_DATA {
+            self.finish();
+            return Ok(());
+        }
+
+        let chunk =
+            serde_json::from_str::<ChatCompletionChunk>(data).map_err(|err| ApiClientError::OpenAiResponse {
+                status: None,
+                message: format!("invalid chunk: {err}"),
+            })?;
+        if let Some(error) = chunk.error {
+            return Err(ApiClientError::OpenAiResponse {
+                status: None,
+                message: error.message,
+            });
+        }
+
+        // Only a single choice is ever requested.
+        let Some(choice) = chunk.choices.into_iter().next() else {
+            return Ok(());
+        };
+        if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+            self.end_tool_call();
+            self.events
+                .push_back(ChatResponseStream::AssistantResponseEvent { content });
+        }
+        for tool_call in choice.delta.tool_calls {
+            if self
+                .tool_call
+                .as_ref()
+                .is_none_or(|(index, ..)| *index != tool_call.index)
+            {
+                self.end_tool_call();
+                let tool_use_id = tool_call
+                    .id
+                    .unwrap_or_else(|| format!("tooluse_{}", uuid::Uuid::new_v4().simple()));
+                let name = tool_call.function.name.clone().unwrap_or_default();
+                self.events.push_back(ChatResponseStream::ToolUseEvent {
+                    tool_use_id: tool_use_id.clone(),
+                    name: name.clone(),
+                    input: None,
+                    stop: None,
+                });
+                self.tool_call = Some((tool_call.index, tool_use_id, name));
+            }
+            if let (Some(arguments), Some((_, tool_use_id, name))) =
+                (tool_call.function.arguments.filter(|a| !a.is_empty()), &self.tool_call)
+            {
+                self.events.push_back(ChatResponseStream::ToolUseEvent {
+                    tool_use_id: tool_use_id.clone(),
+                    name: name.clone(),
+                    input: Some(arguments),
+                    stop: None,
+                });
+            }
+        }
+        if choice.finish_reason.is_some() {
+            self.end_tool_call();
+        }
+
+        Ok(())
+    }
+
+    /// Marks the tool call being received as complete, since the chat expects every tool use to
+    /// end with a stop event.
+    fn end_tool_call(&mut self) {
+        if let Some((_, tool_use_id, name)) = self.tool_call.take() {
+            self.events.push_back(ChatResponseStream::ToolUseEvent {
+                tool_use_id,
+                name,
+                input: None,
+                stop: Some(true),
+            });
+        }
+    }
+
+    fn finish(&mut self) {
+        self.end_tool_call();
+        self.done = true;
+    }
+}
+
+/// Builds the json body of a chat completion request. The model selected in the chat is ignored in
+/// favor of `model`.
+fn request_body(conversation_state: ConversationState, model: &str) -> Value {
+    let ConversationState {
+        user_input_message,
+        history,
+        ..
+    } = conversation_state;
+
+    let tools = user_input_message
+        .user_input_message_context
+        .as_ref()
+        .and_then(|context| context.tools.as_ref())
+        .map(|tools| tools.iter().map(tool_to_json).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let mut messages = Vec::new();
+    for message in history.unwrap_or_default() {
+        match message {
+            ChatMessage::UserInputMessage(message) => push_user_message(&mut messages, message),
+            ChatMessage::AssistantResponseMessage(message) => messages.push(assistant_message_to_json(message)),
+        }
+    }
+    push_user_message(&mut messages, user_input_message);
+
+    let mut body = json!({
+        "model": model,
+        "messages": messages,
+        "stream": true,
+    });
+    if !tools.is_empty() {
+        body["tools"] = Value::Array(tools);
+    }
+    body
+}
+
+/// Tool results are sent as separate `tool` messages which have to directly follow the assistant
+/// message requesting them, so they come before the content of the user message.
+fn push_user_message(messages: &mut Vec<Value>, message: UserInputMessage) {
+    let tool_results = message
+        .user_input_message_context
+        .and_then(|context| context.tool_results)
+        .unwrap_or_default();
+    let has_tool_results = !tool_results.is_empty();
+    for result in tool_results {
+        messages.push(tool_result_to_json(result));
+    }
+
+    let images = message.images.unwrap_or_default();
+    if has_tool_results && message.content.is_empty() && images.is_empty() {
+        return;
+    }
+    let content = match images.is_empty() {
+        true => Value::String(message.content),
+        false => {
+            let mut parts = vec![json!({ "type": "text", "text": message.content })];
+            parts.extend(images.into_iter().filter_map(image_to_json));
+            Value::Array(parts)
+        },
+    };
+    messages.push(json!({ "role": "user", "content": content }));
+}
+
+fn assistant_message_to_json(message: AssistantResponseMessage) -> Value {
+    let mut value = json!({ "role": "assistant", "content": message.content });
+    let tool_calls = message
+        .tool_uses
+        .unwrap_or_default()
+        .into_iter()
+        .map(|tool_use| {
+            json!({
+                "id": tool_use.tool_use_id,
+                "type": "function",
+                "function": {
+                    "name": tool_use.name,
+                    "arguments": serde_json::to_string(&tool_use.input).unwrap_or_default(),
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+    if !tool_calls.is_empty() {
+        value["tool_calls"] = Value::Array(tool_calls);
+    }
+    value
+}
+
+fn tool_result_to_json(result: ToolResult) -> Value {
+    let content = result
+        .content
+        .into_iter()
+        .map(|block| match block {
+            ToolResultContentBlock::Text(text) => text,
+            ToolResultContentBlock::Json(document) => {
+                serde_json::to_string(&FigDocument::from(document)).unwrap_or_default()
+            },
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    // Tool messages have no status, so failures are marked in the content for the model to see.
+    let content = match result.status {
+        ToolResultStatus::Error => format!("Error: {content}"),
+        ToolResultStatus::Success => content,
+    };
+    json!({ "role": "tool", "tool_call_id": result.tool_use_id, "content": content })
+}
+
+fn tool_to_json(tool: &Tool) -> Value {
+    let Tool::ToolSpecification(spec) = tool;
+    json!({
+        "type": "function",
+        "function": {
+            "name": spec.name,
+            "description": spec.description,
+            "parameters": spec.input_schema.json.as_ref().map_or_else(
+                || json!({ "type": "object", "properties": {} }),
+                |schema| serde_json::to_value(schema).unwrap_or_default(),
+            ),
+        },
+    })
+}
+
+fn image_to_json(image: ImageBlock) -> Option<Value> {
+    let ImageSource::Bytes(bytes) = image.source else {
+        return None;
+    };
+    let mime = match image.format {
+        ImageFormat::Gif => "image/gif",
+        ImageFormat::Jpeg => "image/jpeg",
+        ImageFormat::Png => "image/png",
+        ImageFormat::Webp => "image/webp",
+    };
+    Some(json!({
+        "type": "image_url",
+        "image_url": { "url": format!("data:{mime};base64,{}", BASE64.encode(bytes)) },
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    error: Option<ErrorBody>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChunkDelta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ToolCallDelta {
+    #[serde(default)]
+    index: usize,
+    id: Option<String>,
+    #[serde(default)]
+    function: FunctionDelta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    message: String,
+    code: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use super::*;
+    use crate::api_client::model::{
+        ToolInputSchema,
+        ToolResultStatus,
+        ToolSpecification,
+        ToolUse,
+        UserInputMessageContext,
+    };
+
+    fn user_message(content: &str) -> UserInputMessage {
+        UserInputMessage {
+            images: None,
+            content: content.into(),
+            user_input_message_context: None,
+            user_intent: None,
+            model_id: Some("chat-model".to_owned()),
+        }
+    }
+
+    fn config(endpoint: String) -> OpenAiConfig {
+        OpenAiConfig {
+            endpoint,
+            model: Some("local-model".to_owned()),
+            api_key_env: Some("LOCAL_API_KEY".to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_new_requires_model() {
+        let config = OpenAiConfig {
+            model: None,
+            ..config("http://localhost".to_owned())
+        };
+        let err = OpenAiClient::new(config, &Env::from_slice(&[])).unwrap_err();
+        assert!(err.to_string().contains("missing the model"), "{err}");
+    }
+
+    #[test]
+    fn test_request_body() {
+        let mut current = user_message("");
+        current.user_input_message_context = Some(UserInputMessageContext {
+            tool_results: Some(vec![ToolResult {
+                tool_use_id: "call_1".into(),
+                content: vec![ToolResultContentBlock::Text("README.md".into())],
+                status: ToolResultStatus::Success,
+            }]),
+            tools: Some(vec![Tool::ToolSpecification(ToolSpecification {
+                name: "execute_bash".into(),
+                description: "Run a command".into(),
+                input_schema: ToolInputSchema { json: None },
+            })]),
+            ..Default::default()
+        });
+        let body = request_body(
+            ConversationState {
+                conversation_id: None,
+                user_input_message: current,
+                history: Some(vec![
+                    ChatMessage::UserInputMessage(user_message("list files")),
+                    ChatMessage::AssistantResponseMessage(AssistantResponseMessage {
+                        message_id: None,
+                        content: "Listing".into(),
+                        tool_uses: Some(vec![ToolUse {
+                            tool_use_id: "call_1".into(),
+                            name: "execute_bash".into(),
+                            input: serde_json::from_str(r#"{"command":"ls"}"#).unwrap(),
+                        }]),
+                    }),
+                ]),
+            },
+            "local-model",
+        );
+
+        assert_eq!(
+            body,
+            json!({
+                "model": "local-model",
+                "stream": true,
+                "messages": [
+                    { "role": "user", "content": "list files" },
+                    {
+                        "role": "assistant",
+                        "content": "Listing",
+                        "tool_calls": [{
+                            "id": "call_1",
+                            "type": "function",
+                            "function": { "name": "execute_bash", "arguments": "{\"command\":\"ls\"}" },
+                        }],
+                    },
+                    { "role": "tool", "tool_call_id": "call_1", "content": "README.md" },
+                ],
+                "tools": [{
+                    "type": "function",
+                    "function": {
+                        "name": "execute_bash",
+                        "description": "Run a command",
+                        "parameters": { "type": "object", "properties": {} },
+                    },
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_tool_result_error() {
+        let result = ToolResult {
+            tool_use_id: "call_1".into(),
+            content: vec![ToolResultContentBlock::Text("No such file or directory".into())],
+            status: ToolResultStatus::Error,
+        };
+        assert_eq!(
+            tool_result_to_json(result),
+            json!({ "role": "tool", "tool_call_id": "call_1", "content": "Error: No such file or directory" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streamed_response() {
+        let mut server = mockito::Server::new_async().await;
+        let completions = server
+            .mock("POST", "/v1/chat/completions")
+            .match_body(Matcher::PartialJson(json!({ "model": "local-model", "stream": true })))
+            .match_header("authorization", "Bearer secret")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_header(REQUEST_ID_HEADER, "req-1")
+            .with_body(concat!(
+                "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"Hello\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\" there\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"fs_read\",\"arguments\":\"\"}}]}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"path\\\":\"}}]}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\"a\\\"}\"}}]}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\n",
+                "data: [DONE]\n\n",
+            ))
+            .create_async()
+            .await;
+
+        let env = Env::from_slice(&[("LOCAL_API_KEY", "secret")]);
+        let client = OpenAiClient::new(config(format!("{}/v1/", server.url())), &env).unwrap();
+        let mut response = client
+            .send_message(ConversationState {
+                conversation_id: None,
+                user_input_message: user_message("Hello"),
+                history: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(response.request_id(), Some("req-1"));
+
+        let mut events = Vec::new();
+        while let Some(event) = response.recv().await.unwrap() {
+            events.push(event);
+        }
+        let tool_use = |input: Option<&str>, stop: Option<bool>| ChatResponseStream::ToolUseEvent {
+            tool_use_id: "call_1".into(),
+            name: "fs_read".into(),
+            input: input.map(String::from),
+            stop,
+        };
+        assert_eq!(events, vec![
+            ChatResponseStream::AssistantResponseEvent {
+                content: "Hello".into()
+            },
+            ChatResponseStream::AssistantResponseEvent {
+                content: " there".into()
+            },
+            tool_use(None, None),
+            tool_use(Some("{\"path\":"), None),
+            tool_use(Some("\"a\"}"), None),
+            tool_use(None, Some(true)),
+        ]);
+        completions.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_error_response() {
+        let mut server = mockito::Server::new_async().await;
+        let _overflow = server
+            .mock("POST", "/chat/completions")
+            .match_body(Matcher::PartialJson(json!({ "messages": [{ "content": "long" }] })))
+            .with_status(400)
+            .with_body(json!({ "error": { "message": "too long", "code": "context_length_exceeded" } }).to_string())
+            .create_async()
+            .await;
+        let _unavailable = server
+            .mock("POST", "/chat/completions")
+            .match_body(Matcher::PartialJson(json!({ "messages": [{ "content": "hi" }] })))
+            .with_status(503)
+            .with_body(json!({ "error": { "message": "model is loading" } }).to_string())
+            .create_async()
+            .await;
+
+        let client = OpenAiClient::new(config(server.url()), &Env::from_slice(&[])).unwrap();
+        let send = |content: &str| {
+            client.send_message(ConversationState {
+                conversation_id: None,
+                user_input_message: user_message(content),
+                history: None,
+            })
+        };
+        assert!(matches!(
+            send("long").await.unwrap_err(),
+            ApiClientError::ContextWindowOverflow
+        ));
+        assert!(matches!(
+            send("hi").await.unwrap_err(),
+            ApiClientError::OpenAiResponse { status: Some(503), message } if message == "model is loading"
+        ));
+    }
+}
@@ -10,6 +10,11 @@ use tracing::{
     error,
 };
 
+use super::openai::{
+    OpenAiClient,
+    OpenAiConfig,
+    OpenAiResponse,
+};
 use super::shared::{
     bearer_sdk_config,
     stalled_stream_protection_config,
@@ -32,6 +37,7 @@ use crate::database::{
     AuthProfile,
     Database,
 };
+use crate::platform::Env;
 
 mod inner {
     use std::sync::{
@@ -41,12 +47,40 @@ mod inner {
 
     use amzn_codewhisperer_streaming_client::Client as CodewhispererStreamingClient;
 
-    use crate::api_client::model::ChatResponseStream;
+    use crate::api_client::clients::{
+        MockResponse,
+        OpenAiClient,
+    };
 
     #[derive(Clone, Debug)]
     pub enum Inner {
         Codewhisperer(CodewhispererStreamingClient),
-        Mock(Arc<Mutex<std::vec::IntoIter<Vec<ChatResponseStream>>>>),
+        OpenAi(OpenAiClient),
+        Mock(Arc<Mutex<std::vec::IntoIter<MockResponse>>>),
+    }
+}
+
+/// A response returned by a mock client, see [StreamingClient::mock_responses].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MockResponse {
+    pub events: Vec<ChatResponseStream>,
+    /// The error ending the response after its events. Errors of responses without events are
+    /// returned when sending the request instead.
+    pub error: Option<String>,
+}
+
+impl MockResponse {
+    /// Converts [Self::error] back into the [ApiClientError] it was displayed from, for the
+    /// errors the chat handles specially.
+    fn api_error(message: String) -> ApiClientError {
+        [
+            ApiClientError::ContextWindowOverflow,
+            ApiClientError::MonthlyLimitReached,
+            ApiClientError::QuotaBreach("quota has reached its limit"),
+        ]
+        .into_iter()
+        .find(|err| err.to_string() == message)
+        .unwrap_or(ApiClientError::Mock(message))
     }
 }
 
@@ -57,17 +91,38 @@ pub struct StreamingClient {
 }
 
 impl StreamingClient {
-    pub async fn new(database: &mut Database) -> Result<Self, ApiClientError> {
-        Self::new_codewhisperer_client(database, &Endpoint::load_codewhisperer(database)).await
+    pub async fn new(env: &Env, database: &mut Database) -> Result<Self, ApiClientError> {
+        match OpenAiConfig::load(database) {
+            Some(config) => Self::new_openai_client(config, env),
+            None => Self::new_codewhisperer_client(database, &Endpoint::load_codewhisperer(database)).await,
+        }
     }
 
     pub fn mock(events: Vec<Vec<ChatResponseStream>>) -> Self {
+        Self::mock_responses(
+            events
+                .into_iter()
+                .map(|events| MockResponse { events, error: None })
+                .collect(),
+        )
+    }
+
+    /// Creates a client that answers each request with the next of `responses`.
+    pub fn mock_responses(responses: Vec<MockResponse>) -> Self {
         Self {
-            inner: inner::Inner::Mock(Arc::new(Mutex::new(events.into_iter()))),
+            inner: inner::Inner::Mock(Arc::new(Mutex::new(responses.into_iter()))),
             profile: None,
         }
     }
 
+    /// Creates a client for an OpenAI-compatible chat completions endpoint.
+    pub fn new_openai_client(config: OpenAiConfig, env: &Env) -> Result<Self, ApiClientError> {
+        Ok(Self {
+            inner: inner::Inner::OpenAi(OpenAiClient::new(config, env)?),
+            profile: None,
+        })
+    }
+
     pub async fn new_codewhisperer_client(
         database: &mut Database,
         endpoint: &Endpoint,
@@ -174,10 +229,24 @@ impl StreamingClient {
                     },
                 }
             },
-            inner::Inner::Mock(events) => {
-                let mut new_events = events.lock().unwrap().next().unwrap_or_default().clone();
-                new_events.reverse();
-                Ok(SendMessageOutput::Mock(new_events))
+            inner::Inner::OpenAi(client) => Ok(SendMessageOutput::OpenAi(
+                client
+                    .send_message(ConversationState {
+                        conversation_id,
+                        user_input_message,
+                        history,
+                    })
+                    .await?,
+            )),
+            inner::Inner::Mock(responses) => {
+                let MockResponse { mut events, error } = responses.lock().unwrap().next().unwrap_or_default();
+                if events.is_empty() {
+                    if let Some(error) = error {
+                        return Err(MockResponse::api_error(error));
+                    }
+                }
+                events.reverse();
+                Ok(SendMessageOutput::Mock(events, error))
             },
         }
     }
@@ -188,14 +257,17 @@ pub enum SendMessageOutput {
     Codewhisperer(
         amzn_codewhisperer_streaming_client::operation::generate_assistant_response::GenerateAssistantResponseOutput,
     ),
-    Mock(Vec<ChatResponseStream>),
+    OpenAi(OpenAiResponse),
+    /// The remaining events in reverse order, and the error ending them.
+    Mock(Vec<ChatResponseStream>, Option<String>),
 }
 
 impl SendMessageOutput {
     pub fn request_id(&self) -> Option<&str> {
         match self {
             SendMessageOutput::Codewhisperer(output) => output.request_id(),
-            SendMessageOutput::Mock(_) => None,
+            SendMessageOutput::OpenAi(output) => output.request_id(),
+            SendMessageOutput::Mock(..) => None,
         }
     }
 
@@ -206,7 +278,13 @@ impl SendMessageOutput {
                 .recv()
                 .await?
                 .map(|s| s.into())),
-            SendMessageOutput::Mock(vec) => Ok(vec.pop()),
+            SendMessageOutput::OpenAi(output) => output.recv().await,
+            SendMessageOutput::Mock(events, error) => match events.pop() {
+                Some(event) => Ok(Some(event)),
+                None => error
+                    .take()
+                    .map_or(Ok(None), |error| Err(MockResponse::api_error(error))),
+            },
         }
     }
 }
@@ -215,7 +293,8 @@ impl RequestId for SendMessageOutput {
     fn request_id(&self) -> Option<&str> {
         match self {
             SendMessageOutput::Codewhisperer(output) => output.request_id(),
-            SendMessageOutput::Mock(_) => Some("<mock-request-id>"),
+            SendMessageOutput::OpenAi(output) => output.request_id(),
+            SendMessageOutput::Mock(..) => Some("<mock-request-id>"),
         }
     }
 }
@@ -234,7 +313,7 @@ mod tests {
         let mut database = Database::new().await.unwrap();
         let endpoint = Endpoint::load_codewhisperer(&database);
 
-        let _ = StreamingClient::new(&mut database).await;
+        let _ = StreamingClient::new(&Env::new(), &mut database).await;
         let _ = StreamingClient::new_codewhisperer_client(&mut database, &endpoint).await;
     }
 
@@ -273,11 +352,47 @@ mod tests {
         assert_eq!(output_content, "Hello! How can I assist you today?");
     }
 
+    #[tokio::test]
+    async fn test_mock_errors() {
+        let client = StreamingClient::mock_responses(vec![
+            MockResponse {
+                events: vec![],
+                error: Some(ApiClientError::ContextWindowOverflow.to_string()),
+            },
+            MockResponse {
+                events: vec![ChatResponseStream::AssistantResponseEvent {
+                    content: "Hello!".to_owned(),
+                }],
+                error: Some("stream timed out".to_owned()),
+            },
+        ]);
+        let request = || ConversationState {
+            conversation_id: None,
+            user_input_message: UserInputMessage {
+                images: None,
+                content: "Hello".into(),
+                user_input_message_context: None,
+                user_intent: None,
+                model_id: None,
+            },
+            history: None,
+        };
+
+        assert!(matches!(
+            client.send_message(request()).await,
+            Err(ApiClientError::ContextWindowOverflow)
+        ));
+        let mut output = client.send_message(request()).await.unwrap();
+        assert!(output.recv().await.unwrap().is_some());
+        assert_eq!(output.recv().await.unwrap_err().to_string(), "stream timed out");
+        assert!(output.recv().await.unwrap().is_none());
+    }
+
     #[ignore]
     #[tokio::test]
     async fn assistant_response() {
         let mut database = Database::new().await.unwrap();
-        let client = StreamingClient::new(&mut database).await.unwrap();
+        let client = StreamingClient::new(&Env::new(), &mut database).await.unwrap();
         let mut response = client
             .send_message(ConversationState {
                 conversation_id: None,
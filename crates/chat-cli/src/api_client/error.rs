@@ -77,10 +77,21 @@ pub enum ApiClientError {
     #[error(transparent)]
     AuthError(#[from] AuthError),
 
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+
+    /// Returned from an OpenAI-compatible endpoint, see [crate::api_client::clients::OpenAiClient].
+    #[error("model endpoint error{}: {message}", .status.map(|s| format!(" ({s})")).unwrap_or_default())]
+    OpenAiResponse { status: Option<u16>, message: String },
+
     #[error(
         "The model you've selected is temporarily unavailable. Please use '/model' to select a different model and try again."
     )]
     ModelOverloadedError { request_id: Option<String> },
+
+    /// Returned by a mock client, see [crate::api_client::clients::MockResponse].
+    #[error("{0}")]
+    Mock(String),
 }
 
 impl ReasonCode for ApiClientError {
@@ -103,6 +114,9 @@ impl ReasonCode for ApiClientError {
             ApiClientError::AuthError(_) => "AuthError".to_string(),
             ApiClientError::ModelOverloadedError { .. } => "ModelOverloadedError".to_string(),
             ApiClientError::MonthlyLimitReached => "MonthlyLimitReached".to_string(),
+            ApiClientError::Reqwest(_) => "ReqwestError".to_string(),
+            ApiClientError::OpenAiResponse { .. } => "OpenAiResponseError".to_string(),
+            ApiClientError::Mock(_) => "MockError".to_string(),
         }
     }
 }
@@ -0,0 +1,462 @@
+//! Per-conversation checkpoints of files modified by the `fs_write` tool.
+//!
+//! Before a file is written, its current contents are snapshotted into a [CheckpointLog] so that
+//! the edit can later be rolled back with `/undo`, independently of any version control.
+//!
+//! The log is persisted as part of the conversation, so it only references the snapshotted
+//! contents by digest. The contents themselves are stored once per digest in a directory of the
+//! log within [directories::chat_checkpoints_dir], and deleted once no checkpoint of the log
+//! references them.
+
+use std::collections::{
+    BTreeSet,
+    HashSet,
+};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use eyre::{
+    Result,
+    bail,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use sha2::{
+    Digest,
+    Sha256,
+};
+use time::OffsetDateTime;
+use tracing::warn;
+
+use crate::platform::Context;
+use crate::util::directories;
+
+/// A snapshot of a single file taken right before a tool modified it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Monotonically increasing id, unique within a conversation.
+    pub id: usize,
+    /// The user turn during which the file was modified.
+    pub turn: usize,
+    /// The id of the tool use that modified the file.
+    pub tool_use_id: String,
+    /// Absolute path of the modified file.
+    pub path: PathBuf,
+    /// SHA-256 digest of the contents of the file before it was modified, [None] if the file did
+    /// not exist.
+    pub previous_digest: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+impl Checkpoint {
+    /// Whether the file was created by the edit rather than modified.
+    pub fn is_creation(&self) -> bool {
+        self.previous_digest.is_none()
+    }
+}
+
+/// Ordered log of [Checkpoint]s for a single conversation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointLog {
+    /// Names the directory the snapshotted contents of this log are stored in. Logs don't share
+    /// their snapshots, so that the log can tell when one is no longer needed.
+    #[serde(default = "new_log_id")]
+    id: String,
+    checkpoints: Vec<Checkpoint>,
+    next_id: usize,
+    /// Incremented every time the user sends a new prompt.
+    turn: usize,
+}
+
+impl Default for CheckpointLog {
+    fn default() -> Self {
+        Self {
+            id: new_log_id(),
+            checkpoints: Vec::new(),
+            next_id: 0,
+            turn: 0,
+        }
+    }
+}
+
+fn new_log_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+impl CheckpointLog {
+    /// Marks the beginning of a new user turn. Edits made by the assistant in response to the
+    /// prompt are grouped under this turn.
+    pub fn start_turn(&mut self) {
+        self.turn += 1;
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+
+    /// Snapshots the current contents of every path in `paths`, returning the ids of the newly
+    /// created checkpoints.
+    pub async fn snapshot(&mut self, ctx: &Context, tool_use_id: &str, paths: &[PathBuf]) -> Result<Vec<usize>> {
+        let cwd = ctx.env().current_dir()?;
+        let blobs_dir = self.blobs_dir(ctx)?;
+        let mut ids = Vec::with_capacity(paths.len());
+        for path in paths {
+            let path = cwd.join(path);
+            let previous_digest = if ctx.fs().exists(&path) {
+                let contents = ctx.fs().read(&path).await?;
+                let digest = hex::encode(Sha256::digest(&contents));
+                let blob = blobs_dir.join(&digest);
+                if !ctx.fs().exists(&blob) {
+                    ctx.fs().create_dir_all(&blobs_dir).await?;
+                    ctx.fs().write(&blob, &contents).await?;
+                }
+                Some(digest)
+            } else {
+                None
+            };
+            let id = self.next_id;
+            self.next_id += 1;
+            self.checkpoints.push(Checkpoint {
+                id,
+                turn: self.turn,
+                tool_use_id: tool_use_id.to_string(),
+                path,
+                previous_digest,
+                created_at: OffsetDateTime::now_utc(),
+            });
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    /// Reads the snapshotted contents of the file of `checkpoint`, [None] if the file did not
+    /// exist.
+    pub async fn previous(&self, ctx: &Context, checkpoint: &Checkpoint) -> Result<Option<Vec<u8>>> {
+        let Some(digest) = &checkpoint.previous_digest else {
+            return Ok(None);
+        };
+        match ctx.fs().read(self.blobs_dir(ctx)?.join(digest)).await {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) => bail!(
+                "The snapshot of {} is no longer available: {err}",
+                checkpoint.path.display()
+            ),
+        }
+    }
+
+    /// Moves the checkpoints of `other`, e.g. those of a delegated task, into this log as part of
+    /// the current turn, along with their snapshots.
+    pub async fn append(&mut self, ctx: &Context, other: CheckpointLog) -> Result<()> {
+        let (from, to) = (other.blobs_dir(ctx)?, self.blobs_dir(ctx)?);
+        for mut checkpoint in other.checkpoints {
+            if let Some(digest) = &checkpoint.previous_digest {
+                if !ctx.fs().exists(to.join(digest)) {
+                    ctx.fs().create_dir_all(&to).await?;
+                    ctx.fs().rename(from.join(digest), to.join(digest)).await?;
+                }
+            }
+            checkpoint.id = self.next_id;
+            checkpoint.turn = self.turn;
+            self.next_id += 1;
+            self.checkpoints.push(checkpoint);
+        }
+        if ctx.fs().exists(&from) {
+            ctx.fs().remove_dir_all(&from).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes checkpoints without restoring them, e.g. when the tool failed before writing.
+    pub async fn discard(&mut self, ctx: &Context, ids: &[usize]) {
+        let (discarded, kept) = std::mem::take(&mut self.checkpoints)
+            .into_iter()
+            .partition::<Vec<_>, _>(|c| ids.contains(&c.id));
+        self.checkpoints = kept;
+        self.remove_unreferenced_snapshots(ctx, &discarded).await;
+    }
+
+    /// Rolls back every edit made during the last `n` turns that modified files, returning the
+    /// restored checkpoints from newest to oldest.
+    pub async fn undo_turns(&mut self, ctx: &Context, n: usize) -> Result<Vec<Checkpoint>> {
+        let turns = self.checkpoints.iter().map(|c| c.turn).collect::<BTreeSet<_>>();
+        let Some(&first_turn) = turns.iter().rev().take(n).last() else {
+            bail!("There are no edits to undo");
+        };
+        let split = self.checkpoints.partition_point(|c| c.turn < first_turn);
+        self.restore_from(ctx, split).await
+    }
+
+    /// Rolls back a single edit. Later edits to the same file are rolled back as well since they
+    /// were made on top of it. Returns the restored checkpoints from newest to oldest.
+    pub async fn undo_checkpoint(&mut self, ctx: &Context, id: usize) -> Result<Vec<Checkpoint>> {
+        let Some(path) = self.checkpoints.iter().find(|c| c.id == id).map(|c| c.path.clone()) else {
+            bail!("No checkpoint with id {id} exists");
+        };
+        let (to_restore, to_keep) = std::mem::take(&mut self.checkpoints)
+            .into_iter()
+            .partition::<Vec<_>, _>(|c| c.id >= id && c.path == path);
+        self.checkpoints = to_keep;
+        self.restore_all(ctx, to_restore).await
+    }
+
+    async fn restore_from(&mut self, ctx: &Context, index: usize) -> Result<Vec<Checkpoint>> {
+        let to_restore = self.checkpoints.split_off(index);
+        self.restore_all(ctx, to_restore).await
+    }
+
+    /// Restores `checkpoints`, which have been taken out of the log, newest first so that files
+    /// edited multiple times end up in their oldest snapshotted state.
+    ///
+    /// Checkpoints that fail to be restored are put back into the log, along with the older
+    /// checkpoints of the same file, and listed in the returned error.
+    async fn restore_all(&mut self, ctx: &Context, mut checkpoints: Vec<Checkpoint>) -> Result<Vec<Checkpoint>> {
+        checkpoints.reverse();
+        let mut restored = Vec::new();
+        let mut failed: Vec<Checkpoint> = Vec::new();
+        let mut errors = Vec::new();
+        for checkpoint in checkpoints {
+            // Older snapshots of a file only apply on top of the newer ones.
+            if failed.iter().any(|c| c.path == checkpoint.path) {
+                failed.push(checkpoint);
+                continue;
+            }
+            match self.restore(ctx, &checkpoint).await {
+                Ok(()) => restored.push(checkpoint),
+                Err(err) => {
+                    errors.push(format!("{}: {err}", checkpoint.path.display()));
+                    failed.push(checkpoint);
+                },
+            }
+        }
+        self.checkpoints.extend(failed);
+        self.checkpoints.sort_by_key(|c| c.id);
+        self.remove_unreferenced_snapshots(ctx, &restored).await;
+
+        if !errors.is_empty() {
+            bail!(
+                "the following files could not be restored, {} other edits were undone:\n{}",
+                restored.len(),
+                errors.join("\n")
+            );
+        }
+        Ok(restored)
+    }
+
+    /// Writes the snapshotted contents of the file of `checkpoint` back to disk, deleting the file
+    /// if it did not previously exist.
+    async fn restore(&self, ctx: &Context, checkpoint: &Checkpoint) -> Result<()> {
+        let fs = ctx.fs();
+        match self.previous(ctx, checkpoint).await? {
+            Some(contents) => {
+                if let Some(parent) = checkpoint.path.parent() {
+                    fs.create_dir_all(parent).await?;
+                }
+                fs.write(&checkpoint.path, contents).await?;
+            },
+            None => {
+                if fs.exists(&checkpoint.path) {
+                    fs.remove_file(&checkpoint.path).await?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Deletes the snapshots of `dropped`, which have been removed from the log, that no remaining
+    /// checkpoint references.
+    async fn remove_unreferenced_snapshots(&self, ctx: &Context, dropped: &[Checkpoint]) {
+        let Ok(blobs_dir) = self.blobs_dir(ctx) else {
+            return;
+        };
+        let referenced = self
+            .checkpoints
+            .iter()
+            .filter_map(|c| c.previous_digest.as_ref())
+            .collect::<HashSet<_>>();
+        let unreferenced = dropped
+            .iter()
+            .filter_map(|c| c.previous_digest.as_ref())
+            .filter(|digest| !referenced.contains(digest))
+            .collect::<BTreeSet<_>>();
+        for digest in unreferenced {
+            match ctx.fs().remove_file(blobs_dir.join(digest)).await {
+                Err(err) if err.kind() != ErrorKind::NotFound => warn!(?err, "failed to remove a checkpoint snapshot"),
+                _ => (),
+            }
+        }
+    }
+
+    fn blobs_dir(&self, ctx: &Context) -> Result<PathBuf> {
+        Ok(directories::chat_checkpoints_dir(ctx)?.join(&self.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_context() -> std::sync::Arc<Context> {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/existing.txt", "original").await.unwrap();
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_undo_turns() {
+        let ctx = test_context().await;
+        let fs = ctx.fs();
+        let mut log = CheckpointLog::default();
+
+        log.start_turn();
+        log.snapshot(&ctx, "t1", &["/existing.txt".into()]).await.unwrap();
+        fs.write("/existing.txt", "first edit").await.unwrap();
+
+        log.start_turn();
+        log.snapshot(&ctx, "t2", &["/existing.txt".into(), "/new.txt".into()])
+            .await
+            .unwrap();
+        fs.write("/existing.txt", "second edit").await.unwrap();
+        fs.write("/new.txt", "created").await.unwrap();
+
+        let restored = log.undo_turns(&ctx, 1).await.unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(fs.read_to_string("/existing.txt").await.unwrap(), "first edit");
+        assert!(!fs.exists("/new.txt"));
+        assert_eq!(log.checkpoints().len(), 1);
+
+        log.undo_turns(&ctx, 5).await.unwrap();
+        assert_eq!(fs.read_to_string("/existing.txt").await.unwrap(), "original");
+        assert!(log.is_empty());
+        assert!(log.undo_turns(&ctx, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_undo_checkpoint_only_affects_its_file() {
+        let ctx = test_context().await;
+        let fs = ctx.fs();
+        let mut log = CheckpointLog::default();
+
+        log.start_turn();
+        let ids = log.snapshot(&ctx, "t1", &["/existing.txt".into()]).await.unwrap();
+        fs.write("/existing.txt", "first edit").await.unwrap();
+        log.snapshot(&ctx, "t2", &["/other.txt".into()]).await.unwrap();
+        fs.write("/other.txt", "other").await.unwrap();
+        log.snapshot(&ctx, "t3", &["/existing.txt".into()]).await.unwrap();
+        fs.write("/existing.txt", "second edit").await.unwrap();
+
+        let restored = log.undo_checkpoint(&ctx, ids[0]).await.unwrap();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(fs.read_to_string("/existing.txt").await.unwrap(), "original");
+        assert_eq!(fs.read_to_string("/other.txt").await.unwrap(), "other");
+        assert_eq!(log.checkpoints().len(), 1);
+        assert!(log.undo_checkpoint(&ctx, ids[0]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_log_serde_roundtrip() {
+        let ctx = test_context().await;
+        let mut log = CheckpointLog::default();
+        log.start_turn();
+        log.snapshot(&ctx, "t1", &["/existing.txt".into(), "/missing.txt".into()])
+            .await
+            .unwrap();
+
+        // Only the digest of the contents is part of the log.
+        let digest = hex::encode(Sha256::digest(b"original"));
+        assert_eq!(log.checkpoints()[0].previous_digest.as_ref(), Some(&digest));
+
+        let log: CheckpointLog = serde_json::from_str(&serde_json::to_string(&log).unwrap()).unwrap();
+        assert_eq!(
+            log.previous(&ctx, &log.checkpoints()[0]).await.unwrap().as_deref(),
+            Some(b"original".as_slice())
+        );
+        assert!(log.checkpoints()[1].is_creation());
+        assert!(log.previous(&ctx, &log.checkpoints()[1]).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_restores_are_kept() {
+        let ctx = test_context().await;
+        let fs = ctx.fs();
+        ctx.fs().write("/other.txt", "other").await.unwrap();
+        let mut log = CheckpointLog::default();
+
+        log.start_turn();
+        log.snapshot(&ctx, "t1", &["/existing.txt".into(), "/other.txt".into()])
+            .await
+            .unwrap();
+        fs.write("/existing.txt", "first edit").await.unwrap();
+        fs.write("/other.txt", "other edit").await.unwrap();
+        log.snapshot(&ctx, "t2", &["/existing.txt".into()]).await.unwrap();
+        fs.write("/existing.txt", "second edit").await.unwrap();
+
+        // The snapshot of the second edit is lost.
+        let digest = hex::encode(Sha256::digest(b"first edit"));
+        fs.remove_file(log.blobs_dir(&ctx).unwrap().join(digest)).await.unwrap();
+
+        let err = log.undo_turns(&ctx, 1).await.unwrap_err();
+        assert!(err.to_string().contains("/existing.txt"), "{err}");
+        assert_eq!(fs.read_to_string("/other.txt").await.unwrap(), "other");
+        assert_eq!(fs.read_to_string("/existing.txt").await.unwrap(), "second edit");
+        // Both checkpoints of the file that failed to be restored are kept.
+        assert_eq!(
+            log.checkpoints()
+                .iter()
+                .map(|c| c.tool_use_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["t1", "t2"]
+        );
+        assert!(
+            log.checkpoints()
+                .iter()
+                .all(|c| c.path == PathBuf::from("/existing.txt"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreferenced_snapshots_are_removed() {
+        let ctx = test_context().await;
+        let fs = ctx.fs();
+        let mut log = CheckpointLog::default();
+        let blob = log
+            .blobs_dir(&ctx)
+            .unwrap()
+            .join(hex::encode(Sha256::digest(b"original")));
+
+        log.start_turn();
+        let ids = log.snapshot(&ctx, "t1", &["/existing.txt".into()]).await.unwrap();
+        log.snapshot(&ctx, "t2", &["/existing.txt".into()]).await.unwrap();
+        assert!(fs.exists(&blob));
+
+        // The snapshot is still referenced by the second checkpoint.
+        log.discard(&ctx, &ids).await;
+        assert!(fs.exists(&blob));
+
+        log.undo_turns(&ctx, 1).await.unwrap();
+        assert!(!fs.exists(&blob));
+    }
+
+    #[tokio::test]
+    async fn test_append_moves_snapshots() {
+        let ctx = test_context().await;
+        let mut log = CheckpointLog::default();
+        let mut child = CheckpointLog::default();
+        let child_dir = child.blobs_dir(&ctx).unwrap();
+
+        log.start_turn();
+        child.snapshot(&ctx, "t1", &["/existing.txt".into()]).await.unwrap();
+        ctx.fs().write("/existing.txt", "edit").await.unwrap();
+        log.append(&ctx, child).await.unwrap();
+        assert!(!ctx.fs().exists(&child_dir));
+
+        log.undo_turns(&ctx, 1).await.unwrap();
+        assert_eq!(ctx.fs().read_to_string("/existing.txt").await.unwrap(), "original");
+    }
+}
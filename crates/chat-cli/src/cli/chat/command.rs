@@ -16,6 +16,9 @@ use serde::{
     Serialize,
 };
 
+use super::resources;
+use super::tools::execute::sandbox::SandboxMode;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Command {
     Ask {
@@ -44,6 +47,8 @@ pub enum Command {
         prompt: Option<String>,
         show_summary: bool,
         help: bool,
+        /// Show the past compactions of the conversation instead of compacting it.
+        log: bool,
     },
     Tools {
         subcommand: Option<ToolsSubcommand>,
@@ -64,6 +69,78 @@ pub enum Command {
     Subscribe {
         manage: bool,
     },
+    Undo {
+        target: UndoTarget,
+    },
+    Checkpoints,
+    History {
+        all: bool,
+    },
+    Knowledge {
+        subcommand: KnowledgeSubcommand,
+    },
+    /// List the resources offered by MCP servers, optionally only those of one server.
+    Resources {
+        server_name: Option<String>,
+    },
+}
+
+/// What `/undo` should roll back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UndoTarget {
+    /// Every edit made during the last `n` turns.
+    Turns(usize),
+    /// A single edit, identified by its checkpoint id.
+    Checkpoint(usize),
+}
+
+impl UndoTarget {
+    pub const USAGE: &str = "/undo [n] | /undo --edit <checkpoint_id>";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KnowledgeSubcommand {
+    List,
+    Add { path: String, name: Option<String> },
+    Remove { target: String },
+    Search { query: String },
+    Help,
+}
+
+impl KnowledgeSubcommand {
+    const ADD_USAGE: &str = "/knowledge add <path> [--name <name>]";
+    const AVAILABLE_COMMANDS: &str = color_print::cstr! {"<cyan!>Available commands</cyan!>
+  <em>help</em>                           <black!>Show an explanation for the knowledge command</black!>
+  <em>list</em>                           <black!>List the knowledge bases</black!>
+  <em>add <<path>> [--name <<name>>]</em>     <black!>Index a file or directory as a new knowledge base</black!>
+  <em>rm <<name or path>></em>              <black!>Remove a knowledge base</black!>
+  <em>search <<query>></em>                 <black!>Search all knowledge bases</black!>"};
+    const REMOVE_USAGE: &str = "/knowledge rm <name or path>";
+    const SEARCH_USAGE: &str = "/knowledge search <query>";
+
+    fn usage_msg(header: impl AsRef<str>) -> String {
+        format!("{}\n\n{}", header.as_ref(), Self::AVAILABLE_COMMANDS)
+    }
+
+    pub fn help_text() -> String {
+        color_print::cformat!(
+            r#"
+<magenta,em>(Beta) Knowledge Bases</magenta,em>
+
+Knowledge bases let Amazon Q search files and directories that are too large to add as context,
+such as whole repositories or documentation folders. They are indexed once, kept between chat
+sessions, and searched with the knowledge_search tool when they are relevant to your prompt.
+
+{}
+
+<cyan!>Notes</cyan!>
+• Enable knowledge bases with <em>q settings chat.enableKnowledge true</em>
+• Indexing a large directory can take a while, hidden files are skipped
+• Knowledge bases are shared by all profiles
+"#,
+            Self::AVAILABLE_COMMANDS
+        )
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -73,6 +150,7 @@ pub enum ProfileSubcommand {
     Delete { name: String },
     Set { name: String },
     Rename { old_name: String, new_name: String },
+    Sandbox { mode: Option<SandboxMode> },
     Help,
 }
 
@@ -83,10 +161,12 @@ impl ProfileSubcommand {
   <em>create <<name>></em>       <black!>Create a new profile with the specified name</black!>
   <em>delete <<name>></em>       <black!>Delete the specified profile</black!>
   <em>set <<name>></em>          <black!>Switch to the specified profile</black!>
-  <em>rename <<old>> <<new>></em>  <black!>Rename a profile</black!>"};
+  <em>rename <<old>> <<new>></em>  <black!>Rename a profile</black!>
+  <em>sandbox [mode]</em>      <black!>Show or set how shell commands are sandboxed (off, readonly, workspace)</black!>"};
     const CREATE_USAGE: &str = "/profile create <profile_name>";
     const DELETE_USAGE: &str = "/profile delete <profile_name>";
     const RENAME_USAGE: &str = "/profile rename <old_profile_name> <new_profile_name>";
+    const SANDBOX_USAGE: &str = "/profile sandbox [off|readonly|workspace]";
     const SET_USAGE: &str = "/profile set <profile_name>";
 
     fn usage_msg(header: impl AsRef<str>) -> String {
@@ -107,6 +187,10 @@ Profiles allow you to organize and manage different sets of context files for di
 • The "default" profile is used when no profile is specified
 • You can switch between profiles to work on different projects
 • Each profile maintains its own set of context files
+• In the "workspace" sandbox mode, shell commands can only write to the current directory and the
+  temp directory, and have no network or unix socket access (Linux only)
+• In the "readonly" sandbox mode, shell commands cannot write files or access the network, and
+  run without asking for confirmation (Linux only)
 "#,
             Self::AVAILABLE_COMMANDS
         )
@@ -125,7 +209,7 @@ pub enum HooksSubcommand {
     Add {
         name: String,
 
-        #[arg(long, value_parser = ["per_prompt", "conversation_start"])]
+        #[arg(long, value_parser = ["per_prompt", "conversation_start", "pre_tool_use", "post_tool_use"])]
         trigger: String,
 
         #[arg(long, value_parser = clap::value_parser!(String))]
@@ -214,7 +298,8 @@ impl ContextSubcommand {
 
   <em>hooks add [--global] <<name>></em>        <black!>Add a new command context hook</black!>
                                          <black!>--global: Add to global hooks</black!>
-         <em>--trigger <<trigger>></em>           <black!>When to trigger the hook, valid options: `per_prompt` or `conversation_start`</black!>
+         <em>--trigger <<trigger>></em>           <black!>When to trigger the hook, valid options: `per_prompt`, `conversation_start`,</black!>
+                                         <black!>`pre_tool_use` or `post_tool_use`</black!>
          <em>--command <<command>></em>             <black!>Shell command to execute</black!>
 
   <em>hooks rm [--global] <<name>></em>         <black!>Remove an existing context hook</black!>
@@ -285,9 +370,18 @@ in global or local profiles.
 {}
 
 <cyan!>Notes</cyan!>
-• Hooks are executed in parallel
+• Context hooks are executed in parallel
 • 'conversation_start' hooks run on the first user prompt and are attached once to the conversation history sent to Amazon Q
 • 'per_prompt' hooks run on each user prompt and are attached to the prompt, but are not stored in conversation history
+• 'pre_tool_use' and 'post_tool_use' hooks run one after the other before and after each tool use. They receive
+  the tool name, input, and (after use) response as JSON on stdin, e.g. {{"hook_event": "pre_tool_use",
+  "tool_name": "fs_write", "tool_input": {{...}}}}
+• A 'pre_tool_use' hook denies the tool use by exiting with code 2, with stderr as the reason. A 'pre_tool_use'
+  hook that fails or times out denies the tool use as well. A 'post_tool_use' hook exiting with code 2 attaches
+  its stderr to the tool result instead
+• Tool use hooks can also print a JSON object to stdout with the optional fields "decision" ("allow" or "deny"),
+  "reason", "tool_input" (replaces the input of a 'pre_tool_use' tool use), and "feedback" (attached to the
+  tool result)
 "#,
             Self::HOOKS_AVAILABLE_COMMANDS
         )
@@ -302,6 +396,7 @@ pub enum ToolsSubcommand {
     TrustAll,
     Reset,
     ResetSingle { tool_name: String },
+    Rules { command: Option<String> },
     Help,
 }
 
@@ -313,7 +408,8 @@ impl ToolsSubcommand {
   <em>untrust <<tools...>></em>             <black!>Revert a tool or tools to per-request confirmation</black!>
   <em>trustall</em>                       <black!>Trust all tools (equivalent to deprecated /acceptall)</black!>
   <em>reset</em>                          <black!>Reset all tools to default permission levels</black!>
-  <em>reset <<tool name>></em>              <black!>Reset a single tool to default permission level</black!>"};
+  <em>reset <<tool name>></em>              <black!>Reset a single tool to default permission level</black!>
+  <em>rules [command]</em>                <black!>Show the command rules, or which rules match a command</black!>"};
     const BASE_COMMAND: &str = color_print::cstr! {"<cyan!>Usage: /tools [SUBCOMMAND]</cyan!>
 
 <cyan!>Description</cyan!>
@@ -456,10 +552,13 @@ impl Command {
                     let mut prompt = None;
                     let show_summary = true;
                     let mut help = false;
+                    let mut log = false;
 
                     // Check if "help" is the first subcommand
                     if parts.len() > 1 && parts[1].to_lowercase() == "help" {
                         help = true;
+                    } else if parts.get(1) == Some(&"--log") {
+                        log = true;
                     } else {
                         let mut remaining_parts = Vec::new();
 
@@ -475,6 +574,7 @@ impl Command {
                         prompt,
                         show_summary,
                         help,
+                        log,
                     }
                 },
                 "acceptall" => {
@@ -575,6 +675,17 @@ impl Command {
                                 None => usage_err!(ProfileSubcommand::SET_USAGE),
                             }
                         },
+                        "sandbox" => match parts.get(2) {
+                            Some(mode) => match mode.parse() {
+                                Ok(mode) => Self::Profile {
+                                    subcommand: ProfileSubcommand::Sandbox { mode: Some(mode) },
+                                },
+                                Err(_) => usage_err!(ProfileSubcommand::SANDBOX_USAGE),
+                            },
+                            None => Self::Profile {
+                                subcommand: ProfileSubcommand::Sandbox { mode: None },
+                            },
+                        },
                         "help" => Self::Profile {
                             subcommand: ProfileSubcommand::Help,
                         },
@@ -784,6 +895,14 @@ impl Command {
                                 },
                             }
                         },
+                        "rules" => Self::Tools {
+                            subcommand: Some(ToolsSubcommand::Rules {
+                                command: command
+                                    .split_once(parts[1])
+                                    .map(|(_, rest)| rest.trim().to_string())
+                                    .filter(|rest| !rest.is_empty()),
+                            }),
+                        },
                         "help" => Self::Tools {
                             subcommand: Some(ToolsSubcommand::Help),
                         },
@@ -850,6 +969,85 @@ impl Command {
                     let manage = parts.contains(&"--manage");
                     Self::Subscribe { manage }
                 },
+                "undo" => {
+                    let usage_err = || format!("Invalid /undo arguments.\n\nUsage:\n  {}", UndoTarget::USAGE);
+                    let target = match &parts[1..] {
+                        [] => UndoTarget::Turns(1),
+                        ["--edit", id] => match id.parse() {
+                            Ok(id) => UndoTarget::Checkpoint(id),
+                            Err(_) => return Err(usage_err()),
+                        },
+                        [n] => match n.parse() {
+                            Ok(n) if n > 0 => UndoTarget::Turns(n),
+                            _ => return Err(usage_err()),
+                        },
+                        _ => return Err(usage_err()),
+                    };
+                    Self::Undo { target }
+                },
+                "checkpoints" => Self::Checkpoints,
+                "history" => Self::History {
+                    all: parts.contains(&"--all"),
+                },
+                "knowledge" => {
+                    macro_rules! usage_err {
+                        ($usage_str:expr) => {
+                            return Err(format!(
+                                "Invalid /knowledge arguments.\n\nUsage:\n  {}",
+                                $usage_str
+                            ))
+                        };
+                    }
+
+                    let subcommand = match parts.get(1).map(|part| part.to_lowercase()).as_deref() {
+                        None | Some("list") => KnowledgeSubcommand::List,
+                        Some("add") => {
+                            let Some(args) = shlex::split(&parts[2..].join(" ")) else {
+                                return Err("Failed to parse quoted arguments".to_string());
+                            };
+                            match args.as_slice() {
+                                [path] => KnowledgeSubcommand::Add {
+                                    path: path.clone(),
+                                    name: None,
+                                },
+                                [path, flag, name] | [flag, name, path] if flag == "--name" => {
+                                    KnowledgeSubcommand::Add {
+                                        path: path.clone(),
+                                        name: Some(name.clone()),
+                                    }
+                                },
+                                _ => usage_err!(KnowledgeSubcommand::ADD_USAGE),
+                            }
+                        },
+                        Some("rm" | "remove") => match shlex::split(&parts[2..].join(" ")).as_deref() {
+                            Some([target]) => KnowledgeSubcommand::Remove { target: target.clone() },
+                            _ => usage_err!(KnowledgeSubcommand::REMOVE_USAGE),
+                        },
+                        Some("search") => {
+                            if parts.len() < 3 {
+                                usage_err!(KnowledgeSubcommand::SEARCH_USAGE);
+                            }
+                            KnowledgeSubcommand::Search {
+                                query: parts[2..].join(" "),
+                            }
+                        },
+                        Some("help") => KnowledgeSubcommand::Help,
+                        Some(other) => {
+                            return Err(KnowledgeSubcommand::usage_msg(format!(
+                                "Unknown subcommand '{}'.",
+                                other
+                            )));
+                        },
+                    };
+                    Self::Knowledge { subcommand }
+                },
+                "resources" => match parts.as_slice() {
+                    [_] => Self::Resources { server_name: None },
+                    [_, server_name] => Self::Resources {
+                        server_name: Some((*server_name).to_string()),
+                    },
+                    _ => return Err("Invalid /resources arguments.\n\nUsage:\n  /resources [server]".to_string()),
+                },
                 unknown_command => {
                     let looks_like_path = {
                         let after_slash_command_str = parts[1..].join(" ");
@@ -874,7 +1072,10 @@ impl Command {
             });
         }
 
-        if let Some(command) = input.strip_prefix('@') {
+        if let Some(command) = input
+            .strip_prefix('@')
+            .filter(|_| !resources::starts_with_resource_reference(input))
+        {
             let get_command = parse_input_to_prompts_get_command(command)?;
             let subcommand = Some(PromptsSubcommand::Get { get_command });
             return Ok(Self::Prompts { subcommand });
@@ -954,6 +1155,7 @@ mod tests {
                     prompt: $prompt,
                     show_summary: $show_summary,
                     help: false,
+                    log: false,
                 }
             };
         }
@@ -963,6 +1165,12 @@ mod tests {
                 "/compact custom prompt",
                 compact!(Some("custom prompt".to_string()), true),
             ),
+            ("/compact --log", Command::Compact {
+                prompt: None,
+                show_summary: true,
+                help: false,
+                log: true,
+            }),
             ("/profile list", profile!(ProfileSubcommand::List)),
             (
                 "/profile create new_profile",
@@ -989,6 +1197,19 @@ mod tests {
                 "/profile set p",
                 profile!(ProfileSubcommand::Set { name: "p".to_string() }),
             ),
+            ("/profile sandbox", profile!(ProfileSubcommand::Sandbox { mode: None })),
+            (
+                "/profile sandbox readonly",
+                profile!(ProfileSubcommand::Sandbox {
+                    mode: Some(SandboxMode::ReadOnly)
+                }),
+            ),
+            (
+                "/profile sandbox workspace",
+                profile!(ProfileSubcommand::Sandbox {
+                    mode: Some(SandboxMode::Workspace)
+                }),
+            ),
             ("/context show", context!(ContextSubcommand::Show { expand: false })),
             (
                 "/context show --expand",
@@ -1029,6 +1250,58 @@ mod tests {
                 "/context clear --global",
                 context!(ContextSubcommand::Clear { global: true }),
             ),
+            ("/undo", Command::Undo {
+                target: UndoTarget::Turns(1),
+            }),
+            ("/undo 3", Command::Undo {
+                target: UndoTarget::Turns(3),
+            }),
+            ("/undo --edit 7", Command::Undo {
+                target: UndoTarget::Checkpoint(7),
+            }),
+            ("/checkpoints", Command::Checkpoints),
+            ("/history", Command::History { all: false }),
+            ("/history --all", Command::History { all: true }),
+            ("/knowledge", Command::Knowledge {
+                subcommand: KnowledgeSubcommand::List,
+            }),
+            ("/knowledge add ./docs", Command::Knowledge {
+                subcommand: KnowledgeSubcommand::Add {
+                    path: "./docs".to_string(),
+                    name: None,
+                },
+            }),
+            ("/knowledge add \"my docs\" --name team", Command::Knowledge {
+                subcommand: KnowledgeSubcommand::Add {
+                    path: "my docs".to_string(),
+                    name: Some("team".to_string()),
+                },
+            }),
+            ("/knowledge rm team", Command::Knowledge {
+                subcommand: KnowledgeSubcommand::Remove {
+                    target: "team".to_string(),
+                },
+            }),
+            ("/knowledge search how are releases   deployed", Command::Knowledge {
+                subcommand: KnowledgeSubcommand::Search {
+                    query: "how are releases deployed".to_string(),
+                },
+            }),
+            ("/resources", Command::Resources { server_name: None }),
+            ("/resources docs", Command::Resources {
+                server_name: Some("docs".to_string()),
+            }),
+            ("@docs:file:///guide.md summarize this", Command::Ask {
+                prompt: "@docs:file:///guide.md summarize this".to_string(),
+            }),
+            ("/tools rules", Command::Tools {
+                subcommand: Some(ToolsSubcommand::Rules { command: None }),
+            }),
+            ("/tools rules cargo test  | grep ok", Command::Tools {
+                subcommand: Some(ToolsSubcommand::Rules {
+                    command: Some("cargo test  | grep ok".to_string()),
+                }),
+            }),
             ("/issue", Command::Issue { prompt: None }),
             ("/issue there was an error in the chat", Command::Issue {
                 prompt: Some("there was an error in the chat".to_string()),
@@ -0,0 +1,191 @@
+//! Automatic compaction of the conversation history.
+//!
+//! Once the conversation grows past a configurable share of the context window, the history is
+//! compacted with the strategy selected by the `chat.autoCompaction.strategy` setting before the
+//! user is prompted again, or before tool results are sent back to the model. Turns are counted as
+//! user prompts, along with the responses and tool uses that followed them. Every compaction,
+//! automatic or not, is recorded in the conversation state so that it can be inspected with
+//! `/compact --log`.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use time::OffsetDateTime;
+
+use super::consts::CONTEXT_WINDOW_SIZE;
+use crate::database::settings::{
+    Setting,
+    Settings,
+};
+
+/// How the conversation history is compacted once it grows past the threshold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CompactionStrategy {
+    /// The history is never compacted automatically.
+    #[default]
+    Off,
+    /// Summarize the oldest turns with the model, keeping the rest of the history verbatim.
+    SummarizeOldest { turns: usize },
+    /// Replace the contents of the largest tool results with a placeholder until the conversation
+    /// fits below the threshold.
+    DropToolResults,
+    /// Drop everything but the most recent turns.
+    KeepRecent { turns: usize },
+}
+
+impl CompactionStrategy {
+    pub const DEFAULT_TURNS: usize = 10;
+    pub const USAGE: &str = "off | summarize-oldest[:N] | drop-tool-results | keep-recent[:K]";
+}
+
+impl Display for CompactionStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompactionStrategy::Off => write!(f, "off"),
+            CompactionStrategy::SummarizeOldest { turns } => write!(f, "summarize-oldest:{turns}"),
+            CompactionStrategy::DropToolResults => write!(f, "drop-tool-results"),
+            CompactionStrategy::KeepRecent { turns } => write!(f, "keep-recent:{turns}"),
+        }
+    }
+}
+
+impl FromStr for CompactionStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, turns) = match s.trim().split_once(':') {
+            Some((name, turns)) => match turns.parse() {
+                Ok(turns) if turns > 0 => (name, Some(turns)),
+                _ => {
+                    return Err(format!(
+                        "Invalid number of turns '{turns}', expected a positive integer"
+                    ));
+                },
+            },
+            None => (s.trim(), None),
+        };
+        let turns = turns.unwrap_or(Self::DEFAULT_TURNS);
+        match (name.to_lowercase().as_str(), turns) {
+            ("off", _) => Ok(Self::Off),
+            ("summarize-oldest", turns) => Ok(Self::SummarizeOldest { turns }),
+            ("drop-tool-results", _) => Ok(Self::DropToolResults),
+            ("keep-recent", turns) => Ok(Self::KeepRecent { turns }),
+            (other, _) => Err(format!(
+                "Unknown compaction strategy '{other}', expected one of: {}",
+                Self::USAGE
+            )),
+        }
+    }
+}
+
+/// Automatic compaction as configured by the user's settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoCompaction {
+    pub strategy: CompactionStrategy,
+    /// Percentage of the context window at which the history is compacted.
+    pub threshold_percent: usize,
+}
+
+impl AutoCompaction {
+    pub const DEFAULT_THRESHOLD_PERCENT: usize = 80;
+
+    /// Reads the configuration from `settings`, returning an error message for invalid values
+    /// alongside the configuration that is used instead.
+    pub fn from_settings(settings: &Settings) -> (Self, Option<String>) {
+        let mut error = None;
+        let strategy = match settings.get_string(Setting::ChatAutoCompactionStrategy) {
+            Some(strategy) => strategy.parse().unwrap_or_else(|err| {
+                error = Some(format!("{}: {err}", Setting::ChatAutoCompactionStrategy));
+                CompactionStrategy::Off
+            }),
+            None => CompactionStrategy::Off,
+        };
+        let threshold_percent = match settings.get_int(Setting::ChatAutoCompactionThreshold) {
+            Some(percent @ 1..=100) => percent as usize,
+            Some(percent) => {
+                error = Some(format!(
+                    "{}: {percent} is not a percentage between 1 and 100",
+                    Setting::ChatAutoCompactionThreshold
+                ));
+                Self::DEFAULT_THRESHOLD_PERCENT
+            },
+            None => Self::DEFAULT_THRESHOLD_PERCENT,
+        };
+        (
+            Self {
+                strategy,
+                threshold_percent,
+            },
+            error,
+        )
+    }
+
+    /// The number of tokens at which the history is compacted.
+    pub fn threshold_tokens(&self) -> usize {
+        CONTEXT_WINDOW_SIZE * self.threshold_percent / 100
+    }
+}
+
+/// A record of a single compaction of the conversation history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactionRecord {
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    /// Whether the compaction was triggered automatically rather than by `/compact`.
+    pub automatic: bool,
+    /// A description of the strategy used, e.g. `summarize-oldest:10`.
+    pub strategy: String,
+    /// The number of turns removed from the history.
+    pub turns_removed: usize,
+    /// The number of tool results whose contents were removed.
+    pub tool_results_removed: usize,
+    /// The first prompt of each removed turn.
+    pub removed_prompts: Vec<String>,
+    /// The summary that replaced the removed turns, if any.
+    pub summary: Option<String>,
+    pub tokens_before: usize,
+    pub tokens_after: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_strategy() {
+        assert_eq!("off".parse::<CompactionStrategy>().unwrap(), CompactionStrategy::Off);
+        assert_eq!(
+            "summarize-oldest".parse::<CompactionStrategy>().unwrap(),
+            CompactionStrategy::SummarizeOldest {
+                turns: CompactionStrategy::DEFAULT_TURNS
+            }
+        );
+        assert_eq!(
+            "keep-recent:4".parse::<CompactionStrategy>().unwrap(),
+            CompactionStrategy::KeepRecent { turns: 4 }
+        );
+        assert_eq!(
+            "Drop-Tool-Results".parse::<CompactionStrategy>().unwrap(),
+            CompactionStrategy::DropToolResults
+        );
+        assert!("keep-recent:0".parse::<CompactionStrategy>().is_err());
+        assert!("keep-recent:x".parse::<CompactionStrategy>().is_err());
+        assert!("truncate".parse::<CompactionStrategy>().is_err());
+
+        let strategy = CompactionStrategy::SummarizeOldest { turns: 3 };
+        assert_eq!(strategy.to_string().parse::<CompactionStrategy>().unwrap(), strategy);
+    }
+
+    #[test]
+    fn test_threshold_tokens() {
+        let auto = AutoCompaction {
+            strategy: CompactionStrategy::DropToolResults,
+            threshold_percent: 50,
+        };
+        assert_eq!(auto.threshold_tokens(), CONTEXT_WINDOW_SIZE / 2);
+    }
+}
@@ -22,7 +22,10 @@ use super::consts::CONTEXT_FILES_MAX_SIZE;
 use super::hooks::{
     Hook,
     HookExecutor,
+    ToolHookInput,
+    ToolHookOutcome,
 };
+use super::tools::execute::sandbox::SandboxMode;
 use super::util::drop_matched_context_files;
 use crate::platform::Context;
 use crate::util::directories;
@@ -38,6 +41,10 @@ pub struct ContextConfig {
 
     /// Map of Hook Name to [`Hook`]. The hook name serves as the hook's ID.
     pub hooks: HashMap<String, Hook>,
+
+    /// How shell commands are sandboxed. Only read from profile configs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxMode>,
 }
 
 #[allow(dead_code)]
@@ -219,6 +226,37 @@ impl ContextManager {
         Ok(())
     }
 
+    /// Returns the directories MCP servers are told they may operate on: the working directory,
+    /// followed by the directories the context paths of the global and current profile
+    /// configurations point into.
+    pub fn root_dirs(&self) -> Vec<PathBuf> {
+        let Ok(cwd) = self.ctx.env().current_dir() else {
+            return Vec::new();
+        };
+        let mut dirs = vec![cwd.clone()];
+        for path in self.global_config.paths.iter().chain(&self.profile_config.paths) {
+            // Only the part of a glob pattern before the first wildcard names a directory.
+            let literal = path.split(['*', '?', '[']).next().unwrap_or_default();
+            let full_path = match (literal.strip_prefix('~'), self.ctx.env().home()) {
+                (Some(rest), Some(home)) => home.join(rest.trim_start_matches('/')),
+                (Some(_), None) => continue,
+                (None, _) => cwd.join(literal),
+            };
+            let dir = if self.ctx.fs().chroot_path(&full_path).is_dir() {
+                full_path
+            } else {
+                match full_path.parent() {
+                    Some(parent) if self.ctx.fs().chroot_path(parent).is_dir() => parent.to_path_buf(),
+                    _ => continue,
+                }
+            };
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+        dirs
+    }
+
     /// List all available profiles.
     ///
     /// # Returns
@@ -519,6 +557,17 @@ impl ContextManager {
         }
     }
 
+    /// The sandbox mode shell commands run under in the current profile.
+    pub fn sandbox_mode(&self) -> SandboxMode {
+        self.profile_config.sandbox.unwrap_or_default()
+    }
+
+    /// Sets the sandbox mode of the current profile.
+    pub async fn set_sandbox_mode(&mut self, mode: SandboxMode) -> Result<()> {
+        self.profile_config.sandbox = Some(mode);
+        self.save_config(false).await
+    }
+
     /// Add hooks to the context config. If another hook with the same name already exists, throw an
     /// error.
     ///
@@ -584,31 +633,42 @@ impl ContextManager {
         self.save_config(global).await
     }
 
-    /// Run all the currently enabled hooks from both the global and profile contexts.
+    /// Run all the currently enabled context hooks from both the global and profile contexts.
     /// Skipped hooks (disabled) will not appear in the output.
     /// # Arguments
     /// * `updates` - output stream to write hook run status to if Some, else do nothing if None
     /// # Returns
     /// A vector containing pairs of a [`Hook`] definition and its execution output
     pub async fn run_hooks(&mut self, updates: Option<&mut impl Write>) -> Vec<(Hook, String)> {
-        let mut hooks: Vec<&Hook> = Vec::new();
-
-        // Set internal hook states
-        let configs = [
-            (&mut self.global_config.hooks, true),
-            (&mut self.profile_config.hooks, false),
-        ];
-
-        for (hook_list, is_global) in configs {
-            hooks.extend(hook_list.iter_mut().map(|(name, h)| {
-                h.name = name.to_string();
-                h.is_global = is_global;
-                &*h
-            }));
-        }
+        let hooks = all_hooks(&mut self.global_config, &mut self.profile_config)
+            .into_iter()
+            .filter(|h| !h.trigger.is_tool_use())
+            .collect();
 
         self.hook_executor.run_hooks(hooks, updates).await
     }
+
+    /// Run the enabled tool use hooks from both the global and profile contexts whose trigger
+    /// matches `input.hook_event`, global hooks first.
+    pub async fn run_tool_hooks(&mut self, input: ToolHookInput, updates: Option<&mut impl Write>) -> ToolHookOutcome {
+        let hooks = all_hooks(&mut self.global_config, &mut self.profile_config);
+        self.hook_executor.run_tool_hooks(hooks, input, updates).await
+    }
+}
+
+/// Returns the hooks of both configs after setting their internal state.
+fn all_hooks<'a>(global_config: &'a mut ContextConfig, profile_config: &'a mut ContextConfig) -> Vec<&'a Hook> {
+    let mut hooks: Vec<&Hook> = Vec::new();
+    let configs = [(&mut global_config.hooks, true), (&mut profile_config.hooks, false)];
+
+    for (hook_list, is_global) in configs {
+        hooks.extend(hook_list.iter_mut().map(|(name, h)| {
+            h.name = name.to_string();
+            h.is_global = is_global;
+            &*h
+        }));
+    }
+    hooks
 }
 
 fn profile_dir_path(ctx: &Context, profile_name: &str) -> Result<PathBuf> {
@@ -642,6 +702,7 @@ async fn load_global_config(ctx: &Context) -> Result<ContextConfig> {
                 AMAZONQ_FILENAME.to_string(),
             ],
             hooks: HashMap::new(),
+            sandbox: None,
         })
     }
 }
@@ -885,6 +946,26 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_root_dirs() -> Result<()> {
+        let mut manager = create_test_context_manager(None).await?;
+        let ctx = Arc::clone(&manager.ctx);
+        ctx.fs().create_dir_all("/docs/guides").await?;
+        ctx.fs().write("/docs/guides/setup.md", "setup").await?;
+        ctx.fs().create_dir_all("/notes").await?;
+        ctx.fs().write("/notes/todo.md", "todo").await?;
+
+        manager.global_config.paths = vec!["/docs/**/*.md".to_string(), "/missing/*.md".to_string()];
+        manager.profile_config.paths = vec!["/notes/todo.md".to_string(), "/docs/guides".to_string()];
+        assert_eq!(manager.root_dirs(), vec![
+            PathBuf::from("/"),
+            PathBuf::from("/docs"),
+            PathBuf::from("/notes"),
+            PathBuf::from("/docs/guides"),
+        ]);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_collect_exceeds_limit() -> Result<()> {
         let mut manager = create_test_context_manager(Some(2)).await?;
@@ -3,6 +3,7 @@ use std::collections::{
     HashSet,
     VecDeque,
 };
+use std::fmt::Write as _;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
@@ -21,9 +22,12 @@ use tracing::{
     warn,
 };
 
+use super::checkpoint::CheckpointLog;
+use super::compaction::CompactionRecord;
 use super::consts::{
+    CONTEXT_FILES_MAX_SIZE,
+    CONTEXT_WINDOW_SIZE,
     DUMMY_TOOL_NAME,
-    MAX_CHARS,
     MAX_CONVERSATION_STATE_HISTORY_LEN,
     MAX_USER_MESSAGE_SIZE,
 };
@@ -40,9 +44,11 @@ use super::message::{
     UserMessageContent,
     build_env_state,
 };
+use super::resources::ResourceReference;
 use super::token_counter::{
-    CharCount,
-    CharCounter,
+    TokenCount,
+    TokenCounted,
+    TokenCounter,
 };
 use super::tool_manager::ToolManager;
 use super::tools::{
@@ -71,7 +77,10 @@ use crate::api_client::model::{
 };
 use crate::cli::chat::util::shared_writer::SharedWriter;
 use crate::database::Database;
-use crate::mcp_client::Prompt;
+use crate::mcp_client::{
+    Prompt,
+    ResourceReadResult,
+};
 use crate::platform::Context;
 
 const CONTEXT_ENTRY_START_HEADER: &str = "--- CONTEXT ENTRY BEGIN ---\n";
@@ -108,6 +117,12 @@ pub struct ConversationState {
     /// Model explicitly selected by the user in this conversation state via `/model`.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub model: Option<String>,
+    /// Snapshots of files modified by tools in this conversation, used by `/undo`.
+    #[serde(default)]
+    pub checkpoints: CheckpointLog,
+    /// Every compaction of the history, oldest first, as shown by `/compact --log`.
+    #[serde(default)]
+    pub compactions: Vec<CompactionRecord>,
 }
 
 impl ConversationState {
@@ -162,6 +177,8 @@ impl ConversationState {
             latest_summary: None,
             updates,
             model: current_model_id,
+            checkpoints: CheckpointLog::default(),
+            compactions: Vec::new(),
         }
     }
 
@@ -269,15 +286,24 @@ impl ConversationState {
 
     /// Sets the response message according to the currently set [Self::next_message].
     pub fn push_assistant_message(&mut self, message: AssistantMessage, database: &mut Database) {
+        self.append_assistant_message(message);
+
+        if let Ok(cwd) = std::env::current_dir() {
+            database.set_conversation_by_path(&cwd, self).ok();
+            database.set_conversation_history(&cwd, self).ok();
+        }
+    }
+
+    /// Adds `message` to the history as the response to the next user message, without saving the
+    /// conversation. Used by conversations that are not the user's, such as delegated tasks.
+    pub fn append_assistant_message(&mut self, message: AssistantMessage) {
         debug_assert!(self.next_message.is_some(), "next_message should exist");
-        let next_user_message = self.next_message.take().expect("next user message should exist");
+        let mut next_user_message = self.next_message.take().expect("next user message should exist");
+        // Attached resources are only sent once, the prompt still references them.
+        next_user_message.resource_context.clear();
 
         self.append_assistant_transcript(&message);
         self.history.push_back((next_user_message, message));
-
-        if let Ok(cwd) = std::env::current_dir() {
-            database.set_conversation_by_path(cwd, self).ok();
-        }
     }
 
     /// Returns the conversation id.
@@ -285,6 +311,18 @@ impl ConversationState {
         self.conversation_id.as_ref()
     }
 
+    /// Returns the first prompt in the history that was typed by the user, if present.
+    pub fn first_prompt(&self) -> Option<&str> {
+        self.history.iter().find_map(|(user, _)| user.prompt())
+    }
+
+    /// Returns the size of the conversation history in tokens, excluding context messages.
+    pub fn token_count(&self) -> usize {
+        self.history.iter().fold(0, |acc, (user, assistant)| {
+            acc + *user.token_count() + *assistant.token_count()
+        })
+    }
+
     /// Returns the message id associated with the last assistant message, if present.
     ///
     /// This is equivalent to `utterance_id` in the Q API.
@@ -438,6 +476,17 @@ impl ConversationState {
         ));
     }
 
+    /// Attaches the contents of MCP resources to the next user message.
+    pub fn attach_resources<'a>(
+        &mut self,
+        resources: impl IntoIterator<Item = &'a (ResourceReference, ResourceReadResult)>,
+    ) {
+        debug_assert!(self.next_message.is_some(), "next_message should exist");
+        if let Some(next_message) = self.next_message.as_mut() {
+            next_message.resource_context = format_resource_context(resources);
+        }
+    }
+
     /// Returns a [FigConversationState] capable of being sent by [api_client::StreamingClient].
     ///
     /// Params:
@@ -504,6 +553,11 @@ impl ConversationState {
         self.update_state(false).await;
         self.enforce_conversation_invariants();
 
+        // The context paths may have changed since the last turn, e.g. with /profile set.
+        if let Some(cm) = self.context_manager.as_ref() {
+            self.tool_manager.set_roots(cm.root_dirs());
+        }
+
         // Run hooks and add to conversation start and next user message.
         let mut conversation_start_context = None;
         if let (true, Some(cm)) = (run_hooks, self.context_manager.as_mut()) {
@@ -546,7 +600,14 @@ impl ConversationState {
 
     /// Returns a [FigConversationState] capable of replacing the history of the current
     /// conversation with a summary generated by the model.
-    pub async fn create_summary_request(&mut self, custom_prompt: Option<impl AsRef<str>>) -> FigConversationState {
+    ///
+    /// `turns` is the number of oldest turns to summarize, by default everything but the most
+    /// recent turn is summarized.
+    pub async fn create_summary_request(
+        &mut self,
+        custom_prompt: Option<impl AsRef<str>>,
+        turns: Option<usize>,
+    ) -> FigConversationState {
         let summary_content = match custom_prompt {
             Some(custom_prompt) => {
                 // Make the custom instructions much more prominent and directive
@@ -592,12 +653,12 @@ impl ConversationState {
 
         let conv_state = self.backend_conversation_state(false, true).await;
 
-        // Include everything but the last message in the history.
+        // Include everything but the last message in the history, unless told otherwise.
         let history_len = conv_state.history.len();
         let history = if history_len < 2 {
             vec![]
         } else {
-            flatten_history(conv_state.history.take(history_len.saturating_sub(1)))
+            flatten_history(conv_state.history.take(turns.unwrap_or(history_len.saturating_sub(1))))
         };
 
         let mut summary_message = UserInputMessage {
@@ -625,8 +686,10 @@ impl ConversationState {
         }
     }
 
-    pub fn replace_history_with_summary(&mut self, summary: String) {
-        self.history.drain(..(self.history.len().saturating_sub(1)));
+    /// Replaces the oldest `turns` turns of the history with `summary`, by default everything but
+    /// the most recent turn. Returns the prompts of the replaced turns.
+    pub fn replace_history_with_summary(&mut self, summary: String, turns: Option<usize>) -> Vec<String> {
+        let removed_prompts = self.drop_oldest_turns(turns.unwrap_or(self.history.len().saturating_sub(1)));
         self.latest_summary = Some(summary);
         // If the last message contains tool results, then we add the results to the content field
         // instead. This is required to avoid validation errors.
@@ -655,6 +718,82 @@ impl ConversationState {
                 user.content = UserMessageContent::Prompt { prompt };
             }
         }
+        removed_prompts
+    }
+
+    /// Returns the indices the history can be cut at when compacting, i.e. those of turns that can
+    /// start the history because the user message does not contain tool results.
+    pub fn compaction_boundaries(&self) -> Vec<usize> {
+        self.history
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, (user, _))| !user.has_tool_use_results())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns the number of user turns that start within the oldest `entries` history entries.
+    pub fn count_turns(&self, entries: usize) -> usize {
+        self.history
+            .iter()
+            .take(entries)
+            .filter(|(user, _)| !user.has_tool_use_results())
+            .count()
+    }
+
+    /// Removes the oldest `count` turns from the history, returning the first line of the prompt
+    /// of each removed turn.
+    pub fn drop_oldest_turns(&mut self, count: usize) -> Vec<String> {
+        let count = count.min(self.history.len());
+        let removed_prompts = self
+            .history
+            .drain(..count)
+            .filter_map(|(user, _)| {
+                user.prompt()
+                    .and_then(|p| p.lines().next())
+                    .map(|p| truncate_safe(p, 200).to_string())
+            })
+            .collect();
+        self.enforce_conversation_invariants();
+        removed_prompts
+    }
+
+    /// Replaces the contents of the largest tool results in the history with a placeholder until
+    /// at least `tokens_to_free` tokens have been freed. Tool results in the most recent turn are
+    /// kept. Returns the number of tool results removed and the number of tokens freed.
+    pub fn drop_largest_tool_results(&mut self, tokens_to_free: usize) -> (usize, usize) {
+        const PLACEHOLDER: &str = "<tool result removed to free up context space>";
+
+        let recent_turn = self.history.len().saturating_sub(1);
+        let mut results = self
+            .history
+            .iter()
+            .take(recent_turn)
+            .enumerate()
+            .flat_map(|(turn, (user, _))| {
+                user.tool_use_results()
+                    .into_iter()
+                    .flatten()
+                    .enumerate()
+                    .map(move |(i, result)| (turn, i, std::slice::from_ref(result).token_count().value()))
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|a, b| b.2.cmp(&a.2));
+
+        let placeholder_tokens = TokenCounter::tokenizer().count_tokens(PLACEHOLDER);
+        let (mut removed, mut freed) = (0, 0);
+        for (turn, i, tokens) in results {
+            if freed >= tokens_to_free || tokens <= placeholder_tokens {
+                break;
+            }
+            if let Some(result) = self.history[turn].0.tool_use_results_mut().and_then(|r| r.get_mut(i)) {
+                result.content = vec![ToolUseResultBlock::Text(PLACEHOLDER.to_string())];
+                removed += 1;
+                freed += tokens - placeholder_tokens;
+            }
+        }
+        (removed, freed)
     }
 
     pub fn current_profile(&self) -> Option<&str> {
@@ -729,17 +868,20 @@ impl ConversationState {
         self.context_message_length
     }
 
-    /// Calculate the total character count in the conversation
-    pub async fn calculate_char_count(&mut self) -> CharCount {
-        self.backend_conversation_state(false, true).await.char_count()
+    /// Calculate the total token count in the conversation
+    pub async fn calculate_token_count(&mut self) -> TokenCount {
+        self.backend_conversation_state(false, true).await.token_count()
     }
 
-    /// Get the current token warning level
-    pub async fn get_token_warning_level(&mut self) -> TokenWarningLevel {
-        let total_chars = self.calculate_char_count().await;
+    /// Get the current token warning level, given the number of tokens at which the conversation
+    /// is considered large enough to compact.
+    pub async fn get_token_warning_level(&mut self, warning_threshold: usize) -> TokenWarningLevel {
+        let total_tokens = self.calculate_token_count().await;
 
-        if *total_chars >= MAX_CHARS {
+        if *total_tokens >= CONTEXT_WINDOW_SIZE {
             TokenWarningLevel::Critical
+        } else if *total_tokens >= warning_threshold {
+            TokenWarningLevel::Warning
         } else {
             TokenWarningLevel::None
         }
@@ -867,33 +1009,27 @@ impl
     }
 
     pub fn calculate_conversation_size(&self) -> ConversationSize {
-        let mut user_chars = 0;
-        let mut assistant_chars = 0;
-        let mut context_chars = 0;
+        let mut user_tokens = TokenCount::default();
+        let mut assistant_tokens = TokenCount::default();
+        let mut context_tokens = TokenCount::default();
 
-        // Count the chars used by the messages in the history.
+        // Count the tokens used by the messages in the history.
         // this clone is cheap
         let history = self.history.clone();
         for (user, assistant) in history {
-            user_chars += *user.char_count();
-            assistant_chars += *assistant.char_count();
+            user_tokens = user_tokens + user.token_count();
+            assistant_tokens = assistant_tokens + assistant.token_count();
         }
 
-        // Add any chars from context messages, if available.
-        context_chars += self
-            .context_messages
-            .as_ref()
-            .map(|v| {
-                v.iter().fold(0, |acc, (user, assistant)| {
-                    acc + *user.char_count() + *assistant.char_count()
-                })
-            })
-            .unwrap_or_default();
+        // Add any tokens from context messages, if available.
+        for (user, assistant) in self.context_messages.iter().flatten() {
+            context_tokens = context_tokens + user.token_count() + assistant.token_count();
+        }
 
         ConversationSize {
-            context_messages: context_chars.into(),
-            user_messages: user_chars.into(),
-            assistant_messages: assistant_chars.into(),
+            context_messages: context_tokens,
+            user_messages: user_tokens,
+            assistant_messages: assistant_tokens,
         }
     }
 }
@@ -901,9 +1037,9 @@ impl
 /// Reflects a detailed accounting of the context window utilization for a given conversation.
 #[derive(Debug, Clone, Copy)]
 pub struct ConversationSize {
-    pub context_messages: CharCount,
-    pub user_messages: CharCount,
-    pub assistant_messages: CharCount,
+    pub context_messages: TokenCount,
+    pub user_messages: TokenCount,
+    pub assistant_messages: TokenCount,
 }
 
 /// Converts a list of user/assistant message pairs into a flattened list of ChatMessage.
@@ -918,12 +1054,14 @@ where
     })
 }
 
-/// Character count warning levels for conversation size
+/// Token count warning levels for conversation size
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TokenWarningLevel {
     /// No warning, conversation is within normal limits
     None,
-    /// Critical level - at single warning threshold (600K characters)
+    /// Past the automatic compaction threshold, but still within the context window
+    Warning,
+    /// Critical level - at single warning threshold (the size of the context window)
     Critical,
 }
 
@@ -935,6 +1073,47 @@ impl From<InputSchema> for ToolInputSchema {
     }
 }
 
+/// Formats the contents of attached MCP resources, truncating them once they add up to more than
+/// [CONTEXT_FILES_MAX_SIZE] bytes.
+fn format_resource_context<'a>(
+    resources: impl IntoIterator<Item = &'a (ResourceReference, ResourceReadResult)>,
+) -> String {
+    let mut context_content = String::new();
+    let mut remaining = CONTEXT_FILES_MAX_SIZE;
+
+    context_content.push_str(CONTEXT_ENTRY_START_HEADER);
+    context_content.push_str("This section contains MCP resources I have attached to my prompt. I refer to them as @server:uri. Use their contents to answer my prompt.\n\n");
+    for (reference, result) in resources {
+        for contents in &result.contents {
+            match (&contents.text, &contents.mime_type) {
+                (Some(text), _) => {
+                    let shown = truncate_safe(text, remaining);
+                    remaining -= shown.len();
+                    if shown.len() < text.len() {
+                        let _ = writeln!(
+                            context_content,
+                            "[{reference}]\n{shown}\n... (truncated, showing {} of {} bytes)",
+                            shown.len(),
+                            text.len()
+                        );
+                    } else {
+                        let _ = writeln!(context_content, "[{reference}]\n{text}");
+                    }
+                },
+                (None, mime_type) => {
+                    let _ = writeln!(
+                        context_content,
+                        "[{reference}]\n(binary contents of type {} omitted)",
+                        mime_type.as_deref().unwrap_or("unknown")
+                    );
+                },
+            }
+        }
+    }
+    context_content.push_str(CONTEXT_ENTRY_END_HEADER);
+    context_content
+}
+
 fn format_hook_context<'a>(hook_results: impl IntoIterator<Item = &'a (Hook, String)>, trigger: HookTrigger) -> String {
     let mut context_content = String::new();
 
@@ -1161,6 +1340,161 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_conversation_state_saved_to_history() {
+        let mut database = Database::new().await.unwrap();
+        let mut output = SharedWriter::null();
+
+        let mut tool_manager = ToolManager::default();
+        let mut conversation_state = ConversationState::new(
+            Context::new(),
+            "history_conv_id",
+            tool_manager.load_tools(&database, &mut output).await.unwrap(),
+            None,
+            None,
+            tool_manager,
+            None,
+        )
+        .await;
+        for prompt in ["first prompt", "second prompt"] {
+            conversation_state.set_next_user_message(prompt.to_string()).await;
+            conversation_state.push_assistant_message(
+                AssistantMessage::new_response(None, "response".to_string()),
+                &mut database,
+            );
+        }
+
+        let cwd = std::env::current_dir().unwrap();
+        let conversations = database.list_conversations(Some(&cwd)).unwrap();
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].conversation_id, "history_conv_id");
+        assert_eq!(conversations[0].first_prompt.as_deref(), Some("first prompt"));
+        assert_eq!(conversations[0].token_count, conversation_state.token_count());
+        assert!(
+            database
+                .list_conversations(Some(std::path::Path::new("/some/other/dir")))
+                .unwrap()
+                .is_empty()
+        );
+
+        let restored = database.get_conversation_by_id("history_conv_id").unwrap().unwrap();
+        assert_eq!(restored.history().len(), 2);
+        assert!(database.get_conversation_by_id("unknown").unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_conversation_state_resources() {
+        let mut database = Database::new().await.unwrap();
+        let mut output = SharedWriter::null();
+
+        let mut tool_manager = ToolManager::default();
+        let mut conversation_state = ConversationState::new(
+            Context::new(),
+            "fake_conv_id",
+            tool_manager.load_tools(&database, &mut output).await.unwrap(),
+            None,
+            None,
+            tool_manager,
+            None,
+        )
+        .await;
+        let resource = (
+            ResourceReference {
+                server_name: "docs".to_string(),
+                uri: "file:///big.md".to_string(),
+            },
+            ResourceReadResult {
+                contents: vec![crate::mcp_client::ResourceReadContents {
+                    uri: "file:///big.md".to_string(),
+                    mime_type: None,
+                    text: Some("a".repeat(CONTEXT_FILES_MAX_SIZE + 100)),
+                    blob: None,
+                }],
+            },
+        );
+
+        conversation_state
+            .set_next_user_message("summarize @docs:file:///big.md".to_string())
+            .await;
+        conversation_state.attach_resources([&resource]);
+        let s = conversation_state.as_sendable_conversation_state(false).await;
+        assert!(s.user_input_message.content.len() < CONTEXT_FILES_MAX_SIZE + 1000);
+        assert!(s.user_input_message.content.contains(&format!(
+            "(truncated, showing {CONTEXT_FILES_MAX_SIZE} of {} bytes)",
+            CONTEXT_FILES_MAX_SIZE + 100
+        )));
+
+        // The contents are not sent again with later messages.
+        conversation_state.push_assistant_message(
+            AssistantMessage::new_response(None, "Summary".to_string()),
+            &mut database,
+        );
+        conversation_state.set_next_user_message("thanks".to_string()).await;
+        let s = conversation_state.as_sendable_conversation_state(false).await;
+        #[allow(clippy::match_wildcard_for_single_variants)]
+        match &s.history.unwrap()[0] {
+            ChatMessage::UserInputMessage(user) => assert_eq!(user.content, "summarize @docs:file:///big.md"),
+            other => panic!("Expected a user message, found {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_conversation_state_compaction() {
+        let mut database = Database::new().await.unwrap();
+        let mut output = SharedWriter::null();
+
+        let mut tool_manager = ToolManager::default();
+        let mut conversation_state = ConversationState::new(
+            Context::new(),
+            "fake_conv_id",
+            tool_manager.load_tools(&database, &mut output).await.unwrap(),
+            None,
+            None,
+            tool_manager,
+            None,
+        )
+        .await;
+
+        // Each prompt is followed by a tool use, whose result starts a turn that can't begin the
+        // history.
+        for i in 0..4 {
+            conversation_state.set_next_user_message(format!("prompt {i}")).await;
+            conversation_state.push_assistant_message(
+                AssistantMessage::new_tool_use(None, i.to_string(), vec![AssistantToolUse {
+                    id: format!("tool_{i}"),
+                    name: "fs_read".to_string(),
+                    args: serde_json::Value::Null,
+                    ..Default::default()
+                }]),
+                &mut database,
+            );
+            conversation_state.add_tool_results(vec![ToolUseResult {
+                tool_use_id: format!("tool_{i}"),
+                content: vec![ToolUseResultBlock::Text("result ".repeat(100 * (i + 1)))],
+                status: ToolResultStatus::Success,
+            }]);
+            conversation_state
+                .push_assistant_message(AssistantMessage::new_response(None, "done".to_string()), &mut database);
+        }
+        assert_eq!(conversation_state.history().len(), 8);
+        assert_eq!(conversation_state.compaction_boundaries(), vec![2, 4, 6]);
+        assert_eq!(conversation_state.count_turns(5), 3);
+        assert_eq!(conversation_state.count_turns(usize::MAX), 4);
+
+        // The tool result of the most recent turn is kept, the largest of the others removed first.
+        let before = conversation_state.token_count();
+        let (removed, freed) = conversation_state.drop_largest_tool_results(1);
+        assert_eq!(removed, 1);
+        assert_eq!(conversation_state.token_count(), before - freed);
+        let (removed, _) = conversation_state.drop_largest_tool_results(usize::MAX);
+        assert_eq!(removed, 2);
+
+        let removed_prompts = conversation_state.drop_oldest_turns(4);
+        assert_eq!(removed_prompts, vec!["prompt 0", "prompt 1"]);
+        assert_eq!(conversation_state.history().len(), 4);
+        assert_eq!(conversation_state.first_prompt(), Some("prompt 2"));
+    }
+
     #[tokio::test]
     async fn test_conversation_state_with_context_files() {
         let mut database = Database::new().await.unwrap();
@@ -27,6 +27,7 @@ that may eventually reach memory constraints.
 <cyan!>Usage</cyan!>
   <em>/compact</em>                   <black!>Summarize the conversation and clear history</black!>
   <em>/compact [prompt]</em>          <black!>Provide custom guidance for summarization</black!>
+  <em>/compact --log</em>             <black!>Show what previous compactions removed</black!>
 
 <cyan!>When to use</cyan!>
 • When you see the memory constraint warning message
@@ -39,6 +40,13 @@ that may eventually reach memory constraints.
 • Retains key information, code, and tool executions in the summary
 • Clears the conversation history to free up space
 • The assistant will reference the summary context in future responses
+
+<cyan!>Automatic compaction</cyan!>
+Set <em>chat.autoCompaction.strategy</em> to compact the history automatically once it uses
+<em>chat.autoCompaction.threshold</em> percent of the context window (80 by default):
+  <em>summarize-oldest[:N]</em>       <black!>Summarize the oldest N turns (default 10)</black!>
+  <em>drop-tool-results</em>          <black!>Remove the largest tool results first</black!>
+  <em>keep-recent[:K]</em>            <black!>Drop all but the last K turns (default 10)</black!>
 "#
     )
 }
@@ -113,6 +121,10 @@ pub const HELP_COMMANDS: &[CommandHelp] = &[
                 name: "reset",
                 description: "Reset all tools to default permission levels",
             },
+            SubCommand {
+                name: "rules",
+                description: "Show the command rules, or which rules match a command",
+            },
         ],
         supported_os: &["all"],
     },
@@ -156,6 +168,10 @@ pub const HELP_COMMANDS: &[CommandHelp] = &[
                 name: "rename",
                 description: "Rename a profile",
             },
+            SubCommand {
+                name: "sandbox",
+                description: "Show or set how shell commands are sandboxed",
+            },
         ],
         supported_os: &["all"],
     },
@@ -178,6 +194,15 @@ pub const HELP_COMMANDS: &[CommandHelp] = &[
         ],
         supported_os: &["all"],
     },
+    CommandHelp {
+        command: "/resources",
+        description: "List MCP server resources, attach one to a prompt with @server:uri",
+        subcommands: &[SubCommand {
+            name: "[server]",
+            description: "Only list the resources of this server",
+        }],
+        supported_os: &["all"],
+    },
     CommandHelp {
         command: "/context",
         description: "Manage context files and hooks for the chat session",
@@ -227,6 +252,63 @@ pub const HELP_COMMANDS: &[CommandHelp] = &[
         subcommands: &[],
         supported_os: &["all"],
     },
+    CommandHelp {
+        command: "/undo",
+        description: "Roll back file edits made during the last turn",
+        subcommands: &[
+            SubCommand {
+                name: "[n]",
+                description: "Roll back the edits made during the last n turns",
+            },
+            SubCommand {
+                name: "--edit <id>",
+                description: "Roll back a single edit listed by /checkpoints",
+            },
+        ],
+        supported_os: &["all"],
+    },
+    CommandHelp {
+        command: "/checkpoints",
+        description: "List file edits made in this conversation that can be rolled back",
+        subcommands: &[],
+        supported_os: &["all"],
+    },
+    CommandHelp {
+        command: "/history",
+        description: "Browse past conversations from this directory and resume one",
+        subcommands: &[SubCommand {
+            name: "--all",
+            description: "Include conversations from every directory",
+        }],
+        supported_os: &["all"],
+    },
+    CommandHelp {
+        command: "/knowledge",
+        description: "(Beta) Manage knowledge bases that Amazon Q can search",
+        subcommands: &[
+            SubCommand {
+                name: "help",
+                description: "Show knowledge help",
+            },
+            SubCommand {
+                name: "list",
+                description: "List knowledge bases",
+            },
+            SubCommand {
+                name: "add",
+                description: "Index a file or directory [--name <name>]",
+            },
+            SubCommand {
+                name: "rm",
+                description: "Remove a knowledge base",
+            },
+            SubCommand {
+                name: "search",
+                description: "Search all knowledge bases",
+            },
+        ],
+        supported_os: &["all"],
+    },
     CommandHelp {
         command: "/subscribe",
         description: "Upgrade to a Q Developer Pro subscription for increased query limits",
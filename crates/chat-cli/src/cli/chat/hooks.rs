@@ -34,7 +34,12 @@ use spinners::{
     Spinner,
     Spinners,
 };
+use tokio::io::AsyncWriteExt;
 
+use super::tools::{
+    InvokeOutput,
+    OutputKind,
+};
 use super::util::truncate_safe;
 
 const DEFAULT_TIMEOUT_MS: u64 = 30_000;
@@ -112,11 +117,91 @@ pub enum HookType {
     Inline,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum HookTrigger {
     ConversationStart,
     PerPrompt,
+    /// Runs before a tool is used, and can deny the tool use or rewrite its input.
+    PreToolUse,
+    /// Runs after a tool is used, and can attach feedback to its result.
+    PostToolUse,
+}
+
+impl HookTrigger {
+    pub const ALL: [HookTrigger; 4] = [
+        HookTrigger::ConversationStart,
+        HookTrigger::PerPrompt,
+        HookTrigger::PreToolUse,
+        HookTrigger::PostToolUse,
+    ];
+
+    /// Whether hooks with this trigger run around tool uses rather than providing context.
+    pub fn is_tool_use(&self) -> bool {
+        matches!(self, HookTrigger::PreToolUse | HookTrigger::PostToolUse)
+    }
+}
+
+/// Exit code with which a [HookTrigger::PreToolUse] hook denies a tool use, using its stderr as
+/// the reason. For [HookTrigger::PostToolUse] hooks, stderr is attached to the tool result.
+pub const TOOL_HOOK_BLOCKING_EXIT_CODE: i32 = 2;
+
+/// Written as JSON to the stdin of tool use hooks.
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolHookInput {
+    pub hook_event: HookTrigger,
+    pub tool_name: String,
+    pub tool_input: serde_json::Value,
+    /// The result of the tool use, only set for [HookTrigger::PostToolUse].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_response: Option<serde_json::Value>,
+}
+
+impl ToolHookInput {
+    /// The `tool_response` passed to [HookTrigger::PostToolUse] hooks for the result of a tool use.
+    pub fn tool_response(result: &Result<InvokeOutput>) -> serde_json::Value {
+        match result {
+            Ok(result) => serde_json::json!({
+                "status": "success",
+                "output": match &result.output {
+                    OutputKind::Text(text) => serde_json::Value::from(text.as_str()),
+                    OutputKind::Json(json) => json.clone(),
+                    OutputKind::Images(images) => format!("{} image(s)", images.len()).into(),
+                },
+            }),
+            Err(err) => serde_json::json!({ "status": "error", "error": err.to_string() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ToolHookDecision {
+    /// The tool use proceeds as usual.
+    Allow,
+    Deny,
+}
+
+/// The JSON a tool use hook can optionally print to stdout.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct ToolHookOutput {
+    decision: Option<ToolHookDecision>,
+    reason: Option<String>,
+    /// Replacement input for the tool, only honored for [HookTrigger::PreToolUse].
+    tool_input: Option<serde_json::Value>,
+    feedback: Option<String>,
+}
+
+/// The combined effect of the tool use hooks run for a single tool use.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolHookOutcome {
+    /// The reason the tool use was denied, if it was.
+    pub denied: Option<String>,
+    /// The tool input after being rewritten by hooks, [None] if no hook modified it.
+    pub tool_input: Option<serde_json::Value>,
+    /// Feedback to attach to the tool result.
+    pub feedback: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -270,7 +355,7 @@ impl HookExecutor {
         results.iter().skip(start_cache_index).for_each(|(_, (hook, output))| {
             let expiry = match hook.trigger {
                 HookTrigger::ConversationStart => None,
-                HookTrigger::PerPrompt => Some(Instant::now() + Duration::from_secs(hook.cache_ttl_seconds)),
+                _ => Some(Instant::now() + Duration::from_secs(hook.cache_ttl_seconds)),
             };
             self.insert_cache(hook, CachedHook {
                 output: output.clone(),
@@ -293,25 +378,7 @@ impl HookExecutor {
     }
 
     async fn execute_inline_hook(&self, hook: &Hook) -> Result<String> {
-        let command = hook.command.as_ref().ok_or_else(|| eyre!("no command specified"))?;
-
-        #[cfg(unix)]
-        let command_future = tokio::process::Command::new("bash")
-            .arg("-c")
-            .arg(command)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output();
-
-        #[cfg(windows)]
-        let command_future = tokio::process::Command::new("cmd")
-            .arg("/C")
-            .arg(command)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output();
+        let command_future = hook_command(hook)?.output();
 
         let timeout = Duration::from_millis(hook.timeout_ms);
 
@@ -339,6 +406,112 @@ impl HookExecutor {
         }
     }
 
+    /// Run [HookTrigger::PreToolUse] or [HookTrigger::PostToolUse] hooks, depending on
+    /// `input.hook_event`. Hooks are never cached, and run one after the other so that each sees
+    /// the tool input as rewritten by the previous ones. Hooks that fail to execute or time out are
+    /// reported to `updates`, and deny the tool use if they run before it.
+    pub async fn run_tool_hooks(
+        &self,
+        hooks: Vec<&Hook>,
+        mut input: ToolHookInput,
+        mut updates: Option<&mut impl Write>,
+    ) -> ToolHookOutcome {
+        let mut outcome = ToolHookOutcome::default();
+        for hook in hooks {
+            if hook.disabled || hook.trigger != input.hook_event {
+                continue;
+            }
+
+            let output = match self.execute_tool_hook(hook, &input).await {
+                Ok(output) => output,
+                Err(e) => {
+                    if let Some(updates) = updates.as_deref_mut() {
+                        let _ = queue!(
+                            updates,
+                            style::SetForegroundColor(style::Color::Red),
+                            style::Print("✗ "),
+                            style::SetForegroundColor(style::Color::Blue),
+                            style::Print(&hook.name),
+                            style::ResetColor,
+                            style::Print(format!(" failed: {}\n", e)),
+                        );
+                    }
+                    // The tool must not run without the checks of the hook.
+                    if input.hook_event == HookTrigger::PreToolUse {
+                        outcome.denied = Some(format!("'{}': the hook failed: {}", hook.name, e));
+                        break;
+                    }
+                    continue;
+                },
+            };
+
+            let reason = output.reason.filter(|r| !r.is_empty());
+            if output.decision == Some(ToolHookDecision::Deny) {
+                match input.hook_event {
+                    HookTrigger::PreToolUse => {
+                        outcome.denied = Some(format!(
+                            "'{}': {}",
+                            hook.name,
+                            reason.as_deref().unwrap_or("no reason given")
+                        ));
+                        break;
+                    },
+                    // The tool has already run, so the reason is all that can be passed on.
+                    _ => outcome.feedback.extend(reason.map(|r| format!("'{}': {r}", hook.name))),
+                }
+            }
+            if let (HookTrigger::PreToolUse, Some(tool_input)) = (input.hook_event, output.tool_input) {
+                outcome
+                    .feedback
+                    .push(format!("'{}': modified the tool input to {tool_input}", hook.name));
+                input.tool_input = tool_input.clone();
+                outcome.tool_input = Some(tool_input);
+            }
+            if let Some(feedback) = output.feedback.filter(|f| !f.is_empty()) {
+                outcome.feedback.push(format!(
+                    "'{}': {}",
+                    hook.name,
+                    truncate_safe(&feedback, hook.max_output_size)
+                ));
+            }
+        }
+        outcome
+    }
+
+    async fn execute_tool_hook(&self, hook: &Hook, input: &ToolHookInput) -> Result<ToolHookOutput> {
+        let input = serde_json::to_vec(input)?;
+        let mut child = hook_command(hook)?.kill_on_drop(true).spawn()?;
+        let stdin = child.stdin.take();
+        let run = async move {
+            if let Some(mut stdin) = stdin {
+                match stdin.write_all(&input).await {
+                    // Hooks are free to ignore their input.
+                    Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => return Err(err),
+                    _ => (),
+                }
+            }
+            child.wait_with_output().await
+        };
+
+        let timeout = Duration::from_millis(hook.timeout_ms);
+        let output = tokio::time::timeout(timeout, run)
+            .await
+            .map_err(|_err| eyre!("command timed out after {} ms", timeout.as_millis()))??;
+        let stdout = output.stdout.to_str_lossy();
+        let stderr = output.stderr.to_str_lossy().trim().to_string();
+
+        match output.status.code() {
+            Some(0) if stdout.trim_start().starts_with('{') => Ok(serde_json::from_str(&stdout)?),
+            Some(0) => Ok(ToolHookOutput::default()),
+            Some(TOOL_HOOK_BLOCKING_EXIT_CODE) => Ok(ToolHookOutput {
+                decision: Some(ToolHookDecision::Deny),
+                reason: Some(stderr),
+                ..Default::default()
+            }),
+            _ => Err(eyre!("command returned non-zero exit code: {}", output.status)),
+        }
+    }
+
     /// Will return a cached hook's output if it exists and isn't expired.
     fn get_cache(&self, hook: &Hook) -> Option<String> {
         let cache = if hook.is_global {
@@ -371,6 +544,27 @@ impl HookExecutor {
     }
 }
 
+/// Builds the shell command that runs `hook`, with all standard streams piped.
+fn hook_command(hook: &Hook) -> Result<tokio::process::Command> {
+    let command = hook.command.as_ref().ok_or_else(|| eyre!("no command specified"))?;
+
+    #[cfg(unix)]
+    let mut cmd = tokio::process::Command::new("bash");
+    #[cfg(unix)]
+    cmd.arg("-c");
+
+    #[cfg(windows)]
+    let mut cmd = tokio::process::Command::new("cmd");
+    #[cfg(windows)]
+    cmd.arg("/C");
+
+    cmd.arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    Ok(cmd)
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Stdout;
@@ -571,6 +765,101 @@ mod tests {
         assert!(results[0].1.len() <= hook.max_output_size + " ... truncated".len());
     }
 
+    fn tool_hook_input(hook_event: HookTrigger) -> ToolHookInput {
+        ToolHookInput {
+            hook_event,
+            tool_name: "fs_write".to_string(),
+            tool_input: serde_json::json!({ "command": "create", "path": "/etc/passwd" }),
+            tool_response: None,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pre_tool_use_hooks() {
+        let executor = HookExecutor::new();
+        let mut deny = Hook::new_inline_hook(
+            HookTrigger::PreToolUse,
+            "grep -q /etc/ && echo 'protected path' >&2 && exit 2; exit 0".to_string(),
+        );
+        deny.name = "protect".to_string();
+        let mut rewrite = Hook::new_inline_hook(
+            HookTrigger::PreToolUse,
+            r#"echo '{"tool_input": {"command": "create", "path": "/tmp/passwd"}, "feedback": "moved"}'"#.to_string(),
+        );
+        rewrite.name = "rewrite".to_string();
+        let post = Hook::new_inline_hook(HookTrigger::PostToolUse, "exit 2".to_string());
+
+        // Hooks run in order, so the rewritten input is no longer denied.
+        let outcome = executor
+            .run_tool_hooks(
+                vec![&rewrite, &deny, &post],
+                tool_hook_input(HookTrigger::PreToolUse),
+                None::<&mut Stdout>,
+            )
+            .await;
+        assert_eq!(outcome.denied, None);
+        assert_eq!(outcome.tool_input.unwrap()["path"], "/tmp/passwd");
+        assert_eq!(outcome.feedback.len(), 2);
+
+        let outcome = executor
+            .run_tool_hooks(
+                vec![&deny, &rewrite],
+                tool_hook_input(HookTrigger::PreToolUse),
+                None::<&mut Stdout>,
+            )
+            .await;
+        assert_eq!(outcome.denied, Some("'protect': protected path".to_string()));
+        assert_eq!(outcome.tool_input, None);
+
+        // Hooks that fail or time out deny the tool use too.
+        let mut failing = Hook::new_inline_hook(HookTrigger::PreToolUse, "exit 1".to_string());
+        failing.name = "failing".to_string();
+        let mut slow = Hook::new_inline_hook(HookTrigger::PreToolUse, "sleep 5".to_string());
+        slow.name = "slow".to_string();
+        slow.timeout_ms = 100;
+        for hook in [&failing, &slow] {
+            let mut output = Vec::new();
+            let outcome = executor
+                .run_tool_hooks(
+                    vec![hook, &rewrite],
+                    tool_hook_input(HookTrigger::PreToolUse),
+                    Some(&mut output),
+                )
+                .await;
+            let denied = outcome.denied.unwrap();
+            assert!(
+                denied.starts_with(&format!("'{}': the hook failed", hook.name)),
+                "{denied}"
+            );
+            assert_eq!(outcome.tool_input, None);
+            assert!(String::from_utf8(output).unwrap().contains(&hook.name));
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_post_tool_use_hooks() {
+        let executor = HookExecutor::new();
+        let mut lint = Hook::new_inline_hook(
+            HookTrigger::PostToolUse,
+            "grep -q success && echo 'lint failed' >&2 && exit 2".to_string(),
+        );
+        lint.name = "lint".to_string();
+        let mut failing = Hook::new_inline_hook(HookTrigger::PostToolUse, "exit 1".to_string());
+        failing.name = "failing".to_string();
+
+        let mut input = tool_hook_input(HookTrigger::PostToolUse);
+        input.tool_response = Some(serde_json::json!({ "status": "success", "output": "" }));
+        let mut output = Vec::new();
+        let outcome = executor
+            .run_tool_hooks(vec![&failing, &lint], input, Some(&mut output))
+            .await;
+        assert_eq!(outcome.denied, None);
+        assert_eq!(outcome.feedback, vec!["'lint': lint failed".to_string()]);
+        assert!(String::from_utf8(output).unwrap().contains("failing"));
+    }
+
     #[tokio::test]
     async fn test_os_specific_command_execution() {
         let mut executor = HookExecutor::new();
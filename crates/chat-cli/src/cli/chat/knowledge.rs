@@ -0,0 +1,283 @@
+//! Knowledge bases for `/knowledge` and the `knowledge_search` tool.
+//!
+//! Files and directories added with `/knowledge add` are chunked, embedded and persisted by the
+//! semantic search client under `~/.aws/amazonq/knowledge_bases`. They can then be searched on
+//! demand rather than being sent with every request like context files.
+
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use eyre::{
+    Result,
+    bail,
+    eyre,
+};
+use semantic_search_client::embedding::EmbeddingType;
+use semantic_search_client::{
+    MemoryContext,
+    ProgressStatus,
+    SemanticSearchClient,
+};
+use serde::Serialize;
+use tokio::sync::OnceCell;
+
+use crate::database::Database;
+use crate::database::settings::Setting;
+use crate::platform::Context;
+use crate::util::directories;
+
+static KNOWLEDGE_STORE: OnceCell<KnowledgeStore> = OnceCell::const_new();
+
+/// A passage of a knowledge base that matched a search.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KnowledgeResult {
+    /// The name of the knowledge base the passage belongs to.
+    pub knowledge_base: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub text: String,
+    /// The cosine distance between the passage and the query, lower is more relevant.
+    pub distance: f32,
+}
+
+/// The knowledge bases of the user, shared by every chat session of the process.
+#[derive(Clone)]
+pub struct KnowledgeStore {
+    client: Arc<Mutex<SemanticSearchClient>>,
+}
+
+impl KnowledgeStore {
+    pub const DEFAULT_RESULTS: usize = 5;
+
+    /// Checks if knowledge bases are enabled in settings
+    pub fn is_enabled(database: &Database) -> bool {
+        database.settings.get_bool(Setting::EnabledKnowledge).unwrap_or(false)
+    }
+
+    /// Returns the store of the user, loading it on first use. This may download the embedding
+    /// model, so it should be called behind a spinner.
+    pub async fn global(ctx: &Context) -> Result<&'static Self> {
+        let base_dir = directories::chat_knowledge_bases_dir(ctx)?;
+        KNOWLEDGE_STORE
+            .get_or_try_init(|| Self::open(base_dir, EmbeddingType::default()))
+            .await
+    }
+
+    /// Opens the store in `base_dir`, loading every knowledge base persisted there.
+    pub async fn open(base_dir: PathBuf, embedding_type: EmbeddingType) -> Result<Self> {
+        let client =
+            tokio::task::spawn_blocking(move || SemanticSearchClient::with_embedding_type(base_dir, embedding_type))
+                .await?
+                .map_err(|e| eyre!("failed to load the knowledge bases: {e}"))?;
+        Ok(Self {
+            client: Arc::new(Mutex::new(client)),
+        })
+    }
+
+    /// Indexes the file or directory at `path` as a new knowledge base, named after the path
+    /// unless `name` is given.
+    pub async fn add(
+        &self,
+        path: PathBuf,
+        name: Option<String>,
+        progress: impl Fn(ProgressStatus) + Send + 'static,
+    ) -> Result<MemoryContext> {
+        let path = path
+            .canonicalize()
+            .map_err(|e| eyre!("failed to read {}: {e}", path.display()))?;
+        let name = match name {
+            Some(name) => name,
+            None => path
+                .file_name()
+                .map_or_else(|| path.to_string_lossy(), |name| name.to_string_lossy())
+                .into_owned(),
+        };
+        for existing in self.list() {
+            if existing.name == name {
+                bail!("A knowledge base named '{name}' already exists");
+            }
+            if existing.source_path.as_deref() == path.to_str() {
+                bail!(
+                    "{} has already been added as '{}'. Remove it first to index it again.",
+                    path.display(),
+                    existing.name
+                );
+            }
+        }
+
+        let client = self.client.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut client = client.lock().map_err(|_err| eyre!("knowledge store lock poisoned"))?;
+            let description = path.to_string_lossy().into_owned();
+            let id = client
+                .add_context_from_path(&path, &name, &description, true, Some(progress))
+                .map_err(|e| eyre!("failed to index {}: {e}", path.display()))?;
+            client
+                .get_contexts()
+                .into_iter()
+                .find(|context| context.id == id)
+                .ok_or_else(|| eyre!("the knowledge base was not saved"))
+        })
+        .await?
+    }
+
+    /// Removes the knowledge base with the given name, id, or source path.
+    pub async fn remove(&self, target: &str, cwd: &Path) -> Result<MemoryContext> {
+        let path = cwd.join(target).canonicalize().ok();
+        let Some(context) = self.list().into_iter().find(|context| {
+            context.name == target
+                || context.id == target
+                || path
+                    .as_deref()
+                    .is_some_and(|path| context.source_path.as_deref() == path.to_str())
+        }) else {
+            bail!("No knowledge base named '{target}' was found");
+        };
+
+        let client = self.client.clone();
+        let id = context.id.clone();
+        tokio::task::spawn_blocking(move || {
+            client
+                .lock()
+                .map_err(|_err| eyre!("knowledge store lock poisoned"))?
+                .remove_context_by_id(&id, true)
+                .map_err(|e| eyre!("failed to remove the knowledge base: {e}"))
+        })
+        .await??;
+        Ok(context)
+    }
+
+    /// Returns every knowledge base, oldest first.
+    pub fn list(&self) -> Vec<MemoryContext> {
+        let mut contexts = match self.client.lock() {
+            Ok(client) => client.get_contexts(),
+            Err(_) => Vec::new(),
+        };
+        contexts.sort_by_key(|context| context.created_at);
+        contexts
+    }
+
+    /// Returns up to `limit` passages most relevant to `query`, from the knowledge base named
+    /// `knowledge_base` or from all of them.
+    pub async fn search(
+        &self,
+        query: &str,
+        knowledge_base: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KnowledgeResult>> {
+        let contexts = self.list();
+        let context_id = match knowledge_base {
+            Some(target) => match contexts
+                .iter()
+                .find(|context| context.name == target || context.id == target)
+            {
+                Some(context) => Some(context.id.clone()),
+                None => bail!("No knowledge base named '{target}' was found"),
+            },
+            None => None,
+        };
+
+        let client = self.client.clone();
+        let query = query.to_string();
+        let results = tokio::task::spawn_blocking(move || {
+            let client = client.lock().map_err(|_err| eyre!("knowledge store lock poisoned"))?;
+            match context_id {
+                Some(id) => client
+                    .search_context(&id, &query, Some(limit))
+                    .map(|results| vec![(id, results)]),
+                None => client.search_all(&query, Some(limit)),
+            }
+            .map_err(|e| eyre!("failed to search the knowledge bases: {e}"))
+        })
+        .await??;
+
+        let mut results = results
+            .into_iter()
+            .flat_map(|(id, results)| {
+                let name = contexts
+                    .iter()
+                    .find(|context| context.id == id)
+                    .map_or_else(|| id.clone(), |context| context.name.clone());
+                results.into_iter().filter_map(move |result| {
+                    Some(KnowledgeResult {
+                        knowledge_base: name.clone(),
+                        path: result
+                            .point
+                            .payload
+                            .get("path")
+                            .and_then(|path| path.as_str())
+                            .map(str::to_string),
+                        text: result.text()?.to_string(),
+                        distance: result.distance,
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        results.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        results.truncate(limit);
+        Ok(results)
+    }
+}
+
+impl std::fmt::Debug for KnowledgeStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KnowledgeStore").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_knowledge_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        std::fs::create_dir_all(&docs).unwrap();
+        std::fs::write(
+            docs.join("deploy.md"),
+            "Deployments are rolled out with the release pipeline every Tuesday.",
+        )
+        .unwrap();
+        std::fs::write(
+            docs.join("oncall.md"),
+            "The oncall rotation changes every Monday morning.",
+        )
+        .unwrap();
+
+        let store = KnowledgeStore::open(dir.path().join("store"), EmbeddingType::BM25)
+            .await
+            .unwrap();
+        let added = store.add(docs.clone(), None, |_| {}).await.unwrap();
+        assert_eq!(added.name, "docs");
+        assert!(
+            store
+                .add(docs.clone(), Some("other".to_string()), |_| {})
+                .await
+                .is_err()
+        );
+        assert_eq!(store.list().len(), 1);
+
+        let results = store.search("release pipeline", None, 3).await.unwrap();
+        assert!(!results.is_empty());
+        assert_eq!(results[0].knowledge_base, "docs");
+        assert!(results[0].text.contains("release pipeline"));
+        assert!(store.search("release", Some("missing"), 3).await.is_err());
+
+        // Knowledge bases are persisted across stores.
+        let reopened = KnowledgeStore::open(dir.path().join("store"), EmbeddingType::BM25)
+            .await
+            .unwrap();
+        assert_eq!(reopened.list().len(), 1);
+
+        assert!(store.remove("missing", dir.path()).await.is_err());
+        assert_eq!(store.remove("docs", dir.path()).await.unwrap().id, added.id);
+        assert!(store.list().is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+//! Answers the requests MCP servers send to the chat.
+//!
+//! Sampling lets a server ask the model for a completion. Since the server decides what is sent,
+//! every request has to be approved by the user unless all tools are trusted. Roots tell servers
+//! which directories they may operate on.
+
+use std::path::PathBuf;
+use std::sync::RwLock as SyncRwLock;
+
+use tokio::sync::Mutex;
+
+use crate::api_client::StreamingClient;
+use crate::api_client::model::{
+    AssistantResponseMessage,
+    ChatMessage,
+    ChatResponseStream,
+    ConversationState as FigConversationState,
+    UserInputMessage,
+};
+use crate::mcp_client::{
+    ClientRequestError,
+    ClientRequestHandler,
+    CreateMessageParams,
+    CreateMessageResult,
+    ListRootsResult,
+    MessageContent,
+    Role,
+    Root,
+};
+
+/// The model name reported to servers in sampling results.
+const SAMPLING_MODEL: &str = "amazon-q";
+
+/// How much of the prompt is shown when asking the user to approve a sampling request.
+const APPROVAL_PREVIEW_LEN: usize = 500;
+
+#[derive(Debug)]
+pub struct McpRequestHandler {
+    client: StreamingClient,
+    interactive: bool,
+    /// Approve sampling requests without asking, as with `--trust-all-tools`.
+    trust_all: bool,
+    roots: SyncRwLock<Vec<PathBuf>>,
+    /// Held while asking the user, so that approvals for several servers are not interleaved.
+    approval_lock: Mutex<()>,
+}
+
+impl McpRequestHandler {
+    pub fn new(client: StreamingClient, interactive: bool, trust_all: bool, roots: Vec<PathBuf>) -> Self {
+        Self {
+            client,
+            interactive,
+            trust_all,
+            roots: SyncRwLock::new(roots),
+            approval_lock: Mutex::new(()),
+        }
+    }
+
+    /// Replaces the roots listed to servers, returning whether they changed.
+    pub fn set_roots(&self, roots: Vec<PathBuf>) -> bool {
+        match self.roots.write() {
+            Ok(mut current) if *current != roots => {
+                *current = roots;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    async fn approve(&self, server_name: &str, prompt: &str) -> Result<(), ClientRequestError> {
+        if self.trust_all {
+            return Ok(());
+        }
+        if !self.interactive {
+            return Err(ClientRequestError::Rejected(
+                "Sampling requests must be approved in an interactive chat".to_string(),
+            ));
+        }
+
+        let _guard = self.approval_lock.lock().await;
+        let preview = match prompt.char_indices().nth(APPROVAL_PREVIEW_LEN) {
+            Some((idx, _)) => format!("{}...", &prompt[..idx]),
+            None => prompt.to_string(),
+        };
+        let question = format!("{server_name} wants to send this prompt to the model:\n\n{preview}\n\nAllow it?");
+        let approved = tokio::task::spawn_blocking(move || {
+            dialoguer::Confirm::with_theme(&crate::util::dialoguer_theme())
+                .with_prompt(question)
+                .default(false)
+                .interact_opt()
+        })
+        .await
+        .map_err(|e| ClientRequestError::Custom(e.to_string()))?
+        .map_err(|e| ClientRequestError::Custom(e.to_string()))?;
+
+        match approved {
+            Some(true) => Ok(()),
+            _ => Err(ClientRequestError::Rejected(
+                "User rejected sampling request".to_string(),
+            )),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ClientRequestHandler for McpRequestHandler {
+    async fn create_message(
+        &self,
+        server_name: &str,
+        params: CreateMessageParams,
+    ) -> Result<CreateMessageResult, ClientRequestError> {
+        let conversation = build_sampling_conversation(params)?;
+        self.approve(server_name, &conversation.user_input_message.content)
+            .await?;
+
+        let mut output = self
+            .client
+            .send_message(conversation)
+            .await
+            .map_err(|e| ClientRequestError::Custom(e.to_string()))?;
+        let mut text = String::new();
+        while let Some(event) = output
+            .recv()
+            .await
+            .map_err(|e| ClientRequestError::Custom(e.to_string()))?
+        {
+            if let ChatResponseStream::AssistantResponseEvent { content } = event {
+                text.push_str(&content);
+            }
+        }
+
+        Ok(CreateMessageResult {
+            role: Role::Assistant,
+            content: MessageContent::Text { text },
+            model: SAMPLING_MODEL.to_string(),
+            stop_reason: Some("endTurn".to_string()),
+        })
+    }
+
+    async fn list_roots(&self, _server_name: &str) -> Result<ListRootsResult, ClientRequestError> {
+        let roots = self
+            .roots
+            .read()
+            .map_err(|e| ClientRequestError::Custom(e.to_string()))?
+            .iter()
+            .filter_map(|dir| {
+                Some(Root {
+                    uri: url::Url::from_directory_path(dir).ok()?.to_string(),
+                    name: dir.file_name().map(|name| name.to_string_lossy().into_owned()),
+                })
+            })
+            .collect();
+        Ok(ListRootsResult { roots })
+    }
+}
+
+/// Converts the messages of a sampling request into a conversation for the model. Consecutive
+/// messages of the same role are merged, and the system prompt is prepended to the first message.
+fn build_sampling_conversation(params: CreateMessageParams) -> Result<FigConversationState, ClientRequestError> {
+    let CreateMessageParams {
+        messages,
+        system_prompt,
+        ..
+    } = params;
+
+    let mut turns = Vec::<(Role, String)>::new();
+    for message in messages {
+        let text = match message.content {
+            MessageContent::Text { text } => text,
+            other => other.to_string(),
+        };
+        match turns.last_mut() {
+            Some((role, content)) if *role == message.role => {
+                content.push_str("\n\n");
+                content.push_str(&text);
+            },
+            _ => turns.push((message.role, text)),
+        }
+    }
+    if let (Some(system_prompt), Some((_, content))) = (system_prompt, turns.first_mut()) {
+        *content = format!("{system_prompt}\n\n{content}");
+    }
+
+    if turns.first().is_none_or(|(role, _)| *role != Role::User)
+        || turns.last().is_none_or(|(role, _)| *role != Role::User)
+    {
+        return Err(ClientRequestError::InvalidParams(
+            "Sampling messages must start and end with a user message".to_string(),
+        ));
+    }
+
+    let user_message = |content: String| UserInputMessage {
+        content,
+        user_input_message_context: None,
+        user_intent: None,
+        images: None,
+        model_id: None,
+    };
+    let mut turns = turns.into_iter().map(|(_, content)| content);
+    let last = turns.next_back().unwrap_or_default();
+    let history = turns
+        .enumerate()
+        .map(|(i, content)| match i % 2 {
+            0 => ChatMessage::UserInputMessage(user_message(content)),
+            _ => ChatMessage::AssistantResponseMessage(AssistantResponseMessage {
+                message_id: None,
+                content,
+                tool_uses: None,
+            }),
+        })
+        .collect::<Vec<_>>();
+
+    Ok(FigConversationState {
+        conversation_id: None,
+        user_input_message: user_message(last),
+        history: (!history.is_empty()).then_some(history),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(messages: serde_json::Value) -> CreateMessageParams {
+        serde_json::from_value(serde_json::json!({
+            "messages": messages,
+            "systemPrompt": "Be brief.",
+            "maxTokens": 100,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_build_sampling_conversation() {
+        let conversation = build_sampling_conversation(params(serde_json::json!([
+            { "role": "user", "content": { "type": "text", "text": "What is 1 + 1?" } },
+            { "role": "assistant", "content": { "type": "text", "text": "2" } },
+            { "role": "user", "content": { "type": "text", "text": "And 2 + 2?" } },
+            { "role": "user", "content": { "type": "text", "text": "Answer with a number." } },
+        ])))
+        .unwrap();
+        assert_eq!(
+            conversation.user_input_message.content,
+            "And 2 + 2?\n\nAnswer with a number."
+        );
+        let history = conversation.history.unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(
+            matches!(&history[0], ChatMessage::UserInputMessage(message) if message.content == "Be brief.\n\nWhat is 1 + 1?")
+        );
+        assert!(matches!(&history[1], ChatMessage::AssistantResponseMessage(message) if message.content == "2"));
+
+        assert!(
+            build_sampling_conversation(params(serde_json::json!([
+                { "role": "user", "content": { "type": "text", "text": "Hi" } },
+                { "role": "assistant", "content": { "type": "text", "text": "Hello" } },
+            ])))
+            .is_err()
+        );
+        assert!(build_sampling_conversation(params(serde_json::json!([]))).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_roots() {
+        let handler = McpRequestHandler::new(StreamingClient::mock(vec![]), false, false, vec![PathBuf::from(
+            "/home/user/project",
+        )]);
+        let roots = handler.list_roots("server").await.unwrap().roots;
+        assert_eq!(roots, vec![Root {
+            uri: "file:///home/user/project/".to_string(),
+            name: Some("project".to_string()),
+        }]);
+        assert!(!handler.set_roots(vec![PathBuf::from("/home/user/project")]));
+        assert!(handler.set_roots(vec![PathBuf::from("/tmp")]));
+
+        let rejected = handler
+            .create_message(
+                "server",
+                params(serde_json::json!([
+                    { "role": "user", "content": { "type": "text", "text": "Hi" } },
+                ])),
+            )
+            .await;
+        assert!(matches!(rejected, Err(ClientRequestError::Rejected(_))));
+
+        let trusted = McpRequestHandler::new(
+            StreamingClient::mock(vec![vec![ChatResponseStream::AssistantResponseEvent {
+                content: "Hello!".to_string(),
+            }]]),
+            false,
+            true,
+            vec![],
+        );
+        let result = trusted
+            .create_message(
+                "server",
+                params(serde_json::json!([
+                    { "role": "user", "content": { "type": "text", "text": "Hi" } },
+                ])),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(result.content, MessageContent::Text { text } if text == "Hello!"));
+    }
+}
@@ -34,6 +34,11 @@ const USER_ENTRY_END_HEADER: &str = "--- USER MESSAGE END ---\n\n";
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserMessage {
     pub additional_context: String,
+    /// Contents of the MCP resources referenced in the prompt with `@server:uri`. Like
+    /// [Self::additional_context], this is only sent with the message itself, later history
+    /// entries only keep the references in the prompt.
+    #[serde(default)]
+    pub resource_context: String,
     pub env_context: UserEnvContext,
     pub content: UserMessageContent,
     pub images: Option<Vec<ImageBlock>>,
@@ -62,6 +67,7 @@ impl UserMessage {
         Self {
             images: None,
             additional_context: String::new(),
+            resource_context: String::new(),
             env_context: UserEnvContext::generate_new(),
             content: UserMessageContent::Prompt { prompt },
         }
@@ -71,6 +77,7 @@ impl UserMessage {
         Self {
             images: None,
             additional_context: String::new(),
+            resource_context: String::new(),
             env_context: UserEnvContext::generate_new(),
             content: UserMessageContent::CancelledToolUses {
                 prompt,
@@ -90,6 +97,7 @@ impl UserMessage {
     pub fn new_tool_use_results(results: Vec<ToolUseResult>) -> Self {
         Self {
             additional_context: String::new(),
+            resource_context: String::new(),
             env_context: UserEnvContext::generate_new(),
             content: UserMessageContent::ToolUseResults {
                 tool_use_results: results,
@@ -101,6 +109,7 @@ impl UserMessage {
     pub fn new_tool_use_results_with_images(results: Vec<ToolUseResult>, images: Vec<ImageBlock>) -> Self {
         Self {
             additional_context: String::new(),
+            resource_context: String::new(),
             env_context: UserEnvContext::generate_new(),
             content: UserMessageContent::ToolUseResults {
                 tool_use_results: results,
@@ -143,9 +152,12 @@ impl UserMessage {
         };
         UserInputMessage {
             images: self.images,
-            content: format!("{} {}", self.additional_context, formatted_prompt)
-                .trim()
-                .to_string(),
+            content: format!(
+                "{}{} {}",
+                self.additional_context, self.resource_context, formatted_prompt
+            )
+            .trim()
+            .to_string(),
             user_input_message_context: Some(UserInputMessageContext {
                 env_state: self.env_context.env_state,
                 tool_results: match self.content {
@@ -178,10 +190,22 @@ impl UserMessage {
         }
     }
 
+    pub fn tool_use_results_mut(&mut self) -> Option<&mut [ToolUseResult]> {
+        match &mut self.content {
+            UserMessageContent::Prompt { .. } => None,
+            UserMessageContent::CancelledToolUses { tool_use_results, .. } => Some(tool_use_results.as_mut_slice()),
+            UserMessageContent::ToolUseResults { tool_use_results } => Some(tool_use_results.as_mut_slice()),
+        }
+    }
+
     pub fn additional_context(&self) -> &str {
         &self.additional_context
     }
 
+    pub fn resource_context(&self) -> &str {
+        &self.resource_context
+    }
+
     pub fn content(&self) -> &UserMessageContent {
         &self.content
     }
@@ -1,19 +1,28 @@
+mod checkpoint;
 mod command;
+mod compaction;
 mod consts;
 mod context;
 mod conversation_state;
 mod help;
 mod hooks;
 mod input_source;
+#[cfg(feature = "knowledge")]
+mod knowledge;
+mod mcp_request_handler;
 mod message;
+mod output_format;
 mod parse;
 mod parser;
 mod prompt;
 mod prompt_parser;
+mod recording;
+mod resources;
 mod server_messenger;
 #[cfg(unix)]
 mod skim_integration;
 mod token_counter;
+mod tokenizer;
 pub mod tool_manager;
 pub mod tools;
 pub mod util;
@@ -29,6 +38,7 @@ use std::io::{
     Read,
     Write,
 };
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{
@@ -42,8 +52,15 @@ use amzn_codewhisperer_client::types::SubscriptionStatus;
 use clap::Args;
 use command::{
     Command,
+    KnowledgeSubcommand,
     PromptsSubcommand,
     ToolsSubcommand,
+    UndoTarget,
+};
+use compaction::{
+    AutoCompaction,
+    CompactionRecord,
+    CompactionStrategy,
 };
 use consts::{
     CONTEXT_FILES_MAX_SIZE,
@@ -81,23 +98,41 @@ use help::{
 use hooks::{
     Hook,
     HookTrigger,
+    ToolHookInput,
+    ToolHookOutcome,
 };
 use input_source::InputSource;
+#[cfg(feature = "knowledge")]
+use knowledge::KnowledgeStore;
+use mcp_request_handler::McpRequestHandler;
 use message::{
     AssistantMessage,
     AssistantToolUse,
     ToolUseResult,
     ToolUseResultBlock,
 };
+pub use output_format::OutputFormat;
+use output_format::{
+    ChatEvent,
+    ChatEvents,
+    ChatStatus,
+};
 use parse::{
     ParseState,
+    finish_markdown,
     interpret_markdown,
 };
 use parser::{
     RecvErrorKind,
     ResponseParser,
 };
+use recording::{
+    RecordedEvent,
+    SessionRecorder,
+    SessionReplay,
+};
 use regex::Regex;
+use resources::ResourceReference;
 use serde_json::Map;
 use spinners::{
     Spinner,
@@ -105,10 +140,8 @@ use spinners::{
 };
 use thiserror::Error;
 use time::OffsetDateTime;
-use token_counter::{
-    TokenCount,
-    TokenCounter,
-};
+use token_counter::TokenCounter;
+use tokenizer::BpeTokenizer;
 use tokio::signal::ctrl_c;
 use tool_manager::{
     GetPromptError,
@@ -118,8 +151,21 @@ use tool_manager::{
     ToolManager,
     ToolManagerBuilder,
 };
+use tools::delegate::{
+    Delegate,
+    DelegateContext,
+    DelegateUsage,
+};
+use tools::execute::rules::{
+    CommandRules,
+    RuleAction,
+    global_rules_path,
+    profile_rules_path,
+};
+use tools::execute::sandbox::SandboxMode;
 use tools::gh_issue::GhIssueContext;
 use tools::{
+    InvokeOutput,
     OutputKind,
     QueuedTool,
     Tool,
@@ -138,6 +184,7 @@ use unicode_width::UnicodeWidthStr;
 use util::images::RichImageBlock;
 use util::shared_writer::{
     NullWriter,
+    PrefixedWriter,
     SharedWriter,
 };
 use util::ui::draw_box;
@@ -145,6 +192,7 @@ use util::{
     animate_output,
     drop_matched_context_files,
     play_notification_bell,
+    truncate_safe,
 };
 use uuid::Uuid;
 use winnow::Partial;
@@ -153,6 +201,7 @@ use winnow::stream::Offset;
 use crate::api_client::clients::SendMessageOutput;
 use crate::api_client::model::{
     ChatResponseStream,
+    ConversationState as FigConversationState,
     Tool as FigTool,
     ToolResultStatus,
 };
@@ -189,9 +238,11 @@ pub struct ChatArgs {
     /// prompt requests permissions to use a tool, unless --trust-all-tools is also used.
     #[arg(long)]
     pub no_interactive: bool,
-    /// Resumes the previous conversation from this directory.
-    #[arg(short, long)]
-    pub resume: bool,
+    /// Resumes the previous conversation from this directory, or the conversation with the given
+    /// id as listed by /history, e.g. '--resume=<ID>'.
+    #[arg(short, long, value_name = "ID", num_args = 0..=1, require_equals = true)]
+    #[allow(clippy::option_option)]
+    pub resume: Option<Option<String>>,
     /// The first question to ask
     pub input: Option<String>,
     /// Context profile to use
@@ -207,16 +258,34 @@ pub struct ChatArgs {
     /// '--trust-tools=fs_read,fs_write', trust no tools: '--trust-tools='
     #[arg(long, value_delimiter = ',', value_name = "TOOL_NAMES")]
     pub trust_tools: Option<Vec<String>>,
+    /// Output format. The json formats imply --no-interactive and report the conversation as
+    /// events on STDOUT, exiting with 1 on errors and 3 if a tool use was denied.
+    #[arg(long, value_enum, default_value_t)]
+    pub output_format: OutputFormat,
+    /// Records the session to this file: the input, the requests and responses, and the tool
+    /// results. See --replay.
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+    /// Replays a session recorded with --record without network access, answering requests with
+    /// the recorded responses and tools with the recorded results.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["input", "resume", "trust_all_tools", "trust_tools"])]
+    pub replay: Option<PathBuf>,
 }
 
 impl ChatArgs {
     pub async fn execute(self, database: &mut Database, telemetry: &TelemetryThread) -> Result<process::ExitCode> {
         let ctx = Context::new();
+        let replay = self.replay.as_ref().map(SessionReplay::load).transpose()?;
 
         let stdin = std::io::stdin();
-        // no_interactive flag or part of a pipe
-        let interactive = !self.no_interactive && stdin.is_terminal();
-        let input = if !interactive && !stdin.is_terminal() {
+        // no_interactive flag, structured output, or part of a pipe
+        let interactive = match &replay {
+            Some(replay) => replay.interactive,
+            None => !self.no_interactive && self.output_format == OutputFormat::Text && stdin.is_terminal(),
+        };
+        let input = if let Some(replay) = &replay {
+            replay.initial_input.clone()
+        } else if !interactive && !stdin.is_terminal() {
             // append to input string any extra info that was provided, e.g. via pipe
             let mut input = self.input.unwrap_or_default();
             stdin.lock().read_to_string(&mut input)?;
@@ -225,15 +294,19 @@ impl ChatArgs {
             self.input
         };
 
-        let mut output = match interactive {
-            true => SharedWriter::stderr(),
-            false => SharedWriter::stdout(),
+        let mut output = match (interactive, self.output_format) {
+            (true, _) => SharedWriter::stderr(),
+            (false, OutputFormat::Text) => SharedWriter::stdout(),
+            // STDOUT is reserved for events.
+            (false, _) => SharedWriter::null(),
         };
 
-        let client = match ctx.env().get("Q_MOCK_CHAT_RESPONSE") {
-            Ok(json) => create_stream(serde_json::from_str(fs::read_to_string(json)?.as_str())?),
-            _ => StreamingClient::new(database).await?,
+        let client = match (&replay, ctx.env().get("Q_MOCK_CHAT_RESPONSE")) {
+            (Some(replay), _) => replay.client(),
+            (None, Ok(json)) => create_stream(serde_json::from_str(fs::read_to_string(json)?.as_str())?),
+            _ => StreamingClient::new(ctx.env(), database).await?,
         };
+        let recorder = self.record.as_ref().map(SessionRecorder::create).transpose()?;
 
         let mcp_server_configs = match McpServerConfig::load_config(&mut output).await {
             Ok(config) => {
@@ -297,6 +370,27 @@ impl ChatArgs {
         //     database.set_last_used_model_id(id.clone())?;
         // }
 
+        if let Some(path) = database.settings.get_string(Setting::ChatTokenizer) {
+            match BpeTokenizer::from_file(&path) {
+                Ok(tokenizer) => TokenCounter::init(Box::new(tokenizer)),
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        "Failed to load the tokenizer vocabulary, falling back to estimated counts"
+                    );
+                    execute!(
+                        output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print(format!(
+                            "Failed to load the tokenizer set by {}, token counts will be estimated: {err}\n\n",
+                            Setting::ChatTokenizer
+                        )),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                },
+            }
+        }
+
         let conversation_id = Uuid::new_v4().to_string();
         info!(?conversation_id, "Generated new conversation id");
         let (prompt_request_sender, prompt_request_receiver) = std::sync::mpsc::channel::<Option<String>>();
@@ -306,25 +400,46 @@ impl ChatArgs {
         } else {
             Box::new(NullWriter {})
         };
+        let trust_all_tools = match &replay {
+            Some(replay) => replay.trust_all_tools,
+            None => self.accept_all || self.trust_all_tools,
+        };
+        let request_handler = McpRequestHandler::new(
+            client.clone(),
+            interactive,
+            trust_all_tools,
+            ctx.env().current_dir().into_iter().collect(),
+        );
         let mut tool_manager = ToolManagerBuilder::default()
             .mcp_server_config(mcp_server_configs)
             .prompt_list_sender(prompt_response_sender)
             .prompt_list_receiver(prompt_request_receiver)
             .conversation_id(&conversation_id)
             .interactive(interactive)
+            .request_handler(request_handler)
             .build(telemetry, tool_manager_output)
             .await?;
         let tool_config = tool_manager.load_tools(database, &mut output).await?;
         let mut tool_permissions = ToolPermissions::new(tool_config.len());
 
-        let trust_tools = self.trust_tools.map(|mut tools| {
-            if tools.len() == 1 && tools[0].is_empty() {
-                tools.pop();
-            }
-            tools
-        });
+        let trust_tools = match &replay {
+            Some(replay) => replay.trust_tools.clone(),
+            None => self.trust_tools.map(|mut tools| {
+                if tools.len() == 1 && tools[0].is_empty() {
+                    tools.pop();
+                }
+                tools
+            }),
+        };
+        if let Some(recorder) = &recorder {
+            recorder.record(RecordedEvent::Start {
+                interactive,
+                trust_all_tools,
+                trust_tools: trust_tools.clone(),
+            });
+        }
 
-        if self.accept_all || self.trust_all_tools {
+        if trust_all_tools {
             tool_permissions.trust_all = true;
             for tool in tool_config.values() {
                 tool_permissions.trust_tool(&tool.name);
@@ -356,7 +471,10 @@ impl ChatArgs {
             &conversation_id,
             output,
             input,
-            InputSource::new(database, prompt_request_sender, prompt_response_receiver)?,
+            match &replay {
+                Some(replay) => replay.input_source(),
+                None => InputSource::new(database, prompt_request_sender, prompt_response_receiver)?,
+            },
             interactive,
             self.resume,
             client,
@@ -366,13 +484,14 @@ impl ChatArgs {
             model_id,
             tool_config,
             tool_permissions,
+            self.output_format,
         )
         .await?;
+        chat.recorder = recorder;
+        chat.replay = replay;
 
-        let result = chat
-            .try_chat(database, telemetry)
-            .await
-            .map(|_| process::ExitCode::SUCCESS);
+        let result = chat.try_chat(database, telemetry).await;
+        let result = chat.finish(result);
         drop(chat); // Explicit drop for clarity
 
         result
@@ -497,6 +616,9 @@ pub enum ChatError {
     NonInteractiveToolApproval,
     #[error(transparent)]
     GetPromptError(#[from] GetPromptError),
+    /// The chat diverged from the recording being replayed, see --replay.
+    #[error("the replay diverged from its recording: {0}")]
+    Replay(String),
 }
 
 impl ReasonCode for ChatError {
@@ -511,6 +633,7 @@ impl ReasonCode for ChatError {
             ChatError::NonInteractiveToolApproval => "NonInteractiveToolApprovalError".to_string(),
             ChatError::GetPromptError(_) => "GetPromptError".to_string(),
             ChatError::Auth(_) => "AuthError".to_string(),
+            ChatError::Replay(_) => "ReplayError".to_string(),
         }
     }
 }
@@ -539,12 +662,25 @@ pub struct ChatContext {
     tool_use_status: ToolUseStatus,
     /// Any failed requests that could be useful for error report/debugging
     failed_request_ids: Vec<String>,
+    /// The usage of the tasks run by the delegate tool, shown by /usage.
+    delegate_usage: Arc<std::sync::Mutex<Vec<DelegateUsage>>>,
     /// Pending prompts to be sent
     pending_prompts: VecDeque<Prompt>,
+    /// How the history is compacted once it grows too large.
+    auto_compaction: AutoCompaction,
+    /// The length of the history when automatic compaction last ran, if it ran since the user
+    /// last sent a prompt.
+    auto_compaction_attempted: Option<usize>,
+    /// Structured output selected with --output-format.
+    events: ChatEvents,
+    /// Records the session when started with --record.
+    recorder: Option<SessionRecorder>,
+    /// The recording being replayed when started with --replay, supplying the tool results.
+    replay: Option<SessionReplay>,
 }
 
 impl ChatContext {
-    #[allow(clippy::too_many_arguments)]
+    #[allow(clippy::too_many_arguments, clippy::option_option)]
     pub async fn new(
         ctx: Arc<Context>,
         database: &mut Database,
@@ -553,7 +689,7 @@ impl ChatContext {
         mut input: Option<String>,
         input_source: InputSource,
         interactive: bool,
-        resume_conversation: bool,
+        resume_conversation: Option<Option<String>>,
         client: StreamingClient,
         terminal_width_provider: fn() -> Option<usize>,
         tool_manager: ToolManager,
@@ -561,11 +697,13 @@ impl ChatContext {
         model_id: Option<String>,
         tool_config: HashMap<String, ToolSpec>,
         tool_permissions: ToolPermissions,
+        output_format: OutputFormat,
     ) -> Result<Self> {
         let ctx_clone = Arc::clone(&ctx);
         let output_clone = output.clone();
 
         let mut existing_conversation = false;
+        let (auto_compaction, auto_compaction_error) = AutoCompaction::from_settings(&database.settings);
         let valid_model_id = match model_id {
             Some(id) => Some(id),
             None => database
@@ -580,11 +718,17 @@ impl ChatContext {
                 .or_else(|| Some(DEFAULT_MODEL_ID.to_owned())),
         };
 
-        let conversation_state = if resume_conversation {
-            let prior = env::current_dir()
-                .ok()
-                .and_then(|cwd| database.get_conversation_by_path(cwd).ok())
-                .flatten();
+        let conversation_state = if let Some(resume_id) = resume_conversation {
+            let prior = match resume_id {
+                Some(id) => match database.get_conversation_by_id(&id)? {
+                    Some(cs) => Some(cs),
+                    None => bail!("No conversation with id '{id}' was found. Use /history to list past conversations."),
+                },
+                None => env::current_dir()
+                    .ok()
+                    .and_then(|cwd| database.get_conversation_by_path(cwd).ok())
+                    .flatten(),
+            };
 
             // Only restore conversations where there were actual messages.
             // Prevents edge case where user clears conversation with --new, then exits without chatting.
@@ -623,7 +767,7 @@ impl ChatContext {
             .await
         };
 
-        Ok(Self {
+        let mut chat = Self {
             ctx,
             output,
             initial_input: input,
@@ -638,8 +782,25 @@ impl ChatContext {
             tool_use_telemetry_events: HashMap::new(),
             tool_use_status: ToolUseStatus::Idle,
             failed_request_ids: Vec::new(),
+            delegate_usage: Default::default(),
             pending_prompts: VecDeque::new(),
-        })
+            auto_compaction,
+            auto_compaction_attempted: None,
+            events: ChatEvents::new(output_format, io::stdout()),
+            recorder: None,
+            replay: None,
+        };
+        chat.reload_command_rules().await?;
+        if let Some(error) = auto_compaction_error {
+            execute!(
+                chat.output,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print("WARNING: "),
+                style::SetForegroundColor(Color::Reset),
+                style::Print(format!("{error}, automatic compaction uses the default instead\n")),
+            )?;
+        }
+        Ok(chat)
     }
 }
 
@@ -664,6 +825,23 @@ impl Drop for ChatContext {
     }
 }
 
+/// Which tools a tool can share a batch with, see [ChatContext::batch_tool_uses].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ToolConcurrency {
+    /// Runs alongside other read-only tools.
+    ReadOnly,
+    /// Runs alongside writes to other files.
+    Write,
+    /// Always runs on its own.
+    Exclusive,
+}
+
+/// The outcome of invoking a tool as part of a batch, see [ChatContext::invoke_tools].
+struct ToolInvocation {
+    result: Result<InvokeOutput>,
+    duration: Duration,
+}
+
 /// The chat execution state.
 ///
 /// Intended to provide more robust handling around state transitions while dealing with, e.g.,
@@ -702,6 +880,10 @@ enum ChatState {
         show_summary: bool,
         /// Whether or not to show the /compact help text.
         help: bool,
+        /// Number of oldest turns to summarize, [None] to summarize all but the most recent turn.
+        turns: Option<usize>,
+        /// Whether compaction was triggered automatically rather than by /compact.
+        automatic: bool,
     },
     /// Exit the chat.
     Exit,
@@ -852,6 +1034,10 @@ impl ChatContext {
         }
 
         if let Some(user_input) = self.initial_input.take() {
+            self.record(RecordedEvent::Input {
+                line: user_input.clone(),
+                initial: true,
+            });
             next_state = Some(ChatState::HandleInput {
                 input: user_input,
                 tool_uses: None,
@@ -898,10 +1084,12 @@ impl ChatContext {
                     prompt,
                     show_summary,
                     help,
+                    turns,
+                    automatic,
                 } => {
                     let tool_uses_clone = tool_uses.clone();
                     tokio::select! {
-                        res = self.compact_history(telemetry, database, tool_uses, pending_tool_index, prompt, show_summary, help) => res,
+                        res = self.compact_history(telemetry, database, tool_uses, pending_tool_index, prompt, show_summary, help, turns, automatic) => res,
                         Ok(_) = ctrl_c_stream => Err(ChatError::Interrupted { tool_uses: tool_uses_clone })
                     }
                 },
@@ -933,6 +1121,18 @@ impl ChatContext {
         }
     }
 
+    /// Reports how the chat finished when structured output is enabled, returning the exit code.
+    fn finish(&mut self, result: Result<()>) -> Result<process::ExitCode> {
+        if !self.events.is_enabled() {
+            return result.map(|_| process::ExitCode::SUCCESS);
+        }
+        if let Err(err) = &result {
+            self.events.report(ChatStatus::Error, Some(err.to_string()));
+        }
+        let exit_code = self.events.finish(self.conversation_state.conversation_id())?;
+        result.map(|_| exit_code)
+    }
+
     /// Handles the result of processing a [ChatState], returning the next [ChatState] to change
     /// to.
     async fn handle_state_execution_result(
@@ -945,6 +1145,8 @@ impl ChatContext {
         let re = Regex::new(r"((\x9B|\x1B\[)[0-?]*[ -\/]*[@-~])|([^\x00-\x7F]+)").unwrap();
         match result {
             Ok(state) => Ok(state),
+            // The rest of the recording can't be replayed once the chat diverged from it.
+            Err(e @ ChatError::Replay(_)) => Err(e),
             Err(e) => {
                 let (reason, reason_desc) = get_error_reason(&e);
                 self.send_error_telemetry(database, telemetry, reason, Some(reason_desc))
@@ -982,6 +1184,11 @@ impl ChatContext {
                 }
 
                 error!(?e, "An error occurred processing the current state");
+                let status = match e {
+                    ChatError::NonInteractiveToolApproval => ChatStatus::ToolDenied,
+                    _ => ChatStatus::Error,
+                };
+                let error_message = e.to_string();
                 if self.interactive && self.spinner.is_some() {
                     drop(self.spinner.take());
                     queue!(
@@ -1053,6 +1260,8 @@ impl ChatContext {
                                 prompt: None,
                                 show_summary: false,
                                 help: false,
+                                turns: None,
+                                automatic: true,
                             });
                         },
                         crate::api_client::ApiClientError::QuotaBreach(msg) => {
@@ -1139,6 +1348,7 @@ impl ChatContext {
                         print_default_error!(e);
                     },
                 }
+                self.events.report(status, Some(error_message));
                 self.conversation_state.enforce_conversation_invariants();
                 self.conversation_state.reset_next_user_message();
                 Ok(ChatState::PromptUser {
@@ -1164,6 +1374,8 @@ impl ChatContext {
         custom_prompt: Option<String>,
         show_summary: bool,
         help: bool,
+        turns: Option<usize>,
+        automatic: bool,
     ) -> Result<ChatState, ChatError> {
         let hist = self.conversation_state.history();
         debug!(?hist, "compacting history");
@@ -1200,15 +1412,16 @@ impl ChatContext {
         }
 
         // Send a request for summarizing the history.
+        let tokens_before = self.conversation_state.calculate_token_count().await;
         let summary_state = self
             .conversation_state
-            .create_summary_request(custom_prompt.as_ref())
+            .create_summary_request(custom_prompt.as_ref(), turns)
             .await;
         if self.interactive {
             execute!(self.output, cursor::Hide, style::Print("\n"))?;
             self.spinner = Some(Spinner::new(Spinners::Dots, "Creating summary...".to_string()));
         }
-        let response = self.client.send_message(summary_state).await;
+        let response = self.send_message(summary_state).await;
 
         // TODO(brandonskiser): This is a temporary hotfix for failing compaction. We should instead
         // retry except with less context included.
@@ -1226,7 +1439,7 @@ impl ChatContext {
                 )
                 .await;
                 match e {
-                    crate::api_client::ApiClientError::ContextWindowOverflow => {
+                    ChatError::Client(crate::api_client::ApiClientError::ContextWindowOverflow) => {
                         self.conversation_state.clear(true);
                         if self.interactive {
                             self.spinner.take();
@@ -1247,14 +1460,14 @@ impl ChatContext {
                             skip_printing_tools: true,
                         });
                     },
-                    e => return Err(e.into()),
+                    e => return Err(e),
                 }
             },
         };
 
         let request_id = response.request_id().map(|s| s.to_string());
         let summary = {
-            let mut parser = ResponseParser::new(response);
+            let mut parser = ResponseParser::new(response).with_recorder(self.recorder.clone());
             loop {
                 match parser.recv().await {
                     Ok(parser::ResponseEvent::EndStream { message }) => {
@@ -1294,7 +1507,32 @@ impl ChatContext {
         self.send_chat_telemetry(database, telemetry, request_id, TelemetryResult::Succeeded, None, None)
             .await;
 
-        self.conversation_state.replace_history_with_summary(summary.clone());
+        let history_len = self.conversation_state.history().len();
+        let turns_removed = self
+            .conversation_state
+            .count_turns(turns.unwrap_or(history_len.saturating_sub(1)));
+        let removed_prompts = self
+            .conversation_state
+            .replace_history_with_summary(summary.clone(), turns);
+        let tokens_after = self.conversation_state.calculate_token_count().await;
+        if automatic {
+            self.auto_compaction_attempted = Some(self.conversation_state.history().len());
+        }
+        self.conversation_state.compactions.push(CompactionRecord {
+            created_at: OffsetDateTime::now_utc(),
+            automatic,
+            strategy: match (automatic, turns) {
+                (true, _) => self.auto_compaction.strategy.to_string(),
+                (false, Some(turns)) => CompactionStrategy::SummarizeOldest { turns }.to_string(),
+                (false, None) => "summarize".to_string(),
+            },
+            turns_removed,
+            tool_results_removed: 0,
+            removed_prompts,
+            summary: Some(summary.clone()),
+            tokens_before: tokens_before.value(),
+            tokens_after: tokens_after.value(),
+        });
 
         // Print output to the user.
         {
@@ -1354,11 +1592,7 @@ impl ChatContext {
 
         // If a next message is set, then retry the request.
         if self.conversation_state.next_user_message().is_some() {
-            Ok(ChatState::HandleResponseStream(
-                self.client
-                    .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                    .await?,
-            ))
+            Ok(ChatState::HandleResponseStream(self.send_conversation_state().await?))
         } else {
             // Otherwise, return back to the prompt for any pending tool uses.
             Ok(ChatState::PromptUser {
@@ -1384,7 +1618,22 @@ impl ChatContext {
         if pending_tool_index.is_none() {
             // Only display warnings when not waiting for tool approval
             if self.conversation_state.can_create_summary_request().await {
-                if let Err(e) = self.display_char_warnings().await {
+                if let Some(turns) = self.auto_compact_if_needed().await? {
+                    return Ok(ChatState::CompactHistory {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        prompt: None,
+                        show_summary: false,
+                        help: false,
+                        turns: Some(turns),
+                        automatic: true,
+                    });
+                }
+                let warning_level = self
+                    .conversation_state
+                    .get_token_warning_level(self.auto_compaction.threshold_tokens())
+                    .await;
+                if let Err(e) = self.display_char_warnings(warning_level) {
                     warn!("Failed to display character limit warnings: {}", e);
                 }
             }
@@ -1504,12 +1753,20 @@ impl ChatContext {
 
                 // Otherwise continue with normal chat on 'n' or other responses
                 self.tool_use_status = ToolUseStatus::Idle;
+                self.conversation_state.checkpoints.start_turn();
+                self.auto_compaction_attempted = None;
 
+                let resource_references = resources::parse_resource_references(&user_input, |server_name| {
+                    self.conversation_state.tool_manager.clients.contains_key(server_name)
+                });
                 if pending_tool_index.is_some() {
                     self.conversation_state.abandon_tool_use(tool_uses, user_input);
                 } else {
                     self.conversation_state.set_next_user_message(user_input).await;
                 }
+                if !resource_references.is_empty() {
+                    self.attach_resources(resource_references).await?;
+                }
 
                 let conv_state = self.conversation_state.as_sendable_conversation_state(true).await;
                 self.send_tool_use_telemetry(telemetry).await;
@@ -1522,7 +1779,7 @@ impl ChatContext {
                     self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_owned()));
                 }
 
-                ChatState::HandleResponseStream(self.client.send_message(conv_state).await?)
+                ChatState::HandleResponseStream(self.send_message(conv_state).await?)
             },
             Command::Execute { command } => {
                 queue!(self.output, style::Print('\n'))?;
@@ -1608,10 +1865,19 @@ impl ChatContext {
                     skip_printing_tools: true,
                 }
             },
+            Command::Compact { log: true, .. } => {
+                self.print_compaction_log()?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
             Command::Compact {
                 prompt,
                 show_summary,
                 help,
+                log: false,
             } => {
                 self.compact_history(
                     telemetry,
@@ -1621,6 +1887,8 @@ impl ChatContext {
                     prompt,
                     show_summary,
                     help,
+                    None,
+                    false,
                 )
                 .await?
             },
@@ -1794,6 +2062,7 @@ impl ChatContext {
                                     style::Print(format!("\nSwitched to profile: {}\n\n", name)),
                                     style::SetForegroundColor(Color::Reset)
                                 )?;
+                                self.reload_command_rules().await?;
                             },
                             Err(e) => print_err!(e),
                         },
@@ -1810,6 +2079,48 @@ impl ChatContext {
                                 Err(e) => print_err!(e),
                             }
                         },
+                        command::ProfileSubcommand::Sandbox { mode: None } => {
+                            let mode = context_manager.sandbox_mode();
+                            execute!(
+                                self.output,
+                                style::Print(format!(
+                                    "\nSandbox mode of profile {}: ",
+                                    context_manager.current_profile
+                                )),
+                                style::SetForegroundColor(Color::Green),
+                                style::Print(mode),
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print(format!(" ({})\n\n", mode.description())),
+                                style::SetForegroundColor(Color::Reset)
+                            )?;
+                        },
+                        command::ProfileSubcommand::Sandbox { mode: Some(mode) } => {
+                            match context_manager.set_sandbox_mode(mode).await {
+                                Ok(_) => {
+                                    execute!(
+                                        self.output,
+                                        style::SetForegroundColor(Color::Green),
+                                        style::Print(format!(
+                                            "\nSet the sandbox mode of profile {} to {}\n",
+                                            context_manager.current_profile, mode
+                                        )),
+                                        style::SetForegroundColor(Color::Reset)
+                                    )?;
+                                    if mode != SandboxMode::Off && !cfg!(target_os = "linux") {
+                                        execute!(
+                                            self.output,
+                                            style::SetForegroundColor(Color::Yellow),
+                                            style::Print(
+                                                "Sandboxing is only supported on Linux, shell commands will fail to run.\n"
+                                            ),
+                                            style::SetForegroundColor(Color::Reset)
+                                        )?;
+                                    }
+                                    execute!(self.output, style::Print("\n"))?;
+                                },
+                                Err(e) => print_err!(e),
+                            }
+                        },
                         command::ProfileSubcommand::Help => {
                             execute!(
                                 self.output,
@@ -1877,19 +2188,10 @@ impl ChatContext {
                                     style::SetForegroundColor(Color::DarkYellow),
                                     style::Print("\n    🔧 Hooks:\n")
                                 )?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.global_config.hooks,
-                                    HookTrigger::ConversationStart,
-                                )
-                                .map_err(map_chat_error)?;
-
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.global_config.hooks,
-                                    HookTrigger::PerPrompt,
-                                )
-                                .map_err(map_chat_error)?;
+                                for trigger in HookTrigger::ALL {
+                                    print_hook_section(&mut self.output, &context_manager.global_config.hooks, trigger)
+                                        .map_err(map_chat_error)?;
+                                }
                             }
 
                             // Display profile context
@@ -1936,18 +2238,14 @@ impl ChatContext {
                                     style::SetForegroundColor(Color::DarkYellow),
                                     style::Print("    🔧 Hooks:\n")
                                 )?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.profile_config.hooks,
-                                    HookTrigger::ConversationStart,
-                                )
-                                .map_err(map_chat_error)?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.profile_config.hooks,
-                                    HookTrigger::PerPrompt,
-                                )
-                                .map_err(map_chat_error)?;
+                                for trigger in HookTrigger::ALL {
+                                    print_hook_section(
+                                        &mut self.output,
+                                        &context_manager.profile_config.hooks,
+                                        trigger,
+                                    )
+                                    .map_err(map_chat_error)?;
+                                }
                                 execute!(self.output, style::Print("\n"))?;
                             }
 
@@ -2192,10 +2490,11 @@ impl ChatContext {
                                         command,
                                         global,
                                     } => {
-                                        let trigger = if trigger == "conversation_start" {
-                                            HookTrigger::ConversationStart
-                                        } else {
-                                            HookTrigger::PerPrompt
+                                        let trigger = match trigger.as_str() {
+                                            "conversation_start" => HookTrigger::ConversationStart,
+                                            "pre_tool_use" => HookTrigger::PreToolUse,
+                                            "post_tool_use" => HookTrigger::PostToolUse,
+                                            _ => HookTrigger::PerPrompt,
                                         };
 
                                         let result = context_manager
@@ -2353,18 +2652,10 @@ impl ChatContext {
                                     style::SetAttribute(Attribute::Reset),
                                 )?;
 
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.global_config.hooks,
-                                    HookTrigger::ConversationStart,
-                                )
-                                .map_err(map_chat_error)?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.global_config.hooks,
-                                    HookTrigger::PerPrompt,
-                                )
-                                .map_err(map_chat_error)?;
+                                for trigger in HookTrigger::ALL {
+                                    print_hook_section(&mut self.output, &context_manager.global_config.hooks, trigger)
+                                        .map_err(map_chat_error)?;
+                                }
 
                                 queue!(
                                     self.output,
@@ -2374,18 +2665,14 @@ impl ChatContext {
                                     style::SetAttribute(Attribute::Reset),
                                 )?;
 
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.profile_config.hooks,
-                                    HookTrigger::ConversationStart,
-                                )
-                                .map_err(map_chat_error)?;
-                                print_hook_section(
-                                    &mut self.output,
-                                    &context_manager.profile_config.hooks,
-                                    HookTrigger::PerPrompt,
-                                )
-                                .map_err(map_chat_error)?;
+                                for trigger in HookTrigger::ALL {
+                                    print_hook_section(
+                                        &mut self.output,
+                                        &context_manager.profile_config.hooks,
+                                        trigger,
+                                    )
+                                    .map_err(map_chat_error)?;
+                                }
 
                                 execute!(
                                     self.output,
@@ -2546,6 +2833,69 @@ impl ChatContext {
                             )?;
                         }
                     },
+                    Some(ToolsSubcommand::Rules { command: None }) => {
+                        let rules = self.tool_permissions.command_rules.rules();
+                        queue!(
+                            self.output,
+                            style::SetAttribute(Attribute::Bold),
+                            style::Print("\nCommand rules:\n"),
+                            style::SetAttribute(Attribute::Reset),
+                        )?;
+                        if rules.is_empty() {
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print("    <none>\n"),
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        }
+                        for rule in rules {
+                            queue!(self.output, style::Print(format!("- {rule}\n")))?;
+                        }
+
+                        let mut paths = vec![global_rules_path(&self.ctx)];
+                        if let Some(profile) = self.conversation_state.current_profile() {
+                            paths.push(profile_rules_path(&self.ctx, profile));
+                        }
+                        queue!(
+                            self.output,
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print("\nRules are loaded from:\n"),
+                        )?;
+                        for path in paths.into_iter().flatten() {
+                            queue!(self.output, style::Print(format!("    {}\n", path.display())))?;
+                        }
+                        queue!(self.output, style::SetForegroundColor(Color::Reset))?;
+                    },
+                    Some(ToolsSubcommand::Rules { command: Some(command) }) => {
+                        let evaluation = self.tool_permissions.command_rules.evaluate(&command);
+                        queue!(self.output, style::Print("\n"))?;
+                        for segment in &evaluation.segments {
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::Green),
+                                style::Print(&segment.segment),
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print(format!(
+                                    "\n    {}\n",
+                                    segment
+                                        .rule
+                                        .map_or("no matching rule".to_string(), |rule| rule.to_string())
+                                )),
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        }
+                        let outcome = match evaluation.action {
+                            Some(action) => format!("{action}"),
+                            None => "no rule applies, the default tool permissions are used".to_string(),
+                        };
+                        queue!(
+                            self.output,
+                            style::SetAttribute(Attribute::Bold),
+                            style::Print(format!("\nResult: {outcome}\n")),
+                            style::SetAttribute(Attribute::Reset),
+                        )?;
+                    },
                     Some(ToolsSubcommand::Help) => {
                         queue!(
                             self.output,
@@ -2894,11 +3244,10 @@ impl ChatContext {
 
                 let data = state.calculate_conversation_size();
 
-                let context_token_count: TokenCount = data.context_messages.into();
-                let assistant_token_count: TokenCount = data.assistant_messages.into();
-                let user_token_count: TokenCount = data.user_messages.into();
-                let total_token_used: TokenCount =
-                    (data.context_messages + data.user_messages + data.assistant_messages).into();
+                let context_token_count = data.context_messages;
+                let assistant_token_count = data.assistant_messages;
+                let user_token_count = data.user_messages;
+                let total_token_used = data.total();
 
                 let window_width = self.terminal_width();
                 // set a max width for the progress bar for better aesthetic
@@ -3002,6 +3351,50 @@ impl ChatContext {
                     )),
                 )?;
 
+                let delegate_usage = self
+                    .delegate_usage
+                    .lock()
+                    .map(|usage| usage.clone())
+                    .unwrap_or_default();
+                if !delegate_usage.is_empty() {
+                    queue!(
+                        self.output,
+                        style::SetAttribute(Attribute::Bold),
+                        style::Print("Delegated tasks (separate context windows):\n"),
+                        style::SetAttribute(Attribute::Reset),
+                    )?;
+                    for usage in delegate_usage {
+                        let mut task = usage.task.lines().next().unwrap_or_default().to_string();
+                        if task.len() > 60 {
+                            task = format!("{}...", truncate_safe(&task, 57));
+                        }
+                        queue!(
+                            self.output,
+                            style::Print(format!("  ~{} tokens", usage.tokens)),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!(" in {} requests: ", usage.turns)),
+                            style::SetForegroundColor(Color::Reset),
+                            style::Print(format!("{task}\n")),
+                        )?;
+                    }
+                    queue!(self.output, style::Print("\n"))?;
+                }
+
+                let tokenizer = TokenCounter::tokenizer();
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(match tokenizer.is_estimate() {
+                        true => format!(
+                            "Token counts are {}. Set {} to the path of a BPE vocabulary file for exact counts.\n",
+                            tokenizer.name(),
+                            Setting::ChatTokenizer
+                        ),
+                        false => format!("Token counts use the {} tokenizer.\n", tokenizer.name()),
+                    }),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+
                 queue!(
                     self.output,
                     style::SetAttribute(Attribute::Bold),
@@ -3175,6 +3568,83 @@ impl ChatContext {
                     skip_printing_tools: true,
                 }
             },
+            Command::Resources { server_name } => {
+                let servers = self
+                    .conversation_state
+                    .tool_manager
+                    .list_resources()
+                    .into_iter()
+                    .filter(|(name, resources)| {
+                        server_name.as_ref().is_none_or(|server_name| server_name == name)
+                            && !(resources.resources.is_empty() && resources.templates.is_empty())
+                    })
+                    .collect::<Vec<_>>();
+                if servers.is_empty() {
+                    let msg = match &server_name {
+                        Some(server_name) => format!("\n{server_name} does not offer any resources.\n\n"),
+                        None => "\nNo MCP server offers any resources.\n\n".to_string(),
+                    };
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print(msg),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                }
+                for (name, resources) in servers {
+                    queue!(
+                        self.output,
+                        style::Print("\n"),
+                        style::SetAttribute(Attribute::Bold),
+                        style::Print(&name),
+                        style::Print(" (MCP):"),
+                        style::SetAttribute(Attribute::Reset),
+                        style::Print("\n"),
+                    )?;
+                    for resource in &resources.resources {
+                        queue!(
+                            self.output,
+                            style::Print("- "),
+                            style::SetForegroundColor(Color::Cyan),
+                            style::Print(format!("@{name}:{}", resource.uri)),
+                            style::SetForegroundColor(Color::Reset),
+                            style::Print(format!(" {}", resource.name)),
+                        )?;
+                        if let Some(description) = &resource.description {
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print(format!(" - {description}")),
+                                style::SetForegroundColor(Color::Reset),
+                            )?;
+                        }
+                        queue!(self.output, style::Print("\n"))?;
+                    }
+                    for template in &resources.templates {
+                        queue!(
+                            self.output,
+                            style::Print("- "),
+                            style::SetForegroundColor(Color::Cyan),
+                            style::Print(format!("@{name}:{}", template.uri_template)),
+                            style::SetForegroundColor(Color::Reset),
+                            style::Print(format!(" {} (template)", template.name)),
+                            style::Print("\n"),
+                        )?;
+                    }
+                }
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print("\nAttach a resource to your next prompt with @server:uri\n\n"),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+                self.output.flush()?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
             Command::Model => {
                 queue!(self.output, style::Print("\n"))?;
                 let active_model_id = self.conversation_state.model.as_deref();
@@ -3300,1032 +3770,2641 @@ impl ChatContext {
                     skip_printing_tools: true,
                 });
             },
-        })
-    }
+            Command::Undo { target } => {
+                let checkpoints = &mut self.conversation_state.checkpoints;
+                let result = match target {
+                    UndoTarget::Turns(n) => checkpoints.undo_turns(&self.ctx, n).await,
+                    UndoTarget::Checkpoint(id) => checkpoints.undo_checkpoint(&self.ctx, id).await,
+                };
+                match result {
+                    Ok(restored) => {
+                        let cwd = self.ctx.env().current_dir()?;
+                        queue!(self.output, style::Print("\n"))?;
+                        for checkpoint in &restored {
+                            let action = if checkpoint.is_creation() {
+                                "Deleted "
+                            } else {
+                                "Restored"
+                            };
+                            queue!(
+                                self.output,
+                                style::SetForegroundColor(Color::DarkGrey),
+                                style::Print(format!("{action} ")),
+                                style::SetForegroundColor(Color::Green),
+                                style::Print(tools::format_path(&cwd, &checkpoint.path)),
+                                style::SetForegroundColor(Color::Reset),
+                                style::Print("\n"),
+                            )?;
+                        }
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!("\n✔ Rolled back {} edit(s)\n\n", restored.len())),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    },
+                    Err(err) => {
+                        execute!(
+                            self.output,
+                            style::SetForegroundColor(Color::Red),
+                            style::Print(format!("\nFailed to undo: {}\n\n", err)),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    },
+                }
 
-    async fn tool_use_execute(
-        &mut self,
-        database: &Database,
-        telemetry: &TelemetryThread,
-        mut tool_uses: Vec<QueuedTool>,
-    ) -> Result<ChatState, ChatError> {
-        // Verify tools have permissions.
-        for (index, tool) in tool_uses.iter_mut().enumerate() {
-            // Manually accepted by the user or otherwise verified already.
-            if tool.accepted {
-                continue;
-            }
-
-            // If there is an override, we will use it. Otherwise fall back to Tool's default.
-            let allowed = self.tool_permissions.trust_all
-                || (self.tool_permissions.has(&tool.name) && self.tool_permissions.is_trusted(&tool.name))
-                || !tool.tool.requires_acceptance(&self.ctx);
-
-            if database
-                .settings
-                .get_bool(Setting::ChatEnableNotifications)
-                .unwrap_or(false)
-            {
-                play_notification_bell(!allowed);
-            }
-
-            self.print_tool_descriptions(tool, allowed).await?;
-
-            if allowed {
-                tool.accepted = true;
-                continue;
-            }
-
-            let pending_tool_index = Some(index);
-            if !self.interactive {
-                // Cannot request in non-interactive, so fail.
-                return Err(ChatError::NonInteractiveToolApproval);
-            }
-
-            return Ok(ChatState::PromptUser {
-                tool_uses: Some(tool_uses),
-                pending_tool_index,
-                skip_printing_tools: false,
-            });
-        }
-
-        // Execute the requested tools.
-        let mut tool_results = vec![];
-        let mut image_blocks: Vec<RichImageBlock> = Vec::new();
-
-        for tool in tool_uses {
-            let mut tool_telemetry = self.tool_use_telemetry_events.entry(tool.id.clone());
-            tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_accepted = true);
-
-            let tool_start = std::time::Instant::now();
-            let invoke_result = tool.tool.invoke(&self.ctx, &mut self.output).await;
-
-            if self.interactive && self.spinner.is_some() {
-                queue!(
-                    self.output,
-                    terminal::Clear(terminal::ClearType::CurrentLine),
-                    cursor::MoveToColumn(0),
-                    cursor::Show
-                )?;
-            }
-            execute!(self.output, style::Print("\n"))?;
-
-            let tool_time = std::time::Instant::now().duration_since(tool_start);
-            if let Tool::Custom(ct) = &tool.tool {
-                tool_telemetry = tool_telemetry.and_modify(|ev| {
-                    ev.custom_tool_call_latency = Some(tool_time.as_secs() as usize);
-                    ev.input_token_size = Some(ct.get_input_token_size());
-                    ev.is_custom_tool = true;
-                });
-            }
-            let tool_time = format!("{}.{}", tool_time.as_secs(), tool_time.subsec_millis());
-            match invoke_result {
-                Ok(result) => {
-                    match result.output {
-                        OutputKind::Text(ref text) => {
-                            debug!("Output is Text: {}", text);
-                        },
-                        OutputKind::Json(ref json) => {
-                            debug!("Output is JSON: {}", json);
-                        },
-                        OutputKind::Images(ref image) => {
-                            image_blocks.extend(image.clone());
-                        },
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Checkpoints => {
+                if self.conversation_state.checkpoints.is_empty() {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nNo files have been modified in this conversation.\n\n"),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                } else {
+                    let cwd = self.ctx.env().current_dir()?;
+                    let time_format = time::format_description::parse("[hour]:[minute]:[second]")
+                        .map_err(|e| ChatError::Custom(e.to_string().into()))?;
+                    let mut current_turn = None;
+                    for checkpoint in self.conversation_state.checkpoints.checkpoints() {
+                        if current_turn != Some(checkpoint.turn) {
+                            current_turn = Some(checkpoint.turn);
+                            queue!(
+                                self.output,
+                                style::SetAttribute(Attribute::Bold),
+                                style::Print(format!("\nTurn {}\n", checkpoint.turn)),
+                                style::SetAttribute(Attribute::Reset),
+                            )?;
+                        }
+                        let created_at = checkpoint
+                            .created_at
+                            .to_offset(time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC))
+                            .format(&time_format)
+                            .unwrap_or_default();
+                        queue!(
+                            self.output,
+                            style::Print(format!("  #{:<4}", checkpoint.id)),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!("{created_at}  ")),
+                            style::Print(if checkpoint.is_creation() {
+                                "created   "
+                            } else {
+                                "modified  "
+                            }),
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(tools::format_path(&cwd, &checkpoint.path)),
+                            style::SetForegroundColor(Color::Reset),
+                            style::Print("\n"),
+                        )?;
                     }
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!("\nUse {} to roll back.\n\n", UndoTarget::USAGE)),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    self.output.flush()?;
+                }
 
-                    debug!("tool result output: {:#?}", result);
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::History { all } => {
+                let cwd = self.ctx.env().current_dir()?;
+                let conversations = database
+                    .list_conversations((!all).then_some(cwd.as_path()))
+                    .map_err(|e| ChatError::Custom(e.to_string().into()))?;
+                if conversations.is_empty() {
                     execute!(
                         self.output,
-                        style::Print(CONTINUATION_LINE),
-                        style::Print("\n"),
-                        style::SetForegroundColor(Color::Green),
-                        style::SetAttribute(Attribute::Bold),
-                        style::Print(format!(" ● Completed in {}s", tool_time)),
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(if all {
+                            "\nNo past conversations were found.\n\n"
+                        } else {
+                            "\nNo past conversations were found in this directory. Use /history --all to list conversations from every directory.\n\n"
+                        }),
                         style::SetForegroundColor(Color::Reset),
-                        style::Print("\n"),
                     )?;
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
+                    });
+                }
 
-                    tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_success = Some(true));
-                    if let Tool::Custom(_) = &tool.tool {
-                        tool_telemetry
-                            .and_modify(|ev| ev.output_token_size = Some(TokenCounter::count_tokens(result.as_str())));
-                    }
-                    tool_results.push(ToolUseResult {
-                        tool_use_id: tool.id,
-                        content: vec![result.into()],
-                        status: ToolResultStatus::Success,
+                let time_format = time::format_description::parse("[year]-[month]-[day] [hour]:[minute]")
+                    .map_err(|e| ChatError::Custom(e.to_string().into()))?;
+                let local_offset = time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC);
+                let items = conversations
+                    .iter()
+                    .map(|conversation| {
+                        let updated_at = time::OffsetDateTime::from_unix_timestamp(conversation.updated_at)
+                            .map(|t| t.to_offset(local_offset).format(&time_format).unwrap_or_default())
+                            .unwrap_or_default();
+                        let first_prompt = conversation
+                            .first_prompt
+                            .as_deref()
+                            .unwrap_or("(no prompt)")
+                            .lines()
+                            .next()
+                            .unwrap_or_default();
+                        let mut item = format!(
+                            "{}  {updated_at}  {:>7} tokens  {}",
+                            conversation.conversation_id,
+                            conversation.token_count,
+                            truncate_safe(first_prompt, 80)
+                        );
+                        if all {
+                            item.push_str(&format!("  ({})", conversation.path));
+                        }
+                        item
+                    })
+                    .collect::<Vec<_>>();
+
+                // Fall back to printing the list when there is no terminal to run the selector in.
+                let selection = match self.interactive {
+                    true => skim_integration::launch_skim_selector(&items, "Resume conversation: ", false),
+                    false => Err(eyre::eyre!("not interactive")),
+                };
+                let selected_id = match selection {
+                    Ok(selected) => selected
+                        .and_then(|s| s.into_iter().next())
+                        .and_then(|item| item.split_whitespace().next().map(str::to_string)),
+                    Err(_) => {
+                        queue!(self.output, style::Print("\n"))?;
+                        for item in &items {
+                            queue!(self.output, style::Print(format!("{item}\n")))?;
+                        }
+                        queue!(
+                            self.output,
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print("\nResume a conversation with q chat --resume=<id>\n\n"),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                        self.output.flush()?;
+                        None
+                    },
+                };
+
+                let Some(id) = selected_id else {
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
                     });
-                },
-                Err(err) => {
-                    error!(?err, "An error occurred processing the tool");
+                };
+                if id == self.conversation_state.conversation_id() {
                     execute!(
                         self.output,
-                        style::Print(CONTINUATION_LINE),
-                        style::Print("\n"),
-                        style::SetAttribute(Attribute::Bold),
-                        style::SetForegroundColor(Color::Red),
-                        style::Print(format!(" ● Execution failed after {}s:\n", tool_time)),
-                        style::SetAttribute(Attribute::Reset),
-                        style::SetForegroundColor(Color::Red),
-                        style::Print(&err),
-                        style::SetAttribute(Attribute::Reset),
-                        style::Print("\n\n"),
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nThat is the current conversation.\n\n"),
+                        style::SetForegroundColor(Color::Reset),
                     )?;
-
-                    tool_telemetry.and_modify(|ev| ev.is_success = Some(false));
-                    tool_results.push(ToolUseResult {
-                        tool_use_id: tool.id,
-                        content: vec![ToolUseResultBlock::Text(format!(
-                            "An error occurred processing the tool: \n{}",
-                            &err
-                        ))],
-                        status: ToolResultStatus::Error,
+                    return Ok(ChatState::PromptUser {
+                        tool_uses: Some(tool_uses),
+                        pending_tool_index,
+                        skip_printing_tools: true,
                     });
-                    if let ToolUseStatus::Idle = self.tool_use_status {
-                        self.tool_use_status = ToolUseStatus::RetryInProgress(
-                            self.conversation_state
-                                .message_id()
-                                .map_or("No utterance id found".to_string(), |v| v.to_string()),
-                        );
-                    }
-                },
-            }
+                }
+
+                let Some(mut new_state) = database
+                    .get_conversation_by_id(&id)
+                    .map_err(|e| ChatError::Custom(e.to_string().into()))?
+                else {
+                    return Err(ChatError::Custom(format!("conversation {id} no longer exists").into()));
+                };
+                new_state.tool_manager = std::mem::take(&mut self.conversation_state.tool_manager);
+                new_state
+                    .reload_serialized_state(Arc::clone(&self.ctx), Some(self.output.clone()))
+                    .await;
+                new_state.update_state(true).await;
+                new_state.enforce_tool_use_history_invariants();
+                self.conversation_state = new_state;
+
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Green),
+                    style::Print(format!("\n✔ Resumed conversation {id}\n\n")),
+                    style::SetAttribute(Attribute::Reset)
+                )?;
+
+                ChatState::PromptUser {
+                    tool_uses: None,
+                    pending_tool_index: None,
+                    skip_printing_tools: true,
+                }
+            },
+            Command::Knowledge { subcommand } => {
+                self.handle_knowledge_command(database, subcommand).await?;
+                ChatState::PromptUser {
+                    tool_uses: Some(tool_uses),
+                    pending_tool_index,
+                    skip_printing_tools: true,
+                }
+            },
+        })
+    }
+
+    /// Runs a `/knowledge` subcommand against the knowledge bases of the user.
+    #[cfg(feature = "knowledge")]
+    async fn handle_knowledge_command(
+        &mut self,
+        database: &Database,
+        subcommand: KnowledgeSubcommand,
+    ) -> Result<(), ChatError> {
+        macro_rules! print_err {
+            ($err:expr) => {
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Red),
+                    style::Print(format!("\nError: {}\n\n", $err)),
+                    style::SetForegroundColor(Color::Reset)
+                )?
+            };
         }
 
-        if !image_blocks.is_empty() {
-            let images = image_blocks.into_iter().map(|(block, _)| block).collect();
-            self.conversation_state
-                .add_tool_results_with_images(tool_results, images);
+        if subcommand == KnowledgeSubcommand::Help {
             execute!(
                 self.output,
-                style::SetAttribute(Attribute::Reset),
-                style::SetForegroundColor(Color::Reset),
+                style::Print("\n"),
+                style::Print(KnowledgeSubcommand::help_text()),
                 style::Print("\n")
             )?;
-        } else {
-            self.conversation_state.add_tool_results(tool_results);
-        }
-        if self.interactive {
-            execute!(self.output, cursor::Hide)?;
-            execute!(self.output, style::Print("\n"), style::SetAttribute(Attribute::Reset))?;
-            self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_string()));
+            return Ok(());
         }
-
-        self.send_tool_use_telemetry(telemetry).await;
-        return Ok(ChatState::HandleResponseStream(
-            self.client
-                .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                .await?,
-        ));
-    }
-
-    async fn handle_response(
-        &mut self,
-        database: &mut Database,
-        telemetry: &TelemetryThread,
-        response: SendMessageOutput,
-    ) -> Result<ChatState, ChatError> {
-        let request_id = response.request_id().map(|s| s.to_string());
-        let mut buf = String::new();
-        let mut offset = 0;
-        let mut ended = false;
-        let mut parser = ResponseParser::new(response);
-        let mut state = ParseState::new(Some(self.terminal_width()));
-
-        let mut tool_uses = Vec::new();
-        let mut tool_name_being_recvd: Option<String> = None;
-
-        if self.interactive && self.spinner.is_some() {
-            drop(self.spinner.take());
-            queue!(
+        if !KnowledgeStore::is_enabled(database) {
+            execute!(
                 self.output,
-                style::SetForegroundColor(Color::Reset),
-                terminal::Clear(terminal::ClearType::CurrentLine),
-                cursor::MoveToColumn(0),
-                cursor::Show,
-                cursor::MoveUp(1),
-                terminal::Clear(terminal::ClearType::CurrentLine),
+                style::SetForegroundColor(Color::Yellow),
+                style::Print("\nKnowledge bases are disabled. Enable them with "),
+                style::SetForegroundColor(Color::Green),
+                style::Print("q settings chat.enableKnowledge true"),
+                style::SetForegroundColor(Color::Yellow),
+                style::Print(" and start a new chat session.\n\n"),
+                style::SetForegroundColor(Color::Reset)
             )?;
+            return Ok(());
         }
 
-        loop {
-            match parser.recv().await {
-                Ok(msg_event) => {
-                    trace!("Consumed: {:?}", msg_event);
-                    match msg_event {
-                        parser::ResponseEvent::ToolUseStart { name } => {
-                            // We need to flush the buffer here, otherwise text will not be
-                            // printed while we are receiving tool use events.
-                            buf.push('\n');
-                            tool_name_being_recvd = Some(name);
-                        },
-                        parser::ResponseEvent::AssistantText(text) => {
-                            buf.push_str(&text);
-                        },
-                        parser::ResponseEvent::ToolUse(tool_use) => {
-                            if self.interactive && self.spinner.is_some() {
-                                drop(self.spinner.take());
-                                queue!(
-                                    self.output,
-                                    terminal::Clear(terminal::ClearType::CurrentLine),
-                                    cursor::MoveToColumn(0),
-                                    cursor::Show
-                                )?;
-                            }
-                            tool_uses.push(tool_use);
-                            tool_name_being_recvd = None;
-                        },
-                        parser::ResponseEvent::EndStream { message } => {
-                            // This log is attempting to help debug instances where users encounter
-                            // the response timeout message.
-                            if message.content() == RESPONSE_TIMEOUT_CONTENT {
-                                error!(?request_id, ?message, "Encountered an unexpected model response");
-                            }
-                            self.conversation_state.push_assistant_message(message, database);
-                            ended = true;
-                        },
-                    }
-                },
-                Err(recv_error) => {
-                    if let Some(request_id) = &recv_error.request_id {
-                        self.failed_request_ids.push(request_id.clone());
-                    };
-
-                    let (reason, reason_desc) = get_error_reason(&recv_error);
-                    self.send_chat_telemetry(
-                        database,
-                        telemetry,
-                        recv_error.request_id.clone(),
-                        TelemetryResult::Failed,
-                        Some(reason),
-                        Some(reason_desc),
-                    )
-                    .await;
-
-                    match recv_error.source {
-                        RecvErrorKind::StreamTimeout { source, duration } => {
-                            error!(
-                                recv_error.request_id,
-                                ?source,
-                                "Encountered a stream timeout after waiting for {}s",
-                                duration.as_secs()
-                            );
-                            if self.interactive {
-                                execute!(self.output, cursor::Hide)?;
-                                self.spinner =
-                                    Some(Spinner::new(Spinners::Dots, "Dividing up the work...".to_string()));
-                            }
-                            // For stream timeouts, we'll tell the model to try and split its response into
-                            // smaller chunks.
-                            self.conversation_state.push_assistant_message(
-                                AssistantMessage::new_response(None, RESPONSE_TIMEOUT_CONTENT.to_string()),
-                                database,
-                            );
-                            self.conversation_state
-                                .set_next_user_message(
-                                    "You took too long to respond - try to split up the work into smaller steps."
-                                        .to_string(),
-                                )
-                                .await;
-                            self.send_tool_use_telemetry(telemetry).await;
-                            return Ok(ChatState::HandleResponseStream(
-                                self.client
-                                    .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                                    .await?,
-                            ));
-                        },
-                        RecvErrorKind::UnexpectedToolUseEos {
-                            tool_use_id,
-                            name,
-                            message,
-                            ..
-                        } => {
-                            error!(
-                                recv_error.request_id,
-                                tool_use_id, name, "The response stream ended before the entire tool use was received"
-                            );
-                            self.conversation_state.push_assistant_message(*message, database);
-                            let tool_results = vec![ToolUseResult {
-                                    tool_use_id,
-                                    content: vec![ToolUseResultBlock::Text(
-                                        "The generated tool was too large, try again but this time split up the work between multiple tool uses".to_string(),
-                                    )],
-                                    status: ToolResultStatus::Error,
-                                }];
-                            self.conversation_state.add_tool_results(tool_results);
-                            self.send_tool_use_telemetry(telemetry).await;
-                            return Ok(ChatState::HandleResponseStream(
-                                self.client
-                                    .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                                    .await?,
-                            ));
-                        },
-                        _ => return Err(recv_error.into()),
-                    }
-                },
-            }
-
-            // Fix for the markdown parser copied over from q chat:
-            // this is a hack since otherwise the parser might report Incomplete with useful data
-            // still left in the buffer. I'm not sure how this is intended to be handled.
-            if ended {
-                buf.push('\n');
-            }
-
-            if tool_name_being_recvd.is_none() && !buf.is_empty() && self.interactive && self.spinner.is_some() {
-                drop(self.spinner.take());
-                queue!(
-                    self.output,
-                    terminal::Clear(terminal::ClearType::CurrentLine),
-                    cursor::MoveToColumn(0),
-                    cursor::Show
-                )?;
-            }
-
-            // Print the response for normal cases
-            loop {
-                let input = Partial::new(&buf[offset..]);
-                match interpret_markdown(input, &mut self.output, &mut state) {
-                    Ok(parsed) => {
-                        offset += parsed.offset_from(&input);
-                        self.output.flush()?;
-                        state.newline = state.set_newline;
-                        state.set_newline = false;
-                    },
-                    Err(err) => match err.into_inner() {
-                        Some(err) => return Err(ChatError::Custom(err.to_string().into())),
-                        None => break, // Data was incomplete
-                    },
-                }
-
-                // TODO: We should buffer output based on how much we have to parse, not as a constant
-                // Do not remove unless you are nabochay :)
-                tokio::time::sleep(Duration::from_millis(8)).await;
-            }
-
-            // Set spinner after showing all of the assistant text content so far.
-            if let (Some(_name), true) = (&tool_name_being_recvd, self.interactive) {
-                queue!(self.output, cursor::Hide)?;
-                self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_string()));
-            }
-
-            if ended {
-                self.send_chat_telemetry(database, telemetry, request_id, TelemetryResult::Succeeded, None, None)
-                    .await;
-
-                if self.interactive
-                    && database
-                        .settings
-                        .get_bool(Setting::ChatEnableNotifications)
-                        .unwrap_or(false)
-                {
-                    // For final responses (no tools suggested), always play the bell
-                    play_notification_bell(tool_uses.is_empty());
-                }
-
-                if self.interactive {
-                    queue!(self.output, style::ResetColor, style::SetAttribute(Attribute::Reset))?;
-                    execute!(self.output, style::Print("\n"))?;
+        let ctx = Arc::clone(&self.ctx);
+        let store = match with_spinner(self.interactive, &mut self.output, "Loading knowledge bases...", || {
+            KnowledgeStore::global(&ctx)
+        })
+        .await
+        {
+            Ok(store) => store,
+            Err(err) => {
+                print_err!(err);
+                return Ok(());
+            },
+        };
 
-                    for (i, citation) in &state.citations {
+        match subcommand {
+            KnowledgeSubcommand::List => {
+                let knowledge_bases = store.list();
+                if knowledge_bases.is_empty() {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nNo knowledge bases have been added. Add one with /knowledge add <path>\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                } else {
+                    queue!(self.output, style::Print("\n"))?;
+                    for knowledge_base in knowledge_bases {
                         queue!(
                             self.output,
-                            style::Print("\n"),
-                            style::SetForegroundColor(Color::Blue),
-                            style::Print(format!("[^{i}]: ")),
+                            style::SetAttribute(Attribute::Bold),
+                            style::Print(&knowledge_base.name),
+                            style::SetAttribute(Attribute::Reset),
                             style::SetForegroundColor(Color::DarkGrey),
-                            style::Print(format!("{citation}\n")),
-                            style::SetForegroundColor(Color::Reset)
+                            style::Print(format!(
+                                "  {} chunks  added {}\n",
+                                knowledge_base.item_count,
+                                knowledge_base.created_at.format("%Y-%m-%d %H:%M")
+                            )),
+                            style::SetForegroundColor(Color::Green),
+                            style::Print(format!(
+                                "  {}\n",
+                                knowledge_base.source_path.as_deref().unwrap_or_default()
+                            )),
+                            style::SetForegroundColor(Color::Reset),
                         )?;
                     }
+                    execute!(self.output, style::Print("\n"))?;
                 }
-
-                break;
-            }
+            },
+            KnowledgeSubcommand::Add { path, name } => {
+                let path = tools::sanitize_path_tool_arg(&self.ctx, &path);
+                let spinner_text = format!("Indexing {}...", path.display());
+                match with_spinner(self.interactive, &mut self.output, &spinner_text, || {
+                    store.add(path, name, |_| {})
+                })
+                .await
+                {
+                    Ok(knowledge_base) => execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!(
+                            "\n✔ Added knowledge base '{}' with {} chunks\n\n",
+                            knowledge_base.name, knowledge_base.item_count
+                        )),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                    Err(err) => print_err!(err),
+                }
+            },
+            KnowledgeSubcommand::Remove { target } => {
+                let cwd = self.ctx.env().current_dir()?;
+                match store.remove(&target, &cwd).await {
+                    Ok(knowledge_base) => execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!("\n✔ Removed knowledge base '{}'\n\n", knowledge_base.name)),
+                        style::SetForegroundColor(Color::Reset)
+                    )?,
+                    Err(err) => print_err!(err),
+                }
+            },
+            KnowledgeSubcommand::Search { query } => {
+                let results = match with_spinner(self.interactive, &mut self.output, "Searching...", || {
+                    store.search(&query, None, KnowledgeStore::DEFAULT_RESULTS)
+                })
+                .await
+                {
+                    Ok(results) => results,
+                    Err(err) => {
+                        print_err!(err);
+                        return Ok(());
+                    },
+                };
+                if results.is_empty() {
+                    execute!(
+                        self.output,
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print("\nNo matching passages were found.\n\n"),
+                        style::SetForegroundColor(Color::Reset)
+                    )?;
+                }
+                for result in results {
+                    queue!(
+                        self.output,
+                        style::Print("\n"),
+                        style::SetAttribute(Attribute::Bold),
+                        style::Print(&result.knowledge_base),
+                        style::SetAttribute(Attribute::Reset),
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format!("  {}\n", result.path.as_deref().unwrap_or_default())),
+                        style::SetForegroundColor(Color::DarkGrey),
+                        style::Print(format!("{}\n", truncate_safe(result.text.trim(), 500))),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                }
+                execute!(self.output, style::Print("\n"))?;
+            },
+            KnowledgeSubcommand::Help => unreachable!("handled above"),
         }
+        Ok(())
+    }
 
-        if !tool_uses.is_empty() {
-            Ok(ChatState::ValidateTools(tool_uses))
+    #[cfg(not(feature = "knowledge"))]
+    async fn handle_knowledge_command(
+        &mut self,
+        _database: &Database,
+        subcommand: KnowledgeSubcommand,
+    ) -> Result<(), ChatError> {
+        if subcommand == KnowledgeSubcommand::Help {
+            execute!(
+                self.output,
+                style::Print("\n"),
+                style::Print(KnowledgeSubcommand::help_text()),
+                style::Print("\n")
+            )?;
         } else {
-            Ok(ChatState::PromptUser {
-                tool_uses: None,
-                pending_tool_index: None,
-                skip_printing_tools: false,
-            })
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print("\nKnowledge bases are not available in this build of Amazon Q.\n\n"),
+                style::SetForegroundColor(Color::Reset)
+            )?;
         }
+        Ok(())
     }
 
-    async fn validate_tools(
+    async fn tool_use_execute(
         &mut self,
+        database: &Database,
         telemetry: &TelemetryThread,
-        tool_uses: Vec<AssistantToolUse>,
+        mut tool_uses: Vec<QueuedTool>,
     ) -> Result<ChatState, ChatError> {
-        let conv_id = self.conversation_state.conversation_id().to_owned();
-        debug!(?tool_uses, "Validating tool uses");
-        let mut queued_tools: Vec<QueuedTool> = Vec::new();
-        let mut tool_results: Vec<ToolUseResult> = Vec::new();
+        // Verify tools have permissions.
+        for (index, tool) in tool_uses.iter_mut().enumerate() {
+            // Manually accepted by the user or otherwise verified already.
+            if tool.accepted {
+                continue;
+            }
 
-        for tool_use in tool_uses {
-            let tool_use_id = tool_use.id.clone();
-            let tool_use_name = tool_use.name.clone();
-            let mut tool_telemetry = ToolUseEventBuilder::new(
-                conv_id.clone(),
-                tool_use.id.clone(),
-                self.conversation_state.model.clone(),
-            )
-            .set_tool_use_id(tool_use_id.clone())
-            .set_tool_name(tool_use.name.clone())
-            .utterance_id(self.conversation_state.message_id().map(|s| s.to_string()));
-            match self.conversation_state.tool_manager.get_tool_from_tool_use(tool_use) {
-                Ok(mut tool) => {
-                    // Apply non-Q-generated context to tools
-                    self.contextualize_tool(&mut tool);
+            if let Some(reason) = &tool.pre_hook_outcome.denied {
+                // Denied tools are rejected with an error result when executing below.
+                let reason = reason.clone();
+                self.print_tool_descriptions(tool, false).await?;
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::Red),
+                    style::Print(format!("\nBlocked by hook {reason}\n")),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+                self.report_tool_denied(tool, format!("blocked by hook {reason}"))?;
+                tool.accepted = true;
+                continue;
+            }
 
-                    match tool.validate(&self.ctx).await {
-                        Ok(()) => {
-                            tool_telemetry.is_valid = Some(true);
-                            queued_tools.push(QueuedTool {
-                                id: tool_use_id.clone(),
-                                name: tool_use_name,
-                                tool,
-                                accepted: false,
-                            });
-                        },
-                        Err(err) => {
-                            tool_telemetry.is_valid = Some(false);
-                            tool_results.push(ToolUseResult {
-                                tool_use_id: tool_use_id.clone(),
-                                content: vec![ToolUseResultBlock::Text(format!(
-                                    "Failed to validate tool parameters: {err}"
-                                ))],
-                                status: ToolResultStatus::Error,
-                            });
-                        },
-                    };
+            // Command rules take precedence over trust settings, falling back to them when no rule
+            // decides the outcome.
+            let (rule_action, matched_rule) = match self.tool_permissions.evaluate_rules(&tool.tool) {
+                Some(evaluation) => (evaluation.action, evaluation.decisive_rule().map(|r| r.to_string())),
+                None => (None, None),
+            };
+
+            // If there is an override, we will use it. Otherwise fall back to Tool's default.
+            let allowed = match rule_action {
+                Some(RuleAction::Allow) => true,
+                Some(RuleAction::Ask) => false,
+                Some(RuleAction::Deny) => {
+                    // Denied tools are rejected with an error result when executing below.
+                    self.print_tool_descriptions(tool, false).await?;
+                    let rule = matched_rule.unwrap_or_default();
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::Red),
+                        style::Print(format!("\nDenied by command rule: {rule}\n")),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    self.report_tool_denied(tool, format!("denied by command rule: {rule}"))?;
+                    tool.accepted = true;
+                    continue;
                 },
-                Err(err) => {
-                    tool_telemetry.is_valid = Some(false);
-                    tool_results.push(err.into());
+                None => {
+                    self.tool_permissions.trust_all
+                        || (self.tool_permissions.has(&tool.name) && self.tool_permissions.is_trusted(&tool.name))
+                        || !tool.tool.requires_acceptance(&self.ctx)
                 },
+            };
+
+            if database
+                .settings
+                .get_bool(Setting::ChatEnableNotifications)
+                .unwrap_or(false)
+            {
+                play_notification_bell(!allowed);
             }
-            self.tool_use_telemetry_events.insert(tool_use_id, tool_telemetry);
-        }
 
-        // If we have any validation errors, then return them immediately to the model.
-        if !tool_results.is_empty() {
-            debug!(?tool_results, "Error found in the model tools");
-            queue!(
-                self.output,
-                style::SetAttribute(Attribute::Bold),
-                style::Print("Tool validation failed: "),
-                style::SetAttribute(Attribute::Reset),
-            )?;
-            for tool_result in &tool_results {
-                for block in &tool_result.content {
-                    let content: Option<Cow<'_, str>> = match block {
-                        ToolUseResultBlock::Text(t) => Some(t.as_str().into()),
-                        ToolUseResultBlock::Json(d) => serde_json::to_string(d)
-                            .map_err(|err| error!(?err, "failed to serialize tool result content"))
-                            .map(Into::into)
-                            .ok(),
-                    };
-                    if let Some(content) = content {
-                        queue!(
-                            self.output,
-                            style::Print("\n"),
-                            style::SetForegroundColor(Color::Red),
-                            style::Print(format!("{}\n", content)),
-                            style::SetForegroundColor(Color::Reset),
-                        )?;
-                    }
-                }
+            self.print_tool_descriptions(tool, allowed).await?;
+            if let Some(rule) = matched_rule {
+                queue!(
+                    self.output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(format!("Matched command rule: {rule}\n")),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
             }
-            self.conversation_state.add_tool_results(tool_results);
-            self.send_tool_use_telemetry(telemetry).await;
-            if let ToolUseStatus::Idle = self.tool_use_status {
-                self.tool_use_status = ToolUseStatus::RetryInProgress(
-                    self.conversation_state
-                        .message_id()
-                        .map_or("No utterance id found".to_string(), |v| v.to_string()),
-                );
+
+            if allowed {
+                tool.accepted = true;
+                continue;
+            }
+
+            let pending_tool_index = Some(index);
+            if !self.interactive {
+                // Cannot request in non-interactive, so fail.
+                self.report_tool_denied(tool, "requires approval".to_string())?;
+                return Err(ChatError::NonInteractiveToolApproval);
             }
 
-            let response = self
-                .client
-                .send_message(self.conversation_state.as_sendable_conversation_state(false).await)
-                .await?;
-            return Ok(ChatState::HandleResponseStream(response));
+            return Ok(ChatState::PromptUser {
+                tool_uses: Some(tool_uses),
+                pending_tool_index,
+                skip_printing_tools: false,
+            });
         }
 
-        Ok(ChatState::ExecuteTools(queued_tools))
-    }
+        // Execute the requested tools. Consecutive tools that can run concurrently are invoked
+        // together, any other tool runs on its own once the tools before it have finished.
+        let mut tool_results = vec![];
+        let mut image_blocks: Vec<RichImageBlock> = Vec::new();
 
-    async fn upgrade_to_pro(&mut self, database: &mut Database) -> Result<(), ChatError> {
-        queue!(self.output, style::Print("\n"),)?;
+        for batch in self.batch_tool_uses(tool_uses) {
+            let mut prepared = Vec::new();
+            for mut tool in batch {
+                if let Some(reason) = tool.pre_hook_outcome.denied.take() {
+                    tool_results.push(ToolUseResult {
+                        tool_use_id: tool.id,
+                        content: vec![ToolUseResultBlock::Text(format!(
+                            "The tool use was blocked by the user's hook {reason}"
+                        ))],
+                        status: ToolResultStatus::Error,
+                    });
+                    continue;
+                }
 
-        // Get current subscription status
-        match get_subscription_status_with_spinner(self.interactive, &mut self.output, database).await {
-            Ok(status) => {
-                if status == ActualSubscriptionStatus::Active {
+                let denied_by = self
+                    .tool_permissions
+                    .evaluate_rules(&tool.tool)
+                    .filter(|evaluation| evaluation.action == Some(RuleAction::Deny))
+                    .map(|evaluation| evaluation.decisive_rule().map(|r| r.to_string()).unwrap_or_default());
+                if let Some(rule) = denied_by {
+                    tool_results.push(ToolUseResult {
+                        tool_use_id: tool.id,
+                        content: vec![ToolUseResultBlock::Text(format!(
+                            "The command was not executed because it is denied by the user's command rules ({rule}). \
+                            Do not attempt to run it, or an equivalent command, another way."
+                        ))],
+                        status: ToolResultStatus::Error,
+                    });
+                    continue;
+                }
+
+                self.tool_use_telemetry_events
+                    .entry(tool.id.clone())
+                    .and_modify(|ev| ev.is_accepted = true);
+
+                // Snapshot the files about to be modified so the edit can be rolled back with /undo.
+                let checkpoint_ids = match &tool.tool {
+                    Tool::FsWrite(fs_write) => {
+                        let paths = match fs_write.paths(&self.ctx) {
+                            Ok(paths) => paths,
+                            Err(err) => {
+                                tool_results.push(ToolUseResult {
+                                    tool_use_id: tool.id,
+                                    content: vec![ToolUseResultBlock::Text(format!(
+                                        "The files to write could not be determined: {err}"
+                                    ))],
+                                    status: ToolResultStatus::Error,
+                                });
+                                continue;
+                            },
+                        };
+                        match self
+                            .conversation_state
+                            .checkpoints
+                            .snapshot(&self.ctx, &tool.id, &paths)
+                            .await
+                        {
+                            Ok(ids) => ids,
+                            // Writes that could not be rolled back with /undo are not made at all.
+                            Err(err) => {
+                                error!(?err, "failed to create a checkpoint before writing");
+                                tool_results.push(ToolUseResult {
+                                    tool_use_id: tool.id,
+                                    content: vec![ToolUseResultBlock::Text(format!(
+                                        "The files could not be checkpointed before writing, so they were not written: {err}"
+                                    ))],
+                                    status: ToolResultStatus::Error,
+                                });
+                                continue;
+                            },
+                        }
+                    },
+                    _ => Vec::new(),
+                };
+
+                if let Some(recorder) = &self.recorder {
+                    recorder.record(RecordedEvent::ToolUse {
+                        tool_use_id: tool.id.clone(),
+                        name: tool.name.clone(),
+                        input: tool.input.clone(),
+                    });
+                }
+                prepared.push((tool, checkpoint_ids));
+            }
+
+            let invocations = self.invoke_tools(prepared.iter().map(|(tool, _)| tool).collect()).await;
+            for ((tool, checkpoint_ids), invocation) in prepared.into_iter().zip(invocations) {
+                let ToolInvocation {
+                    result: invoke_result,
+                    duration: tool_time,
+                } = invocation;
+                if invoke_result.is_err() {
+                    self.conversation_state
+                        .checkpoints
+                        .discard(&self.ctx, &checkpoint_ids)
+                        .await;
+                }
+                // Files written by a delegated task can be rolled back like any other edit.
+                if let Tool::Delegate(Delegate {
+                    context: Some(context), ..
+                }) = &tool.tool
+                {
+                    let child_checkpoints = std::mem::take(&mut *context.checkpoints.lock().await);
+                    if let Err(err) = self
+                        .conversation_state
+                        .checkpoints
+                        .append(&self.ctx, child_checkpoints)
+                        .await
+                    {
+                        error!(?err, "failed to keep the checkpoints of a delegated task");
+                    }
+                }
+
+                if self.interactive && self.spinner.is_some() {
                     queue!(
                         self.output,
-                        style::SetForegroundColor(Color::Yellow),
-                        style::Print("Your Builder ID already has a Q Developer Pro subscription.\n\n"),
-                        style::SetForegroundColor(Color::Reset),
+                        terminal::Clear(terminal::ClearType::CurrentLine),
+                        cursor::MoveToColumn(0),
+                        cursor::Show
                     )?;
-                    return Ok(());
                 }
-            },
-            Err(e) => {
-                execute!(
-                    self.output,
-                    style::SetForegroundColor(Color::Red),
-                    style::Print(format!("{}\n\n", e)),
-                    style::SetForegroundColor(Color::Reset),
-                )?;
-                // Don't exit early here, the check isn't required to subscribe.
-            },
-        }
+                execute!(self.output, style::Print("\n"))?;
 
-        // Upgrade information
-        queue!(
-            self.output,
-            style::Print(SUBSCRIBE_TITLE_TEXT),
-            style::SetForegroundColor(Color::Grey),
-            style::Print(format!("\n\n{}\n\n", SUBSCRIBE_TEXT)),
-            style::SetForegroundColor(Color::Reset),
-            cursor::Show
-        )?;
+                let mut tool_telemetry = self.tool_use_telemetry_events.entry(tool.id.clone());
+                if let Tool::Custom(ct) = &tool.tool {
+                    tool_telemetry = tool_telemetry.and_modify(|ev| {
+                        ev.custom_tool_call_latency = Some(tool_time.as_secs() as usize);
+                        ev.input_token_size = Some(ct.get_input_token_size());
+                        ev.is_custom_tool = true;
+                    });
+                }
+                let tool_time = format!("{}.{}", tool_time.as_secs(), tool_time.subsec_millis());
+                let tool_response = ToolHookInput::tool_response(&invoke_result);
+                if let Some(recorder) = &self.recorder {
+                    recorder.record(RecordedEvent::ToolResult {
+                        tool_use_id: tool.id.clone(),
+                        response: tool_response.clone(),
+                    });
+                }
+                match invoke_result {
+                    Ok(result) => {
+                        match result.output {
+                            OutputKind::Text(ref text) => {
+                                debug!("Output is Text: {}", text);
+                            },
+                            OutputKind::Json(ref json) => {
+                                debug!("Output is JSON: {}", json);
+                            },
+                            OutputKind::Images(ref image) => {
+                                image_blocks.extend(image.clone());
+                            },
+                        }
 
-        let prompt = format!(
-            "{}{}{}{}{}",
-            "Would you like to open the AWS console to upgrade? [".dark_grey(),
-            "y".green(),
-            "/".dark_grey(),
-            "n".green(),
-            "]: ".dark_grey(),
-        );
+                        debug!("tool result output: {:#?}", result);
+                        execute!(
+                            self.output,
+                            style::Print(CONTINUATION_LINE),
+                            style::Print("\n"),
+                            style::SetForegroundColor(Color::Green),
+                            style::SetAttribute(Attribute::Bold),
+                            style::Print(format!(" ● Completed in {}s", tool_time)),
+                            style::SetForegroundColor(Color::Reset),
+                            style::Print("\n"),
+                        )?;
 
-        let user_input = self.read_user_input(&prompt, true);
-        queue!(self.output, style::SetForegroundColor(Color::Reset), style::Print("\n"),)?;
+                        tool_telemetry = tool_telemetry.and_modify(|ev| ev.is_success = Some(true));
+                        if let Tool::Custom(_) = &tool.tool {
+                            tool_telemetry.and_modify(|ev| {
+                                ev.output_token_size = Some(TokenCounter::count_tokens(result.as_str()));
+                            });
+                        }
+                        tool_results.push(ToolUseResult {
+                            tool_use_id: tool.id,
+                            content: vec![result.into()],
+                            status: ToolResultStatus::Success,
+                        });
+                    },
+                    Err(err) => {
+                        error!(?err, "An error occurred processing the tool");
+                        execute!(
+                            self.output,
+                            style::Print(CONTINUATION_LINE),
+                            style::Print("\n"),
+                            style::SetAttribute(Attribute::Bold),
+                            style::SetForegroundColor(Color::Red),
+                            style::Print(format!(" ● Execution failed after {}s:\n", tool_time)),
+                            style::SetAttribute(Attribute::Reset),
+                            style::SetForegroundColor(Color::Red),
+                            style::Print(&err),
+                            style::SetAttribute(Attribute::Reset),
+                            style::Print("\n\n"),
+                        )?;
 
-        if !user_input.is_some_and(|i| ["y", "Y"].contains(&i.as_str())) {
-            execute!(
-                self.output,
-                style::SetForegroundColor(Color::Red),
-                style::Print("Upgrade cancelled.\n\n"),
-                style::SetForegroundColor(Color::Reset),
-            )?;
-            return Ok(());
-        }
+                        tool_telemetry.and_modify(|ev| ev.is_success = Some(false));
+                        tool_results.push(ToolUseResult {
+                            tool_use_id: tool.id,
+                            content: vec![ToolUseResultBlock::Text(format!(
+                                "An error occurred processing the tool: \n{}",
+                                &err
+                            ))],
+                            status: ToolResultStatus::Error,
+                        });
+                        if let ToolUseStatus::Idle = self.tool_use_status {
+                            self.tool_use_status = ToolUseStatus::RetryInProgress(
+                                self.conversation_state
+                                    .message_id()
+                                    .map_or("No utterance id found".to_string(), |v| v.to_string()),
+                            );
+                        }
+                    },
+                }
 
-        // Create a subscription token and open the webpage
-        let url = with_spinner(
-            self.interactive,
-            &mut self.output,
-            "Preparing to upgrade...",
-            || async {
-                let r = Client::new(database, None).await?.create_subscription_token().await?;
-                Ok::<String, ChatError>(r.encoded_verification_url().to_string())
-            },
-        )
-        .await?;
+                let post_hook_outcome = self
+                    .run_tool_hooks(ToolHookInput {
+                        hook_event: HookTrigger::PostToolUse,
+                        tool_name: tool.name,
+                        tool_input: tool.input,
+                        tool_response: Some(tool_response),
+                    })
+                    .await;
+                let feedback = (tool.pre_hook_outcome.feedback.into_iter())
+                    .chain(post_hook_outcome.feedback)
+                    .collect::<Vec<_>>();
+                if let (false, Some(result)) = (feedback.is_empty(), tool_results.last_mut()) {
+                    result.content.push(ToolUseResultBlock::Text(format!(
+                        "Feedback from the user's tool use hooks:\n{}",
+                        feedback.join("\n")
+                    )));
+                }
+            }
+        }
+        for tool_result in &tool_results {
+            self.events.emit(tool_result.into())?;
+        }
 
-        // Skip opening the URL in test environments
-        if cfg!(test) || is_remote() {
-            // In tests or remote environments, just display the URL without trying to open it
-            queue!(
-                self.output,
-                style::SetForegroundColor(Color::DarkGrey),
-                style::Print(format!("{} Subscription URL: {}\n\n", "?".magenta(), url.blue())),
-                style::SetForegroundColor(Color::Reset),
-            )?;
-        } else if crate::util::open::open_url_async(&url).await.is_err() {
-            // Only show error message if we actually tried to open the URL
-            queue!(
+        if !image_blocks.is_empty() {
+            let images = image_blocks.into_iter().map(|(block, _)| block).collect();
+            self.conversation_state
+                .add_tool_results_with_images(tool_results, images);
+            execute!(
                 self.output,
-                style::SetForegroundColor(Color::DarkGrey),
-                style::Print(format!(
-                    "{} Having issues opening the AWS console? Try copy and pasting the URL > {}\n\n",
-                    "?".magenta(),
-                    url.blue()
-                )),
+                style::SetAttribute(Attribute::Reset),
                 style::SetForegroundColor(Color::Reset),
+                style::Print("\n")
             )?;
+        } else {
+            self.conversation_state.add_tool_results(tool_results);
         }
+        self.send_tool_use_telemetry(telemetry).await;
 
-        execute!(
-            self.output,
-            style::Print("Once upgraded, type a new prompt to continue your work, or type /quit to exit the chat.\n\n")
-        )?;
-
-        Ok(())
-    }
-
-    /// Apply program context to tools that Q may not have.
-    // We cannot attach this any other way because Tools are constructed by deserializing
-    // output from Amazon Q.
-    // TODO: Is there a better way?
-    fn contextualize_tool(&self, tool: &mut Tool) {
-        #[allow(clippy::single_match)]
-        match tool {
-            Tool::GhIssue(gh_issue) => {
-                gh_issue.set_context(GhIssueContext {
-                    // Ideally we avoid cloning, but this function is not called very often.
-                    // Using references with lifetimes requires a large refactor, and Arc<Mutex<T>>
-                    // seems like overkill and may incur some performance cost anyway.
-                    context_manager: self.conversation_state.context_manager.clone(),
-                    transcript: self.conversation_state.transcript.clone(),
-                    failed_request_ids: self.failed_request_ids.clone(),
-                    tool_permissions: self.tool_permissions.permissions.clone(),
-                    interactive: self.interactive,
+        // Long running tool loops can outgrow the context window without ever prompting the user.
+        if self.conversation_state.can_create_summary_request().await {
+            if let Some(turns) = self.auto_compact_if_needed().await? {
+                return Ok(ChatState::CompactHistory {
+                    tool_uses: None,
+                    pending_tool_index: None,
+                    prompt: None,
+                    show_summary: false,
+                    help: false,
+                    turns: Some(turns),
+                    automatic: true,
                 });
-            },
-            _ => (),
-        };
-    }
-
-    async fn print_tool_descriptions(&mut self, tool_use: &QueuedTool, trusted: bool) -> Result<(), ChatError> {
-        queue!(
-            self.output,
-            style::SetForegroundColor(Color::Magenta),
-            style::Print(format!(
-                "🛠️  Using tool: {}{}",
-                tool_use.tool.display_name(),
-                if trusted { " (trusted)".dark_green() } else { "".reset() }
-            )),
-            style::SetForegroundColor(Color::Reset)
-        )?;
-        if let Tool::Custom(ref tool) = tool_use.tool {
-            queue!(
-                self.output,
-                style::SetForegroundColor(Color::Reset),
-                style::Print(" from mcp server "),
-                style::SetForegroundColor(Color::Magenta),
-                style::Print(tool.client.get_server_name()),
-                style::SetForegroundColor(Color::Reset),
-            )?;
+            }
         }
-        queue!(self.output, style::Print("\n"), style::Print(CONTINUATION_LINE))?;
-        queue!(self.output, style::Print("\n"))?;
-        queue!(self.output, style::Print(TOOL_BULLET))?;
-
-        self.output.flush()?;
 
-        tool_use
-            .tool
-            .queue_description(&self.ctx, &mut self.output)
-            .await
-            .map_err(|e| ChatError::Custom(format!("failed to print tool, `{}`: {}", tool_use.name, e).into()))?;
+        if self.interactive {
+            execute!(self.output, cursor::Hide)?;
+            execute!(self.output, style::Print("\n"), style::SetAttribute(Attribute::Reset))?;
+            self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_string()));
+        }
 
-        Ok(())
+        return Ok(ChatState::HandleResponseStream(self.send_conversation_state().await?));
     }
 
-    /// Helper function to read user input with a prompt and Ctrl+C handling
-    fn read_user_input(&mut self, prompt: &str, exit_on_single_ctrl_c: bool) -> Option<String> {
-        let mut ctrl_c = false;
-        loop {
-            match (self.input_source.read_line(Some(prompt)), ctrl_c) {
-                (Ok(Some(line)), _) => {
-                    if line.trim().is_empty() {
-                        continue; // Reprompt if the input is empty
-                    }
-                    return Some(line);
+    /// Splits the tool uses of a response into batches that run one after the other. A batch holds
+    /// either a single tool, or consecutive tools that can run concurrently, see
+    /// [Self::concurrency]: read-only tools, or file writes to different files.
+    fn batch_tool_uses(&self, tool_uses: Vec<QueuedTool>) -> Vec<Vec<QueuedTool>> {
+        let mut batches: Vec<Vec<QueuedTool>> = Vec::new();
+        let mut written_paths = HashSet::new();
+        let mut last_concurrency = ToolConcurrency::Exclusive;
+        for tool in tool_uses {
+            let paths = match &tool.tool {
+                Tool::FsWrite(fs_write) => fs_write.paths(&self.ctx).ok(),
+                _ => Some(Vec::new()),
+            };
+            // A write whose paths are unknown could conflict with any other, so it runs alone.
+            let concurrency = match paths {
+                Some(_) => self.concurrency(&tool),
+                None => ToolConcurrency::Exclusive,
+            };
+            let paths = paths.unwrap_or_default();
+            let joins_batch = match (last_concurrency, concurrency) {
+                (ToolConcurrency::ReadOnly, ToolConcurrency::ReadOnly) => true,
+                (ToolConcurrency::Write, ToolConcurrency::Write) => {
+                    paths.iter().all(|path| !written_paths.contains(path))
                 },
-                (Ok(None), false) => {
-                    if exit_on_single_ctrl_c {
-                        return None;
-                    }
-                    execute!(
-                        self.output,
-                        style::Print(format!(
-                            "\n(To exit the CLI, press Ctrl+C or Ctrl+D again or type {})\n\n",
-                            "/quit".green()
-                        ))
-                    )
-                    .unwrap_or_default();
-                    ctrl_c = true;
+                _ => false,
+            };
+            match batches.last_mut() {
+                Some(batch) if joins_batch => batch.push(tool),
+                _ => {
+                    written_paths.clear();
+                    batches.push(vec![tool]);
                 },
-                (Ok(None), true) => return None, // Exit if Ctrl+C was pressed twice
-                (Err(_), _) => return None,
             }
+            written_paths.extend(paths);
+            last_concurrency = concurrency;
         }
+        batches
     }
 
-    /// Helper function to generate a prompt based on the current context
-    fn generate_tool_trust_prompt(&self) -> String {
-        prompt::generate_prompt(self.conversation_state.current_profile(), self.all_tools_trusted())
-    }
-
-    async fn send_tool_use_telemetry(&mut self, telemetry: &TelemetryThread) {
-        for (_, mut event) in self.tool_use_telemetry_events.drain() {
-            event.user_input_id = match self.tool_use_status {
-                ToolUseStatus::Idle => self.conversation_state.message_id(),
-                ToolUseStatus::RetryInProgress(ref id) => Some(id.as_str()),
-            }
-            .map(|v| v.to_string());
-
-            telemetry.send_tool_use_suggested(event).ok();
+    /// Which tools `tool` may run alongside. Only tools that didn't need the user's approval run
+    /// concurrently, and only when they can't affect each other: tools that only read, or file
+    /// writes. Shell commands and delegated tasks can do anything, so they always run on their own,
+    /// as do custom tools their server marks as modifying their environment. Other custom tools
+    /// are trusted, or they would have needed approval, and run alongside read-only tools.
+    fn concurrency(&self, tool: &QueuedTool) -> ToolConcurrency {
+        let allowed = tool.pre_hook_outcome.denied.is_none()
+            && self
+                .tool_permissions
+                .allows_without_asking(&tool.name, &tool.tool, &self.ctx);
+        match &tool.tool {
+            _ if !allowed => ToolConcurrency::Exclusive,
+            Tool::FsRead(_) | Tool::WorkspaceSearch(_) | Tool::Thinking(_) => ToolConcurrency::ReadOnly,
+            #[cfg(feature = "knowledge")]
+            Tool::KnowledgeSearch(_) => ToolConcurrency::ReadOnly,
+            Tool::FsWrite(_) => ToolConcurrency::Write,
+            Tool::Custom(_) => {
+                let read_only = self
+                    .conversation_state
+                    .tool_manager
+                    .schema
+                    .get(&tool.name)
+                    .and_then(|spec| spec.annotations.as_ref())
+                    .and_then(|annotations| annotations.read_only_hint);
+                match read_only {
+                    Some(false) => ToolConcurrency::Exclusive,
+                    Some(true) | None => ToolConcurrency::ReadOnly,
+                }
+            },
+            Tool::ExecuteCommand(_) | Tool::UseAws(_) | Tool::GhIssue(_) | Tool::Delegate(_) => {
+                ToolConcurrency::Exclusive
+            },
         }
     }
 
-    fn terminal_width(&self) -> usize {
-        (self.terminal_width_provider)().unwrap_or(80)
-    }
-
-    fn all_tools_trusted(&self) -> bool {
-        self.conversation_state.tools.values().flatten().all(|t| match t {
-            FigTool::ToolSpecification(t) => self.tool_permissions.is_trusted(&t.name),
-        })
-    }
-
-    /// Display character limit warnings based on current conversation size
-    async fn display_char_warnings(&mut self) -> Result<(), std::io::Error> {
-        let warning_level = self.conversation_state.get_token_warning_level().await;
-
-        match warning_level {
-            TokenWarningLevel::Critical => {
-                // Memory constraint warning with gentler wording
-                execute!(
-                    self.output,
-                    style::SetForegroundColor(Color::Yellow),
-                    style::SetAttribute(Attribute::Bold),
-                    style::Print("\n⚠️ This conversation is getting lengthy.\n"),
-                    style::SetAttribute(Attribute::Reset),
-                    style::Print(
-                        "To ensure continued smooth operation, please use /compact to summarize the conversation.\n\n"
-                    ),
-                    style::SetForegroundColor(Color::Reset)
-                )?;
-            },
-            TokenWarningLevel::None => {
-                // No warning needed
-            },
+    /// Invokes a batch of tools, concurrently when there is more than one. The output of concurrent
+    /// tools is printed as it comes, line by line, with each line prefixed by the tool's position
+    /// in the batch and name.
+    async fn invoke_tools(&mut self, tools: Vec<&QueuedTool>) -> Vec<ToolInvocation> {
+        if let Some(replay) = self.replay.as_mut() {
+            return tools
+                .into_iter()
+                .map(|tool| ToolInvocation {
+                    result: replay.take_tool_result(&tool.id),
+                    duration: Duration::ZERO,
+                })
+                .collect();
         }
 
-        Ok(())
-    }
+        if let [tool] = tools[..] {
+            let start = std::time::Instant::now();
+            let result = tool.tool.invoke(&self.ctx, &mut self.output).await;
+            return vec![ToolInvocation {
+                result,
+                duration: start.elapsed(),
+            }];
+        }
 
-    async fn send_chat_telemetry(
-        &self,
-        database: &Database,
-        telemetry: &TelemetryThread,
-        request_id: Option<String>,
-        result: TelemetryResult,
-        reason: Option<String>,
-        reason_desc: Option<String>,
-    ) {
-        telemetry
-            .send_chat_added_message(
-                database,
-                self.conversation_state.conversation_id().to_owned(),
-                self.conversation_state.message_id().map(|s| s.to_owned()),
-                request_id,
-                self.conversation_state.context_message_length(),
+        let ctx = &self.ctx;
+        let output = &self.output;
+        futures::future::join_all(tools.into_iter().enumerate().map(|(i, tool)| async move {
+            let prefix = format!("[{} {}] ", i + 1, tool.tool.display_name());
+            let mut output = PrefixedWriter::new(output.clone(), prefix);
+            let start = std::time::Instant::now();
+            let result = tool.tool.invoke(ctx, &mut output).await;
+            ToolInvocation {
                 result,
-                reason,
-                reason_desc,
-                self.conversation_state.model.clone(),
-            )
-            .await
-            .ok();
+                duration: start.elapsed(),
+            }
+        }))
+        .await
     }
 
-    async fn send_error_telemetry(
-        &self,
-        database: &Database,
+    async fn handle_response(
+        &mut self,
+        database: &mut Database,
         telemetry: &TelemetryThread,
-        reason: String,
-        reason_desc: Option<String>,
-    ) {
-        telemetry
-            .send_response_error(
-                database,
-                self.conversation_state.conversation_id().to_owned(),
-                self.conversation_state.context_message_length(),
-                TelemetryResult::Failed,
-                Some(reason),
-                reason_desc,
-            )
-            .await
-            .ok();
-    }
-}
+        response: SendMessageOutput,
+    ) -> Result<ChatState, ChatError> {
+        let request_id = response.request_id().map(|s| s.to_string());
+        let mut buf = String::new();
+        let mut offset = 0;
+        let mut ended = false;
+        let mut parser = ResponseParser::new(response).with_recorder(self.recorder.clone());
+        let mut state = ParseState::new(Some(self.terminal_width()));
 
-/// Display a spinner + text while waiting for an async function to finish.
-async fn with_spinner<T, E, F, Fut>(
-    interactive: bool,
-    output: &mut impl std::io::Write,
-    spinner_text: &str,
-    f: F,
-) -> Result<T, E>
-where
-    F: FnOnce() -> Fut,
-    Fut: std::future::Future<Output = Result<T, E>>,
-{
-    let spinner = if interactive {
-        let _ = queue!(output, cursor::Hide,);
-        Some(Spinner::new(Spinners::Dots, spinner_text.to_owned()))
-    } else {
-        None
-    };
+        let mut tool_uses = Vec::new();
+        let mut tool_name_being_recvd: Option<String> = None;
 
-    let result = f().await;
+        if self.interactive && self.spinner.is_some() {
+            drop(self.spinner.take());
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Reset),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+                cursor::MoveToColumn(0),
+                cursor::Show,
+                cursor::MoveUp(1),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+            )?;
+        }
 
-    if let Some(mut s) = spinner {
-        s.stop();
-        let _ = queue!(
-            output,
-            terminal::Clear(terminal::ClearType::CurrentLine),
-            cursor::MoveToColumn(0),
-        );
-    }
+        loop {
+            match parser.recv().await {
+                Ok(msg_event) => {
+                    trace!("Consumed: {:?}", msg_event);
+                    match msg_event {
+                        parser::ResponseEvent::ToolUseStart { name } => {
+                            // We need to flush the buffer here, otherwise text will not be
+                            // printed while we are receiving tool use events.
+                            buf.push('\n');
+                            tool_name_being_recvd = Some(name);
+                        },
+                        parser::ResponseEvent::AssistantText(text) => {
+                            buf.push_str(&text);
+                            self.events.emit(ChatEvent::AssistantText { text })?;
+                        },
+                        parser::ResponseEvent::ToolUse(tool_use) => {
+                            self.events.emit(ChatEvent::ToolUse {
+                                id: tool_use.id.clone(),
+                                name: tool_use.name.clone(),
+                                input: tool_use.args.clone(),
+                            })?;
+                            if self.interactive && self.spinner.is_some() {
+                                drop(self.spinner.take());
+                                queue!(
+                                    self.output,
+                                    terminal::Clear(terminal::ClearType::CurrentLine),
+                                    cursor::MoveToColumn(0),
+                                    cursor::Show
+                                )?;
+                            }
+                            tool_uses.push(tool_use);
+                            tool_name_being_recvd = None;
+                        },
+                        parser::ResponseEvent::EndStream { message } => {
+                            // This log is attempting to help debug instances where users encounter
+                            // the response timeout message.
+                            if message.content() == RESPONSE_TIMEOUT_CONTENT {
+                                error!(?request_id, ?message, "Encountered an unexpected model response");
+                            }
+                            self.conversation_state.push_assistant_message(message, database);
+                            ended = true;
+                        },
+                    }
+                },
+                Err(recv_error) => {
+                    if let Some(request_id) = &recv_error.request_id {
+                        self.failed_request_ids.push(request_id.clone());
+                    };
 
-    result
-}
+                    let (reason, reason_desc) = get_error_reason(&recv_error);
+                    self.send_chat_telemetry(
+                        database,
+                        telemetry,
+                        recv_error.request_id.clone(),
+                        TelemetryResult::Failed,
+                        Some(reason),
+                        Some(reason_desc),
+                    )
+                    .await;
 
-/// Prints hook configuration grouped by trigger: conversation session start or per user message
-fn print_hook_section(output: &mut impl Write, hooks: &HashMap<String, Hook>, trigger: HookTrigger) -> Result<()> {
-    let section = match trigger {
-        HookTrigger::ConversationStart => "On Session Start",
-        HookTrigger::PerPrompt => "Per User Message",
-    };
-    let hooks: Vec<(&String, &Hook)> = hooks.iter().filter(|(_, h)| h.trigger == trigger).collect();
+                    match recv_error.source {
+                        RecvErrorKind::StreamTimeout { source, duration } => {
+                            error!(
+                                recv_error.request_id,
+                                ?source,
+                                "Encountered a stream timeout after waiting for {}s",
+                                duration.as_secs()
+                            );
+                            if self.interactive {
+                                execute!(self.output, cursor::Hide)?;
+                                self.spinner =
+                                    Some(Spinner::new(Spinners::Dots, "Dividing up the work...".to_string()));
+                            }
+                            // For stream timeouts, we'll tell the model to try and split its response into
+                            // smaller chunks.
+                            self.conversation_state.push_assistant_message(
+                                AssistantMessage::new_response(None, RESPONSE_TIMEOUT_CONTENT.to_string()),
+                                database,
+                            );
+                            self.conversation_state
+                                .set_next_user_message(
+                                    "You took too long to respond - try to split up the work into smaller steps."
+                                        .to_string(),
+                                )
+                                .await;
+                            self.send_tool_use_telemetry(telemetry).await;
+                            return Ok(ChatState::HandleResponseStream(self.send_conversation_state().await?));
+                        },
+                        RecvErrorKind::UnexpectedToolUseEos {
+                            tool_use_id,
+                            name,
+                            message,
+                            ..
+                        } => {
+                            error!(
+                                recv_error.request_id,
+                                tool_use_id, name, "The response stream ended before the entire tool use was received"
+                            );
+                            self.conversation_state.push_assistant_message(*message, database);
+                            let tool_results = vec![ToolUseResult {
+                                    tool_use_id,
+                                    content: vec![ToolUseResultBlock::Text(
+                                        "The generated tool was too large, try again but this time split up the work between multiple tool uses".to_string(),
+                                    )],
+                                    status: ToolResultStatus::Error,
+                                }];
+                            self.conversation_state.add_tool_results(tool_results);
+                            self.send_tool_use_telemetry(telemetry).await;
+                            return Ok(ChatState::HandleResponseStream(self.send_conversation_state().await?));
+                        },
+                        _ => return Err(recv_error.into()),
+                    }
+                },
+            }
 
-    queue!(
-        output,
-        style::SetForegroundColor(Color::Cyan),
-        style::Print(format!("    {section}:\n")),
-        style::SetForegroundColor(Color::Reset),
-    )?;
+            // Fix for the markdown parser copied over from q chat:
+            // this is a hack since otherwise the parser might report Incomplete with useful data
+            // still left in the buffer. I'm not sure how this is intended to be handled.
+            if ended {
+                buf.push('\n');
+            }
 
-    if hooks.is_empty() {
-        queue!(
-            output,
-            style::SetForegroundColor(Color::DarkGrey),
-            style::Print("      <none>\n"),
-            style::SetForegroundColor(Color::Reset)
-        )?;
-    } else {
-        for (name, hook) in hooks {
-            if hook.disabled {
+            if tool_name_being_recvd.is_none() && !buf.is_empty() && self.interactive && self.spinner.is_some() {
+                drop(self.spinner.take());
                 queue!(
-                    output,
-                    style::SetForegroundColor(Color::DarkGrey),
-                    style::Print(format!("      {} (disabled)\n", name)),
-                    style::SetForegroundColor(Color::Reset)
+                    self.output,
+                    terminal::Clear(terminal::ClearType::CurrentLine),
+                    cursor::MoveToColumn(0),
+                    cursor::Show
                 )?;
-            } else {
-                queue!(output, style::Print(format!("      {}\n", name)),)?;
             }
-        }
-    }
-    Ok(())
-}
 
-/// Testing helper
-fn split_tool_use_event(value: &Map<String, serde_json::Value>) -> Vec<ChatResponseStream> {
-    let tool_use_id = value.get("tool_use_id").unwrap().as_str().unwrap().to_string();
-    let name = value.get("name").unwrap().as_str().unwrap().to_string();
-    let args_str = value.get("args").unwrap().to_string();
-    let split_point = args_str.len() / 2;
-    vec![
-        ChatResponseStream::ToolUseEvent {
-            tool_use_id: tool_use_id.clone(),
-            name: name.clone(),
-            input: None,
-            stop: None,
-        },
-        ChatResponseStream::ToolUseEvent {
-            tool_use_id: tool_use_id.clone(),
-            name: name.clone(),
-            input: Some(args_str.split_at(split_point).0.to_string()),
-            stop: None,
-        },
-        ChatResponseStream::ToolUseEvent {
-            tool_use_id: tool_use_id.clone(),
-            name: name.clone(),
-            input: Some(args_str.split_at(split_point).1.to_string()),
-            stop: None,
-        },
-        ChatResponseStream::ToolUseEvent {
-            tool_use_id: tool_use_id.clone(),
-            name: name.clone(),
-            input: None,
-            stop: Some(true),
-        },
-    ]
-}
+            // Print the response for normal cases
+            loop {
+                let input = Partial::new(&buf[offset..]);
+                match interpret_markdown(input, &mut self.output, &mut state) {
+                    Ok(parsed) => {
+                        offset += parsed.offset_from(&input);
+                        self.output.flush()?;
+                        state.newline = state.set_newline;
+                        state.set_newline = false;
+                    },
+                    Err(err) => match err.into_inner() {
+                        Some(err) => return Err(ChatError::Custom(err.to_string().into())),
+                        None => break, // Data was incomplete
+                    },
+                }
 
-/// Testing helper
-fn create_stream(model_responses: serde_json::Value) -> StreamingClient {
-    let mut mock = Vec::new();
-    for response in model_responses.as_array().unwrap() {
-        let mut stream = Vec::new();
-        for event in response.as_array().unwrap() {
-            match event {
-                serde_json::Value::String(assistant_text) => {
-                    stream.push(ChatResponseStream::AssistantResponseEvent {
-                        content: assistant_text.to_string(),
-                    });
-                },
-                serde_json::Value::Object(tool_use) => {
-                    stream.append(&mut split_tool_use_event(tool_use));
-                },
-                other => panic!("Unexpected value: {:?}", other),
+                // TODO: We should buffer output based on how much we have to parse, not as a constant
+                // Do not remove unless you are nabochay :)
+                tokio::time::sleep(Duration::from_millis(8)).await;
             }
-        }
-        mock.push(stream);
-    }
-    StreamingClient::mock(mock)
-}
 
-/// Replaces amzn_codewhisperer_client::types::SubscriptionStatus with a more descriptive type.
-/// See response expectations in [`get_subscription_status`] for reasoning.
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum ActualSubscriptionStatus {
-    Active,   // User has paid for this month
-    Expiring, // User has paid for this month but cancelled
-    None,     // User has not paid for this month
-}
+            // Set spinner after showing all of the assistant text content so far.
+            if let (Some(_name), true) = (&tool_name_being_recvd, self.interactive) {
+                queue!(self.output, cursor::Hide)?;
+                self.spinner = Some(Spinner::new(Spinners::Dots, "Thinking...".to_string()));
+            }
+
+            if ended {
+                finish_markdown(&mut self.output, &mut state)?;
+                self.send_chat_telemetry(database, telemetry, request_id, TelemetryResult::Succeeded, None, None)
+                    .await;
+
+                if self.events.is_enabled() {
+                    let size = self
+                        .conversation_state
+                        .backend_conversation_state(false, true)
+                        .await
+                        .calculate_conversation_size();
+                    self.events.emit(ChatEvent::Usage {
+                        context_tokens: size.context_messages.value(),
+                        user_tokens: size.user_messages.value(),
+                        assistant_tokens: size.assistant_messages.value(),
+                        total_tokens: size.total().value(),
+                        context_window_tokens: CONTEXT_WINDOW_SIZE,
+                    })?;
+                }
+
+                if self.interactive
+                    && database
+                        .settings
+                        .get_bool(Setting::ChatEnableNotifications)
+                        .unwrap_or(false)
+                {
+                    // For final responses (no tools suggested), always play the bell
+                    play_notification_bell(tool_uses.is_empty());
+                }
+
+                if self.interactive {
+                    queue!(self.output, style::ResetColor, style::SetAttribute(Attribute::Reset))?;
+                    execute!(self.output, style::Print("\n"))?;
+
+                    for (i, citation) in &state.citations {
+                        queue!(
+                            self.output,
+                            style::Print("\n"),
+                            style::SetForegroundColor(Color::Blue),
+                            style::Print(format!("[^{i}]: ")),
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!("{citation}\n")),
+                            style::SetForegroundColor(Color::Reset)
+                        )?;
+                    }
+                }
+
+                break;
+            }
+        }
+
+        if !tool_uses.is_empty() {
+            Ok(ChatState::ValidateTools(tool_uses))
+        } else {
+            Ok(ChatState::PromptUser {
+                tool_uses: None,
+                pending_tool_index: None,
+                skip_printing_tools: false,
+            })
+        }
+    }
+
+    async fn validate_tools(
+        &mut self,
+        telemetry: &TelemetryThread,
+        tool_uses: Vec<AssistantToolUse>,
+    ) -> Result<ChatState, ChatError> {
+        let conv_id = self.conversation_state.conversation_id().to_owned();
+        debug!(?tool_uses, "Validating tool uses");
+        let mut queued_tools: Vec<QueuedTool> = Vec::new();
+        let mut tool_results: Vec<ToolUseResult> = Vec::new();
+
+        for mut tool_use in tool_uses {
+            let tool_use_id = tool_use.id.clone();
+            let tool_use_name = tool_use.name.clone();
+
+            // Pre tool use hooks may rewrite the input before it is parsed.
+            let mut pre_hook_outcome = self
+                .run_tool_hooks(ToolHookInput {
+                    hook_event: HookTrigger::PreToolUse,
+                    tool_name: tool_use.name.clone(),
+                    tool_input: tool_use.args.clone(),
+                    tool_response: None,
+                })
+                .await;
+            if let Some(tool_input) = pre_hook_outcome.tool_input.take() {
+                tool_use.args = tool_input;
+            }
+            let tool_input = tool_use.args.clone();
+
+            let mut tool_telemetry = ToolUseEventBuilder::new(
+                conv_id.clone(),
+                tool_use.id.clone(),
+                self.conversation_state.model.clone(),
+            )
+            .set_tool_use_id(tool_use_id.clone())
+            .set_tool_name(tool_use.name.clone())
+            .utterance_id(self.conversation_state.message_id().map(|s| s.to_string()));
+            match self.conversation_state.tool_manager.get_tool_from_tool_use(tool_use) {
+                Ok(mut tool) => {
+                    // Apply non-Q-generated context to tools
+                    self.contextualize_tool(&mut tool);
+
+                    match tool.validate(&self.ctx).await {
+                        Ok(()) => {
+                            tool_telemetry.is_valid = Some(true);
+                            queued_tools.push(QueuedTool {
+                                id: tool_use_id.clone(),
+                                name: tool_use_name,
+                                tool,
+                                accepted: false,
+                                input: tool_input,
+                                pre_hook_outcome,
+                            });
+                        },
+                        Err(err) => {
+                            tool_telemetry.is_valid = Some(false);
+                            tool_results.push(ToolUseResult {
+                                tool_use_id: tool_use_id.clone(),
+                                content: vec![ToolUseResultBlock::Text(format!(
+                                    "Failed to validate tool parameters: {err}"
+                                ))],
+                                status: ToolResultStatus::Error,
+                            });
+                        },
+                    };
+                },
+                Err(err) => {
+                    tool_telemetry.is_valid = Some(false);
+                    tool_results.push(err.into());
+                },
+            }
+            self.tool_use_telemetry_events.insert(tool_use_id, tool_telemetry);
+        }
+
+        // If we have any validation errors, then return them immediately to the model.
+        if !tool_results.is_empty() {
+            debug!(?tool_results, "Error found in the model tools");
+            queue!(
+                self.output,
+                style::SetAttribute(Attribute::Bold),
+                style::Print("Tool validation failed: "),
+                style::SetAttribute(Attribute::Reset),
+            )?;
+            for tool_result in &tool_results {
+                for block in &tool_result.content {
+                    let content: Option<Cow<'_, str>> = match block {
+                        ToolUseResultBlock::Text(t) => Some(t.as_str().into()),
+                        ToolUseResultBlock::Json(d) => serde_json::to_string(d)
+                            .map_err(|err| error!(?err, "failed to serialize tool result content"))
+                            .map(Into::into)
+                            .ok(),
+                    };
+                    if let Some(content) = content {
+                        queue!(
+                            self.output,
+                            style::Print("\n"),
+                            style::SetForegroundColor(Color::Red),
+                            style::Print(format!("{}\n", content)),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    }
+                }
+            }
+            for tool_result in &tool_results {
+                self.events.emit(tool_result.into())?;
+            }
+            self.conversation_state.add_tool_results(tool_results);
+            self.send_tool_use_telemetry(telemetry).await;
+            if let ToolUseStatus::Idle = self.tool_use_status {
+                self.tool_use_status = ToolUseStatus::RetryInProgress(
+                    self.conversation_state
+                        .message_id()
+                        .map_or("No utterance id found".to_string(), |v| v.to_string()),
+                );
+            }
+
+            return Ok(ChatState::HandleResponseStream(self.send_conversation_state().await?));
+        }
+
+        Ok(ChatState::ExecuteTools(queued_tools))
+    }
+
+    async fn upgrade_to_pro(&mut self, database: &mut Database) -> Result<(), ChatError> {
+        queue!(self.output, style::Print("\n"),)?;
+
+        // Get current subscription status
+        match get_subscription_status_with_spinner(self.interactive, &mut self.output, database).await {
+            Ok(status) => {
+                if status == ActualSubscriptionStatus::Active {
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print("Your Builder ID already has a Q Developer Pro subscription.\n\n"),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                    return Ok(());
+                }
+            },
+            Err(e) => {
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Red),
+                    style::Print(format!("{}\n\n", e)),
+                    style::SetForegroundColor(Color::Reset),
+                )?;
+                // Don't exit early here, the check isn't required to subscribe.
+            },
+        }
+
+        // Upgrade information
+        queue!(
+            self.output,
+            style::Print(SUBSCRIBE_TITLE_TEXT),
+            style::SetForegroundColor(Color::Grey),
+            style::Print(format!("\n\n{}\n\n", SUBSCRIBE_TEXT)),
+            style::SetForegroundColor(Color::Reset),
+            cursor::Show
+        )?;
+
+        let prompt = format!(
+            "{}{}{}{}{}",
+            "Would you like to open the AWS console to upgrade? [".dark_grey(),
+            "y".green(),
+            "/".dark_grey(),
+            "n".green(),
+            "]: ".dark_grey(),
+        );
+
+        let user_input = self.read_user_input(&prompt, true);
+        queue!(self.output, style::SetForegroundColor(Color::Reset), style::Print("\n"),)?;
+
+        if !user_input.is_some_and(|i| ["y", "Y"].contains(&i.as_str())) {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::Red),
+                style::Print("Upgrade cancelled.\n\n"),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+            return Ok(());
+        }
+
+        // Create a subscription token and open the webpage
+        let url = with_spinner(
+            self.interactive,
+            &mut self.output,
+            "Preparing to upgrade...",
+            || async {
+                let r = Client::new(database, None).await?.create_subscription_token().await?;
+                Ok::<String, ChatError>(r.encoded_verification_url().to_string())
+            },
+        )
+        .await?;
+
+        // Skip opening the URL in test environments
+        if cfg!(test) || is_remote() {
+            // In tests or remote environments, just display the URL without trying to open it
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!("{} Subscription URL: {}\n\n", "?".magenta(), url.blue())),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        } else if crate::util::open::open_url_async(&url).await.is_err() {
+            // Only show error message if we actually tried to open the URL
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!(
+                    "{} Having issues opening the AWS console? Try copy and pasting the URL > {}\n\n",
+                    "?".magenta(),
+                    url.blue()
+                )),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+
+        execute!(
+            self.output,
+            style::Print("Once upgraded, type a new prompt to continue your work, or type /quit to exit the chat.\n\n")
+        )?;
+
+        Ok(())
+    }
+
+    /// Apply program context to tools that Q may not have.
+    // We cannot attach this any other way because Tools are constructed by deserializing
+    // output from Amazon Q.
+    // TODO: Is there a better way?
+    fn contextualize_tool(&self, tool: &mut Tool) {
+        match tool {
+            Tool::GhIssue(gh_issue) => {
+                gh_issue.set_context(GhIssueContext {
+                    // Ideally we avoid cloning, but this function is not called very often.
+                    // Using references with lifetimes requires a large refactor, and Arc<Mutex<T>>
+                    // seems like overkill and may incur some performance cost anyway.
+                    context_manager: self.conversation_state.context_manager.clone(),
+                    transcript: self.conversation_state.transcript.clone(),
+                    failed_request_ids: self.failed_request_ids.clone(),
+                    tool_permissions: self.tool_permissions.permissions.clone(),
+                    interactive: self.interactive,
+                });
+            },
+            Tool::ExecuteCommand(execute_command) => {
+                execute_command.sandbox = self
+                    .conversation_state
+                    .context_manager
+                    .as_ref()
+                    .map(|cm| cm.sandbox_mode())
+                    .unwrap_or_default();
+            },
+            Tool::Delegate(delegate) => {
+                delegate.context = Some(DelegateContext {
+                    ctx: Arc::clone(&self.ctx),
+                    client: self.client.clone(),
+                    tool_manager: self.conversation_state.tool_manager.clone(),
+                    tools: self.conversation_state.tools.clone(),
+                    tool_permissions: self.tool_permissions.clone(),
+                    context_manager: self.conversation_state.context_manager.clone(),
+                    checkpoints: Default::default(),
+                    sandbox: self
+                        .conversation_state
+                        .context_manager
+                        .as_ref()
+                        .map(|cm| cm.sandbox_mode())
+                        .unwrap_or_default(),
+                    profile: self.conversation_state.current_profile().map(str::to_string),
+                    model: self.conversation_state.model.clone(),
+                    usage: Arc::clone(&self.delegate_usage),
+                });
+            },
+            _ => (),
+        };
+    }
+
+    async fn print_tool_descriptions(&mut self, tool_use: &QueuedTool, trusted: bool) -> Result<(), ChatError> {
+        queue!(
+            self.output,
+            style::SetForegroundColor(Color::Magenta),
+            style::Print(format!(
+                "🛠️  Using tool: {}{}",
+                tool_use.tool.display_name(),
+                if trusted { " (trusted)".dark_green() } else { "".reset() }
+            )),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+        if let Tool::Custom(ref tool) = tool_use.tool {
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Reset),
+                style::Print(" from mcp server "),
+                style::SetForegroundColor(Color::Magenta),
+                style::Print(tool.client.get_server_name()),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+        }
+        queue!(self.output, style::Print("\n"), style::Print(CONTINUATION_LINE))?;
+        queue!(self.output, style::Print("\n"))?;
+        queue!(self.output, style::Print(TOOL_BULLET))?;
+
+        self.output.flush()?;
+
+        tool_use
+            .tool
+            .queue_description(&self.ctx, &mut self.output)
+            .await
+            .map_err(|e| ChatError::Custom(format!("failed to print tool, `{}`: {}", tool_use.name, e).into()))?;
+
+        Ok(())
+    }
+
+    /// Sends a request to the model, recording it when the session is being recorded, and checking
+    /// it against the recording when one is being replayed.
+    async fn send_message(&mut self, conversation_state: FigConversationState) -> Result<SendMessageOutput, ChatError> {
+        let request = RecordedEvent::request(&conversation_state);
+        if let Some(replay) = self.replay.as_mut() {
+            replay
+                .check_request(&request)
+                .map_err(|err| ChatError::Replay(err.to_string()))?;
+        }
+        self.record(request);
+        let response = self.client.send_message(conversation_state).await;
+        if let Err(err) = &response {
+            self.record(RecordedEvent::ResponseEnd {
+                error: Some(err.to_string()),
+            });
+        }
+        Ok(response?)
+    }
+
+    /// Sends the conversation so far to the model.
+    async fn send_conversation_state(&mut self) -> Result<SendMessageOutput, ChatError> {
+        let conversation_state = self.conversation_state.as_sendable_conversation_state(false).await;
+        self.send_message(conversation_state).await
+    }
+
+    fn record(&self, event: RecordedEvent) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(event);
+        }
+    }
+
+    /// Helper function to read user input with a prompt and Ctrl+C handling
+    fn read_user_input(&mut self, prompt: &str, exit_on_single_ctrl_c: bool) -> Option<String> {
+        let mut ctrl_c = false;
+        loop {
+            match (self.input_source.read_line(Some(prompt)), ctrl_c) {
+                (Ok(Some(line)), _) => {
+                    if line.trim().is_empty() {
+                        continue; // Reprompt if the input is empty
+                    }
+                    self.record(RecordedEvent::Input {
+                        line: line.clone(),
+                        initial: false,
+                    });
+                    return Some(line);
+                },
+                (Ok(None), false) => {
+                    if exit_on_single_ctrl_c {
+                        return None;
+                    }
+                    execute!(
+                        self.output,
+                        style::Print(format!(
+                            "\n(To exit the CLI, press Ctrl+C or Ctrl+D again or type {})\n\n",
+                            "/quit".green()
+                        ))
+                    )
+                    .unwrap_or_default();
+                    ctrl_c = true;
+                },
+                (Ok(None), true) => return None, // Exit if Ctrl+C was pressed twice
+                (Err(_), _) => return None,
+            }
+        }
+    }
+
+    /// Helper function to generate a prompt based on the current context
+    fn generate_tool_trust_prompt(&self) -> String {
+        prompt::generate_prompt(self.conversation_state.current_profile(), self.all_tools_trusted())
+    }
+
+    /// Reads the MCP resources referenced in the prompt and attaches them to the next user
+    /// message. Resources that can't be read are reported and left out.
+    async fn attach_resources(&mut self, references: Vec<ResourceReference>) -> Result<(), ChatError> {
+        let tool_manager = &self.conversation_state.tool_manager;
+        let reads = with_spinner(self.interactive, &mut self.output, "Reading resources...", || async {
+            Ok::<_, ChatError>(
+                futures::future::join_all(references.into_iter().map(|reference| async move {
+                    let result = tool_manager.read_resource(&reference.server_name, &reference.uri).await;
+                    (reference, result)
+                }))
+                .await,
+            )
+        })
+        .await?;
+
+        let mut resources = Vec::new();
+        for (reference, result) in reads {
+            match result {
+                Ok(result) => {
+                    if self.interactive {
+                        queue!(
+                            self.output,
+                            style::SetForegroundColor(Color::DarkGrey),
+                            style::Print(format!("Attached {reference}\n")),
+                            style::SetForegroundColor(Color::Reset),
+                        )?;
+                    }
+                    resources.push((reference, result));
+                },
+                Err(err) => {
+                    queue!(
+                        self.output,
+                        style::SetForegroundColor(Color::Yellow),
+                        style::Print(format!("Failed to attach {reference}: {err}\n")),
+                        style::SetForegroundColor(Color::Reset),
+                    )?;
+                },
+            }
+        }
+        if !resources.is_empty() {
+            self.conversation_state.attach_resources(&resources);
+        }
+        Ok(())
+    }
+
+    async fn send_tool_use_telemetry(&mut self, telemetry: &TelemetryThread) {
+        for (_, mut event) in self.tool_use_telemetry_events.drain() {
+            event.user_input_id = match self.tool_use_status {
+                ToolUseStatus::Idle => self.conversation_state.message_id(),
+                ToolUseStatus::RetryInProgress(ref id) => Some(id.as_str()),
+            }
+            .map(|v| v.to_string());
+
+            telemetry.send_tool_use_suggested(event).ok();
+        }
+    }
+
+    fn terminal_width(&self) -> usize {
+        (self.terminal_width_provider)().unwrap_or(80)
+    }
+
+    /// Emits a [ChatEvent::ToolDenied] event and status for a tool use that will not be executed,
+    /// e.g. because the user or a hook denied it.
+    fn report_tool_denied(&mut self, tool: &QueuedTool, reason: String) -> Result<(), ChatError> {
+        self.events.emit(ChatEvent::ToolDenied {
+            tool_use_id: tool.id.clone(),
+            name: tool.name.clone(),
+            reason,
+        })?;
+        self.events.report(ChatStatus::ToolDenied, None);
+        Ok(())
+    }
+
+    /// Runs the tool use hooks matching `input.hook_event`, if there is a context manager.
+    async fn run_tool_hooks(&mut self, input: ToolHookInput) -> ToolHookOutcome {
+        match self.conversation_state.context_manager.as_mut() {
+            Some(context_manager) => context_manager.run_tool_hooks(input, Some(&mut self.output)).await,
+            None => ToolHookOutcome::default(),
+        }
+    }
+
+    /// Loads the global command rules and those of the current profile, warning about any rules
+    /// files that could not be loaded.
+    async fn reload_command_rules(&mut self) -> Result<(), ChatError> {
+        let (rules, errors) = CommandRules::load(&self.ctx, self.conversation_state.current_profile()).await;
+        for error in errors {
+            queue!(
+                self.output,
+                style::SetForegroundColor(Color::Yellow),
+                style::Print("WARNING: "),
+                style::SetForegroundColor(Color::Reset),
+                style::Print(format!("{error}\n")),
+            )?;
+        }
+        self.tool_permissions.command_rules = rules;
+        Ok(())
+    }
+
+    /// Compacts the history with [Self::auto_compact] once it has grown past the threshold.
+    /// Compaction is only attempted again once the history has grown since the last attempt, or
+    /// the user sends a new prompt.
+    async fn auto_compact_if_needed(&mut self) -> Result<Option<usize>, ChatError> {
+        if self.auto_compaction.strategy == CompactionStrategy::Off {
+            return Ok(None);
+        }
+        let warning_level = self
+            .conversation_state
+            .get_token_warning_level(self.auto_compaction.threshold_tokens())
+            .await;
+        let history_len = self.conversation_state.history().len();
+        if warning_level == TokenWarningLevel::None
+            || self.auto_compaction_attempted.is_some_and(|len| len >= history_len)
+        {
+            return Ok(None);
+        }
+        let turns = self.auto_compact().await?;
+        self.auto_compaction_attempted = Some(self.conversation_state.history().len());
+        Ok(turns)
+    }
+
+    /// Compacts the history with the configured automatic compaction strategy. Turns are counted
+    /// as user prompts, along with the responses and tool uses that followed them.
+    ///
+    /// Strategies that don't need the model are applied immediately. For those that do, the
+    /// number of oldest history entries to summarize is returned instead.
+    async fn auto_compact(&mut self) -> Result<Option<usize>, ChatError> {
+        let strategy = self.auto_compaction.strategy;
+        let history_len = self.conversation_state.history().len();
+        // The history entries at which every user turn but the first starts.
+        let boundaries = self.conversation_state.compaction_boundaries();
+        let tokens_before = self.conversation_state.calculate_token_count().await;
+
+        let (removed_prompts, turns_removed, tool_results_removed) = match strategy {
+            CompactionStrategy::Off => return Ok(None),
+            CompactionStrategy::SummarizeOldest { turns } => {
+                // Without an earlier turn to summarize, e.g. during a long running tool loop,
+                // everything but the most recent tool use of the current turn is summarized.
+                let (boundary, message) = match boundaries.get(turns - 1).or(boundaries.last()) {
+                    Some(&boundary) => (
+                        boundary,
+                        format!("the oldest {} turns", boundaries.partition_point(|b| *b <= boundary)),
+                    ),
+                    None if history_len >= 2 => (history_len - 1, "the current turn so far".to_string()),
+                    None => return Ok(None),
+                };
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Yellow),
+                    style::Print(format!(
+                        "\nThe conversation is using {tokens_before} tokens, summarizing {message}..."
+                    )),
+                    style::SetForegroundColor(Color::Reset),
+                    style::Print("\n"),
+                )?;
+                return Ok(Some(boundary));
+            },
+            CompactionStrategy::DropToolResults => {
+                // Aim below the threshold so that compaction doesn't trigger again right away.
+                let target = self.auto_compaction.threshold_tokens() * 3 / 4;
+                let (removed, _) = self
+                    .conversation_state
+                    .drop_largest_tool_results(tokens_before.value().saturating_sub(target));
+                (Vec::new(), 0, removed)
+            },
+            CompactionStrategy::KeepRecent { turns } => {
+                let Some(&boundary) = boundaries.len().checked_sub(turns).and_then(|i| boundaries.get(i)) else {
+                    return Ok(None);
+                };
+                let turns_removed = self.conversation_state.count_turns(boundary);
+                (self.conversation_state.drop_oldest_turns(boundary), turns_removed, 0)
+            },
+        };
+
+        if self.conversation_state.history().len() == history_len && tool_results_removed == 0 {
+            return Ok(None);
+        }
+        let tokens_after = self.conversation_state.calculate_token_count().await;
+        self.conversation_state.compactions.push(CompactionRecord {
+            created_at: OffsetDateTime::now_utc(),
+            automatic: true,
+            strategy: strategy.to_string(),
+            turns_removed,
+            tool_results_removed,
+            removed_prompts,
+            summary: None,
+            tokens_before: tokens_before.value(),
+            tokens_after: tokens_after.value(),
+        });
+
+        execute!(
+            self.output,
+            style::SetForegroundColor(Color::Green),
+            style::Print(format!(
+                "\n✔ Compacted the conversation history with {strategy} ({tokens_before} → {tokens_after} tokens). "
+            )),
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("Run /compact --log for details.\n"),
+            style::SetForegroundColor(Color::Reset),
+        )?;
+        Ok(None)
+    }
+
+    /// Prints the compactions recorded for the conversation.
+    fn print_compaction_log(&mut self) -> Result<(), ChatError> {
+        if self.conversation_state.compactions.is_empty() {
+            execute!(
+                self.output,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print("\nThe conversation history has not been compacted.\n\n"),
+                style::SetForegroundColor(Color::Reset),
+            )?;
+            return Ok(());
+        }
+
+        let time_format = time::format_description::parse("[year]-[month]-[day] [hour]:[minute]:[second]")
+            .map_err(|e| ChatError::Custom(e.to_string().into()))?;
+        let local_offset = time::UtcOffset::current_local_offset().unwrap_or(time::UtcOffset::UTC);
+        for (i, record) in self.conversation_state.compactions.iter().enumerate() {
+            let created_at = record
+                .created_at
+                .to_offset(local_offset)
+                .format(&time_format)
+                .unwrap_or_default();
+            queue!(
+                self.output,
+                style::SetAttribute(Attribute::Bold),
+                style::Print(format!("\n#{} {}", i + 1, record.strategy)),
+                style::SetAttribute(Attribute::Reset),
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!(
+                    "  {created_at}  {}\n",
+                    if record.automatic { "automatic" } else { "manual" }
+                )),
+                style::SetForegroundColor(Color::Reset),
+                style::Print(format!(
+                    "  Removed {} turns and {} tool results, {} → {} tokens\n",
+                    record.turns_removed, record.tool_results_removed, record.tokens_before, record.tokens_after
+                )),
+                style::SetForegroundColor(Color::DarkGrey),
+            )?;
+            for prompt in &record.removed_prompts {
+                queue!(self.output, style::Print(format!("  > {prompt}\n")))?;
+            }
+            if let Some(summary) = &record.summary {
+                queue!(self.output, style::Print("  Summary:\n"))?;
+                for line in summary.lines() {
+                    queue!(self.output, style::Print(format!("    {line}\n")))?;
+                }
+            }
+            queue!(self.output, style::SetForegroundColor(Color::Reset))?;
+        }
+        queue!(self.output, style::Print("\n"))?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn all_tools_trusted(&self) -> bool {
+        self.conversation_state.tools.values().flatten().all(|t| match t {
+            FigTool::ToolSpecification(t) => self.tool_permissions.is_trusted(&t.name),
+        })
+    }
+
+    /// Display token limit warnings based on current conversation size
+    fn display_char_warnings(&mut self, warning_level: TokenWarningLevel) -> Result<(), std::io::Error> {
+        match warning_level {
+            TokenWarningLevel::Critical => {
+                // Memory constraint warning with gentler wording
+                execute!(
+                    self.output,
+                    style::SetForegroundColor(Color::Yellow),
+                    style::SetAttribute(Attribute::Bold),
+                    style::Print("\n⚠️ This conversation is getting lengthy.\n"),
+                    style::SetAttribute(Attribute::Reset),
+                    style::Print(
+                        "To ensure continued smooth operation, please use /compact to summarize the conversation.\n\n"
+                    ),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
+            },
+            TokenWarningLevel::Warning | TokenWarningLevel::None => {
+                // No warning needed
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn send_chat_telemetry(
+        &self,
+        database: &Database,
+        telemetry: &TelemetryThread,
+        request_id: Option<String>,
+        result: TelemetryResult,
+        reason: Option<String>,
+        reason_desc: Option<String>,
+    ) {
+        telemetry
+            .send_chat_added_message(
+                database,
+                self.conversation_state.conversation_id().to_owned(),
+                self.conversation_state.message_id().map(|s| s.to_owned()),
+                request_id,
+                self.conversation_state.context_message_length(),
+                result,
+                reason,
+                reason_desc,
+                self.conversation_state.model.clone(),
+            )
+            .await
+            .ok();
+    }
+
+    async fn send_error_telemetry(
+        &self,
+        database: &Database,
+        telemetry: &TelemetryThread,
+        reason: String,
+        reason_desc: Option<String>,
+    ) {
+        telemetry
+            .send_response_error(
+                database,
+                self.conversation_state.conversation_id().to_owned(),
+                self.conversation_state.context_message_length(),
+                TelemetryResult::Failed,
+                Some(reason),
+                reason_desc,
+            )
+            .await
+            .ok();
+    }
+}
+
+/// Display a spinner + text while waiting for an async function to finish.
+async fn with_spinner<T, E, F, Fut>(
+    interactive: bool,
+    output: &mut impl std::io::Write,
+    spinner_text: &str,
+    f: F,
+) -> Result<T, E>
+where
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+{
+    let spinner = if interactive {
+        let _ = queue!(output, cursor::Hide,);
+        Some(Spinner::new(Spinners::Dots, spinner_text.to_owned()))
+    } else {
+        None
+    };
+
+    let result = f().await;
+
+    if let Some(mut s) = spinner {
+        s.stop();
+        let _ = queue!(
+            output,
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            cursor::MoveToColumn(0),
+        );
+    }
+
+    result
+}
+
+/// Prints hook configuration grouped by trigger: conversation session start or per user message
+fn print_hook_section(output: &mut impl Write, hooks: &HashMap<String, Hook>, trigger: HookTrigger) -> Result<()> {
+    let section = match trigger {
+        HookTrigger::ConversationStart => "On Session Start",
+        HookTrigger::PerPrompt => "Per User Message",
+        HookTrigger::PreToolUse => "Before Tool Use",
+        HookTrigger::PostToolUse => "After Tool Use",
+    };
+    let hooks: Vec<(&String, &Hook)> = hooks.iter().filter(|(_, h)| h.trigger == trigger).collect();
+
+    queue!(
+        output,
+        style::SetForegroundColor(Color::Cyan),
+        style::Print(format!("    {section}:\n")),
+        style::SetForegroundColor(Color::Reset),
+    )?;
+
+    if hooks.is_empty() {
+        queue!(
+            output,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print("      <none>\n"),
+            style::SetForegroundColor(Color::Reset)
+        )?;
+    } else {
+        for (name, hook) in hooks {
+            if hook.disabled {
+                queue!(
+                    output,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(format!("      {} (disabled)\n", name)),
+                    style::SetForegroundColor(Color::Reset)
+                )?;
+            } else {
+                queue!(output, style::Print(format!("      {}\n", name)),)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Testing helper
+fn split_tool_use_event(value: &Map<String, serde_json::Value>) -> Vec<ChatResponseStream> {
+    let tool_use_id = value.get("tool_use_id").unwrap().as_str().unwrap().to_string();
+    let name = value.get("name").unwrap().as_str().unwrap().to_string();
+    let args_str = value.get("args").unwrap().to_string();
+    let split_point = args_str.len() / 2;
+    vec![
+        ChatResponseStream::ToolUseEvent {
+            tool_use_id: tool_use_id.clone(),
+            name: name.clone(),
+            input: None,
+            stop: None,
+        },
+        ChatResponseStream::ToolUseEvent {
+            tool_use_id: tool_use_id.clone(),
+            name: name.clone(),
+            input: Some(args_str.split_at(split_point).0.to_string()),
+            stop: None,
+        },
+        ChatResponseStream::ToolUseEvent {
+            tool_use_id: tool_use_id.clone(),
+            name: name.clone(),
+            input: Some(args_str.split_at(split_point).1.to_string()),
+            stop: None,
+        },
+        ChatResponseStream::ToolUseEvent {
+            tool_use_id: tool_use_id.clone(),
+            name: name.clone(),
+            input: None,
+            stop: Some(true),
+        },
+    ]
+}
+
+/// Testing helper
+fn create_stream(model_responses: serde_json::Value) -> StreamingClient {
+    let mut mock = Vec::new();
+    for response in model_responses.as_array().unwrap() {
+        let mut stream = Vec::new();
+        for event in response.as_array().unwrap() {
+            match event {
+                serde_json::Value::String(assistant_text) => {
+                    stream.push(ChatResponseStream::AssistantResponseEvent {
+                        content: assistant_text.to_string(),
+                    });
+                },
+                serde_json::Value::Object(tool_use) => {
+                    stream.append(&mut split_tool_use_event(tool_use));
+                },
+                other => panic!("Unexpected value: {:?}", other),
+            }
+        }
+        mock.push(stream);
+    }
+    StreamingClient::mock(mock)
+}
+
+/// Replaces amzn_codewhisperer_client::types::SubscriptionStatus with a more descriptive type.
+/// See response expectations in [`get_subscription_status`] for reasoning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ActualSubscriptionStatus {
+    Active,   // User has paid for this month
+    Expiring, // User has paid for this month but cancelled
+    None,     // User has not paid for this month
+}
+
+// NOTE: The subscription API behaves in a non-intuitive way. We expect the following responses:
+//
+// 1. SubscriptionStatus::Active:
+//    - The user *has* a subscription, but it is set to *not auto-renew* (i.e., cancelled).
+//    - We return ActualSubscriptionStatus::Expiring to indicate they are eligible to re-subscribe
+//
+// 2. SubscriptionStatus::Inactive:
+//    - The user has no subscription at all (no Pro access).
+//    - We return ActualSubscriptionStatus::None to indicate they are eligible to subscribe.
+//
+// 3. ConflictException (as an error):
+//    - The user already has an active subscription *with auto-renewal enabled*.
+//    - We return ActualSubscriptionStatus::Active since they don’t need to subscribe again.
+//
+// Also, it is currently not possible to subscribe or re-subscribe via console, only IDE/CLI.
+async fn get_subscription_status(database: &mut Database) -> Result<ActualSubscriptionStatus> {
+    if is_idc_user(database).await? {
+        return Ok(ActualSubscriptionStatus::Active);
+    }
+
+    let client = Client::new(database, None).await?;
+    match client.create_subscription_token().await {
+        Ok(response) => match response.status() {
+            SubscriptionStatus::Active => Ok(ActualSubscriptionStatus::Expiring),
+            SubscriptionStatus::Inactive => Ok(ActualSubscriptionStatus::None),
+            _ => Ok(ActualSubscriptionStatus::None),
+        },
+        Err(ApiClientError::CreateSubscriptionToken(e)) => {
+            let sdk_error_code = e.as_service_error().and_then(|err| err.meta().code());
+
+            if sdk_error_code.is_some_and(|c| c.contains("ConflictException")) {
+                Ok(ActualSubscriptionStatus::Active)
+            } else {
+                Err(e.into())
+            }
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn get_subscription_status_with_spinner(
+    interactive: bool,
+    output: &mut SharedWriter,
+    database: &mut Database,
+) -> Result<ActualSubscriptionStatus> {
+    return with_spinner(interactive, output, "Checking subscription status...", || async {
+        get_subscription_status(database).await
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::chat::tools::custom_tool::{
+        CustomTool,
+        CustomToolClient,
+    };
+    use crate::cli::chat::util::shared_writer::TestWriterWithSink;
+    use crate::platform::Env;
+
+    #[tokio::test]
+    async fn test_flow() {
+        // let _ = tracing_subscriber::fmt::try_init();
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "Sure, I'll create a file for you",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file.txt",
+                    }
+                }
+            ],
+            [
+                "Hope that looks good to you!",
+            ],
+        ]));
+
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
+
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![
+                "create a new file".to_string(),
+                "y".to_string(),
+                "exit".to_string(),
+            ]),
+            true,
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            None,
+            tool_config,
+            ToolPermissions::new(0),
+            OutputFormat::Text,
+        )
+        .await
+        .unwrap()
+        .try_chat(&mut database, &telemetry)
+        .await
+        .unwrap();
+
+        assert_eq!(ctx.fs().read_to_string("/file.txt").await.unwrap(), "Hello, world!\n");
+    }
+
+    #[tokio::test]
+    async fn test_flow_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorded = dir.path().join("recorded.jsonl");
+        let replayed = dir.path().join("replayed.jsonl");
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+
+        // Record a session where the model creates a file.
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "Sure, I'll create a file for you",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file.txt",
+                    }
+                }
+            ],
+            [
+                "Hope that looks good to you!",
+            ],
+        ]));
+        let recorder = SessionRecorder::create(&recorded).unwrap();
+        recorder.record(RecordedEvent::Start {
+            interactive: true,
+            trust_all_tools: false,
+            trust_tools: None,
+        });
+        let mut chat = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::null(),
+            None,
+            InputSource::new_mock(vec!["create a new file".to_string(), "y".to_string()]),
+            true,
+            None,
+            test_client,
+            || Some(80),
+            ToolManager::default(),
+            None,
+            None,
+            tool_config.clone(),
+            ToolPermissions::new(0),
+            OutputFormat::Text,
+        )
+        .await
+        .unwrap();
+        chat.recorder = Some(recorder);
+        chat.try_chat(&mut database, &telemetry).await.unwrap();
+        assert_eq!(ctx.fs().read_to_string("/file.txt").await.unwrap(), "Hello, world!\n");
+
+        // Replaying serves the recorded responses and tool results without running the tool.
+        let replay = SessionReplay::load(&recorded).unwrap();
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let recorder = SessionRecorder::create(&replayed).unwrap();
+        recorder.record(RecordedEvent::Start {
+            interactive: replay.interactive,
+            trust_all_tools: replay.trust_all_tools,
+            trust_tools: replay.trust_tools.clone(),
+        });
+        let mut chat = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::null(),
+            replay.initial_input.clone(),
+            replay.input_source(),
+            replay.interactive,
+            None,
+            replay.client(),
+            || Some(80),
+            ToolManager::default(),
+            None,
+            None,
+            tool_config.clone(),
+            ToolPermissions::new(0),
+            OutputFormat::Text,
+        )
+        .await
+        .unwrap();
+        chat.recorder = Some(recorder);
+        chat.replay = Some(replay);
+        chat.try_chat(&mut database, &telemetry).await.unwrap();
+
+        assert!(!ctx.fs().exists("/file.txt"));
+        let recorded = std::fs::read_to_string(recorded).unwrap();
+        assert!(recorded.contains(r#""type":"tool_result","tool_use_id":"1""#));
+        assert_eq!(std::fs::read_to_string(replayed).unwrap(), recorded);
+
+        // Replaying fails as soon as a request differs from the recorded one.
+        let mut replay = SessionReplay::load(dir.path().join("recorded.jsonl")).unwrap();
+        replay.initial_input = Some("create another file".to_string());
+        let mut chat = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::null(),
+            replay.initial_input.clone(),
+            replay.input_source(),
+            replay.interactive,
+            None,
+            replay.client(),
+            || Some(80),
+            ToolManager::default(),
+            None,
+            None,
+            tool_config,
+            ToolPermissions::new(0),
+            OutputFormat::Text,
+        )
+        .await
+        .unwrap();
+        chat.replay = Some(replay);
+        let err = chat.try_chat(&mut database, &telemetry).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<ChatError>(), Some(ChatError::Replay(_))));
+    }
+
+    #[tokio::test]
+    async fn test_flow_tool_permissions() {
+        // let _ = tracing_subscriber::fmt::try_init();
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "Ok",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file1.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+            [
+                "Ok",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file2.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+            [
+                "Ok",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file3.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+            [
+                "Ok",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file4.txt",
+                    }
+                }
+            ],
+            [
+                "Ok, I won't make it.",
+            ],
+            [
+                "Ok",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file5.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+            [
+                "Ok",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file6.txt",
+                    }
+                }
+            ],
+            [
+                "Ok, I won't make it.",
+            ],
+        ]));
+
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
 
-// NOTE: The subscription API behaves in a non-intuitive way. We expect the following responses:
-//
-// 1. SubscriptionStatus::Active:
-//    - The user *has* a subscription, but it is set to *not auto-renew* (i.e., cancelled).
-//    - We return ActualSubscriptionStatus::Expiring to indicate they are eligible to re-subscribe
-//
-// 2. SubscriptionStatus::Inactive:
-//    - The user has no subscription at all (no Pro access).
-//    - We return ActualSubscriptionStatus::None to indicate they are eligible to subscribe.
-//
-// 3. ConflictException (as an error):
-//    - The user already has an active subscription *with auto-renewal enabled*.
-//    - We return ActualSubscriptionStatus::Active since they don’t need to subscribe again.
-//
-// Also, it is currently not possible to subscribe or re-subscribe via console, only IDE/CLI.
-async fn get_subscription_status(database: &mut Database) -> Result<ActualSubscriptionStatus> {
-    if is_idc_user(database).await? {
-        return Ok(ActualSubscriptionStatus::Active);
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![
+                "/tools".to_string(),
+                "/tools help".to_string(),
+                "create a new file".to_string(),
+                "y".to_string(),
+                "create a new file".to_string(),
+                "t".to_string(),
+                "create a new file".to_string(), // should make without prompting due to 't'
+                "/tools untrust fs_write".to_string(),
+                "create a file".to_string(), // prompt again due to untrust
+                "n".to_string(),             // cancel
+                "/tools trust fs_write".to_string(),
+                "create a file".to_string(), // again without prompting due to '/tools trust'
+                "/tools reset".to_string(),
+                "create a file".to_string(), // prompt again due to reset
+                "n".to_string(),             // cancel
+                "exit".to_string(),
+            ]),
+            true,
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            None,
+            tool_config,
+            ToolPermissions::new(0),
+            OutputFormat::Text,
+        )
+        .await
+        .unwrap()
+        .try_chat(&mut database, &telemetry)
+        .await
+        .unwrap();
+
+        assert_eq!(ctx.fs().read_to_string("/file2.txt").await.unwrap(), "Hello, world!\n");
+        assert_eq!(ctx.fs().read_to_string("/file3.txt").await.unwrap(), "Hello, world!\n");
+        assert!(!ctx.fs().exists("/file4.txt"));
+        assert_eq!(ctx.fs().read_to_string("/file5.txt").await.unwrap(), "Hello, world!\n");
+        assert!(!ctx.fs().exists("/file6.txt"));
     }
 
-    let client = Client::new(database, None).await?;
-    match client.create_subscription_token().await {
-        Ok(response) => match response.status() {
-            SubscriptionStatus::Active => Ok(ActualSubscriptionStatus::Expiring),
-            SubscriptionStatus::Inactive => Ok(ActualSubscriptionStatus::None),
-            _ => Ok(ActualSubscriptionStatus::None),
-        },
-        Err(ApiClientError::CreateSubscriptionToken(e)) => {
-            let sdk_error_code = e.as_service_error().and_then(|err| err.meta().code());
+    #[tokio::test]
+    async fn test_flow_multiple_tools() {
+        // let _ = tracing_subscriber::fmt::try_init();
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "Sure, I'll create a file for you",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file1.txt",
+                    }
+                },
+                {
+                    "tool_use_id": "2",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file2.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+            [
+                "Sure, I'll create a file for you",
+                {
+                    "tool_use_id": "1",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file3.txt",
+                    }
+                },
+                {
+                    "tool_use_id": "2",
+                    "name": "fs_write",
+                    "args": {
+                        "command": "create",
+                        "file_text": "Hello, world!",
+                        "path": "/file4.txt",
+                    }
+                }
+            ],
+            [
+                "Done",
+            ],
+        ]));
 
-            if sdk_error_code.is_some_and(|c| c.contains("ConflictException")) {
-                Ok(ActualSubscriptionStatus::Active)
-            } else {
-                Err(e.into())
-            }
-        },
-        Err(e) => Err(e.into()),
-    }
-}
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
 
-async fn get_subscription_status_with_spinner(
-    interactive: bool,
-    output: &mut SharedWriter,
-    database: &mut Database,
-) -> Result<ActualSubscriptionStatus> {
-    return with_spinner(interactive, output, "Checking subscription status...", || async {
-        get_subscription_status(database).await
-    })
-    .await;
-}
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![
+                "create 2 new files parallel".to_string(),
+                "t".to_string(),
+                "/tools reset".to_string(),
+                "create 2 new files parallel".to_string(),
+                "y".to_string(),
+                "y".to_string(),
+                "exit".to_string(),
+            ]),
+            true,
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            None,
+            tool_config,
+            ToolPermissions::new(0),
+            OutputFormat::Text,
+        )
+        .await
+        .unwrap()
+        .try_chat(&mut database, &telemetry)
+        .await
+        .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::cli::chat::util::shared_writer::TestWriterWithSink;
-    use crate::platform::Env;
+        assert_eq!(ctx.fs().read_to_string("/file1.txt").await.unwrap(), "Hello, world!\n");
+        assert_eq!(ctx.fs().read_to_string("/file2.txt").await.unwrap(), "Hello, world!\n");
+        assert_eq!(ctx.fs().read_to_string("/file3.txt").await.unwrap(), "Hello, world!\n");
+        assert_eq!(ctx.fs().read_to_string("/file4.txt").await.unwrap(), "Hello, world!\n");
+    }
 
     #[tokio::test]
-    async fn test_flow() {
-        // let _ = tracing_subscriber::fmt::try_init();
+    async fn test_flow_concurrent_tools() {
         let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/notes.txt", "Some notes").await.unwrap();
+        ctx.fs().write("/readme.txt", "Read me").await.unwrap();
+        let append = |id: &str, new_str: &str| {
+            serde_json::json!({
+                "tool_use_id": id,
+                "name": "fs_write",
+                "args": {
+                    "command": "append",
+                    "new_str": new_str,
+                    "path": "/notes.txt",
+                }
+            })
+        };
         let test_client = create_stream(serde_json::json!([
             [
-                "Sure, I'll create a file for you",
+                "Sure, I'll write the files for you",
                 {
                     "tool_use_id": "1",
                     "name": "fs_write",
                     "args": {
                         "command": "create",
                         "file_text": "Hello, world!",
-                        "path": "/file.txt",
+                        "path": "/file1.txt",
+                    }
+                },
+                append("2", "one"),
+                append("3", "two"),
+                {
+                    "tool_use_id": "4",
+                    "name": "fs_read",
+                    "args": {
+                        "mode": "Line",
+                        "path": "/readme.txt",
                     }
                 }
-            ],
-            [
-                "Hope that looks good to you!",
-            ],
+            ],
+            [
+                "Done",
+            ],
+        ]));
+
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
+
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        let mut chat = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![
+                "/tools trustall".to_string(),
+                "write the files".to_string(),
+                "exit".to_string(),
+            ]),
+            true,
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            None,
+            tool_config,
+            ToolPermissions::new(0),
+            OutputFormat::Text,
+        )
+        .await
+        .unwrap();
+        chat.try_chat(&mut database, &telemetry).await.unwrap();
+
+        // The second append waits for the first one, the other tools run alongside them.
+        assert_eq!(ctx.fs().read_to_string("/file1.txt").await.unwrap(), "Hello, world!\n");
+        assert_eq!(
+            ctx.fs().read_to_string("/notes.txt").await.unwrap(),
+            "Some notes\none\ntwo\n"
+        );
+        let tool_results = chat
+            .conversation_state
+            .history()
+            .iter()
+            .flat_map(|(user, _)| user.tool_use_results().unwrap_or_default())
+            .map(|result| {
+                (
+                    result.tool_use_id.as_str(),
+                    matches!(result.status, ToolResultStatus::Success),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(tool_results, vec![("1", true), ("2", true), ("3", true), ("4", true)]);
+    }
+
+    #[tokio::test]
+    async fn test_flow_auto_compaction_during_tool_uses() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/big.txt", "word ".repeat(3000)).await.unwrap();
+        let read = |id: &str| {
+            serde_json::json!({
+                "tool_use_id": id,
+                "name": "fs_read",
+                "args": {
+                    "mode": "Line",
+                    "path": "/big.txt",
+                }
+            })
+        };
+        let test_client = create_stream(serde_json::json!([
+            ["Reading", read("1")],
+            ["Reading again", read("2")],
+            ["And again", read("3")],
+            ["Done"],
         ]));
 
         let env = Env::new();
@@ -4335,19 +6414,15 @@ mod tests {
         let tool_manager = ToolManager::default();
         let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
             .expect("Tools failed to load");
-        ChatContext::new(
+        let mut chat = ChatContext::new(
             Arc::clone(&ctx),
             &mut database,
             "fake_conv_id",
             SharedWriter::stdout(),
             None,
-            InputSource::new_mock(vec![
-                "create a new file".to_string(),
-                "y".to_string(),
-                "exit".to_string(),
-            ]),
+            InputSource::new_mock(vec!["read the file".to_string(), "exit".to_string()]),
             true,
-            false,
+            None,
             test_client,
             || Some(80),
             tool_manager,
@@ -4355,110 +6430,288 @@ mod tests {
             None,
             tool_config,
             ToolPermissions::new(0),
+            OutputFormat::Text,
         )
         .await
-        .unwrap()
-        .try_chat(&mut database, &telemetry)
+        .unwrap();
+        chat.auto_compaction = AutoCompaction {
+            strategy: CompactionStrategy::DropToolResults,
+            threshold_percent: 1,
+        };
+        chat.try_chat(&mut database, &telemetry).await.unwrap();
+
+        // The history was compacted before the last tool results were sent, and again once the
+        // user was prompted.
+        let compactions = &chat.conversation_state.compactions;
+        assert_eq!(compactions.len(), 2);
+        assert!(compactions.iter().all(|c| c.automatic && c.tool_results_removed == 1));
+        let first_result = &chat.conversation_state.history()[1].0.tool_use_results().unwrap()[0];
+        assert!(matches!(
+            &first_result.content[..],
+            [ToolUseResultBlock::Text(text)] if text.contains("removed to free up context space")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_batch_tool_uses() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut database = Database::new().await.unwrap();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        let mut tool_permissions = ToolPermissions::new(0);
+        tool_permissions.trust_all = true;
+        let mut tool_manager = ToolManager::default();
+        for (name, read_only) in [("lookup", Some(true)), ("search", None), ("deploy", Some(false))] {
+            let spec = serde_json::json!({
+                "name": format!("server___{name}"),
+                "description": name,
+                "inputSchema": { "type": "object" },
+                "annotations": { "readOnlyHint": read_only },
+            });
+            tool_manager
+                .schema
+                .insert(format!("server___{name}"), serde_json::from_value(spec).unwrap());
+        }
+        let chat = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec![]),
+            true,
+            None,
+            create_stream(serde_json::json!([])),
+            || Some(80),
+            tool_manager,
+            None,
+            None,
+            tool_config,
+            tool_permissions,
+            OutputFormat::Text,
+        )
         .await
         .unwrap();
 
-        assert_eq!(ctx.fs().read_to_string("/file.txt").await.unwrap(), "Hello, world!\n");
+        let queued = |id: &str, tool: Tool| QueuedTool {
+            id: id.to_string(),
+            name: tool.display_name(),
+            accepted: true,
+            tool,
+            input: serde_json::Value::Null,
+            pre_hook_outcome: ToolHookOutcome::default(),
+        };
+        let write = |path: &str| {
+            Tool::FsWrite(
+                serde_json::from_value(serde_json::json!({
+                    "command": "create",
+                    "file_text": "Hello, world!",
+                    "path": path,
+                }))
+                .unwrap(),
+            )
+        };
+        let read = |path: &str| {
+            Tool::FsRead(serde_json::from_value(serde_json::json!({ "mode": "Line", "path": path })).unwrap())
+        };
+        let bash =
+            || Tool::ExecuteCommand(serde_json::from_value(serde_json::json!({ "command": "cargo test" })).unwrap());
+        let client = Arc::new(
+            CustomToolClient::from_config(
+                "server".to_string(),
+                serde_json::from_value(serde_json::json!({ "url": "http://127.0.0.1:1/mcp" })).unwrap(),
+            )
+            .unwrap(),
+        );
+        let custom = |id: &str, name: &str| QueuedTool {
+            name: format!("server___{name}"),
+            ..queued(
+                id,
+                Tool::Custom(CustomTool {
+                    name: name.to_string(),
+                    client: Arc::clone(&client),
+                    method: "tools/call".to_string(),
+                    params: None,
+                }),
+            )
+        };
+
+        let batches = chat.batch_tool_uses(vec![
+            queued("1", write("/file1.txt")),
+            queued("2", write("/file2.txt")),
+            queued("3", bash()),
+            queued("4", read("/file1.txt")),
+            queued("5", read("/file2.txt")),
+            queued("6", write("/file1.txt")),
+            queued("7", bash()),
+            queued("8", bash()),
+            custom("9", "lookup"),
+            custom("10", "search"),
+            queued("11", read("/file1.txt")),
+            custom("12", "deploy"),
+            custom("13", "lookup"),
+        ]);
+        let batches = batches
+            .iter()
+            .map(|batch| batch.iter().map(|tool| tool.id.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        // Shell commands never run alongside file writes, reads, or each other. Custom tools run
+        // alongside reads unless their server says they modify their environment.
+        assert_eq!(batches, vec![
+            vec!["1", "2"],
+            vec!["3"],
+            vec!["4", "5"],
+            vec!["6"],
+            vec!["7"],
+            vec!["8"],
+            vec!["9", "10", "11"],
+            vec!["12"],
+            vec!["13"],
+        ]);
     }
 
     #[tokio::test]
-    async fn test_flow_tool_permissions() {
-        // let _ = tracing_subscriber::fmt::try_init();
+    async fn test_flow_delegate() {
         let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/notes.txt", "The build is broken").await.unwrap();
         let test_client = create_stream(serde_json::json!([
             [
-                "Ok",
+                "I'll have a look",
                 {
                     "tool_use_id": "1",
-                    "name": "fs_write",
+                    "name": "delegate",
                     "args": {
-                        "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file1.txt",
+                        "task": "Find out what the notes say",
+                        "tools": ["fs_read", "fs_write"],
                     }
                 }
             ],
+            // The child conversation.
             [
-                "Done",
-            ],
-            [
-                "Ok",
                 {
-                    "tool_use_id": "1",
-                    "name": "fs_write",
+                    "tool_use_id": "child-1",
+                    "name": "fs_read",
                     "args": {
-                        "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file2.txt",
+                        "mode": "Line",
+                        "path": "/notes.txt",
                     }
-                }
-            ],
-            [
-                "Done",
-            ],
-            [
-                "Ok",
+                },
                 {
-                    "tool_use_id": "1",
+                    "tool_use_id": "child-2",
                     "name": "fs_write",
                     "args": {
                         "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file3.txt",
+                        "file_text": "Fixed",
+                        "path": "/notes.txt",
                     }
+                },
+                {
+                    "tool_use_id": "child-3",
+                    "name": "use_aws",
+                    "args": {}
                 }
             ],
             [
-                "Done",
+                "The notes say the build is broken",
             ],
+            // Back to the parent conversation.
             [
-                "Ok",
+                "The build is broken",
+            ],
+        ]));
+
+        let env = Env::new();
+        let mut database = Database::new().await.unwrap();
+        let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
+
+        let tool_manager = ToolManager::default();
+        let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
+            .expect("Tools failed to load");
+        let mut chat = ChatContext::new(
+            Arc::clone(&ctx),
+            &mut database,
+            "fake_conv_id",
+            SharedWriter::stdout(),
+            None,
+            InputSource::new_mock(vec!["what do the notes say".to_string(), "exit".to_string()]),
+            true,
+            None,
+            test_client,
+            || Some(80),
+            tool_manager,
+            None,
+            None,
+            tool_config,
+            ToolPermissions::new(0),
+            OutputFormat::Text,
+        )
+        .await
+        .unwrap();
+        chat.try_chat(&mut database, &telemetry).await.unwrap();
+
+        // The untrusted write was rejected since the child cannot ask for approval.
+        assert_eq!(
+            ctx.fs().read_to_string("/notes.txt").await.unwrap(),
+            "The build is broken"
+        );
+        // Only the final answer of the child is added to the conversation.
+        let history = chat.conversation_state.history();
+        assert_eq!(history.len(), 2);
+        let result = &history[1].0.tool_use_results().unwrap()[0];
+        assert_eq!(result.tool_use_id, "1");
+        assert!(matches!(
+            &result.content[..],
+            [ToolUseResultBlock::Text(text)] if text == "The notes say the build is broken"
+        ));
+        let usage = chat.delegate_usage.lock().unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].task, "Find out what the notes say");
+        assert_eq!(usage[0].turns, 2);
+    }
+
+    #[tokio::test]
+    async fn test_flow_delegate_hooks_and_checkpoints() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/notes.txt", "The build is broken").await.unwrap();
+        let test_client = create_stream(serde_json::json!([
+            [
+                "I'll fix the notes",
                 {
                     "tool_use_id": "1",
-                    "name": "fs_write",
+                    "name": "delegate",
                     "args": {
-                        "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file4.txt",
+                        "task": "Fix the notes",
+                        "tools": ["fs_write"],
                     }
                 }
             ],
+            // The child conversation.
             [
-                "Ok, I won't make it.",
-            ],
-            [
-                "Ok",
                 {
-                    "tool_use_id": "1",
+                    "tool_use_id": "child-1",
                     "name": "fs_write",
                     "args": {
                         "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file5.txt",
+                        "file_text": "Fixed",
+                        "path": "/notes.txt",
                     }
-                }
-            ],
-            [
-                "Done",
-            ],
-            [
-                "Ok",
+                },
                 {
-                    "tool_use_id": "1",
+                    "tool_use_id": "child-2",
                     "name": "fs_write",
                     "args": {
                         "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file6.txt",
+                        "file_text": "Leaked",
+                        "path": "/secret.txt",
                     }
                 }
             ],
             [
-                "Ok, I won't make it.",
+                "The notes are fixed",
+            ],
+            // Back to the parent conversation.
+            [
+                "The notes are fixed",
             ],
         ]));
 
@@ -4469,32 +6722,21 @@ mod tests {
         let tool_manager = ToolManager::default();
         let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
             .expect("Tools failed to load");
-        ChatContext::new(
+        let mut chat = ChatContext::new(
             Arc::clone(&ctx),
             &mut database,
             "fake_conv_id",
             SharedWriter::stdout(),
             None,
             InputSource::new_mock(vec![
-                "/tools".to_string(),
-                "/tools help".to_string(),
-                "create a new file".to_string(),
-                "y".to_string(),
-                "create a new file".to_string(),
-                "t".to_string(),
-                "create a new file".to_string(), // should make without prompting due to 't'
-                "/tools untrust fs_write".to_string(),
-                "create a file".to_string(), // prompt again due to untrust
-                "n".to_string(),             // cancel
                 "/tools trust fs_write".to_string(),
-                "create a file".to_string(), // again without prompting due to '/tools trust'
-                "/tools reset".to_string(),
-                "create a file".to_string(), // prompt again due to reset
-                "n".to_string(),             // cancel
+                "/context hooks add deny-secret --trigger pre_tool_use --command 'grep -q secret.txt && exit 2; exit 0'"
+                    .to_string(),
+                "fix the notes".to_string(),
                 "exit".to_string(),
             ]),
             true,
-            false,
+            None,
             test_client,
             || Some(80),
             tool_manager,
@@ -4502,23 +6744,30 @@ mod tests {
             None,
             tool_config,
             ToolPermissions::new(0),
+            OutputFormat::Text,
         )
         .await
-        .unwrap()
-        .try_chat(&mut database, &telemetry)
-        .await
         .unwrap();
-
-        assert_eq!(ctx.fs().read_to_string("/file2.txt").await.unwrap(), "Hello, world!\n");
-        assert_eq!(ctx.fs().read_to_string("/file3.txt").await.unwrap(), "Hello, world!\n");
-        assert!(!ctx.fs().exists("/file4.txt"));
-        assert_eq!(ctx.fs().read_to_string("/file5.txt").await.unwrap(), "Hello, world!\n");
-        assert!(!ctx.fs().exists("/file6.txt"));
+        chat.try_chat(&mut database, &telemetry).await.unwrap();
+
+        // The hooks of the parent conversation apply to the child conversation.
+        assert_eq!(ctx.fs().read_to_string("/notes.txt").await.unwrap(), "Fixed\n");
+        assert!(!ctx.fs().exists("/secret.txt"));
+
+        // The write of the child conversation can be rolled back from the parent conversation.
+        let checkpoints = chat.conversation_state.checkpoints.checkpoints();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(checkpoints[0].tool_use_id, "child-1");
+        chat.conversation_state.checkpoints.undo_turns(&ctx, 1).await.unwrap();
+        assert_eq!(
+            ctx.fs().read_to_string("/notes.txt").await.unwrap(),
+            "The build is broken"
+        );
     }
 
-    #[tokio::test]
-    async fn test_flow_multiple_tools() {
-        // let _ = tracing_subscriber::fmt::try_init();
+    /// Runs a non-interactive chat in which the assistant wants to write a file, which requires
+    /// approval, returning the exit code and the events written in `format`.
+    async fn run_tool_denied_flow(format: OutputFormat) -> (process::ExitCode, String) {
         let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
         let test_client = create_stream(serde_json::json!([
             [
@@ -4529,40 +6778,7 @@ mod tests {
                     "args": {
                         "command": "create",
                         "file_text": "Hello, world!",
-                        "path": "/file1.txt",
-                    }
-                },
-                {
-                    "tool_use_id": "2",
-                    "name": "fs_write",
-                    "args": {
-                        "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file2.txt",
-                    }
-                }
-            ],
-            [
-                "Done",
-            ],
-            [
-                "Sure, I'll create a file for you",
-                {
-                    "tool_use_id": "1",
-                    "name": "fs_write",
-                    "args": {
-                        "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file3.txt",
-                    }
-                },
-                {
-                    "tool_use_id": "2",
-                    "name": "fs_write",
-                    "args": {
-                        "command": "create",
-                        "file_text": "Hello, world!",
-                        "path": "/file4.txt",
+                        "path": "/file.txt",
                     }
                 }
             ],
@@ -4575,44 +6791,74 @@ mod tests {
         let mut database = Database::new().await.unwrap();
         let telemetry = TelemetryThread::new(&env, &mut database).await.unwrap();
 
-        let tool_manager = ToolManager::default();
         let tool_config = serde_json::from_str::<HashMap<String, ToolSpec>>(include_str!("tools/tool_index.json"))
             .expect("Tools failed to load");
-        ChatContext::new(
+        let mut chat = ChatContext::new(
             Arc::clone(&ctx),
             &mut database,
             "fake_conv_id",
-            SharedWriter::stdout(),
-            None,
-            InputSource::new_mock(vec![
-                "create 2 new files parallel".to_string(),
-                "t".to_string(),
-                "/tools reset".to_string(),
-                "create 2 new files parallel".to_string(),
-                "y".to_string(),
-                "y".to_string(),
-                "exit".to_string(),
-            ]),
-            true,
+            SharedWriter::null(),
+            Some("create a new file".to_string()),
+            InputSource::new_mock(vec![]),
             false,
+            None,
             test_client,
             || Some(80),
-            tool_manager,
+            ToolManager::default(),
             None,
             None,
             tool_config,
             ToolPermissions::new(0),
+            format,
         )
         .await
-        .unwrap()
-        .try_chat(&mut database, &telemetry)
-        .await
         .unwrap();
+        let writer = TestWriterWithSink {
+            sink: Arc::new(std::sync::Mutex::new(Vec::new())),
+        };
+        chat.events = ChatEvents::new(format, writer.clone());
+        let result = chat.try_chat(&mut database, &telemetry).await;
+        let exit_code = chat.finish(result).unwrap();
 
-        assert_eq!(ctx.fs().read_to_string("/file1.txt").await.unwrap(), "Hello, world!\n");
-        assert_eq!(ctx.fs().read_to_string("/file2.txt").await.unwrap(), "Hello, world!\n");
-        assert_eq!(ctx.fs().read_to_string("/file3.txt").await.unwrap(), "Hello, world!\n");
-        assert_eq!(ctx.fs().read_to_string("/file4.txt").await.unwrap(), "Hello, world!\n");
+        assert!(!ctx.fs().exists("/file.txt"));
+        (exit_code, String::from_utf8(writer.get_content()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_flow_tool_denied_json() {
+        let expected = serde_json::json!([
+            {
+                "type": "tool_use",
+                "id": "1",
+                "name": "fs_write",
+                "input": { "command": "create", "file_text": "Hello, world!", "path": "/file.txt" },
+            },
+            { "type": "tool_denied", "tool_use_id": "1", "name": "fs_write", "reason": "requires approval" },
+        ]);
+        // Text and usage events are covered by the output_format tests.
+        let is_tool_event = |event: &serde_json::Value| event["type"] == "tool_use" || event["type"] == "tool_denied";
+
+        let (exit_code, output) = run_tool_denied_flow(OutputFormat::Json).await;
+        assert_eq!(exit_code, process::ExitCode::from(3));
+        let events = serde_json::from_str::<Vec<serde_json::Value>>(&output).unwrap();
+        let result = events.last().unwrap();
+        assert_eq!(result["type"], "result");
+        assert_eq!(result["status"], "tool_denied");
+        let tool_events = events.iter().filter(|e| is_tool_event(e)).cloned().collect::<Vec<_>>();
+        assert_eq!(serde_json::Value::Array(tool_events), expected);
+
+        // The same events are streamed, one per line.
+        let (exit_code, output) = run_tool_denied_flow(OutputFormat::StreamJson).await;
+        assert_eq!(exit_code, process::ExitCode::from(3));
+        let events = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        let result = events.last().unwrap();
+        assert_eq!(result["type"], "result");
+        assert_eq!(result["status"], "tool_denied");
+        let tool_events = events.iter().filter(|e| is_tool_event(e)).cloned().collect::<Vec<_>>();
+        assert_eq!(serde_json::Value::Array(tool_events), expected);
     }
 
     #[tokio::test]
@@ -4673,7 +6919,7 @@ mod tests {
                 "exit".to_string(),
             ]),
             true,
-            false,
+            None,
             test_client,
             || Some(80),
             tool_manager,
@@ -4681,6 +6927,7 @@ mod tests {
             None,
             tool_config,
             ToolPermissions::new(0),
+            OutputFormat::Text,
         )
         .await
         .unwrap()
@@ -4730,7 +6977,7 @@ mod tests {
             None,
             InputSource::new_mock(vec!["/subscribe".to_string(), "y".to_string(), "/quit".to_string()]),
             true,
-            false,
+            None,
             create_stream(serde_json::json!([])),
             || Some(80),
             tool_manager,
@@ -4738,6 +6985,7 @@ mod tests {
             None,
             tool_config,
             ToolPermissions::new(0),
+            OutputFormat::Text,
         )
         .await
         .unwrap()
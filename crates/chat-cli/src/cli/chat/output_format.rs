@@ -0,0 +1,268 @@
+//! Machine readable output for `q chat --output-format json|stream-json`.
+//!
+//! In these modes the human readable output is discarded and the chat instead reports what
+//! happens as [ChatEvent]s written to stdout: as newline delimited JSON while the chat runs with
+//! `stream-json`, or as a single JSON array once it has finished with `json`.
+
+use std::io::{
+    self,
+    Write,
+};
+use std::process::ExitCode;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use super::message::{
+    ToolUseResult,
+    ToolUseResultBlock,
+};
+use crate::api_client::model::ToolResultStatus;
+
+/// The format `q chat` writes its output in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Formatted text for humans.
+    #[default]
+    Text,
+    /// A JSON array of every event, written once the chat has finished.
+    Json,
+    /// Newline delimited JSON events, written as they happen.
+    StreamJson,
+}
+
+/// How the chat finished, reported by the final [ChatEvent::Result] and the exit code.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatStatus {
+    #[default]
+    Success,
+    /// A tool use was denied, by a command rule, a hook, or because it required approval.
+    ToolDenied,
+    Error,
+}
+
+impl ChatStatus {
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            ChatStatus::Success => ExitCode::SUCCESS,
+            ChatStatus::Error => ExitCode::from(1),
+            ChatStatus::ToolDenied => ExitCode::from(3),
+        }
+    }
+}
+
+/// Something that happened during the chat.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    /// Part of the assistant's response, as it is received.
+    AssistantText { text: String },
+    /// The assistant requested a tool use.
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    /// A tool use was denied and not executed.
+    ToolDenied {
+        tool_use_id: String,
+        name: String,
+        reason: String,
+    },
+    /// The result of a tool use that is sent back to the assistant.
+    ToolResult {
+        tool_use_id: String,
+        is_error: bool,
+        content: Vec<serde_json::Value>,
+    },
+    /// Token usage of the conversation after a response was received.
+    Usage {
+        context_tokens: usize,
+        user_tokens: usize,
+        assistant_tokens: usize,
+        total_tokens: usize,
+        context_window_tokens: usize,
+    },
+    /// The chat finished. This is always the last event.
+    Result {
+        status: ChatStatus,
+        conversation_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+impl From<&ToolUseResult> for ChatEvent {
+    fn from(result: &ToolUseResult) -> Self {
+        ChatEvent::ToolResult {
+            tool_use_id: result.tool_use_id.clone(),
+            is_error: matches!(result.status, ToolResultStatus::Error),
+            content: result
+                .content
+                .iter()
+                .map(|block| match block {
+                    ToolUseResultBlock::Text(text) => serde_json::Value::from(text.as_str()),
+                    ToolUseResultBlock::Json(json) => json.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Writes [ChatEvent]s in the selected [OutputFormat].
+pub struct ChatEvents {
+    format: OutputFormat,
+    writer: Box<dyn Write + Send>,
+    /// Events held back until the chat finishes, for [OutputFormat::Json].
+    buffered: Vec<ChatEvent>,
+    status: ChatStatus,
+    /// The last error the chat ran into.
+    error: Option<String>,
+}
+
+impl ChatEvents {
+    pub fn new(format: OutputFormat, writer: impl Write + Send + 'static) -> Self {
+        Self {
+            format,
+            writer: Box::new(writer),
+            buffered: Vec::new(),
+            status: ChatStatus::Success,
+            error: None,
+        }
+    }
+
+    /// Whether events are written at all, i.e. the output format is not [OutputFormat::Text].
+    pub fn is_enabled(&self) -> bool {
+        self.format != OutputFormat::Text
+    }
+
+    pub fn emit(&mut self, event: ChatEvent) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Text => Ok(()),
+            OutputFormat::StreamJson => {
+                serde_json::to_writer(&mut self.writer, &event)?;
+                self.writer.write_all(b"\n")?;
+                self.writer.flush()
+            },
+            OutputFormat::Json => {
+                // Text deltas are only useful while streaming.
+                match (self.buffered.last_mut(), event) {
+                    (Some(ChatEvent::AssistantText { text }), ChatEvent::AssistantText { text: delta }) => {
+                        text.push_str(&delta);
+                    },
+                    (_, event) => self.buffered.push(event),
+                }
+                Ok(())
+            },
+        }
+    }
+
+    /// Records how the chat went, keeping the most severe status reported so far.
+    pub fn report(&mut self, status: ChatStatus, error: Option<String>) {
+        self.status = self.status.max(status);
+        if error.is_some() {
+            self.error = error;
+        }
+    }
+
+    /// Writes the final [ChatEvent::Result], returning the exit code for the chat.
+    pub fn finish(&mut self, conversation_id: &str) -> io::Result<ExitCode> {
+        let error = self.error.take();
+        self.emit(ChatEvent::Result {
+            status: self.status,
+            conversation_id: conversation_id.to_string(),
+            error,
+        })?;
+        if self.format == OutputFormat::Json {
+            serde_json::to_writer(&mut self.writer, &self.buffered)?;
+            self.writer.write_all(b"\n")?;
+            self.writer.flush()?;
+        }
+        Ok(self.status.exit_code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        Mutex,
+    };
+
+    use super::*;
+    use crate::cli::chat::util::shared_writer::TestWriterWithSink;
+
+    fn test_events(format: OutputFormat) -> (ChatEvents, TestWriterWithSink) {
+        let writer = TestWriterWithSink {
+            sink: Arc::new(Mutex::new(Vec::new())),
+        };
+        (ChatEvents::new(format, writer.clone()), writer)
+    }
+
+    fn text(delta: &str) -> ChatEvent {
+        ChatEvent::AssistantText {
+            text: delta.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_stream_json() {
+        let (mut events, writer) = test_events(OutputFormat::StreamJson);
+        events.emit(text("Hello")).unwrap();
+        events.emit(text(" world")).unwrap();
+        events.report(ChatStatus::ToolDenied, None);
+        assert_eq!(events.finish("abc").unwrap(), ExitCode::from(3));
+
+        let output = String::from_utf8(writer.get_content()).unwrap();
+        let lines = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[0],
+            serde_json::json!({ "type": "assistant_text", "text": "Hello" })
+        );
+        assert_eq!(
+            lines[2],
+            serde_json::json!({ "type": "result", "status": "tool_denied", "conversation_id": "abc" })
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let (mut events, writer) = test_events(OutputFormat::Json);
+        events.emit(text("Hello")).unwrap();
+        events.emit(text(" world")).unwrap();
+        events
+            .emit(ChatEvent::from(&ToolUseResult {
+                tool_use_id: "1".to_string(),
+                content: vec![ToolUseResultBlock::Text("done".to_string())],
+                status: ToolResultStatus::Success,
+            }))
+            .unwrap();
+        assert!(writer.get_content().is_empty());
+        events.report(ChatStatus::Error, Some("failed".to_string()));
+        events.report(ChatStatus::ToolDenied, None);
+        assert_eq!(events.finish("abc").unwrap(), ExitCode::from(1));
+
+        let output: serde_json::Value = serde_json::from_slice(&writer.get_content()).unwrap();
+        assert_eq!(
+            output,
+            serde_json::json!([
+                { "type": "assistant_text", "text": "Hello world" },
+                { "type": "tool_result", "tool_use_id": "1", "is_error": false, "content": ["done"] },
+                { "type": "result", "status": "error", "conversation_id": "abc", "error": "failed" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_text_writes_nothing() {
+        let (mut events, writer) = test_events(OutputFormat::Text);
+        events.emit(text("Hello")).unwrap();
+        assert_eq!(events.finish("abc").unwrap(), ExitCode::SUCCESS);
+        assert!(writer.get_content().is_empty());
+    }
+}
@@ -1,4 +1,5 @@
 use std::io::Write;
+use std::sync::LazyLock;
 
 use crossterm::style::{
     Attribute,
@@ -9,6 +10,16 @@ use crossterm::{
     Command,
     style,
 };
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{
+    Color as SyntectColor,
+    ScopeSelectors,
+    StyleModifier,
+    Theme,
+    ThemeItem,
+    ThemeSettings,
+};
+use syntect::parsing::SyntaxSet;
 use unicode_width::{
     UnicodeWidthChar,
     UnicodeWidthStr,
@@ -31,6 +42,7 @@ use winnow::combinator::{
 use winnow::error::{
     ErrMode,
     ErrorKind,
+    Needed,
     ParserError,
 };
 use winnow::prelude::*;
@@ -53,6 +65,9 @@ const URL_LINK_COLOR: Color = Color::DarkGrey;
 
 const DEFAULT_RULE_WIDTH: usize = 40;
 
+static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static CODE_THEME: LazyLock<Theme> = LazyLock::new(terminal_theme);
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error<'a> {
     #[error(transparent)]
@@ -90,6 +105,33 @@ pub struct ParseState {
     pub set_newline: bool,
     pub newline: bool,
     pub citations: Vec<(String, String)>,
+    /// Highlights the lines of the current code block, if its language is known.
+    highlighter: Option<CodeHighlighter>,
+    /// The table being received. Tables are only printed once complete since the width of each
+    /// column depends on every row.
+    table: Option<Table>,
+}
+
+struct CodeHighlighter(HighlightLines<'static>);
+
+impl std::fmt::Debug for CodeHighlighter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CodeHighlighter").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug)]
+struct Table {
+    alignments: Vec<Alignment>,
+    /// The header followed by the body rows, each with one cell per column.
+    rows: Vec<Vec<String>>,
 }
 
 impl ParseState {
@@ -104,10 +146,22 @@ impl ParseState {
             set_newline: false,
             newline: true,
             citations: vec![],
+            highlighter: None,
+            table: None,
         }
     }
 }
 
+/// Prints whatever is still held back once the response has ended, i.e. a table that was the last
+/// thing in the response.
+pub fn finish_markdown(mut o: impl Write, state: &mut ParseState) -> std::io::Result<()> {
+    if let Some(table) = state.table.take() {
+        queue_table(&mut o, &table, state.terminal_width)?;
+        state.column = 0;
+    }
+    Ok(())
+}
+
 pub fn interpret_markdown<'a, 'b>(
     mut i: Partial<&'a str>,
     mut o: impl Write + 'b,
@@ -138,6 +192,8 @@ pub fn interpret_markdown<'a, 'b>(
     match state.in_codeblock {
         false => {
             stateful_alt!(
+                // Tables come first since any other line ends the table being received
+                table,
                 // This pattern acts as a short circuit for alphanumeric plaintext
                 // More importantly, it's needed to support manual wordwrapping
                 text,
@@ -169,6 +225,7 @@ pub fn interpret_markdown<'a, 'b>(
         },
         true => {
             stateful_alt!(
+                codeblock_highlighted_line,
                 codeblock_less_than,
                 codeblock_greater_than,
                 codeblock_ampersand,
@@ -198,6 +255,95 @@ fn text<'a, 'b>(
     }
 }
 
+fn table<'a, 'b>(
+    mut o: impl Write + 'b,
+    state: &'b mut ParseState,
+) -> impl FnMut(&mut Partial<&'a str>) -> PResult<(), Error<'a>> + 'b {
+    move |i| {
+        if !state.newline {
+            return Err(ErrMode::from_error_kind(i, ErrorKind::Fail));
+        }
+
+        match state.table.as_mut() {
+            Some(table) => {
+                let start = i.checkpoint();
+                match table_row.parse_next(i) {
+                    Ok(mut cells) => {
+                        cells.resize(table.alignments.len(), String::new());
+                        table.rows.push(cells);
+                    },
+                    Err(ErrMode::Backtrack(_)) => {
+                        // Any other line ends the table, which can now be laid out
+                        i.reset(&start);
+                        finish_markdown(&mut o, state).map_err(|err| ErrMode::Cut(Error::Stdio(err)))?;
+                    },
+                    Err(err) => return Err(err),
+                }
+                state.set_newline = true;
+            },
+            None => {
+                let header = table_row.parse_next(i)?;
+                let alignments = table_row
+                    .verify_map(|cells| {
+                        cells
+                            .iter()
+                            .map(|cell| parse_alignment(cell))
+                            .collect::<Option<Vec<_>>>()
+                            .filter(|alignments| alignments.len() == header.len())
+                    })
+                    .parse_next(i)?;
+                state.table = Some(Table {
+                    alignments,
+                    rows: vec![header],
+                });
+                state.set_newline = true;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a row of a table, e.g. `| a | b |`, into its cells.
+fn table_row<'a>(i: &mut Partial<&'a str>) -> PResult<Vec<String>, Error<'a>> {
+    let row = delimited((space0, "|"), till_line_ending, ascii::line_ending).parse_next(i)?;
+    let row = row.trim_end();
+    let row = row.strip_suffix('|').filter(|row| !row.ends_with('\\')).unwrap_or(row);
+
+    // Pipes escaped as \\| are part of the cell
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'|') => cell.push(chars.next().unwrap_or('|')),
+            '|' => cells.push(std::mem::take(&mut cell)),
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell);
+
+    Ok(cells
+        .into_iter()
+        .map(|cell| unescape_entities(cell.trim()).replace("**", "").replace('`', ""))
+        .collect())
+}
+
+/// Parses a cell of the delimiter row of a table, e.g. `:---`.
+fn parse_alignment(cell: &str) -> Option<Alignment> {
+    let left = cell.starts_with(':');
+    let right = cell.len() > 1 && cell.ends_with(':');
+    let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+    if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+        return None;
+    }
+    Some(match (left, right) {
+        (true, true) => Alignment::Center,
+        (false, true) => Alignment::Right,
+        _ => Alignment::Left,
+    })
+}
+
 fn heading<'a, 'b>(
     mut o: impl Write + 'b,
     state: &'b mut ParseState,
@@ -555,12 +701,20 @@ fn codeblock_begin<'a, 'b>(
         ascii::line_ending.parse_next(i)?;
 
         state.in_codeblock = true;
+        // The info string may hold more than the language, e.g. ```rust title="main.rs"
+        state.highlighter = language
+            .split_whitespace()
+            .next()
+            .and_then(|token| SYNTAX_SET.find_syntax_by_token(token))
+            .map(|syntax| CodeHighlighter(HighlightLines::new(syntax, &CODE_THEME)));
 
         if !language.is_empty() {
             queue(&mut o, style::Print(format!("{}\n", language).bold()))?;
         }
 
-        queue(&mut o, style::SetForegroundColor(CODE_COLOR))?;
+        if state.highlighter.is_none() {
+            queue(&mut o, style::SetForegroundColor(CODE_COLOR))?;
+        }
 
         Ok(())
     }
@@ -573,10 +727,58 @@ fn codeblock_end<'a, 'b>(
     move |i| {
         "```".parse_next(i)?;
         state.in_codeblock = false;
+        state.highlighter = None;
         queue(&mut o, style::ResetColor)
     }
 }
 
+/// Highlights a line of a code block whose language is known. Highlighting needs whole lines, so
+/// this waits for the line to be complete, which is either at its end or at the closing fence.
+fn codeblock_highlighted_line<'a, 'b>(
+    mut o: impl Write + 'b,
+    state: &'b mut ParseState,
+) -> impl FnMut(&mut Partial<&'a str>) -> PResult<(), Error<'a>> + 'b {
+    move |i| {
+        let Some(CodeHighlighter(highlighter)) = state.highlighter.as_mut() else {
+            return Err(ErrMode::from_error_kind(i, ErrorKind::Fail));
+        };
+
+        let (len, newline) = match (i.find("```"), i.find('\n')) {
+            (Some(fence), Some(newline)) if newline < fence => (newline + 1, true),
+            (Some(fence), _) => (fence, false),
+            (None, Some(newline)) => (newline + 1, true),
+            (None, None) => return Err(ErrMode::Incomplete(Needed::Unknown)),
+        };
+        if len == 0 {
+            // The closing fence is handled by codeblock_end
+            return Err(ErrMode::from_error_kind(i, ErrorKind::Fail));
+        }
+
+        let line = i.next_slice(len);
+        let mut code = unescape_entities(line.trim_end_matches(['\r', '\n']));
+        code.push('\n');
+        match highlighter.highlight_line(&code, &SYNTAX_SET) {
+            Ok(ranges) => {
+                for (style, text) in ranges {
+                    queue(&mut o, style::SetForegroundColor(crossterm_color(style.foreground)))?;
+                    queue(&mut o, style::Print(text.trim_end_matches('\n')))?;
+                }
+                queue(&mut o, style::ResetColor)?;
+            },
+            Err(_) => {
+                queue(&mut o, style::SetForegroundColor(CODE_COLOR))?;
+                queue(&mut o, style::Print(code.trim_end_matches('\n')))?;
+                queue(&mut o, style::ResetColor)?;
+            },
+        }
+        if newline {
+            queue(&mut o, style::Print("\n"))?;
+        }
+
+        Ok(())
+    }
+}
+
 fn codeblock_less_than<'a, 'b>(
     mut o: impl Write + 'b,
     _state: &'b mut ParseState,
@@ -637,6 +839,215 @@ fn codeblock_fallback<'a, 'b>(
     }
 }
 
+/// Replaces the entities the model escapes its output with.
+fn unescape_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+/// Prints a table with box drawing borders, wrapping its cells so that it fits `terminal_width`.
+fn queue_table(o: &mut impl Write, table: &Table, terminal_width: Option<usize>) -> std::io::Result<()> {
+    use crossterm::QueueableCommand;
+
+    let widths = column_widths(table, terminal_width);
+    let border = |left: &str, middle: &str, right: &str| {
+        let lines = widths.iter().map(|width| "─".repeat(width + 2)).collect::<Vec<_>>();
+        format!("{left}{}{right}\n", lines.join(middle))
+    };
+
+    o.queue(style::SetForegroundColor(BLOCKQUOTE_COLOR))?;
+    o.queue(style::Print(border("┌", "┬", "┐")))?;
+    for (row_index, row) in table.rows.iter().enumerate() {
+        if row_index == 1 {
+            o.queue(style::Print(border("├", "┼", "┤")))?;
+        }
+        let cells = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| wrap_text(cell, *width))
+            .collect::<Vec<_>>();
+        let height = cells.iter().map(Vec::len).max().unwrap_or(1);
+        for line_index in 0..height {
+            for ((lines, width), alignment) in cells.iter().zip(&widths).zip(&table.alignments) {
+                let line = lines.get(line_index).map_or("", String::as_str);
+                let padding = width.saturating_sub(line.width());
+                let (before, after) = match alignment {
+                    Alignment::Left => (0, padding),
+                    Alignment::Center => (padding / 2, padding - padding / 2),
+                    Alignment::Right => (padding, 0),
+                };
+                o.queue(style::Print("│ "))?;
+                o.queue(style::ResetColor)?;
+                o.queue(style::Print(" ".repeat(before)))?;
+                match row_index {
+                    0 => o.queue(style::Print(line.bold()))?,
+                    _ => o.queue(style::Print(line))?,
+                };
+                o.queue(style::Print(" ".repeat(after + 1)))?;
+                o.queue(style::SetForegroundColor(BLOCKQUOTE_COLOR))?;
+            }
+            o.queue(style::Print("│\n"))?;
+        }
+    }
+    o.queue(style::Print(border("└", "┴", "┘")))?;
+    o.queue(style::ResetColor)?;
+    Ok(())
+}
+
+/// Returns the width of each column of `table`. Columns keep their natural width when the table
+/// fits, otherwise the widest columns are shrunk until it does.
+fn column_widths(table: &Table, terminal_width: Option<usize>) -> Vec<usize> {
+    let mut widths = vec![1; table.alignments.len()];
+    for row in &table.rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.width());
+        }
+    }
+
+    let Some(terminal_width) = terminal_width else {
+        return widths;
+    };
+    // Each column is padded by a space on both sides and followed by a border
+    let mut available = terminal_width.saturating_sub(3 * widths.len() + 1);
+    if widths.iter().sum::<usize>() <= available {
+        return widths;
+    }
+
+    // Columns narrower than an even share of the remaining width keep their natural width, the
+    // others split what is left.
+    let mut shrunk = widths.iter().map(|_| false).collect::<Vec<_>>();
+    loop {
+        let remaining = shrunk.iter().filter(|shrunk| !**shrunk).count();
+        if remaining == 0 {
+            break;
+        }
+        let share = available / remaining;
+        let mut changed = false;
+        for (width, shrunk) in widths.iter().zip(shrunk.iter_mut()) {
+            if !*shrunk && *width <= share {
+                *shrunk = true;
+                available -= *width;
+                changed = true;
+            }
+        }
+        if !changed {
+            let mut extra = available % remaining;
+            for (width, shrunk) in widths.iter_mut().zip(&shrunk) {
+                if !*shrunk {
+                    *width = (share + usize::from(extra > 0)).max(1);
+                    extra = extra.saturating_sub(1);
+                }
+            }
+            break;
+        }
+    }
+    widths
+}
+
+/// Wraps `text` into lines no wider than `width`, breaking words that do not fit on a line.
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.width() + 1 + word.width() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        for c in word.chars() {
+            if !line.is_empty() && line.width() + c.width().unwrap_or(0) > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            line.push(c);
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+/// A theme that colors code with the palette of the terminal, so that it fits whatever colors the
+/// user has picked. Colors with an alpha of 0 hold an ANSI color index rather than a RGB color,
+/// see [crossterm_color].
+fn terminal_theme() -> Theme {
+    const fn ansi(index: u8) -> SyntectColor {
+        SyntectColor {
+            r: index,
+            g: 0,
+            b: 0,
+            a: 0,
+        }
+    }
+
+    let scopes = [
+        ("comment, punctuation.definition.comment", ansi(8)),
+        ("string, constant.character.escape, markup.inline.raw", ansi(2)),
+        ("constant.numeric, constant.language, constant.character", ansi(5)),
+        (
+            "keyword, storage.type, storage.modifier, keyword.operator.word",
+            ansi(5),
+        ),
+        ("entity.name.function, support.function, meta.function-call", ansi(4)),
+        (
+            "entity.name.type, entity.name.class, entity.name.struct, entity.name.enum, support.type, support.class",
+            ansi(3),
+        ),
+        ("entity.name.tag, meta.tag", ansi(1)),
+        ("entity.other.attribute-name, variable.parameter", ansi(3)),
+        ("variable.language, support.constant", ansi(6)),
+        ("markup.heading, entity.name.section", ansi(4)),
+        ("markup.inserted", ansi(2)),
+        ("markup.deleted", ansi(1)),
+    ];
+
+    Theme {
+        name: Some("terminal".to_string()),
+        settings: ThemeSettings {
+            // An alpha of 1 stands for the default foreground color of the terminal
+            foreground: Some(SyntectColor { r: 0, g: 0, b: 0, a: 1 }),
+            ..Default::default()
+        },
+        scopes: scopes
+            .into_iter()
+            .filter_map(|(scope, color)| {
+                Some(ThemeItem {
+                    scope: scope.parse::<ScopeSelectors>().ok()?,
+                    style: StyleModifier {
+                        foreground: Some(color),
+                        background: None,
+                        font_style: None,
+                    },
+                })
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// Converts a color of [terminal_theme] to the terminal color it stands for.
+fn crossterm_color(color: SyntectColor) -> Color {
+    match (color.a, color.r) {
+        (0, 0) => Color::Black,
+        (0, 1) => Color::DarkRed,
+        (0, 2) => Color::DarkGreen,
+        (0, 3) => Color::DarkYellow,
+        (0, 4) => Color::DarkBlue,
+        (0, 5) => Color::DarkMagenta,
+        (0, 6) => Color::DarkCyan,
+        (0, 7) => Color::Grey,
+        (0, 8) => Color::DarkGrey,
+        (0, index) => Color::AnsiValue(index),
+        (1, _) => Color::Reset,
+        _ => Color::Rgb {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+        },
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::io::Write;
@@ -693,6 +1104,18 @@ mod tests {
         style::SetAttribute(Attribute::Bold),
         style::Print("java\n"),
         style::SetAttribute(Attribute::Reset),
+        style::SetForegroundColor(Color::Reset),
+        style::Print("hello world"),
+        style::SetForegroundColor(Color::DarkMagenta),
+        style::Print("!"),
+        style::SetForegroundColor(Color::Reset),
+        style::ResetColor,
+        style::ResetColor,
+    ]);
+    validate!(codeblock_unknown_language_1, "```nolang\nhello world!```", [
+        style::SetAttribute(Attribute::Bold),
+        style::Print("nolang\n"),
+        style::SetAttribute(Attribute::Reset),
         style::SetForegroundColor(CODE_COLOR),
         style::Print("hello world!"),
         style::ResetColor,
@@ -759,4 +1182,56 @@ mod tests {
     validate!(square_bracket_url_like_2, "[text](without url part", [style::Print(
         "[text](without url part"
     )]);
+    validate!(table_1, "| a | b |\n|:-:|--:|\n| c | dd |\nend", [
+        style::SetForegroundColor(BLOCKQUOTE_COLOR),
+        style::Print("┌───┬────┐\n"),
+        style::Print("│ "),
+        style::ResetColor,
+        style::Print(""),
+        style::Print("a".bold()),
+        style::Print(" "),
+        style::SetForegroundColor(BLOCKQUOTE_COLOR),
+        style::Print("│ "),
+        style::ResetColor,
+        style::Print(" "),
+        style::Print("b".bold()),
+        style::Print(" "),
+        style::SetForegroundColor(BLOCKQUOTE_COLOR),
+        style::Print("│\n"),
+        style::Print("├───┼────┤\n"),
+        style::Print("│ "),
+        style::ResetColor,
+        style::Print(""),
+        style::Print("c"),
+        style::Print(" "),
+        style::SetForegroundColor(BLOCKQUOTE_COLOR),
+        style::Print("│ "),
+        style::ResetColor,
+        style::Print(""),
+        style::Print("dd"),
+        style::Print(" "),
+        style::SetForegroundColor(BLOCKQUOTE_COLOR),
+        style::Print("│\n"),
+        style::Print("└───┴────┘\n"),
+        style::ResetColor,
+        style::Print("end"),
+    ]);
+
+    #[test]
+    fn column_widths_fit_terminal() {
+        let table = Table {
+            alignments: vec![Alignment::Left; 3],
+            rows: vec![vec!["id".to_string(), "a".repeat(30), "b".repeat(50)]],
+        };
+        assert_eq!(column_widths(&table, None), vec![2, 30, 50]);
+        // 40 columns less the borders and padding leaves 30 for the cells
+        assert_eq!(column_widths(&table, Some(40)), vec![2, 14, 14]);
+    }
+
+    #[test]
+    fn wrap_text_breaks_long_words() {
+        assert_eq!(wrap_text("hello big world", 9), vec!["hello big", "world"]);
+        assert_eq!(wrap_text("abcdef", 4), vec!["abcd", "ef"]);
+        assert_eq!(wrap_text("", 4), vec![""]);
+    }
 }
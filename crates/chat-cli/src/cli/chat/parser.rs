@@ -15,6 +15,10 @@ use super::message::{
     AssistantMessage,
     AssistantToolUse,
 };
+use super::recording::{
+    RecordedEvent,
+    SessionRecorder,
+};
 use crate::api_client::clients::SendMessageOutput;
 use crate::api_client::model::ChatResponseStream;
 use crate::telemetry::ReasonCode;
@@ -97,6 +101,8 @@ pub struct ResponseParser {
     /// Whether or not we are currently receiving tool use delta events. Tuple of
     /// `Some((tool_use_id, name))` if true, [None] otherwise.
     parsing_tool_use: Option<(String, String)>,
+    /// Records the received events when the session is being recorded.
+    recorder: Option<SessionRecorder>,
 }
 
 impl ResponseParser {
@@ -110,9 +116,16 @@ impl ResponseParser {
             assistant_text: String::new(),
             tool_uses: Vec::new(),
             parsing_tool_use: None,
+            recorder: None,
         }
     }
 
+    /// Records every event received from the response with `recorder`.
+    pub fn with_recorder(mut self, recorder: Option<SessionRecorder>) -> Self {
+        self.recorder = recorder;
+        self
+    }
+
     /// Consumes the associated [ConverseStreamResponse] until a valid [ResponseEvent] is parsed.
     pub async fn recv(&mut self) -> Result<ResponseEvent, RecvError> {
         if let Some((id, name)) = self.parsing_tool_use.take() {
@@ -280,6 +293,15 @@ impl ResponseParser {
         let start = std::time::Instant::now();
         let result = self.response.recv().await;
         let duration = std::time::Instant::now().duration_since(start);
+        if let Some(recorder) = &self.recorder {
+            recorder.record(match &result {
+                Ok(Some(event)) => RecordedEvent::Response { event: event.clone() },
+                Ok(None) => RecordedEvent::ResponseEnd { error: None },
+                Err(err) => RecordedEvent::ResponseEnd {
+                    error: Some(err.to_string()),
+                },
+            });
+        }
         match result {
             Ok(r) => {
                 trace!(?r, "Received new event");
@@ -377,7 +399,7 @@ mod tests {
             },
         ];
         events.reverse();
-        let mock = SendMessageOutput::Mock(events);
+        let mock = SendMessageOutput::Mock(events, None);
         let mut parser = ResponseParser::new(mock);
 
         for _ in 0..5 {
@@ -52,6 +52,8 @@ pub const COMMANDS: &[&str] = &[
     "/tools untrust",
     "/tools trustall",
     "/tools reset",
+    "/tools rules",
+    "/resources",
     "/model",
     "/profile",
     "/profile help",
@@ -59,6 +61,7 @@ pub const COMMANDS: &[&str] = &[
     "/profile create",
     "/profile delete",
     "/profile rename",
+    "/profile sandbox",
     "/profile set",
     "/context help",
     "/context show",
@@ -78,9 +81,20 @@ pub const COMMANDS: &[&str] = &[
     "/context hooks disable-all",
     "/compact",
     "/compact help",
+    "/compact --log",
     "/usage",
     "/save",
     "/load",
+    "/undo",
+    "/checkpoints",
+    "/history",
+    "/history --all",
+    "/knowledge",
+    "/knowledge help",
+    "/knowledge list",
+    "/knowledge add",
+    "/knowledge rm",
+    "/knowledge search",
     "/subscribe",
 ];
 
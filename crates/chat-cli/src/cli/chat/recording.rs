@@ -0,0 +1,355 @@
+//! Session recordings for `q chat --record <file>` and `q chat --replay <file>`.
+//!
+//! A recording is a newline delimited JSON file of [RecordedEvent]s: the lines the user entered,
+//! the requests sent to the model along with every event of their responses, and the tools that
+//! were invoked along with their results. Replaying a recording drives the chat with the recorded
+//! input, serves the recorded responses instead of calling the model, and returns the recorded
+//! tool results instead of invoking the tools, so a session can be reproduced offline.
+//!
+//! Replaying while recording to another file produces the same recording as long as the chat
+//! behaves the same, which makes recordings of bug reports usable as regression tests. Replays
+//! fail as soon as the chat sends a request that differs from the recorded one.
+
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+use std::fs::File;
+use std::io::{
+    BufRead,
+    BufReader,
+    Write,
+};
+use std::path::Path;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use eyre::{
+    Result,
+    eyre,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tracing::error;
+
+use super::input_source::InputSource;
+use super::tools::{
+    InvokeOutput,
+    OutputKind,
+};
+use crate::api_client::StreamingClient;
+use crate::api_client::clients::MockResponse;
+use crate::api_client::model::{
+    ChatResponseStream,
+    ConversationState,
+};
+
+/// An entry of a session recording.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecordedEvent {
+    /// How the chat was started, always the first event.
+    Start {
+        interactive: bool,
+        trust_all_tools: bool,
+        trust_tools: Option<Vec<String>>,
+    },
+    /// A line entered by the user, or the question given on the command line if `initial`.
+    Input {
+        line: String,
+        #[serde(default)]
+        initial: bool,
+    },
+    /// A request sent to the model.
+    Request {
+        content: String,
+        tool_result_ids: Vec<String>,
+        history_len: usize,
+    },
+    /// An event of the response to the last request.
+    Response { event: ChatResponseStream },
+    /// The end of the response to the last request, with the error that ended it if any.
+    ResponseEnd { error: Option<String> },
+    /// A tool about to be invoked.
+    ToolUse {
+        tool_use_id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    /// The result of a tool, in the same shape as the `tool_response` given to post tool use
+    /// hooks.
+    ToolResult {
+        tool_use_id: String,
+        response: serde_json::Value,
+    },
+}
+
+impl RecordedEvent {
+    pub fn request(conversation_state: &ConversationState) -> Self {
+        let tool_result_ids = conversation_state
+            .user_input_message
+            .user_input_message_context
+            .as_ref()
+            .and_then(|context| context.tool_results.as_ref())
+            .map(|results| results.iter().map(|result| result.tool_use_id.clone()).collect())
+            .unwrap_or_default();
+        Self::Request {
+            content: conversation_state.user_input_message.content.clone(),
+            tool_result_ids,
+            history_len: conversation_state.history.as_ref().map_or(0, Vec::len),
+        }
+    }
+}
+
+/// Appends [RecordedEvent]s to a recording file. Clones write to the same file.
+#[derive(Debug, Clone)]
+pub struct SessionRecorder {
+    file: Arc<Mutex<File>>,
+}
+
+impl SessionRecorder {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path).map_err(|err| eyre!("failed to create {}: {err}", path.display()))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Writes `event` to the recording. Failures are logged rather than interrupting the chat.
+    pub fn record(&self, event: RecordedEvent) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        let result = serde_json::to_vec(&event)
+            .map_err(std::io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                file.write_all(&line)
+            });
+        if let Err(err) = result {
+            error!(?err, "failed to write to the session recording");
+        }
+    }
+}
+
+/// A recording loaded for replay.
+#[derive(Debug, Default)]
+pub struct SessionReplay {
+    pub interactive: bool,
+    pub trust_all_tools: bool,
+    pub trust_tools: Option<Vec<String>>,
+    /// The question given on the command line.
+    pub initial_input: Option<String>,
+    /// The lines entered by the user.
+    inputs: Vec<String>,
+    /// The requests sent to the model, as [RecordedEvent::Request]s.
+    requests: VecDeque<RecordedEvent>,
+    /// The response to each request.
+    responses: Vec<MockResponse>,
+    /// The recorded `tool_response` of each tool use by id.
+    tool_results: HashMap<String, VecDeque<serde_json::Value>>,
+}
+
+impl SessionReplay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|err| eyre!("failed to open {}: {err}", path.display()))?;
+        let mut events = Vec::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = serde_json::from_str(&line)
+                .map_err(|err| eyre!("invalid recording {}:{}: {err}", path.display(), index + 1))?;
+            events.push(event);
+        }
+        Ok(Self::from_events(events))
+    }
+
+    pub fn from_events(events: impl IntoIterator<Item = RecordedEvent>) -> Self {
+        let mut replay = Self::default();
+        for event in events {
+            match event {
+                RecordedEvent::Start {
+                    interactive,
+                    trust_all_tools,
+                    trust_tools,
+                } => {
+                    replay.interactive = interactive;
+                    replay.trust_all_tools = trust_all_tools;
+                    replay.trust_tools = trust_tools;
+                },
+                RecordedEvent::Input { line, initial: true } => replay.initial_input = Some(line),
+                RecordedEvent::Input { line, initial: false } => replay.inputs.push(line),
+                request @ RecordedEvent::Request { .. } => {
+                    replay.requests.push_back(request);
+                    replay.responses.push(MockResponse::default());
+                },
+                RecordedEvent::Response { event } => {
+                    if let Some(response) = replay.responses.last_mut() {
+                        response.events.push(event);
+                    }
+                },
+                RecordedEvent::ResponseEnd { error } => {
+                    if let Some(response) = replay.responses.last_mut() {
+                        response.error = error;
+                    }
+                },
+                RecordedEvent::ToolUse { .. } => {},
+                RecordedEvent::ToolResult { tool_use_id, response } => {
+                    replay.tool_results.entry(tool_use_id).or_default().push_back(response);
+                },
+            }
+        }
+        replay
+    }
+
+    /// A client that answers each request with the recorded response, raising the error that
+    /// ended it if any.
+    pub fn client(&self) -> StreamingClient {
+        StreamingClient::mock_responses(self.responses.clone())
+    }
+
+    /// Checks that `request`, a [RecordedEvent::Request], is the next request of the recording.
+    pub fn check_request(&mut self, request: &RecordedEvent) -> Result<()> {
+        match self.requests.pop_front() {
+            Some(expected) if expected == *request => Ok(()),
+            Some(expected) => Err(eyre!(
+                "the request does not match the recording, expected {expected:?} but got {request:?}"
+            )),
+            None => Err(eyre!("the recording has no more requests, but got {request:?}")),
+        }
+    }
+
+    /// An input source that enters the recorded lines, ending the chat once they run out.
+    pub fn input_source(&self) -> InputSource {
+        InputSource::new_mock(self.inputs.clone())
+    }
+
+    /// Returns the recorded result of a tool use in place of invoking the tool.
+    pub fn take_tool_result(&mut self, tool_use_id: &str) -> Result<InvokeOutput> {
+        let response = self
+            .tool_results
+            .get_mut(tool_use_id)
+            .and_then(VecDeque::pop_front)
+            .ok_or_else(|| eyre!("the recording has no result for the tool use {tool_use_id}"))?;
+        match response.get("status").and_then(|status| status.as_str()) {
+            Some("success") => Ok(InvokeOutput {
+                output: match response.get("output") {
+                    Some(serde_json::Value::String(text)) => OutputKind::Text(text.clone()),
+                    Some(json) => OutputKind::Json(json.clone()),
+                    None => OutputKind::Text(String::new()),
+                },
+            }),
+            _ => Err(eyre!(
+                "{}",
+                response.get("error").and_then(|err| err.as_str()).unwrap_or_default()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_from_events() {
+        let tool_use = |input: Option<&str>, stop: Option<bool>| ChatResponseStream::ToolUseEvent {
+            tool_use_id: "1".to_string(),
+            name: "fs_read".to_string(),
+            input: input.map(str::to_string),
+            stop,
+        };
+        let request = |content: &str| RecordedEvent::Request {
+            content: content.to_string(),
+            tool_result_ids: vec![],
+            history_len: 0,
+        };
+        let mut replay = SessionReplay::from_events([
+            RecordedEvent::Start {
+                interactive: true,
+                trust_all_tools: false,
+                trust_tools: None,
+            },
+            RecordedEvent::Input {
+                line: "read it".to_string(),
+                initial: true,
+            },
+            request("read it"),
+            RecordedEvent::Response {
+                event: tool_use(None, None),
+            },
+            RecordedEvent::Response {
+                event: tool_use(Some("{}"), Some(true)),
+            },
+            RecordedEvent::ResponseEnd { error: None },
+            RecordedEvent::Input {
+                line: "y".to_string(),
+                initial: false,
+            },
+            RecordedEvent::ToolResult {
+                tool_use_id: "1".to_string(),
+                response: serde_json::json!({ "status": "success", "output": "contents" }),
+            },
+            request(""),
+            RecordedEvent::ResponseEnd {
+                error: Some("stream timed out".to_string()),
+            },
+        ]);
+
+        assert!(replay.interactive);
+        assert_eq!(replay.initial_input.as_deref(), Some("read it"));
+        assert_eq!(replay.inputs, vec!["y".to_string()]);
+        assert_eq!(replay.responses, vec![
+            MockResponse {
+                events: vec![tool_use(None, None), tool_use(Some("{}"), Some(true))],
+                error: None,
+            },
+            MockResponse {
+                events: vec![],
+                error: Some("stream timed out".to_string()),
+            }
+        ]);
+        assert_eq!(replay.take_tool_result("1").unwrap().as_str(), "contents");
+        assert!(replay.take_tool_result("1").is_err());
+
+        assert!(replay.check_request(&request("read it")).is_ok());
+        assert!(replay.check_request(&request("something else")).is_err());
+        assert!(replay.check_request(&request("")).is_err());
+    }
+
+    #[test]
+    fn test_recorder_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let recorder = SessionRecorder::create(&path).unwrap();
+        let events = vec![
+            RecordedEvent::Input {
+                line: "hello".to_string(),
+                initial: false,
+            },
+            RecordedEvent::Response {
+                event: ChatResponseStream::AssistantResponseEvent {
+                    content: "hi".to_string(),
+                },
+            },
+        ];
+        for event in &events {
+            recorder.clone().record(event.clone());
+        }
+
+        let recorded = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<RecordedEvent>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(recorded, events);
+    }
+}
@@ -0,0 +1,87 @@
+//! MCP resources referenced in prompts.
+//!
+//! Servers that support resources list them as they start up, which `/resources` shows. A prompt
+//! can then attach the contents of a resource by referencing it as `@server:uri`, for example
+//! `summarize @docs:file:///guide.md`.
+
+/// A resource referenced in a prompt with `@server:uri`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceReference {
+    pub server_name: String,
+    pub uri: String,
+}
+
+impl std::fmt::Display for ResourceReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "@{}:{}", self.server_name, self.uri)
+    }
+}
+
+/// Characters that may end a reference without being part of the uri, e.g. `see @docs:guide.md.`
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', '!', '?', ')', '"', '\''];
+
+/// Returns the distinct resources referenced in `prompt`. Only references to servers for which
+/// `is_server` returns true are considered, so that other uses of `@` are left alone.
+pub fn parse_resource_references(prompt: &str, is_server: impl Fn(&str) -> bool) -> Vec<ResourceReference> {
+    let mut references = Vec::<ResourceReference>::new();
+    for word in prompt.split_whitespace() {
+        let Some((server_name, uri)) = word.strip_prefix('@').and_then(|word| word.split_once(':')) else {
+            continue;
+        };
+        let uri = uri.trim_end_matches(TRAILING_PUNCTUATION);
+        if uri.is_empty() || !is_server(server_name) {
+            continue;
+        }
+        let reference = ResourceReference {
+            server_name: server_name.to_string(),
+            uri: uri.to_string(),
+        };
+        if !references.contains(&reference) {
+            references.push(reference);
+        }
+    }
+    references
+}
+
+/// Whether the input starts with a resource reference rather than a prompt name, in which case
+/// it should be sent as a question instead of being treated as `@prompt`.
+pub fn starts_with_resource_reference(input: &str) -> bool {
+    input
+        .strip_prefix('@')
+        .and_then(|input| input.split_whitespace().next())
+        .is_some_and(|word| word.contains(':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resource_references() {
+        let is_server = |name: &str| ["docs", "db"].contains(&name);
+        let references = parse_resource_references(
+            "compare @docs:file:///guide.md, @db:postgres://main/users and @docs:file:///guide.md. \
+             email me@example.com or try @unknown:thing and @docs:",
+            is_server,
+        );
+        assert_eq!(references, vec![
+            ResourceReference {
+                server_name: "docs".to_string(),
+                uri: "file:///guide.md".to_string(),
+            },
+            ResourceReference {
+                server_name: "db".to_string(),
+                uri: "postgres://main/users".to_string(),
+            },
+        ]);
+        assert_eq!(references[0].to_string(), "@docs:file:///guide.md");
+    }
+
+    #[test]
+    fn test_starts_with_resource_reference() {
+        assert!(starts_with_resource_reference("@docs:file:///guide.md summarize this"));
+        assert!(!starts_with_resource_reference("@my-prompt arg1"));
+        assert!(!starts_with_resource_reference("@server/my-prompt \"a:b\""));
+        assert!(!starts_with_resource_reference("hello @docs:guide"));
+    }
+}
@@ -1,4 +1,5 @@
 use std::ops::Deref;
+use std::sync::OnceLock;
 
 use super::conversation_state::{
     BackendConversationState,
@@ -11,17 +12,21 @@ use super::message::{
     UserMessage,
     UserMessageContent,
 };
+use super::tokenizer::{
+    HeuristicTokenizer,
+    Tokenizer,
+};
 
-#[derive(Debug, Clone, Copy)]
-pub struct CharCount(usize);
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TokenCount(usize);
 
-impl CharCount {
+impl TokenCount {
     pub fn value(&self) -> usize {
         self.0
     }
 }
 
-impl Deref for CharCount {
+impl Deref for TokenCount {
     type Target = usize;
 
     fn deref(&self) -> &Self::Target {
@@ -29,152 +34,156 @@ impl Deref for CharCount {
     }
 }
 
-impl From<usize> for CharCount {
+impl From<usize> for TokenCount {
     fn from(value: usize) -> Self {
         Self(value)
     }
 }
 
-impl std::ops::Add for CharCount {
-    type Output = CharCount;
+impl std::ops::Add for TokenCount {
+    type Output = TokenCount;
 
     fn add(self, rhs: Self) -> Self::Output {
         Self(self.value() + rhs.value())
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct TokenCount(usize);
-
-impl TokenCount {
-    pub fn value(&self) -> usize {
-        self.0
-    }
-}
-
-impl Deref for TokenCount {
-    type Target = usize;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl From<CharCount> for TokenCount {
-    fn from(value: CharCount) -> Self {
-        Self(TokenCounter::count_tokens_char_count(value.value()))
-    }
-}
-
 impl std::fmt::Display for TokenCount {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
 
+/// The tokenizer used for the lifetime of the process, see [TokenCounter::init].
+static TOKENIZER: OnceLock<Box<dyn Tokenizer>> = OnceLock::new();
+
 pub struct TokenCounter;
 
 impl TokenCounter {
-    pub const TOKEN_TO_CHAR_RATIO: usize = 3;
-
-    /// Estimates the number of tokens in the input content.
-    /// Currently uses a simple heuristic: content length / TOKEN_TO_CHAR_RATIO
-    ///
-    /// Rounds up to the nearest multiple of 10 to avoid giving users a false sense of precision.
-    pub fn count_tokens(content: &str) -> usize {
-        Self::count_tokens_char_count(content.len())
+    /// Sets the tokenizer used to count tokens. Has no effect if tokens have already been counted,
+    /// in which case the previously used tokenizer is kept.
+    pub fn init(tokenizer: Box<dyn Tokenizer>) {
+        TOKENIZER.set(tokenizer).ok();
     }
 
-    fn count_tokens_char_count(count: usize) -> usize {
-        (count / Self::TOKEN_TO_CHAR_RATIO + 5) / 10 * 10
+    /// Returns the tokenizer used to count tokens, falling back to [HeuristicTokenizer] if none
+    /// was configured.
+    pub fn tokenizer() -> &'static dyn Tokenizer {
+        TOKENIZER.get_or_init(|| Box::new(HeuristicTokenizer)).as_ref()
     }
 
-    pub const fn token_to_chars(token: usize) -> usize {
-        token * Self::TOKEN_TO_CHAR_RATIO
+    /// Counts the number of tokens in the input content using the configured tokenizer.
+    ///
+    /// Estimated counts are rounded to the nearest multiple of 10 to avoid giving users a false
+    /// sense of precision.
+    pub fn count_tokens(content: &str) -> usize {
+        let tokenizer = Self::tokenizer();
+        let count = tokenizer.count_tokens(content);
+        match tokenizer.is_estimate() {
+            true => (count + 5) / 10 * 10,
+            false => count,
+        }
     }
 }
 
-/// A trait for types that represent some number of characters (aka bytes). For use in calculating
-/// context window size utilization.
-pub trait CharCounter {
-    /// Returns the number of characters contained within this type.
-    ///
-    /// One "character" is essentially the same as one "byte"
-    fn char_count(&self) -> CharCount;
+/// A trait for types containing text that takes up space in the context window.
+pub trait TokenCounted {
+    /// Returns the sum of `count` applied to each piece of text contained within this type.
+    fn count_text(&self, count: &dyn Fn(&str) -> usize) -> usize;
+
+    /// Returns the number of tokens contained within this type according to the configured
+    /// tokenizer.
+    fn token_count(&self) -> TokenCount {
+        let tokenizer = TokenCounter::tokenizer();
+        // Counts based on the length of the text are rounded down, so they are computed from the
+        // total length rather than added up for every piece of text.
+        if let Some(count) = tokenizer.count_tokens_for_len(self.count_text(&str::len)) {
+            return TokenCount(count);
+        }
+        TokenCount(self.count_text(&|text| tokenizer.count_tokens(text)))
+    }
 }
 
-impl CharCounter for BackendConversationState<'_> {
-    fn char_count(&self) -> CharCount {
-        self.calculate_conversation_size().char_count()
+impl TokenCounted for BackendConversationState<'_> {
+    fn count_text(&self, count: &dyn Fn(&str) -> usize) -> usize {
+        self.context_messages
+            .iter()
+            .flatten()
+            .chain(self.history.clone())
+            .fold(0, |acc, (user, assistant)| {
+                acc + user.count_text(count) + assistant.count_text(count)
+            })
     }
 }
 
-impl CharCounter for ConversationSize {
-    fn char_count(&self) -> CharCount {
+impl ConversationSize {
+    /// The total number of tokens in the conversation.
+    pub fn total(&self) -> TokenCount {
         self.user_messages + self.assistant_messages + self.context_messages
     }
 }
 
-impl CharCounter for UserMessage {
-    fn char_count(&self) -> CharCount {
-        let mut total_chars = 0;
-        total_chars += self.additional_context().len();
+impl TokenCounted for UserMessage {
+    fn count_text(&self, count: &dyn Fn(&str) -> usize) -> usize {
+        let mut total = 0;
+        total += count(self.additional_context());
+        total += count(self.resource_context());
         match self.content() {
             UserMessageContent::Prompt { prompt } => {
-                total_chars += prompt.len();
+                total += count(prompt);
             },
             UserMessageContent::CancelledToolUses {
                 prompt,
                 tool_use_results,
             } => {
-                total_chars += prompt.as_ref().map_or(0, String::len);
-                total_chars += tool_use_results.as_slice().char_count().0;
+                total += prompt.as_deref().map_or(0, count);
+                total += tool_use_results.as_slice().count_text(count);
             },
             UserMessageContent::ToolUseResults { tool_use_results } => {
-                total_chars += tool_use_results.as_slice().char_count().0;
+                total += tool_use_results.as_slice().count_text(count);
             },
         }
-        total_chars.into()
+        total
     }
 }
 
-impl CharCounter for AssistantMessage {
-    fn char_count(&self) -> CharCount {
-        let mut total_chars = 0;
-        total_chars += self.content().len();
+impl TokenCounted for AssistantMessage {
+    fn count_text(&self, count: &dyn Fn(&str) -> usize) -> usize {
+        let mut total = 0;
+        total += count(self.content());
         if let Some(tool_uses) = self.tool_uses() {
-            total_chars += tool_uses
+            total += tool_uses
                 .iter()
-                .map(|v| calculate_value_char_count(&v.args))
-                .reduce(|acc, e| acc + e)
-                .unwrap_or_default();
+                .map(|v| calculate_value_text_count(&v.args, count))
+                .sum::<usize>();
         }
-        total_chars.into()
+        total
     }
 }
 
-impl CharCounter for &[ToolUseResult] {
-    fn char_count(&self) -> CharCount {
-        self.iter()
-            .flat_map(|v| &v.content)
-            .fold(0, |acc, v| {
-                acc + match v {
-                    ToolUseResultBlock::Json(v) => calculate_value_char_count(v),
-                    ToolUseResultBlock::Text(s) => s.len(),
-                }
-            })
-            .into()
+impl TokenCounted for &[ToolUseResult] {
+    fn count_text(&self, count: &dyn Fn(&str) -> usize) -> usize {
+        self.iter().flat_map(|v| &v.content).fold(0, |acc, v| {
+            acc + match v {
+                ToolUseResultBlock::Json(v) => calculate_value_text_count(v, count),
+                ToolUseResultBlock::Text(s) => count(s),
+            }
+        })
     }
 }
 
-fn calculate_value_char_count(document: &serde_json::Value) -> usize {
+/// Applies `count` to the strings contained within `document`. Other scalar values are counted
+/// as 1.
+fn calculate_value_text_count(document: &serde_json::Value, count: &dyn Fn(&str) -> usize) -> usize {
     match document {
         serde_json::Value::Null => 1,
         serde_json::Value::Bool(_) => 1,
         serde_json::Value::Number(_) => 1,
-        serde_json::Value::String(s) => s.len(),
-        serde_json::Value::Array(vec) => vec.iter().fold(0, |acc, v| acc + calculate_value_char_count(v)),
-        serde_json::Value::Object(map) => map.values().fold(0, |acc, v| acc + calculate_value_char_count(v)),
+        serde_json::Value::String(s) => count(s),
+        serde_json::Value::Array(vec) => vec.iter().fold(0, |acc, v| acc + calculate_value_text_count(v, count)),
+        serde_json::Value::Object(map) => map
+            .values()
+            .fold(0, |acc, v| acc + calculate_value_text_count(v, count)),
     }
 }
 
@@ -182,6 +191,11 @@ fn calculate_value_char_count(document: &serde_json::Value) -> usize {
 mod tests {
 
     use super::*;
+    use crate::api_client::model::ToolResultStatus;
+
+    fn calculate_value_char_count(document: &serde_json::Value) -> usize {
+        calculate_value_text_count(document, &str::len)
+    }
 
     #[test]
     fn test_token_count() {
@@ -190,6 +204,20 @@ mod tests {
         assert_eq!(count, (text.len() / 3 + 5) / 10 * 10);
     }
 
+    #[test]
+    fn test_token_count_of_many_short_pieces() {
+        let results = vec![ToolUseResult {
+            tool_use_id: "id".to_string(),
+            content: vec![ToolUseResultBlock::Text("ab".to_string()); 30],
+            status: ToolResultStatus::Success,
+        }];
+        // Each piece on its own would round down to 0 tokens.
+        assert_eq!(
+            *results.as_slice().token_count(),
+            60 / HeuristicTokenizer::TOKEN_TO_CHAR_RATIO
+        );
+    }
+
     #[test]
     fn test_calculate_value_char_count() {
         // Test simple types
@@ -0,0 +1,226 @@
+//! Tokenizer backends used to measure how much of the context window a conversation takes up.
+//!
+//! By default token counts are estimated from the length of the text. A byte pair encoding
+//! vocabulary in the `tiktoken` format (one base64 encoded token and its rank per line) can be
+//! configured with the `chat.tokenizer` setting to count tokens exactly, without network access.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use eyre::{
+    Result,
+    eyre,
+};
+use regex::Regex;
+
+/// Counts the tokens in a piece of text.
+pub trait Tokenizer: std::fmt::Debug + Send + Sync {
+    /// A short description of the tokenizer, shown in `/usage`.
+    fn name(&self) -> &str;
+
+    /// Returns the number of tokens `text` is made up of.
+    fn count_tokens(&self, text: &str) -> usize;
+
+    /// Whether counts are approximations rather than the output of a real tokenizer.
+    fn is_estimate(&self) -> bool {
+        false
+    }
+
+    /// Returns the number of tokens in `len` bytes of text if the count only depends on the length
+    /// of the text, so that the lengths of many pieces of text can be added up before counting.
+    fn count_tokens_for_len(&self, _len: usize) -> Option<usize> {
+        None
+    }
+}
+
+/// Estimates token counts as a fixed ratio of the number of bytes in the text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicTokenizer;
+
+impl HeuristicTokenizer {
+    pub const TOKEN_TO_CHAR_RATIO: usize = 3;
+}
+
+impl Tokenizer for HeuristicTokenizer {
+    fn name(&self) -> &str {
+        "estimated from character count"
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        text.len() / Self::TOKEN_TO_CHAR_RATIO
+    }
+
+    fn is_estimate(&self) -> bool {
+        true
+    }
+
+    fn count_tokens_for_len(&self, len: usize) -> Option<usize> {
+        Some(len / Self::TOKEN_TO_CHAR_RATIO)
+    }
+}
+
+/// Splits text into the pieces that byte pair merges are applied within.
+///
+/// This approximates the pattern used by the cl100k and o200k vocabularies. The `regex` crate does
+/// not support look-around, so trailing whitespace before a word is kept with the whitespace run
+/// rather than the word, which rarely changes the count by more than a token.
+static PRE_TOKENIZE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+",
+    )
+    .expect("pre-tokenize regex is valid")
+});
+
+/// Pieces longer than this are merged in chunks to bound the quadratic cost of merging, e.g. for
+/// minified code or encoded data.
+const MAX_PIECE_LEN: usize = 256;
+
+/// A byte pair encoding tokenizer backed by a ranked vocabulary.
+#[derive(Debug, Clone)]
+pub struct BpeTokenizer {
+    name: String,
+    ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl BpeTokenizer {
+    /// Creates a tokenizer from the byte sequences of a vocabulary and their merge ranks.
+    pub fn new(name: impl Into<String>, ranks: HashMap<Vec<u8>, u32>) -> Self {
+        Self {
+            name: name.into(),
+            ranks,
+        }
+    }
+
+    /// Loads a vocabulary in the `tiktoken` format.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| eyre!("failed to read {}: {e}", path.display()))?;
+        let name = path
+            .file_stem()
+            .map_or_else(|| "bpe".to_string(), |s| s.to_string_lossy().into_owned());
+        Self::parse(name, &contents).map_err(|e| eyre!("invalid vocabulary {}: {e}", path.display()))
+    }
+
+    fn parse(name: String, contents: &str) -> Result<Self> {
+        let mut ranks = HashMap::new();
+        for (i, line) in contents.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| eyre!("line {} is not of the form '<base64 token> <rank>'", i + 1))?;
+            let token = STANDARD
+                .decode(token)
+                .map_err(|e| eyre!("line {} has an invalid token: {e}", i + 1))?;
+            let rank = rank
+                .trim()
+                .parse()
+                .map_err(|e| eyre!("line {} has an invalid rank: {e}", i + 1))?;
+            ranks.insert(token, rank);
+        }
+        if ranks.is_empty() {
+            return Err(eyre!("the vocabulary is empty"));
+        }
+        Ok(Self::new(name, ranks))
+    }
+
+    /// Returns the number of tokens `piece` is encoded as, by repeatedly merging the adjacent pair
+    /// of parts with the lowest rank.
+    fn count_piece_tokens(&self, piece: &[u8]) -> usize {
+        if piece.is_empty() {
+            return 0;
+        }
+        if self.ranks.contains_key(piece) {
+            return 1;
+        }
+
+        // Start offsets of each part, followed by the end of the piece.
+        let mut boundaries = (0..=piece.len()).collect::<Vec<_>>();
+        loop {
+            let best = boundaries
+                .windows(3)
+                .enumerate()
+                .filter_map(|(i, w)| self.ranks.get(&piece[w[0]..w[2]]).map(|rank| (*rank, i)))
+                .min();
+            match best {
+                Some((_, i)) => {
+                    boundaries.remove(i + 1);
+                },
+                None => break,
+            }
+        }
+        boundaries.len() - 1
+    }
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        PRE_TOKENIZE_REGEX
+            .find_iter(text)
+            .flat_map(|piece| piece.as_str().as_bytes().chunks(MAX_PIECE_LEN))
+            .map(|piece| self.count_piece_tokens(piece))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A vocabulary containing every single byte, plus a few merges.
+    fn test_tokenizer() -> BpeTokenizer {
+        let mut ranks = (0..=255u8).map(|b| (vec![b], b as u32)).collect::<HashMap<_, _>>();
+        for (i, token) in ["he", "ll", "hell", "hello", " w", " wor", "ld", " world"]
+            .iter()
+            .enumerate()
+        {
+            ranks.insert(token.as_bytes().to_vec(), 256 + i as u32);
+        }
+        BpeTokenizer::new("test", ranks)
+    }
+
+    #[test]
+    fn test_heuristic_tokenizer() {
+        assert_eq!(HeuristicTokenizer.count_tokens("123456789"), 3);
+        assert!(HeuristicTokenizer.is_estimate());
+    }
+
+    #[test]
+    fn test_bpe_count_tokens() {
+        let tokenizer = test_tokenizer();
+        assert_eq!(tokenizer.count_tokens(""), 0);
+        assert_eq!(tokenizer.count_tokens("hello"), 1);
+        assert_eq!(tokenizer.count_tokens("hello world"), 2);
+        // "he" + "l" + "p", since neither "hel" nor "lp" are in the vocabulary.
+        assert_eq!(tokenizer.count_tokens("help"), 3);
+        // Unknown multi-byte characters fall back to one token per byte.
+        assert_eq!(tokenizer.count_tokens("é"), 2);
+        assert!(!tokenizer.is_estimate());
+    }
+
+    #[test]
+    fn test_bpe_long_piece() {
+        let tokenizer = test_tokenizer();
+        let text = "hello".repeat(1000);
+        assert!(tokenizer.count_tokens(&text) >= 1000);
+    }
+
+    #[test]
+    fn test_bpe_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tiny.tiktoken");
+        std::fs::write(&path, "aGk= 0\naA== 1\naQ== 2\n").unwrap();
+        let tokenizer = BpeTokenizer::from_file(&path).unwrap();
+        assert_eq!(tokenizer.name(), "tiny");
+        assert_eq!(tokenizer.count_tokens("hi"), 1);
+
+        std::fs::write(&path, "not a vocabulary").unwrap();
+        assert!(BpeTokenizer::from_file(&path).is_err());
+        assert!(BpeTokenizer::from_file(dir.path().join("missing")).is_err());
+    }
+}
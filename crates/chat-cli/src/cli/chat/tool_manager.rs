@@ -55,6 +55,7 @@ use tokio::sync::{
     RwLock,
 };
 use tracing::{
+    debug,
     error,
     warn,
 };
@@ -66,6 +67,7 @@ use crate::api_client::model::{
     ToolResultStatus,
 };
 use crate::cli::chat::command::PromptsGetCommand;
+use crate::cli::chat::mcp_request_handler::McpRequestHandler;
 use crate::cli::chat::message::AssistantToolUse;
 use crate::cli::chat::server_messenger::{
     ServerMessengerBuilder,
@@ -76,10 +78,14 @@ use crate::cli::chat::tools::custom_tool::{
     CustomToolClient,
     CustomToolConfig,
 };
+use crate::cli::chat::tools::delegate::Delegate;
 use crate::cli::chat::tools::execute::ExecuteCommand;
 use crate::cli::chat::tools::fs_read::FsRead;
+use crate::cli::chat::tools::fs_search::WorkspaceSearch;
 use crate::cli::chat::tools::fs_write::FsWrite;
 use crate::cli::chat::tools::gh_issue::GhIssue;
+#[cfg(feature = "knowledge")]
+use crate::cli::chat::tools::knowledge_search::KnowledgeSearch;
 use crate::cli::chat::tools::thinking::Thinking;
 use crate::cli::chat::tools::use_aws::UseAws;
 use crate::cli::chat::tools::{
@@ -93,6 +99,9 @@ use crate::mcp_client::{
     JsonRpcResponse,
     Messenger,
     PromptGet,
+    ResourceInfo,
+    ResourceReadResult,
+    ResourceTemplateInfo,
 };
 use crate::platform::Context;
 use crate::telemetry::TelemetryThread;
@@ -248,6 +257,7 @@ pub struct ToolManagerBuilder {
     prompt_list_receiver: Option<std::sync::mpsc::Receiver<Option<String>>>,
     conversation_id: Option<String>,
     is_interactive: bool,
+    request_handler: Option<Arc<McpRequestHandler>>,
 }
 
 impl ToolManagerBuilder {
@@ -276,6 +286,11 @@ impl ToolManagerBuilder {
         self
     }
 
+    pub fn request_handler(mut self, request_handler: McpRequestHandler) -> Self {
+        self.request_handler.replace(Arc::new(request_handler));
+        self
+    }
+
     pub async fn build(
         mut self,
         telemetry: &TelemetryThread,
@@ -408,6 +423,8 @@ impl ToolManagerBuilder {
         let notify_weak = Arc::downgrade(&notify);
         let load_record = Arc::new(Mutex::new(HashMap::<String, Vec<LoadingRecord>>::new()));
         let load_record_clone = load_record.clone();
+        let resources = Arc::new(SyncRwLock::new(HashMap::<String, ServerResources>::new()));
+        let resources_clone = resources.clone();
         tokio::spawn(async move {
             let mut record_temp_buf = Vec::<u8>::new();
             let mut initialized = HashSet::<String>::new();
@@ -549,14 +566,37 @@ impl ToolManagerBuilder {
                         server_name: _,
                         result: _,
                     } => {},
-                    UpdateEventMessage::ResourcesListResult {
-                        server_name: _,
-                        result: _,
-                    } => {},
-                    UpdateEventMessage::ResourceTemplatesListResult {
-                        server_name: _,
-                        result: _,
-                    } => {},
+                    UpdateEventMessage::ResourcesListResult { server_name, result } => match result {
+                        Ok(result) => {
+                            let list = result
+                                .resources
+                                .into_iter()
+                                .filter_map(|v| serde_json::from_value::<ResourceInfo>(v).ok())
+                                .collect::<Vec<_>>();
+                            if let Ok(mut resources_wl) = resources_clone.write() {
+                                resources_wl.entry(server_name).or_default().resources = list;
+                            }
+                        },
+                        Err(e) => {
+                            warn!("Failed to list resources for {server_name}: {:?}", e);
+                        },
+                    },
+                    UpdateEventMessage::ResourceTemplatesListResult { server_name, result } => match result {
+                        Ok(result) => {
+                            let list = result
+                                .resource_templates
+                                .into_iter()
+                                .filter_map(|v| serde_json::from_value::<ResourceTemplateInfo>(v).ok())
+                                .collect::<Vec<_>>();
+                            if let Ok(mut resources_wl) = resources_clone.write() {
+                                resources_wl.entry(server_name).or_default().templates = list;
+                            }
+                        },
+                        Err(e) => {
+                            // Templates are optional for servers that offer resources.
+                            debug!("Failed to list resource templates for {server_name}: {:?}", e);
+                        },
+                    },
                     UpdateEventMessage::InitStart { server_name } => {
                         pending_clone.write().await.insert(server_name.clone());
                         loading_servers.insert(server_name, std::time::Instant::now());
@@ -569,6 +609,9 @@ impl ToolManagerBuilder {
             match init_res {
                 Ok(mut client) => {
                     client.assign_messenger(Box::new(messenger));
+                    if let Some(request_handler) = &self.request_handler {
+                        client.assign_request_handler(request_handler.clone());
+                    }
                     let mut client = Arc::new(client);
                     while let Some(collided_client) = clients.insert(name.clone(), client) {
                         // to avoid server name collision we are going to circumvent this by
@@ -680,6 +723,8 @@ impl ToolManagerBuilder {
             conversation_id,
             clients,
             prompts,
+            resources,
+            request_handler: self.request_handler,
             pending_clients: pending,
             notify: Some(notify),
             loading_status_sender,
@@ -703,6 +748,13 @@ pub struct PromptBundle {
     pub prompt_get: PromptGet,
 }
 
+/// The resources offered by a server, as last listed by it.
+#[derive(Clone, Debug, Default)]
+pub struct ServerResources {
+    pub resources: Vec<ResourceInfo>,
+    pub templates: Vec<ResourceTemplateInfo>,
+}
+
 /// Categorizes different types of tool name validation failures:
 /// - `TooLong`: The tool name exceeds the maximum allowed length
 /// - `IllegalChar`: The tool name contains characters that are not allowed
@@ -750,6 +802,13 @@ pub struct ToolManager {
     /// cases where multiple servers offer prompts with the same name.
     pub prompts: Arc<SyncRwLock<HashMap<String, Vec<PromptBundle>>>>,
 
+    /// Cache for resources collected from different servers, keyed by server name.
+    /// This is kept up to date as servers notify us that their resource lists have changed.
+    pub resources: Arc<SyncRwLock<HashMap<String, ServerResources>>>,
+
+    /// Answers the sampling and roots requests of servers.
+    request_handler: Option<Arc<McpRequestHandler>>,
+
     /// A notifier to understand if the initial loading has completed.
     /// This is only used for initial loading and is discarded after.
     notify: Option<Arc<Notify>>,
@@ -786,6 +845,8 @@ impl Clone for ToolManager {
             has_new_stuff: self.has_new_stuff.clone(),
             new_tool_specs: self.new_tool_specs.clone(),
             prompts: self.prompts.clone(),
+            resources: self.resources.clone(),
+            request_handler: self.request_handler.clone(),
             tn_map: self.tn_map.clone(),
             schema: self.schema.clone(),
             is_interactive: self.is_interactive,
@@ -809,6 +870,13 @@ impl ToolManager {
             if !crate::cli::chat::tools::thinking::Thinking::is_enabled(database) {
                 tool_specs.remove("thinking");
             }
+            #[cfg(feature = "knowledge")]
+            let knowledge_enabled = crate::cli::chat::knowledge::KnowledgeStore::is_enabled(database);
+            #[cfg(not(feature = "knowledge"))]
+            let knowledge_enabled = false;
+            if !knowledge_enabled {
+                tool_specs.remove("knowledge_search");
+            }
 
             #[cfg(windows)]
             {
@@ -835,6 +903,7 @@ impl ToolManager {
                     },
                         "required": ["command"]})),
                     tool_origin: ToolOrigin::Native,
+                    annotations: None,
                 });
             }
 
@@ -931,6 +1000,15 @@ impl ToolManager {
         Ok(self.schema.clone())
     }
 
+    /// Returns a tool manager for a child conversation that only knows about `tool_names`. Servers
+    /// are shared with this tool manager, but tools they add later are not picked up.
+    pub fn scoped(&self, tool_names: &HashSet<String>) -> Self {
+        let mut tool_manager = self.clone();
+        tool_manager.schema.retain(|name, _| tool_names.contains(name));
+        tool_manager.has_new_stuff = Arc::new(AtomicBool::new(false));
+        tool_manager
+    }
+
     pub fn get_tool_from_tool_use(&self, value: AssistantToolUse) -> Result<Tool, ToolResult> {
         let map_err = |parse_error| ToolResult {
             tool_use_id: value.id.clone(),
@@ -942,6 +1020,9 @@ impl ToolManager {
 
         Ok(match value.name.as_str() {
             "fs_read" => Tool::FsRead(serde_json::from_value::<FsRead>(value.args).map_err(map_err)?),
+            "fs_search" => {
+                Tool::WorkspaceSearch(serde_json::from_value::<WorkspaceSearch>(value.args).map_err(map_err)?)
+            },
             "fs_write" => Tool::FsWrite(serde_json::from_value::<FsWrite>(value.args).map_err(map_err)?),
             #[cfg(windows)]
             "execute_cmd" => {
@@ -954,6 +1035,11 @@ impl ToolManager {
             "use_aws" => Tool::UseAws(serde_json::from_value::<UseAws>(value.args).map_err(map_err)?),
             "report_issue" => Tool::GhIssue(serde_json::from_value::<GhIssue>(value.args).map_err(map_err)?),
             "thinking" => Tool::Thinking(serde_json::from_value::<Thinking>(value.args).map_err(map_err)?),
+            #[cfg(feature = "knowledge")]
+            "knowledge_search" => {
+                Tool::KnowledgeSearch(serde_json::from_value::<KnowledgeSearch>(value.args).map_err(map_err)?)
+            },
+            "delegate" => Tool::Delegate(serde_json::from_value::<Delegate>(value.args).map_err(map_err)?),
             // Note that this name is namespaced with server_name{DELIMITER}tool_name
             name => {
                 // Note: tn_map also has tools that underwent no transformation. In otherwords, if
@@ -1206,6 +1292,60 @@ impl ToolManager {
         }
     }
 
+    /// Updates the directories listed to servers as roots, notifying them if they changed.
+    pub fn set_roots(&self, roots: Vec<PathBuf>) {
+        let Some(request_handler) = &self.request_handler else {
+            return;
+        };
+        if !request_handler.set_roots(roots) {
+            return;
+        }
+        for client in self.clients.values() {
+            let client = client.clone();
+            tokio::spawn(async move {
+                if let Err(e) = client.notify("roots/list_changed", None).await {
+                    warn!(
+                        "Failed to notify {} that the roots changed: {:?}",
+                        client.get_server_name(),
+                        e
+                    );
+                }
+            });
+        }
+    }
+
+    /// Returns the resources offered by each server, sorted by server name.
+    pub fn list_resources(&self) -> Vec<(String, ServerResources)> {
+        let Ok(resources_rl) = self.resources.read() else {
+            error!("Error retrieving read lock on resources");
+            return Vec::new();
+        };
+        let mut resources = resources_rl
+            .iter()
+            .map(|(server_name, resources)| (server_name.clone(), resources.clone()))
+            .collect::<Vec<_>>();
+        resources.sort_by(|a, b| a.0.cmp(&b.0));
+        resources
+    }
+
+    /// Reads the resource at `uri` from the server named `server_name`.
+    pub async fn read_resource(&self, server_name: &str, uri: &str) -> eyre::Result<ResourceReadResult> {
+        let client = self
+            .clients
+            .get(server_name)
+            .ok_or(eyre::eyre!("No MCP server named {server_name} is running"))?;
+        let resp = client
+            .request("resources/read", Some(serde_json::json!({ "uri": uri })))
+            .await?;
+        if let Some(error) = resp.error {
+            eyre::bail!("{server_name} failed to read {uri}: {}", error.message);
+        }
+        let result = resp
+            .result
+            .ok_or(eyre::eyre!("{server_name} returned no contents for {uri}"))?;
+        Ok(serde_json::from_value::<ResourceReadResult>(result)?)
+    }
+
     pub fn refresh_prompts(&self, prompts_wl: &mut HashMap<String, Vec<PromptBundle>>) -> Result<(), GetPromptError> {
         *prompts_wl = self.clients.iter().fold(
             HashMap::<String, Vec<PromptBundle>>::new(),
@@ -4,8 +4,10 @@ use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
 use crossterm::{
+    cursor,
     queue,
     style,
+    terminal,
 };
 use eyre::Result;
 use serde::{
@@ -13,6 +15,7 @@ use serde::{
     Serialize,
 };
 use tokio::sync::RwLock;
+use tokio::sync::mpsc::UnboundedSender;
 use tracing::warn;
 
 use super::InvokeOutput;
@@ -21,25 +24,39 @@ use crate::cli::chat::token_counter::TokenCounter;
 use crate::mcp_client::{
     Client as McpClient,
     ClientConfig as McpClientConfig,
+    ClientRequestHandler,
+    HttpTransport,
     JsonRpcResponse,
     JsonRpcStdioTransport,
     MessageContent,
     Messenger,
+    ProgressNotification,
     PromptGet,
+    RemoteClientConfig,
     ServerCapabilities,
     StdioTransport,
     ToolCallResult,
+    Transport,
+    WebSocketTransport,
 };
 use crate::platform::Context;
 
-// TODO: support http transport type
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct CustomToolConfig {
+    /// The command used to spawn a local server. Unused when [CustomToolConfig::url] is set.
+    #[serde(default)]
     pub command: String,
     #[serde(default)]
     pub args: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub env: Option<HashMap<String, String>>,
+    /// The endpoint of a remote server. `http(s)://` urls use the streamable HTTP transport
+    /// while `ws(s)://` urls use the websocket transport.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Additional headers sent with every request to a remote server, e.g. for authorization.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HashMap<String, String>>,
     #[serde(default = "default_timeout")]
     pub timeout: u64,
 }
@@ -48,6 +65,13 @@ pub fn default_timeout() -> u64 {
     120 * 1000
 }
 
+impl CustomToolConfig {
+    /// A short human readable description of where the server lives, used for display purposes.
+    pub fn endpoint(&self) -> &str {
+        self.url.as_deref().unwrap_or(self.command.as_str())
+    }
+}
+
 #[derive(Debug)]
 pub enum CustomToolClient {
     Stdio {
@@ -55,26 +79,68 @@ pub enum CustomToolClient {
         client: McpClient<StdioTransport>,
         server_capabilities: RwLock<Option<ServerCapabilities>>,
     },
+    Http {
+        server_name: String,
+        client: McpClient<HttpTransport>,
+        server_capabilities: RwLock<Option<ServerCapabilities>>,
+    },
+    WebSocket {
+        server_name: String,
+        client: McpClient<WebSocketTransport>,
+        server_capabilities: RwLock<Option<ServerCapabilities>>,
+    },
 }
 
 impl CustomToolClient {
-    // TODO: add support for http transport
     pub fn from_config(server_name: String, config: CustomToolConfig) -> Result<Self> {
         let CustomToolConfig {
             command,
             args,
             env,
+            url,
+            headers,
             timeout,
         } = config;
+        let client_info = serde_json::json!({
+           "name": "Q CLI Chat",
+           "version": "1.0.0"
+        });
+        if let Some(url) = url {
+            let remote_config = RemoteClientConfig {
+                server_name: server_name.clone(),
+                url: url.clone(),
+                headers: headers.unwrap_or_default(),
+                timeout,
+                client_info,
+            };
+            let scheme = url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+            return match scheme.as_deref() {
+                Some("http" | "https") => Ok(CustomToolClient::Http {
+                    server_name,
+                    client: McpClient::<HttpTransport>::from_remote_config(remote_config)?,
+                    server_capabilities: RwLock::new(None),
+                }),
+                Some("ws" | "wss") => Ok(CustomToolClient::WebSocket {
+                    server_name,
+                    client: McpClient::<WebSocketTransport>::from_remote_config(remote_config)?,
+                    server_capabilities: RwLock::new(None),
+                }),
+                _ => Err(eyre::eyre!(
+                    "Unsupported url {url} for server {server_name}: expected an http(s):// or ws(s):// url"
+                )),
+            };
+        }
+        if command.is_empty() {
+            return Err(eyre::eyre!(
+                "Server {server_name} must specify either a command or a url"
+            ));
+        }
         let mcp_client_config = McpClientConfig {
             server_name: server_name.clone(),
             bin_path: command.clone(),
             args,
             timeout,
-            client_info: serde_json::json!({
-               "name": "Q CLI Chat",
-               "version": "1.0.0"
-            }),
+            client_info,
             env,
         };
         let client = McpClient::<JsonRpcStdioTransport>::from_config(mcp_client_config)?;
@@ -91,63 +157,132 @@ impl CustomToolClient {
                 client,
                 server_capabilities,
                 ..
-            } => {
-                if let Some(messenger) = &client.messenger {
-                    let _ = messenger.send_init_msg().await;
-                }
-                // We'll need to first initialize. This is the handshake every client and server
-                // needs to do before proceeding to anything else
-                let cap = client.init().await?;
-                // We'll be scrapping this for background server load: https://github.com/aws/amazon-q-developer-cli/issues/1466
-                // So don't worry about the tidiness for now
-                server_capabilities.write().await.replace(cap);
-                Ok(())
-            },
+            } => Self::init_client(client, server_capabilities).await,
+            CustomToolClient::Http {
+                client,
+                server_capabilities,
+                ..
+            } => Self::init_client(client, server_capabilities).await,
+            CustomToolClient::WebSocket {
+                client,
+                server_capabilities,
+                ..
+            } => Self::init_client(client, server_capabilities).await,
         }
     }
 
+    async fn init_client<T: Transport>(
+        client: &McpClient<T>,
+        server_capabilities: &RwLock<Option<ServerCapabilities>>,
+    ) -> Result<()> {
+        if let Some(messenger) = &client.messenger {
+            let _ = messenger.send_init_msg().await;
+        }
+        // We'll need to first initialize. This is the handshake every client and server
+        // needs to do before proceeding to anything else
+        let cap = client.init().await?;
+        // We'll be scrapping this for background server load: https://github.com/aws/amazon-q-developer-cli/issues/1466
+        // So don't worry about the tidiness for now
+        server_capabilities.write().await.replace(cap);
+        Ok(())
+    }
+
     pub fn assign_messenger(&mut self, messenger: Box<dyn Messenger>) {
         match self {
             CustomToolClient::Stdio { client, .. } => {
                 client.messenger = Some(messenger);
             },
+            CustomToolClient::Http { client, .. } => {
+                client.messenger = Some(messenger);
+            },
+            CustomToolClient::WebSocket { client, .. } => {
+                client.messenger = Some(messenger);
+            },
         }
     }
 
     pub fn get_server_name(&self) -> &str {
         match self {
-            CustomToolClient::Stdio { server_name, .. } => server_name.as_str(),
+            CustomToolClient::Stdio { server_name, .. }
+            | CustomToolClient::Http { server_name, .. }
+            | CustomToolClient::WebSocket { server_name, .. } => server_name.as_str(),
+        }
+    }
+
+    pub fn assign_request_handler(&mut self, request_handler: Arc<dyn ClientRequestHandler>) {
+        match self {
+            CustomToolClient::Stdio { client, .. } => {
+                client.request_handler = Some(request_handler);
+            },
+            CustomToolClient::Http { client, .. } => {
+                client.request_handler = Some(request_handler);
+            },
+            CustomToolClient::WebSocket { client, .. } => {
+                client.request_handler = Some(request_handler);
+            },
         }
     }
 
     pub async fn request(&self, method: &str, params: Option<serde_json::Value>) -> Result<JsonRpcResponse> {
         match self {
             CustomToolClient::Stdio { client, .. } => Ok(client.request(method, params).await?),
+            CustomToolClient::Http { client, .. } => Ok(client.request(method, params).await?),
+            CustomToolClient::WebSocket { client, .. } => Ok(client.request(method, params).await?),
+        }
+    }
+
+    /// Like [Self::request], with the progress the server reports for the request sent through
+    /// `progress`.
+    pub async fn request_with_progress(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+        progress: UnboundedSender<ProgressNotification>,
+    ) -> Result<JsonRpcResponse> {
+        match self {
+            CustomToolClient::Stdio { client, .. } => {
+                Ok(client.request_with_progress(method, params, Some(progress)).await?)
+            },
+            CustomToolClient::Http { client, .. } => {
+                Ok(client.request_with_progress(method, params, Some(progress)).await?)
+            },
+            CustomToolClient::WebSocket { client, .. } => {
+                Ok(client.request_with_progress(method, params, Some(progress)).await?)
+            },
         }
     }
 
     pub fn list_prompt_gets(&self) -> Arc<std::sync::RwLock<HashMap<String, PromptGet>>> {
         match self {
             CustomToolClient::Stdio { client, .. } => client.prompt_gets.clone(),
+            CustomToolClient::Http { client, .. } => client.prompt_gets.clone(),
+            CustomToolClient::WebSocket { client, .. } => client.prompt_gets.clone(),
         }
     }
 
-    #[allow(dead_code)]
     pub async fn notify(&self, method: &str, params: Option<serde_json::Value>) -> Result<()> {
         match self {
             CustomToolClient::Stdio { client, .. } => Ok(client.notify(method, params).await?),
+            CustomToolClient::Http { client, .. } => Ok(client.notify(method, params).await?),
+            CustomToolClient::WebSocket { client, .. } => Ok(client.notify(method, params).await?),
         }
     }
 
     pub fn is_prompts_out_of_date(&self) -> bool {
         match self {
             CustomToolClient::Stdio { client, .. } => client.is_prompts_out_of_date.load(Ordering::Relaxed),
+            CustomToolClient::Http { client, .. } => client.is_prompts_out_of_date.load(Ordering::Relaxed),
+            CustomToolClient::WebSocket { client, .. } => client.is_prompts_out_of_date.load(Ordering::Relaxed),
         }
     }
 
     pub fn prompts_updated(&self) {
         match self {
             CustomToolClient::Stdio { client, .. } => client.is_prompts_out_of_date.store(false, Ordering::Relaxed),
+            CustomToolClient::Http { client, .. } => client.is_prompts_out_of_date.store(false, Ordering::Relaxed),
+            CustomToolClient::WebSocket { client, .. } => {
+                client.is_prompts_out_of_date.store(false, Ordering::Relaxed);
+            },
         }
     }
 }
@@ -169,9 +304,41 @@ pub struct CustomTool {
 }
 
 impl CustomTool {
-    pub async fn invoke(&self, _ctx: &Context, _updates: &mut impl Write) -> Result<InvokeOutput> {
+    pub async fn invoke(&self, _ctx: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
         // Assuming a response shape as per https://spec.modelcontextprotocol.io/specification/2024-11-05/server/tools/#calling-tools
-        let resp = self.client.request(self.method.as_str(), self.params.clone()).await?;
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+        let request = self
+            .client
+            .request_with_progress(self.method.as_str(), self.params.clone(), progress_tx);
+        tokio::pin!(request);
+        // Dropping the request, e.g. when the user interrupts the tool, cancels it on the server.
+        let mut showing_progress = false;
+        let resp = loop {
+            tokio::select! {
+                resp = &mut request => break resp,
+                Some(update) = progress_rx.recv() => {
+                    queue!(
+                        updates,
+                        cursor::MoveToColumn(0),
+                        terminal::Clear(terminal::ClearType::CurrentLine),
+                        style::SetForegroundColor(style::Color::DarkGrey),
+                        style::Print(format_progress(&update)),
+                        style::ResetColor,
+                    )?;
+                    updates.flush()?;
+                    showing_progress = true;
+                },
+            }
+        };
+        if showing_progress {
+            queue!(
+                updates,
+                cursor::MoveToColumn(0),
+                terminal::Clear(terminal::ClearType::CurrentLine),
+            )?;
+            updates.flush()?;
+        }
+        let resp = resp?;
         let result = match resp.result {
             Some(result) => result,
             None => {
@@ -240,3 +407,33 @@ impl CustomTool {
             + TokenCounter::count_tokens(self.params.as_ref().map_or("", |p| p.as_str().unwrap_or_default()))
     }
 }
+
+/// Formats the progress reported by a server as a single line, e.g. `Indexing files (3/10)`.
+fn format_progress(update: &ProgressNotification) -> String {
+    let message = update.message.as_deref().unwrap_or("Working...");
+    match update.total {
+        Some(total) => format!("{message} ({}/{})", update.progress, total),
+        None => format!("{message} ({})", update.progress),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_progress() {
+        let mut update = ProgressNotification {
+            progress_token: serde_json::json!(1),
+            progress: 3.0,
+            total: Some(10.0),
+            message: Some("Indexing files".to_string()),
+        };
+        assert_eq!(format_progress(&update), "Indexing files (3/10)");
+        update.total = None;
+        update.message = None;
+        assert_eq!(format_progress(&update), "Working... (3)");
+        update.progress = 0.5;
+        assert_eq!(format_progress(&update), "Working... (0.5)");
+    }
+}
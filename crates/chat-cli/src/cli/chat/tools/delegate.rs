@@ -0,0 +1,620 @@
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::io::Write;
+use std::sync::{
+    Arc,
+    Mutex,
+};
+
+use crossterm::style::Color;
+use crossterm::{
+    queue,
+    style,
+};
+use eyre::{
+    Result,
+    bail,
+    eyre,
+};
+use serde::Deserialize;
+
+use super::execute::sandbox::SandboxMode;
+use super::{
+    InvokeOutput,
+    OutputKind,
+    Tool,
+    ToolOrigin,
+    ToolPermissions,
+};
+use crate::api_client::StreamingClient;
+use crate::api_client::model::{
+    Tool as FigTool,
+    ToolResultStatus,
+};
+use crate::cli::chat::checkpoint::CheckpointLog;
+use crate::cli::chat::context::ContextManager;
+use crate::cli::chat::conversation_state::ConversationState;
+use crate::cli::chat::hooks::{
+    HookTrigger,
+    ToolHookInput,
+    ToolHookOutcome,
+};
+use crate::cli::chat::message::{
+    AssistantToolUse,
+    ToolUseResult,
+    ToolUseResultBlock,
+};
+use crate::cli::chat::parser::{
+    ResponseEvent,
+    ResponseParser,
+};
+use crate::cli::chat::token_counter::TokenCount;
+use crate::cli::chat::tool_manager::ToolManager;
+use crate::platform::Context;
+
+/// The tools given to the child conversation when the model doesn't choose any.
+const DEFAULT_TOOLS: [&str; 2] = ["fs_read", "fs_search"];
+
+/// The maximum number of requests the child conversation can send before giving up.
+const MAX_TURNS: usize = 25;
+
+const TASK_PROMPT: &str = "You are working on a task delegated to you by another assistant, which only \
+receives your final message. Complete the task using the tools available to you, you cannot ask the user \
+any questions. Once done, reply with a concise summary of the outcome that includes every detail the \
+other assistant needs, such as file paths, findings, and anything you could not do.";
+
+/// Runs a task in a child conversation with its own context window and a restricted set of tools,
+/// returning only the final answer of the child to the parent conversation.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Delegate {
+    pub task: String,
+    /// The names of the tools the child conversation may use, defaulting to [DEFAULT_TOOLS].
+    pub tools: Option<Vec<String>>,
+
+    #[serde(skip_deserializing)]
+    pub context: Option<DelegateContext>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DelegateContext {
+    pub ctx: Arc<Context>,
+    pub client: StreamingClient,
+    pub tool_manager: ToolManager,
+    /// The tools of the parent conversation.
+    pub tools: HashMap<ToolOrigin, Vec<FigTool>>,
+    pub tool_permissions: ToolPermissions,
+    /// The context manager of the parent conversation, whose tool use hooks also run for the
+    /// tools of the child conversation.
+    pub context_manager: Option<ContextManager>,
+    /// Snapshots of the files written by the child conversation, moved into the checkpoints of
+    /// the parent conversation once the task has run so that `/undo` rolls them back.
+    pub checkpoints: Arc<tokio::sync::Mutex<CheckpointLog>>,
+    pub sandbox: SandboxMode,
+    pub profile: Option<String>,
+    pub model: Option<String>,
+    /// The usage of every delegated task of the chat session, as shown by `/usage`.
+    pub usage: Arc<Mutex<Vec<DelegateUsage>>>,
+}
+
+/// The usage of a delegated task.
+#[derive(Debug, Clone)]
+pub struct DelegateUsage {
+    pub task: String,
+    /// The number of requests sent by the child conversation.
+    pub turns: usize,
+    /// The size of the context window of the child conversation once it finished.
+    pub tokens: TokenCount,
+}
+
+impl Delegate {
+    pub async fn validate(&mut self, _ctx: &Context) -> Result<()> {
+        if self.task.trim().is_empty() {
+            bail!("The task cannot be empty");
+        }
+        if let (Some(context), Some(tools)) = (self.context.as_ref(), self.tools.as_ref()) {
+            let available = Self::tool_names(&context.tools);
+            for name in tools {
+                if name == "delegate" {
+                    bail!("Delegated tasks cannot delegate further");
+                }
+                if !available.contains(name) {
+                    bail!("Unknown tool '{name}'");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        let tools = match &self.tools {
+            Some(tools) => tools.join(", "),
+            None => DEFAULT_TOOLS.join(", "),
+        };
+        queue!(
+            updates,
+            style::Print("Delegating task: "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(&self.task),
+            style::ResetColor,
+            style::Print("\nWith tools: "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(tools),
+            style::ResetColor,
+            style::Print("\n"),
+        )?;
+        Ok(())
+    }
+
+    pub async fn invoke(&self, updates: &mut impl Write) -> Result<InvokeOutput> {
+        let Some(context) = self.context.as_ref() else {
+            return Err(eyre!(
+                "delegate: Required tool context (DelegateContext) not set by the program."
+            ));
+        };
+
+        let tools = self.scoped_tools(&context.tools);
+        let tool_names = Self::tool_names(&tools);
+
+        let mut conversation = ConversationState::new(
+            Arc::clone(&context.ctx),
+            &uuid::Uuid::new_v4().to_string(),
+            HashMap::new(),
+            context.profile.clone(),
+            None,
+            context.tool_manager.scoped(&tool_names),
+            context.model.clone(),
+        )
+        .await;
+        conversation.tools = tools;
+        conversation
+            .set_next_user_message(format!("{TASK_PROMPT}\n\nTask: {}", self.task))
+            .await;
+        let mut context_manager = context.context_manager.clone();
+
+        for turn in 1..=MAX_TURNS {
+            let request = conversation.as_sendable_conversation_state(false).await;
+            let mut parser = ResponseParser::new(context.client.send_message(request).await?);
+            let message = loop {
+                if let ResponseEvent::EndStream { message } = parser.recv().await? {
+                    break message;
+                }
+            };
+            let answer = message.content().to_string();
+            let tool_uses = message.tool_uses().map(<[_]>::to_vec).unwrap_or_default();
+            conversation.append_assistant_message(message);
+
+            if tool_uses.is_empty() {
+                let tokens = conversation
+                    .backend_conversation_state(false, true)
+                    .await
+                    .calculate_conversation_size()
+                    .total();
+                queue!(
+                    updates,
+                    style::SetForegroundColor(Color::DarkGrey),
+                    style::Print(format!(
+                        "Delegated task finished after {turn} requests (~{tokens} tokens)\n"
+                    )),
+                    style::ResetColor,
+                )?;
+                if let Ok(mut usage) = context.usage.lock() {
+                    usage.push(DelegateUsage {
+                        task: self.task.clone(),
+                        turns: turn,
+                        tokens,
+                    });
+                }
+                return Ok(InvokeOutput {
+                    output: OutputKind::Text(answer),
+                });
+            }
+
+            let mut results = Vec::new();
+            for tool_use in tool_uses {
+                results.push(
+                    Self::run_tool(
+                        context,
+                        &mut context_manager,
+                        &conversation.tool_manager,
+                        &tool_names,
+                        tool_use,
+                        updates,
+                    )
+                    .await?,
+                );
+            }
+            conversation.add_tool_results(results);
+        }
+
+        bail!("The delegated task did not finish within {MAX_TURNS} requests")
+    }
+
+    /// The tools of the parent conversation, `tools`, that the child conversation may use.
+    fn scoped_tools(&self, tools: &HashMap<ToolOrigin, Vec<FigTool>>) -> HashMap<ToolOrigin, Vec<FigTool>> {
+        let allowed = match &self.tools {
+            Some(tools) => tools.iter().map(String::as_str).collect::<HashSet<_>>(),
+            None => HashSet::from(DEFAULT_TOOLS),
+        };
+        tools
+            .iter()
+            .map(|(origin, tools)| {
+                let tools = tools
+                    .iter()
+                    .filter(|FigTool::ToolSpecification(spec)| {
+                        spec.name != "delegate" && allowed.contains(spec.name.as_str())
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (origin.clone(), tools)
+            })
+            .filter(|(_, tools)| !tools.is_empty())
+            .collect()
+    }
+
+    /// Runs a tool requested by the child conversation the same way the parent conversation
+    /// would, running the tool use hooks and checkpointing written files. Tools are only run if
+    /// they are available to the child and the user would not have been asked to approve them.
+    async fn run_tool(
+        context: &DelegateContext,
+        context_manager: &mut Option<ContextManager>,
+        tool_manager: &ToolManager,
+        tool_names: &HashSet<String>,
+        mut tool_use: AssistantToolUse,
+        updates: &mut impl Write,
+    ) -> Result<ToolUseResult> {
+        let tool_use_id = tool_use.id.clone();
+        let name = tool_use.name.clone();
+        let error = |message: String| ToolUseResult {
+            tool_use_id: tool_use_id.clone(),
+            content: vec![ToolUseResultBlock::Text(message)],
+            status: ToolResultStatus::Error,
+        };
+
+        if !tool_names.contains(&name) {
+            return Ok(error(format!("The tool '{name}' is not available to this task")));
+        }
+
+        // Pre tool use hooks may rewrite the input before it is parsed.
+        let pre_hook_outcome = Self::run_tool_hooks(
+            context_manager,
+            ToolHookInput {
+                hook_event: HookTrigger::PreToolUse,
+                tool_name: name.clone(),
+                tool_input: tool_use.args.clone(),
+                tool_response: None,
+            },
+            updates,
+        )
+        .await;
+        if let Some(reason) = pre_hook_outcome.denied {
+            return Ok(error(format!("The tool use was blocked by the user's hook {reason}")));
+        }
+        if let Some(tool_input) = pre_hook_outcome.tool_input {
+            tool_use.args = tool_input;
+        }
+        let tool_input = tool_use.args.clone();
+
+        let mut tool = match tool_manager.get_tool_from_tool_use(tool_use) {
+            Ok(tool) => tool,
+            Err(result) => return Ok(result.into()),
+        };
+        if let Tool::ExecuteCommand(execute_command) = &mut tool {
+            execute_command.sandbox = context.sandbox;
+        }
+        if let Err(err) = tool.validate(&context.ctx).await {
+            return Ok(error(format!("Failed to validate tool parameters: {err}")));
+        }
+
+        let allowed = context
+            .tool_permissions
+            .allows_without_asking(&name, &tool, &context.ctx);
+        queue!(
+            updates,
+            style::SetForegroundColor(Color::DarkGrey),
+            style::Print(format!(" ↳ {name}")),
+            style::Print(if allowed {
+                "\n"
+            } else {
+                " (skipped, requires approval)\n"
+            }),
+            style::ResetColor,
+        )?;
+        if !allowed {
+            return Ok(error(format!(
+                "The tool '{name}' requires the user's approval, which delegated tasks cannot ask for"
+            )));
+        }
+
+        // Writes that could not be rolled back with /undo are not made at all.
+        let checkpoint_ids = match &tool {
+            Tool::FsWrite(fs_write) => {
+                let paths = match fs_write.paths(&context.ctx) {
+                    Ok(paths) => paths,
+                    Err(err) => return Ok(error(format!("The files to write could not be determined: {err}"))),
+                };
+                let mut checkpoints = context.checkpoints.lock().await;
+                match checkpoints.snapshot(&context.ctx, &tool_use_id, &paths).await {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        return Ok(error(format!(
+                            "The files could not be checkpointed before writing, so they were not written: {err}"
+                        )));
+                    },
+                }
+            },
+            _ => Vec::new(),
+        };
+
+        let result = tool.invoke(&context.ctx, &mut std::io::sink()).await;
+        if result.is_err() {
+            context
+                .checkpoints
+                .lock()
+                .await
+                .discard(&context.ctx, &checkpoint_ids)
+                .await;
+        }
+
+        let post_hook_outcome = Self::run_tool_hooks(
+            context_manager,
+            ToolHookInput {
+                hook_event: HookTrigger::PostToolUse,
+                tool_name: name,
+                tool_input,
+                tool_response: Some(ToolHookInput::tool_response(&result)),
+            },
+            updates,
+        )
+        .await;
+        let mut result = match result {
+            Ok(output) => ToolUseResult {
+                tool_use_id,
+                content: vec![output.into()],
+                status: ToolResultStatus::Success,
+            },
+            Err(err) => error(format!("An error occurred processing the tool: \n{err}")),
+        };
+        let feedback = (pre_hook_outcome.feedback.into_iter())
+            .chain(post_hook_outcome.feedback)
+            .collect::<Vec<_>>();
+        if !feedback.is_empty() {
+            result.content.push(ToolUseResultBlock::Text(format!(
+                "Feedback from the user's tool use hooks:\n{}",
+                feedback.join("\n")
+            )));
+        }
+        Ok(result)
+    }
+
+    async fn run_tool_hooks(
+        context_manager: &mut Option<ContextManager>,
+        input: ToolHookInput,
+        updates: &mut impl Write,
+    ) -> ToolHookOutcome {
+        match context_manager.as_mut() {
+            Some(context_manager) => context_manager.run_tool_hooks(input, Some(updates)).await,
+            None => ToolHookOutcome::default(),
+        }
+    }
+
+    fn tool_names(tools: &HashMap<ToolOrigin, Vec<FigTool>>) -> HashSet<String> {
+        tools
+            .values()
+            .flatten()
+            .map(|FigTool::ToolSpecification(spec)| spec.name.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::chat::create_stream;
+
+    fn fig_tools(names: &[&str]) -> Vec<FigTool> {
+        names
+            .iter()
+            .map(|name| {
+                FigTool::ToolSpecification(crate::api_client::model::ToolSpecification {
+                    name: (*name).to_string(),
+                    description: String::new(),
+                    input_schema: crate::api_client::model::ToolInputSchema { json: None },
+                })
+            })
+            .collect()
+    }
+
+    fn delegate_context(
+        ctx: &Arc<Context>,
+        client: StreamingClient,
+        tool_permissions: ToolPermissions,
+    ) -> DelegateContext {
+        DelegateContext {
+            ctx: Arc::clone(ctx),
+            client,
+            tool_manager: ToolManager::default(),
+            tools: HashMap::from([(
+                ToolOrigin::Native,
+                fig_tools(&["fs_read", "fs_search", "fs_write", "delegate"]),
+            )]),
+            tool_permissions,
+            context_manager: None,
+            checkpoints: Default::default(),
+            sandbox: SandboxMode::default(),
+            profile: None,
+            model: None,
+            usage: Default::default(),
+        }
+    }
+
+    fn delegate(tools: &[&str], context: DelegateContext) -> Delegate {
+        Delegate {
+            task: "Find out what the notes say".to_string(),
+            tools: Some(tools.iter().map(|tool| (*tool).to_string()).collect()),
+            context: Some(context),
+        }
+    }
+
+    fn write_tool_use(id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "tool_use_id": id,
+            "name": "fs_write",
+            "args": {
+                "command": "create",
+                "file_text": "Hello",
+                "path": "/written.txt",
+            }
+        })
+    }
+
+    fn read_tool_use(id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "tool_use_id": id,
+            "name": "fs_read",
+            "args": {
+                "mode": "Line",
+                "path": "/notes.txt",
+            }
+        })
+    }
+
+    #[test]
+    fn test_scoped_tools() {
+        let tools = HashMap::from([
+            (ToolOrigin::Native, fig_tools(&["fs_read", "fs_search", "delegate"])),
+            (
+                ToolOrigin::McpServer("server".to_string()),
+                fig_tools(&["server___lookup"]),
+            ),
+        ]);
+        let scoped = |tool: Delegate| {
+            let mut names = Delegate::tool_names(&tool.scoped_tools(&tools))
+                .into_iter()
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let tool = |tools: Option<&[&str]>| Delegate {
+            task: "task".to_string(),
+            tools: tools.map(|tools| tools.iter().map(|tool| (*tool).to_string()).collect()),
+            context: None,
+        };
+
+        assert_eq!(scoped(tool(None)), vec!["fs_read", "fs_search"]);
+        assert_eq!(scoped(tool(Some(&["server___lookup", "delegate"]))), vec![
+            "server___lookup"
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_invoke() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/notes.txt", "The build is broken").await.unwrap();
+        let client = create_stream(serde_json::json!([
+            ["Reading the notes", read_tool_use("1"), write_tool_use("2")],
+            ["The notes say that the build is broken"],
+        ]));
+        let mut tool_permissions = ToolPermissions::new(0);
+        tool_permissions.trust_all = true;
+        let context = delegate_context(&ctx, client, tool_permissions);
+        let usage = Arc::clone(&context.usage);
+
+        let output = delegate(&["fs_read"], context)
+            .invoke(&mut std::io::sink())
+            .await
+            .unwrap();
+        assert!(matches!(
+            output.output,
+            OutputKind::Text(text) if text == "The notes say that the build is broken"
+        ));
+        // Tools outside of the task's scope are not run, even when trusted.
+        assert!(!ctx.fs().exists("/written.txt"));
+
+        let usage = usage.lock().unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].task, "Find out what the notes say");
+        assert_eq!(usage[0].turns, 2);
+        assert!(usage[0].tokens.value() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_invoke_skips_tools_requiring_approval() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/notes.txt", "The build is broken").await.unwrap();
+        let client = create_stream(serde_json::json!([
+            ["Writing the file", write_tool_use("1"), read_tool_use("2")],
+            ["I could not write the file"],
+        ]));
+        let context = delegate_context(&ctx, client, ToolPermissions::new(0));
+
+        let mut output = Vec::new();
+        delegate(&["fs_read", "fs_write"], context)
+            .invoke(&mut output)
+            .await
+            .unwrap();
+        assert!(!ctx.fs().exists("/written.txt"));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("fs_write (skipped, requires approval)"), "{output}");
+        assert!(!output.contains("fs_read (skipped"), "{output}");
+    }
+
+    #[tokio::test]
+    async fn test_invoke_max_turns() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        ctx.fs().write("/notes.txt", "The build is broken").await.unwrap();
+        // The child never stops using tools, one more response would have finished the task.
+        let mut responses = (0..MAX_TURNS)
+            .map(|turn| serde_json::json!(["Reading again", read_tool_use(&turn.to_string())]))
+            .collect::<Vec<_>>();
+        responses.push(serde_json::json!(["Done"]));
+        let client = create_stream(serde_json::Value::Array(responses));
+        let context = delegate_context(&ctx, client, ToolPermissions::new(0));
+        let usage = Arc::clone(&context.usage);
+
+        let err = delegate(&["fs_read"], context)
+            .invoke(&mut std::io::sink())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("did not finish within 25 requests"), "{err}");
+        assert!(usage.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_validate() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut tool = serde_json::from_value::<Delegate>(serde_json::json!({
+            "task": "Summarize the README",
+            "tools": ["fs_read", "delegate"],
+        }))
+        .unwrap();
+        // Tool names can only be checked once the context is set.
+        assert!(tool.validate(&ctx).await.is_ok());
+
+        let tools = fig_tools(&["fs_read", "fs_search", "delegate"]);
+        tool.context = Some(DelegateContext {
+            ctx: Arc::clone(&ctx),
+            client: StreamingClient::mock(vec![]),
+            tool_manager: ToolManager::default(),
+            tools: HashMap::from([(ToolOrigin::Native, tools)]),
+            tool_permissions: ToolPermissions::new(0),
+            context_manager: None,
+            checkpoints: Default::default(),
+            sandbox: SandboxMode::default(),
+            profile: None,
+            model: None,
+            usage: Default::default(),
+        });
+        assert!(tool.validate(&ctx).await.is_err());
+
+        tool.tools = Some(vec!["fs_read".to_string(), "execute_bash".to_string()]);
+        assert!(tool.validate(&ctx).await.is_err());
+
+        tool.tools = Some(vec!["fs_read".to_string(), "fs_search".to_string()]);
+        assert!(tool.validate(&ctx).await.is_ok());
+
+        tool.task = " ".to_string();
+        assert!(tool.validate(&ctx).await.is_err());
+    }
+}
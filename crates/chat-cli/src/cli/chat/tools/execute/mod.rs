@@ -8,6 +8,10 @@ use crossterm::style::{
 use eyre::Result;
 use serde::Deserialize;
 
+use self::sandbox::{
+    SandboxMode,
+    SandboxPolicy,
+};
 use crate::cli::chat::tools::{
     InvokeOutput,
     MAX_TOOL_RESPONSE_SIZE,
@@ -31,6 +35,9 @@ mod unix;
 #[cfg(not(windows))]
 pub use unix::*;
 
+pub mod rules;
+pub mod sandbox;
+
 // Common readonly commands that are safe to execute without user confirmation
 pub const READONLY_COMMANDS: &[&str] = &[
     "ls", "cat", "echo", "pwd", "which", "head", "tail", "find", "grep", "dir", "type",
@@ -40,10 +47,18 @@ pub const READONLY_COMMANDS: &[&str] = &[
 pub struct ExecuteCommand {
     pub command: String,
     pub summary: Option<String>,
+    /// Set from the current profile rather than by the model.
+    #[serde(skip)]
+    pub sandbox: SandboxMode,
 }
 
 impl ExecuteCommand {
     pub fn requires_acceptance(&self) -> bool {
+        // The sandbox keeps the command from modifying anything or reaching the network.
+        if self.sandbox == SandboxMode::ReadOnly {
+            return false;
+        }
+
         let Some(args) = shlex::split(&self.command) else {
             return true;
         };
@@ -100,8 +115,15 @@ impl ExecuteCommand {
         false
     }
 
-    pub async fn invoke(&self, updates: impl Write) -> Result<InvokeOutput> {
-        let output = run_command(&self.command, MAX_TOOL_RESPONSE_SIZE / 3, Some(updates)).await?;
+    pub async fn invoke(&self, ctx: &Context, updates: impl Write) -> Result<InvokeOutput> {
+        let sandbox = SandboxPolicy::for_mode(ctx, self.sandbox)?;
+        let output = run_command(
+            &self.command,
+            sandbox.as_ref(),
+            MAX_TOOL_RESPONSE_SIZE / 3,
+            Some(updates),
+        )
+        .await?;
         let result = serde_json::json!({
             "exit_status": output.exit_status.unwrap_or(0).to_string(),
             "stdout": output.stdout,
@@ -129,6 +151,15 @@ impl ExecuteCommand {
             style::ResetColor
         )?;
 
+        if self.sandbox != SandboxMode::Off {
+            queue!(
+                updates,
+                style::SetForegroundColor(Color::DarkGrey),
+                style::Print(format!("Sandbox ({}): {}\n", self.sandbox, self.sandbox.description())),
+                style::ResetColor
+            )?;
+        }
+
         // Add the summary if available
         if let Some(summary) = &self.summary {
             queue!(
@@ -213,4 +244,16 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_requires_acceptance_when_sandboxed() {
+        let tool = |sandbox: SandboxMode| ExecuteCommand {
+            command: "rm -rf build && curl https://example.com".to_string(),
+            summary: None,
+            sandbox,
+        };
+        assert!(tool(SandboxMode::Off).requires_acceptance());
+        assert!(tool(SandboxMode::Workspace).requires_acceptance());
+        assert!(!tool(SandboxMode::ReadOnly).requires_acceptance());
+    }
 }
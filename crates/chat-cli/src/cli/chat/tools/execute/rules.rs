@@ -0,0 +1,513 @@
+//! User defined allow/deny/ask rules for shell commands.
+//!
+//! Rules are read from `command_rules.json`, both globally (`~/.aws/amazonq/command_rules.json`)
+//! and for the active profile (`~/.aws/amazonq/profiles/<profile>/command_rules.json`):
+//!
+//! ```json
+//! {
+//!   "rules": [
+//!     { "action": "allow", "glob": "cargo test*" },
+//!     { "action": "deny", "glob": "git push*" },
+//!     { "action": "ask", "regex": "^kubectl\\s" }
+//!   ]
+//! }
+//! ```
+//!
+//! Commands are split into their pipeline and list segments (`|`, `&&`, `||`, `;`, `&`) and each
+//! segment is matched separately. A command is denied if any segment matches a deny rule, must be
+//! confirmed if any segment matches an ask rule, and is allowed only if every segment matches an
+//! allow rule. Otherwise, the default behavior of the tool applies. Segments that substitute
+//! commands or redirect output to a file (`>`, `>>`, `>|`, `&>`, `2>`) are never allowed by a rule.
+
+use std::fmt::Display;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+use eyre::{
+    Result,
+    WrapErr as _,
+    bail,
+};
+use globset::{
+    GlobBuilder,
+    GlobMatcher,
+};
+use regex::Regex;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::cli::chat::context::profile_context_path;
+use crate::platform::Context;
+use crate::util::directories;
+
+const RULES_FILE_NAME: &str = "command_rules.json";
+
+/// Shell syntax that runs commands which a rule cannot see, e.g. `echo $(rm -rf ~)`. Segments
+/// containing these are never allowed by a rule, though they may still be denied or asked.
+const SUBSTITUTION_PATTERNS: &[&str] = &["$(", "`", "<(", ">("];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Allow,
+    Deny,
+    Ask,
+}
+
+impl Display for RuleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleAction::Allow => write!(f, "allow"),
+            RuleAction::Deny => write!(f, "deny"),
+            RuleAction::Ask => write!(f, "ask"),
+        }
+    }
+}
+
+/// A rule as written in a rules file. Exactly one of `glob` or `regex` must be set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandRuleConfig {
+    pub action: RuleAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommandRulesConfig {
+    #[serde(default)]
+    pub rules: Vec<CommandRuleConfig>,
+}
+
+#[derive(Debug, Clone)]
+enum RulePattern {
+    Glob(String, GlobMatcher),
+    Regex(Regex),
+}
+
+/// Where a rule was defined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleSource {
+    Global,
+    Profile(String),
+}
+
+/// A compiled rule.
+#[derive(Debug, Clone)]
+pub struct CommandRule {
+    pub action: RuleAction,
+    pub source: RuleSource,
+    pattern: RulePattern,
+}
+
+impl CommandRule {
+    pub fn new(config: &CommandRuleConfig, source: RuleSource) -> Result<Self> {
+        let pattern = match (&config.glob, &config.regex) {
+            (Some(glob), None) => {
+                let matcher = GlobBuilder::new(glob)
+                    .literal_separator(false)
+                    .build()
+                    .wrap_err_with(|| format!("Invalid glob '{glob}'"))?
+                    .compile_matcher();
+                RulePattern::Glob(glob.clone(), matcher)
+            },
+            (None, Some(regex)) => {
+                RulePattern::Regex(Regex::new(regex).wrap_err_with(|| format!("Invalid regex '{regex}'"))?)
+            },
+            _ => bail!(
+                "Exactly one of `glob` or `regex` must be set for a {} rule",
+                config.action
+            ),
+        };
+        Ok(Self {
+            action: config.action,
+            source,
+            pattern,
+        })
+    }
+
+    pub fn is_match(&self, segment: &str) -> bool {
+        match &self.pattern {
+            RulePattern::Glob(_, matcher) => matcher.is_match(segment),
+            RulePattern::Regex(regex) => regex.is_match(segment),
+        }
+    }
+}
+
+impl Display for CommandRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.pattern {
+            RulePattern::Glob(glob, _) => write!(f, "{} glob '{}'", self.action, glob)?,
+            RulePattern::Regex(regex) => write!(f, "{} regex '{}'", self.action, regex.as_str())?,
+        }
+        match &self.source {
+            RuleSource::Global => write!(f, " (global)"),
+            RuleSource::Profile(name) => write!(f, " (profile {name})"),
+        }
+    }
+}
+
+/// The rule, if any, that applies to a single segment of a command.
+#[derive(Debug, Clone)]
+pub struct SegmentMatch<'a> {
+    pub segment: String,
+    pub rule: Option<&'a CommandRule>,
+}
+
+/// The result of evaluating a command against the rules.
+#[derive(Debug, Clone)]
+pub struct RuleEvaluation<'a> {
+    /// [None] if the rules do not decide the outcome, in which case the tool's default logic
+    /// applies.
+    pub action: Option<RuleAction>,
+    pub segments: Vec<SegmentMatch<'a>>,
+}
+
+impl RuleEvaluation<'_> {
+    /// The rule responsible for [Self::action].
+    pub fn decisive_rule(&self) -> Option<&CommandRule> {
+        let action = self.action?;
+        self.segments.iter().filter_map(|s| s.rule).find(|r| r.action == action)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandRules {
+    rules: Vec<CommandRule>,
+}
+
+impl CommandRules {
+    pub fn new(rules: Vec<CommandRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Loads the global rules followed by those of `profile`. Files that fail to load are
+    /// skipped, with the reason returned alongside the rules that did load.
+    pub async fn load(ctx: &Context, profile: Option<&str>) -> (Self, Vec<String>) {
+        let mut rules = Vec::new();
+        let mut errors = Vec::new();
+
+        let mut sources = vec![(global_rules_path(ctx), RuleSource::Global)];
+        if let Some(profile) = profile {
+            sources.push((
+                profile_rules_path(ctx, profile),
+                RuleSource::Profile(profile.to_string()),
+            ));
+        }
+        for (path, source) in sources {
+            let path = match path {
+                Ok(path) if ctx.fs().exists(&path) => path,
+                Ok(_) => continue,
+                Err(err) => {
+                    errors.push(err.to_string());
+                    continue;
+                },
+            };
+            match load_file(ctx, &path, source).await {
+                Ok(file_rules) => rules.extend(file_rules),
+                Err(err) => errors.push(format!("Failed to load {}: {:#}", path.display(), err)),
+            }
+        }
+
+        (Self::new(rules), errors)
+    }
+
+    pub fn rules(&self) -> &[CommandRule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn evaluate(&self, command: &str) -> RuleEvaluation<'_> {
+        let segments = split_segments(command)
+            .into_iter()
+            .map(|segment| {
+                let allowable =
+                    !SUBSTITUTION_PATTERNS.iter().any(|p| segment.contains(p)) && !has_output_redirection(&segment);
+                // Deny takes precedence over ask, which takes precedence over allow.
+                let rule = [RuleAction::Deny, RuleAction::Ask, RuleAction::Allow]
+                    .into_iter()
+                    .filter(|action| allowable || *action != RuleAction::Allow)
+                    .find_map(|action| {
+                        self.rules
+                            .iter()
+                            .find(|rule| rule.action == action && rule.is_match(&segment))
+                    });
+                SegmentMatch { segment, rule }
+            })
+            .collect::<Vec<_>>();
+
+        let has_action = |action: RuleAction| segments.iter().any(|s| s.rule.is_some_and(|r| r.action == action));
+        let action = if has_action(RuleAction::Deny) {
+            Some(RuleAction::Deny)
+        } else if has_action(RuleAction::Ask) {
+            Some(RuleAction::Ask)
+        } else if !segments.is_empty() && segments.iter().all(|s| s.rule.is_some()) {
+            Some(RuleAction::Allow)
+        } else {
+            None
+        };
+
+        RuleEvaluation { action, segments }
+    }
+}
+
+pub fn global_rules_path(ctx: &Context) -> Result<PathBuf> {
+    Ok(directories::chat_global_context_path(ctx)?.with_file_name(RULES_FILE_NAME))
+}
+
+pub fn profile_rules_path(ctx: &Context, profile: &str) -> Result<PathBuf> {
+    Ok(profile_context_path(ctx, profile)?.with_file_name(RULES_FILE_NAME))
+}
+
+async fn load_file(ctx: &Context, path: &Path, source: RuleSource) -> Result<Vec<CommandRule>> {
+    let contents = ctx.fs().read_to_string(path).await?;
+    let config: CommandRulesConfig = serde_json::from_str(&contents)?;
+    config
+        .rules
+        .iter()
+        .map(|rule| CommandRule::new(rule, source.clone()))
+        .collect()
+}
+
+/// Splits a shell command on the operators separating pipeline and list segments, ignoring any
+/// that are quoted or escaped, and returns the trimmed non-empty segments.
+pub fn split_segments(command: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut chars = command.chars().peekable();
+    let (mut in_single, mut in_double) = (false, false);
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !in_single => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+                continue;
+            },
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            // `>|` is a redirection rather than a pipe.
+            '|' if current.ends_with('>') => (),
+            '|' | ';' | '\n' if !in_single && !in_double => {
+                // `||` is a single operator.
+                chars.next_if_eq(&'|');
+                segments.push(std::mem::take(&mut current));
+                continue;
+            },
+            '&' if !in_single && !in_double => {
+                // `>&` and `&>` are redirections rather than operators.
+                if current.ends_with('>') || chars.peek() == Some(&'>') {
+                    current.push(c);
+                    continue;
+                }
+                chars.next_if_eq(&'&');
+                segments.push(std::mem::take(&mut current));
+                continue;
+            },
+            _ => (),
+        }
+        current.push(c);
+    }
+    segments.push(current);
+
+    segments
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Whether a command segment redirects output to a file, e.g. `cargo test > ~/.bashrc`. Writing
+/// to a file could do anything, so such segments are never allowed by a rule. Duplicating a file
+/// descriptor (`2>&1`) or closing one (`>&-`) doesn't write to a file and is fine.
+fn has_output_redirection(segment: &str) -> bool {
+    let mut chars = segment.chars().peekable();
+    let (mut in_single, mut in_double) = (false, false);
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !in_single => {
+                chars.next();
+            },
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '>' if !in_single && !in_double => {
+                // `>>` and `>|` write to the file just the same.
+                chars.next_if(|c| *c == '>' || *c == '|');
+                if chars.next_if_eq(&'&').is_none() {
+                    return true;
+                }
+                // `>&word` redirects both stdout and stderr to `word` unless it is a descriptor.
+                let mut target = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    target.push(c);
+                }
+                if target != "-" && (target.is_empty() || !target.chars().all(|c| c.is_ascii_digit())) {
+                    return true;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[(RuleAction, &str)]) -> CommandRules {
+        CommandRules::new(
+            rules
+                .iter()
+                .map(|(action, pattern)| {
+                    let config = match pattern.strip_prefix("re:") {
+                        Some(regex) => CommandRuleConfig {
+                            action: *action,
+                            glob: None,
+                            regex: Some(regex.to_string()),
+                        },
+                        None => CommandRuleConfig {
+                            action: *action,
+                            glob: Some((*pattern).to_string()),
+                            regex: None,
+                        },
+                    };
+                    CommandRule::new(&config, RuleSource::Global).unwrap()
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_split_segments() {
+        assert_eq!(split_segments("cargo test"), vec!["cargo test"]);
+        assert_eq!(split_segments("cat a | grep b && echo c || echo d; ls & pwd"), vec![
+            "cat a", "grep b", "echo c", "echo d", "ls", "pwd"
+        ]);
+        assert_eq!(split_segments("echo 'a | b' \"c && d\" e\\;f"), vec![
+            "echo 'a | b' \"c && d\" e\\;f"
+        ]);
+        assert_eq!(split_segments("cargo test 2>&1 &> out.txt"), vec![
+            "cargo test 2>&1 &> out.txt"
+        ]);
+        assert_eq!(split_segments("cargo test >| out.txt"), vec!["cargo test >| out.txt"]);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rules = rules(&[
+            (RuleAction::Allow, "cargo test*"),
+            (RuleAction::Allow, "grep *"),
+            (RuleAction::Deny, "git push*"),
+            (RuleAction::Ask, "re:^kubectl\\s"),
+        ]);
+
+        let eval = rules.evaluate("cargo test --all 2>&1 | grep FAILED");
+        assert_eq!(eval.action, Some(RuleAction::Allow));
+
+        let eval = rules.evaluate("cargo test && git push origin main");
+        assert_eq!(eval.action, Some(RuleAction::Deny));
+        assert_eq!(
+            eval.decisive_rule().unwrap().to_string(),
+            "deny glob 'git push*' (global)"
+        );
+
+        let eval = rules.evaluate("kubectl get pods | grep web");
+        assert_eq!(eval.action, Some(RuleAction::Ask));
+
+        // Not every segment is covered by a rule.
+        assert_eq!(rules.evaluate("cargo test | tee out.txt").action, None);
+        assert_eq!(rules.evaluate("npm install").action, None);
+
+        // Substitutions are never allowed by a rule.
+        assert_eq!(rules.evaluate("cargo test $(rm -rf ~)").action, None);
+
+        // Neither are redirections to a file.
+        assert_eq!(rules.evaluate("cargo test > ~/.bashrc").action, None);
+        assert_eq!(rules.evaluate("cargo test >> ~/.ssh/authorized_keys").action, None);
+        assert_eq!(rules.evaluate("cargo test | grep a >| out.txt").action, None);
+        assert_eq!(rules.evaluate("git push 2> err.txt").action, Some(RuleAction::Deny));
+    }
+
+    #[test]
+    fn test_has_output_redirection() {
+        for segment in [
+            "cargo test > out.txt",
+            "cargo test>out.txt",
+            "cargo test >> out.txt",
+            "cargo test >| out.txt",
+            "cargo test &> out.txt",
+            "cargo test 2> err.txt",
+            "cargo test >& out.txt",
+            "cargo test 2>&1 > out.txt",
+        ] {
+            assert!(has_output_redirection(segment), "{segment}");
+        }
+        for segment in [
+            "cargo test",
+            "cargo test 2>&1",
+            "cargo test >&2",
+            "cargo test 2>&-",
+            "cargo test < in.txt",
+            "echo 'a > b' \"c >> d\" e\\>f",
+        ] {
+            assert!(!has_output_redirection(segment), "{segment}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let config = CommandRuleConfig {
+            action: RuleAction::Allow,
+            glob: None,
+            regex: None,
+        };
+        assert!(CommandRule::new(&config, RuleSource::Global).is_err());
+        let config = CommandRuleConfig {
+            action: RuleAction::Deny,
+            glob: None,
+            regex: Some("(".to_string()),
+        };
+        assert!(CommandRule::new(&config, RuleSource::Global).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let global = global_rules_path(&ctx).unwrap();
+        ctx.fs().create_dir_all(global.parent().unwrap()).await.unwrap();
+        ctx.fs()
+            .write(&global, r#"{ "rules": [{ "action": "allow", "glob": "ls*" }] }"#)
+            .await
+            .unwrap();
+        let profile = profile_rules_path(&ctx, "dev").unwrap();
+        ctx.fs().create_dir_all(profile.parent().unwrap()).await.unwrap();
+        ctx.fs()
+            .write(&profile, r#"{ "rules": [{ "action": "deny", "regex": "^ls -la" }] }"#)
+            .await
+            .unwrap();
+
+        let (rules, errors) = CommandRules::load(&ctx, Some("dev")).await;
+        assert!(errors.is_empty());
+        assert_eq!(rules.rules().len(), 2);
+        assert_eq!(rules.evaluate("ls -la").action, Some(RuleAction::Deny));
+        assert_eq!(rules.rules()[1].source, RuleSource::Profile("dev".to_string()));
+
+        ctx.fs().write(&profile, "not json").await.unwrap();
+        let (rules, errors) = CommandRules::load(&ctx, Some("dev")).await;
+        assert_eq!(errors.len(), 1);
+        assert_eq!(rules.evaluate("ls -la").action, Some(RuleAction::Allow));
+    }
+}
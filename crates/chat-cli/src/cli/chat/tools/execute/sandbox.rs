@@ -0,0 +1,337 @@
+//! Sandboxed execution of shell commands.
+//!
+//! On Linux, a sandboxed command runs in new user and network namespaces, leaving it with only an
+//! unconfigured loopback interface, and uses Landlock to deny writes outside of the workspace and
+//! a few scratch directories, or outside of `/dev` in the read only mode. The rest of the
+//! filesystem stays readable so that compilers, interpreters, and the like keep working.
+//!
+//! Landlock doesn't restrict connecting to unix domain sockets on the filesystem, which would let
+//! a command reach services such as `/var/run/docker.sock` and escape the sandbox. A seccomp
+//! filter therefore denies creating unix domain sockets altogether, along with io_uring which
+//! could create them without a system call.
+
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use eyre::Result;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::platform::Context;
+
+/// How commands run by the `execute_bash` tool are isolated, selected per profile.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxMode {
+    /// Commands run with the user's full privileges.
+    #[default]
+    Off,
+    /// Commands cannot write to the filesystem and have no network or unix socket access, so they
+    /// run without asking for confirmation.
+    ReadOnly,
+    /// Commands can only write beneath the workspace and have no network or unix socket access.
+    Workspace,
+}
+
+impl SandboxMode {
+    pub const VALUES: &[&str] = &["off", "readonly", "workspace"];
+
+    /// A short explanation of the restrictions applied to commands in this mode.
+    pub fn description(&self) -> &'static str {
+        match self {
+            SandboxMode::Off => "commands run with your full permissions",
+            SandboxMode::ReadOnly => "no filesystem writes, network or unix socket access",
+            SandboxMode::Workspace => {
+                "writes limited to the workspace and temp directory, no network or unix socket access"
+            },
+        }
+    }
+}
+
+impl Display for SandboxMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxMode::Off => write!(f, "off"),
+            SandboxMode::ReadOnly => write!(f, "readonly"),
+            SandboxMode::Workspace => write!(f, "workspace"),
+        }
+    }
+}
+
+impl FromStr for SandboxMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(SandboxMode::Off),
+            "readonly" => Ok(SandboxMode::ReadOnly),
+            "workspace" => Ok(SandboxMode::Workspace),
+            other => Err(format!(
+                "Unknown sandbox mode '{other}', expected one of: {}",
+                Self::VALUES.join(", ")
+            )),
+        }
+    }
+}
+
+/// The restrictions to apply to a single command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxPolicy {
+    /// Directories the command may write beneath.
+    pub writable_paths: Vec<PathBuf>,
+}
+
+impl SandboxPolicy {
+    /// Returns the policy for `mode` in the current workspace, or [None] if commands should not be
+    /// sandboxed.
+    pub fn for_mode(ctx: &Context, mode: SandboxMode) -> Result<Option<Self>> {
+        match mode {
+            SandboxMode::Off => Ok(None),
+            SandboxMode::ReadOnly => Ok(Some(Self {
+                writable_paths: vec![PathBuf::from("/dev")],
+            })),
+            SandboxMode::Workspace => Ok(Some(Self {
+                writable_paths: vec![
+                    ctx.env().current_dir()?,
+                    std::env::temp_dir(),
+                    // Commands commonly redirect to /dev/null or write to the terminal.
+                    PathBuf::from("/dev"),
+                ],
+            })),
+        }
+    }
+}
+
+/// Configures `command` to run under `policy` once spawned.
+///
+/// Spawning fails if the restrictions cannot be fully enforced, rather than running the command
+/// unsandboxed.
+#[cfg(target_os = "linux")]
+pub fn apply(command: &mut tokio::process::Command, policy: &SandboxPolicy) -> Result<()> {
+    use std::ffi::CString;
+    use std::io;
+
+    use landlock::{
+        ABI,
+        AccessFs,
+        CompatLevel,
+        Compatible,
+        Ruleset,
+        RulesetAttr,
+        RulesetCreatedAttr,
+        RulesetStatus,
+        path_beneath_rules,
+    };
+
+    let abi = ABI::V3;
+    let ruleset = Ruleset::default()
+        .set_compatibility(CompatLevel::BestEffort)
+        .handle_access(AccessFs::from_write(abi))?
+        .create()?
+        .add_rules(path_beneath_rules(&policy.writable_paths, AccessFs::from_write(abi)))?;
+
+    // Everything used after fork is prepared up front, since allocating in the child is not safe.
+    let (uid, gid) = (nix::unistd::getuid(), nix::unistd::getgid());
+    let uid_map = format!("{uid} {uid} 1");
+    let gid_map = format!("{gid} {gid} 1");
+    let files = [
+        (CString::new("/proc/self/setgroups")?, "deny".to_string()),
+        (CString::new("/proc/self/uid_map")?, uid_map),
+        (CString::new("/proc/self/gid_map")?, gid_map),
+    ];
+    let mut ruleset = Some(ruleset);
+    let filter = unix_socket_filter()?;
+
+    fn write_proc_file(path: &CString, contents: &str) -> io::Result<()> {
+        // SAFETY: `path` is a valid nul-terminated string and `contents` outlives the call.
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let written = libc::write(fd, contents.as_ptr().cast(), contents.len());
+            let err = io::Error::last_os_error();
+            libc::close(fd);
+            if written < 0 {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    // SAFETY: the closure only performs system calls on data prepared before forking.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            for (path, contents) in &files {
+                write_proc_file(path, contents)?;
+            }
+            let status = ruleset
+                .take()
+                .ok_or_else(|| io::Error::other("sandbox already applied"))?
+                .restrict_self()
+                .map_err(io::Error::other)?;
+            if status.ruleset == RulesetStatus::NotEnforced {
+                return Err(io::Error::other("Landlock is not supported by the running kernel"));
+            }
+
+            let program = libc::sock_fprog {
+                len: filter.len() as u16,
+                filter: filter.as_ptr().cast_mut(),
+            };
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                || libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &raw const program,
+                    0,
+                    0,
+                ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+/// Returns a seccomp filter that fails creating unix domain sockets, as well as setting up
+/// io_uring, with `EPERM`. System calls of other architectures, e.g. 32-bit ones, are denied
+/// entirely since their numbers differ.
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn unix_socket_filter() -> Result<Vec<libc::sock_filter>> {
+    // From linux/audit.h.
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+    // System calls of the x32 ABI have this bit set, see linux/unistd.h.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+    // Offsets into `struct seccomp_data`.
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+    const ARG0: u32 = 16;
+
+    let load = |offset: u32| libc::sock_filter {
+        code: (libc::BPF_LD | libc::BPF_W | libc::BPF_ABS) as u16,
+        jt: 0,
+        jf: 0,
+        k: offset,
+    };
+    // Jumps skip `jt` instructions if the condition holds, `jf` otherwise.
+    let jump = |op: u32, k: u32, jt: u8, jf: u8| libc::sock_filter {
+        code: (libc::BPF_JMP | op | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let ret = |k: u32| libc::sock_filter {
+        code: (libc::BPF_RET | libc::BPF_K) as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+
+    Ok(vec![
+        load(ARCH),
+        jump(libc::BPF_JEQ, AUDIT_ARCH, 0, 7),
+        load(NR),
+        jump(libc::BPF_JGE, X32_SYSCALL_BIT, 5, 0),
+        jump(libc::BPF_JEQ, libc::SYS_io_uring_setup as u32, 4, 0),
+        jump(libc::BPF_JEQ, libc::SYS_socket as u32, 0, 2),
+        load(ARG0),
+        jump(libc::BPF_JEQ, libc::AF_UNIX as u32, 1, 0),
+        ret(libc::SECCOMP_RET_ALLOW),
+        ret(libc::SECCOMP_RET_ERRNO | (libc::EPERM as u32 & libc::SECCOMP_RET_DATA)),
+    ])
+}
+
+/// The system call numbers the filter checks are only known for the architectures above.
+#[cfg(all(target_os = "linux", not(any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn unix_socket_filter() -> Result<Vec<libc::sock_filter>> {
+    eyre::bail!("Sandboxed command execution is not supported on this architecture")
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(_command: &mut tokio::process::Command, _policy: &SandboxPolicy) -> Result<()> {
+    eyre::bail!("Sandboxed command execution is only supported on Linux")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sandbox_mode_parse() {
+        assert_eq!("workspace".parse::<SandboxMode>().unwrap(), SandboxMode::Workspace);
+        assert_eq!("OFF".parse::<SandboxMode>().unwrap(), SandboxMode::Off);
+        assert!("strict".parse::<SandboxMode>().is_err());
+        assert_eq!("readonly".parse::<SandboxMode>().unwrap(), SandboxMode::ReadOnly);
+        assert_eq!(serde_json::to_string(&SandboxMode::Workspace).unwrap(), "\"workspace\"");
+        assert_eq!(serde_json::to_string(&SandboxMode::ReadOnly).unwrap(), "\"readonly\"");
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_policy() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        assert!(SandboxPolicy::for_mode(&ctx, SandboxMode::Off).unwrap().is_none());
+        let policy = SandboxPolicy::for_mode(&ctx, SandboxMode::Workspace).unwrap().unwrap();
+        assert!(policy.writable_paths.contains(&ctx.env().current_dir().unwrap()));
+        let policy = SandboxPolicy::for_mode(&ctx, SandboxMode::ReadOnly).unwrap().unwrap();
+        assert_eq!(policy.writable_paths, vec![PathBuf::from("/dev")]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_sandboxed_command() {
+        let workspace = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let policy = SandboxPolicy {
+            writable_paths: vec![workspace.path().to_path_buf(), PathBuf::from("/dev")],
+        };
+        let run = |command: String| {
+            let policy = policy.clone();
+            async move { super::super::run_command::<std::io::Sink>(&command, Some(&policy), 1024, None).await }
+        };
+
+        // Not every kernel (or container) allows unprivileged user namespaces or Landlock.
+        if let Err(err) = run("true".to_string()).await {
+            println!("skipping, sandboxing is unavailable: {err:?}");
+            return;
+        }
+        let run = |command: String| {
+            let run = &run;
+            async move { run(command).await.unwrap() }
+        };
+
+        let result = run(format!("echo hi > {}/file", workspace.path().display())).await;
+        assert_eq!(result.exit_status, Some(0), "{}", result.stderr);
+        let result = run(format!("echo hi > {}/file", outside.path().display())).await;
+        assert_ne!(result.exit_status, Some(0));
+        assert!(!outside.path().join("file").exists());
+        let result = run("exec 3<>/dev/tcp/1.1.1.1/80".to_string()).await;
+        assert_ne!(result.exit_status, Some(0));
+
+        // Unix domain sockets, such as the docker socket, cannot be reached.
+        let socket_path = workspace.path().join("server.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&socket_path).unwrap();
+        let python = std::process::Command::new("python3").arg("--version").output();
+        if python.is_ok_and(|output| output.status.success()) {
+            let connect = format!(
+                "python3 -c \"import socket; socket.socket(socket.AF_UNIX).connect('{}')\"",
+                socket_path.display()
+            );
+            let result = run(connect).await;
+            assert_ne!(result.exit_status, Some(0));
+            assert!(result.stderr.contains("PermissionError"), "{}", result.stderr);
+        }
+    }
+}
@@ -10,6 +10,7 @@ use tokio::io::AsyncBufReadExt;
 use tokio::select;
 use tracing::error;
 
+use super::sandbox::SandboxPolicy;
 use super::{
     CommandResult,
     format_output,
@@ -18,22 +19,28 @@ use super::{
 /// Run a bash command on Unix systems.
 /// # Arguments
 /// * `command` - The command to run
+/// * `sandbox` - restrictions to run the command under, if any
 /// * `max_result_size` - max size of output streams, truncating if required
 /// * `updates` - output stream to push informational messages about the progress
 /// # Returns
 /// A [`CommandResult`]
 pub async fn run_command<W: Write>(
     command: &str,
+    sandbox: Option<&SandboxPolicy>,
     max_result_size: usize,
     mut updates: Option<W>,
 ) -> Result<CommandResult> {
     // We need to maintain a handle on stderr and stdout, but pipe it to the terminal as well
-    let mut child = tokio::process::Command::new("bash")
-        .arg("-c")
+    let mut cmd = tokio::process::Command::new("bash");
+    cmd.arg("-c")
         .arg(command)
         .stdin(Stdio::inherit())
         .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(sandbox) = sandbox {
+        super::sandbox::apply(&mut cmd, sandbox).wrap_err("Unable to sandbox the command")?;
+    }
+    let mut child = cmd
         .spawn()
         .wrap_err_with(|| format!("Unable to spawn command '{}'", command))?;
 
@@ -120,10 +127,12 @@ pub async fn run_command<W: Write>(
 mod tests {
     use crate::cli::chat::tools::OutputKind;
     use crate::cli::chat::tools::execute::ExecuteCommand;
+    use crate::platform::Context;
 
     #[ignore = "todo: fix failing on musl for some reason"]
     #[tokio::test]
     async fn test_execute_bash_tool() {
+        let ctx = Context::new();
         let mut stdout = std::io::stdout();
 
         // Verifying stdout
@@ -132,7 +141,7 @@ mod tests {
         });
         let out = serde_json::from_value::<ExecuteCommand>(v)
             .unwrap()
-            .invoke(&mut stdout)
+            .invoke(&ctx, &mut stdout)
             .await
             .unwrap();
 
@@ -150,7 +159,7 @@ mod tests {
         });
         let out = serde_json::from_value::<ExecuteCommand>(v)
             .unwrap()
-            .invoke(&mut stdout)
+            .invoke(&ctx, &mut stdout)
             .await
             .unwrap();
 
@@ -168,7 +177,7 @@ mod tests {
         });
         let out = serde_json::from_value::<ExecuteCommand>(v)
             .unwrap()
-            .invoke(&mut stdout)
+            .invoke(&ctx, &mut stdout)
             .await
             .unwrap();
         if let OutputKind::Json(json) = out.output {
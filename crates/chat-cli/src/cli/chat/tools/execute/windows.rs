@@ -10,6 +10,7 @@ use tokio::io::AsyncBufReadExt;
 use tokio::select;
 use tracing::error;
 
+use super::sandbox::SandboxPolicy;
 use super::{
     CommandResult,
     format_output,
@@ -18,15 +19,21 @@ use super::{
 /// Run a command on Windows using cmd.exe.
 /// # Arguments
 /// * `command` - The command to run
+/// * `sandbox` - restrictions to run the command under, if any
 /// * `max_result_size` - max size of output streams, truncating if required
 /// * `updates` - output stream to push informational messages about the progress
 /// # Returns
 /// A [`CommandResult`]
 pub async fn run_command<W: Write>(
     command: &str,
+    sandbox: Option<&SandboxPolicy>,
     max_result_size: usize,
     mut updates: Option<W>,
 ) -> Result<CommandResult> {
+    if sandbox.is_some() {
+        eyre::bail!("Sandboxed command execution is not supported on Windows");
+    }
+
     // We need to maintain a handle on stderr and stdout, but pipe it to the terminal as well
     let mut child = tokio::process::Command::new("cmd")
         .arg("/C")
@@ -0,0 +1,445 @@
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crossterm::queue;
+use crossterm::style::{
+    self,
+    Color,
+};
+use eyre::{
+    Result,
+    bail,
+};
+use globset::{
+    Glob,
+    GlobSet,
+    GlobSetBuilder,
+};
+use ignore::WalkBuilder;
+use regex::{
+    Regex,
+    RegexBuilder,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use tracing::debug;
+
+use super::{
+    InvokeOutput,
+    MAX_TOOL_RESPONSE_SIZE,
+    OutputKind,
+    format_path,
+    sanitize_path_tool_arg,
+};
+use crate::platform::Context;
+
+/// Searches the contents of every file beneath a directory, skipping files ignored by
+/// `.gitignore`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WorkspaceSearch {
+    /// The regular expression, or literal string if [Self::literal] is set, to search for.
+    pub pattern: String,
+    /// The directory or file to search, defaulting to the current working directory.
+    pub path: Option<String>,
+    #[serde(default)]
+    pub literal: bool,
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Globs that files must match to be searched, relative to [Self::path].
+    pub include: Option<Vec<String>>,
+    /// Globs of files and directories to skip, relative to [Self::path].
+    pub exclude: Option<Vec<String>>,
+    pub context_lines: Option<usize>,
+    pub max_results: Option<usize>,
+}
+
+impl WorkspaceSearch {
+    const CONTEXT_LINE_PREFIX: &str = "  ";
+    const DEFAULT_MAX_RESULTS: usize = 100;
+    const MATCHING_LINE_PREFIX: &str = "→ ";
+    const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+    /// Matching lines are truncated to this many bytes, e.g. for minified files.
+    const MAX_LINE_LEN: usize = 500;
+    const MAX_RESULTS: usize = 1000;
+
+    pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
+        if self.pattern.is_empty() {
+            bail!("Search pattern cannot be empty");
+        }
+        self.regex()?;
+        self.include_globs()?;
+        self.exclude_globs()?;
+        let path = self.search_path(ctx)?;
+        if !path.exists() {
+            bail!("Path not found: {}", format_path(ctx.env().current_dir()?, &path));
+        }
+        if self.max_results == Some(0) {
+            bail!("max_results must be greater than 0");
+        }
+        Ok(())
+    }
+
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        queue!(
+            updates,
+            style::Print("Searching: "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(self.path.as_deref().unwrap_or(".")),
+            style::ResetColor,
+            style::Print(" for pattern: "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(&self.pattern),
+            style::ResetColor,
+        )?;
+        for (label, globs) in [("including", &self.include), ("excluding", &self.exclude)] {
+            if let Some(globs) = globs.as_ref().filter(|globs| !globs.is_empty()) {
+                queue!(updates, style::Print(format!(" {label} {}", globs.join(", "))))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn invoke(&self, ctx: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
+        let root = self.search_path(ctx)?;
+        let search = Search {
+            display_root: PathBuf::from(self.path.as_deref().unwrap_or(".")),
+            regex: self.regex()?,
+            include: self.include_globs()?,
+            exclude: self.exclude_globs()?,
+            max_results: self
+                .max_results
+                .unwrap_or(Self::DEFAULT_MAX_RESULTS)
+                .min(Self::MAX_RESULTS),
+            context_lines: self.context_lines.unwrap_or_default(),
+        };
+        debug!(?root, pattern = self.pattern, "Searching files");
+        // Walking the tree and reading files is blocking, so it runs off of the async runtime.
+        let (results, files_with_matches) = tokio::task::spawn_blocking(move || search.run(root)).await?;
+
+        queue!(
+            updates,
+            style::Print(format!(
+                "Found {}{} matches for pattern '{}' in {} of {} files searched\n",
+                results.matches.len(),
+                if results.truncated { "+" } else { "" },
+                self.pattern,
+                files_with_matches,
+                results.files_searched,
+            )),
+            style::Print("\n"),
+        )?;
+
+        let output = serde_json::to_string(&results)?;
+        if output.len() > MAX_TOOL_RESPONSE_SIZE {
+            bail!(
+                "The search results are {} bytes, which is more than the {MAX_TOOL_RESPONSE_SIZE} bytes this tool can return. Try a more specific pattern, include filter, or a lower max_results.",
+                output.len()
+            );
+        }
+        Ok(InvokeOutput {
+            output: OutputKind::Text(output),
+        })
+    }
+
+    fn search_path(&self, ctx: &Context) -> Result<PathBuf> {
+        Ok(match &self.path {
+            Some(path) => sanitize_path_tool_arg(ctx, path),
+            None => sanitize_path_tool_arg(ctx, ctx.env().current_dir()?),
+        })
+    }
+
+    fn regex(&self) -> Result<Regex> {
+        let pattern = match self.literal {
+            true => regex::escape(&self.pattern),
+            false => self.pattern.clone(),
+        };
+        match RegexBuilder::new(&pattern)
+            .case_insensitive(self.case_insensitive)
+            .build()
+        {
+            Ok(regex) => Ok(regex),
+            Err(err) => bail!("Invalid regex pattern '{}': {err}", self.pattern),
+        }
+    }
+
+    fn include_globs(&self) -> Result<Option<GlobSet>> {
+        build_glob_set(self.include.as_deref())
+    }
+
+    fn exclude_globs(&self) -> Result<Option<GlobSet>> {
+        build_glob_set(self.exclude.as_deref())
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct SearchResults {
+    matches: Vec<SearchMatch>,
+    files_searched: usize,
+    /// Whether the search stopped early after reaching the maximum number of results.
+    truncated: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchMatch {
+    path: String,
+    line_number: usize,
+    line: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<String>,
+}
+
+fn truncate_line(line: &str) -> &str {
+    match line.char_indices().nth(WorkspaceSearch::MAX_LINE_LEN) {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+fn build_glob_set(globs: Option<&[String]>) -> Result<Option<GlobSet>> {
+    let Some(globs) = globs.filter(|globs| !globs.is_empty()) else {
+        return Ok(None);
+    };
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        match Glob::new(glob) {
+            Ok(glob) => builder.add(glob),
+            Err(err) => bail!("Invalid glob '{glob}': {err}"),
+        };
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// A search of the files beneath a directory.
+struct Search {
+    /// The path that results are shown relative to, as given by the model.
+    display_root: PathBuf,
+    regex: Regex,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    max_results: usize,
+    context_lines: usize,
+}
+
+impl Search {
+    /// Searches `root`, returning the results and the number of files with a match.
+    fn run(self, root: PathBuf) -> (SearchResults, usize) {
+        let exclude = self.exclude.clone();
+        let walk_root = root.clone();
+        let walker = WalkBuilder::new(&root)
+            // Hidden files are searched, only the repository itself is skipped.
+            .hidden(false)
+            .require_git(false)
+            .filter_entry(move |entry| {
+                let relative = entry.path().strip_prefix(&walk_root).unwrap_or(entry.path());
+                entry.file_name() != ".git" && !exclude.as_ref().is_some_and(|glob| glob.is_match(relative))
+            })
+            // Visit entries in alphabetical order so results are stable.
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+
+        let mut results = SearchResults::default();
+        let mut files_with_matches = 0;
+        'walk: for entry in walker.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let relative = path.strip_prefix(&root).unwrap_or(path);
+            if !entry.file_type().is_some_and(|file_type| file_type.is_file())
+                || entry
+                    .metadata()
+                    .map_or(true, |metadata| metadata.len() > WorkspaceSearch::MAX_FILE_SIZE)
+                || self.include.as_ref().is_some_and(|glob| !glob.is_match(relative))
+            {
+                continue;
+            }
+            let Ok(contents) = std::fs::read(path) else {
+                continue;
+            };
+            // Skip binary files, like ripgrep does.
+            if contents[..contents.len().min(8192)].contains(&0) {
+                continue;
+            }
+            let contents = String::from_utf8_lossy(&contents);
+            results.files_searched += 1;
+
+            let display_path = self.display_root.join(relative).to_string_lossy().into_owned();
+            let lines = contents.lines().collect::<Vec<_>>();
+            let mut has_match = false;
+            for (i, line) in lines.iter().enumerate() {
+                if !self.regex.is_match(line) {
+                    continue;
+                }
+                if results.matches.len() == self.max_results {
+                    results.truncated = true;
+                    break 'walk;
+                }
+                has_match = true;
+                results.matches.push(SearchMatch {
+                    path: display_path.clone(),
+                    line_number: i + 1,
+                    line: truncate_line(line).to_string(),
+                    context: (self.context_lines > 0).then(|| self.context(&lines, i)),
+                });
+            }
+            files_with_matches += usize::from(has_match);
+        }
+        (results, files_with_matches)
+    }
+
+    /// Formats the lines around the match on line `i`.
+    fn context(&self, lines: &[&str], i: usize) -> String {
+        let start = i.saturating_sub(self.context_lines);
+        let end = lines.len().min(i + self.context_lines + 1);
+        let mut context = String::new();
+        for (j, line) in lines.iter().enumerate().take(end).skip(start) {
+            let prefix = match j == i {
+                true => WorkspaceSearch::MATCHING_LINE_PREFIX,
+                false => WorkspaceSearch::CONTEXT_LINE_PREFIX,
+            };
+            let _ = writeln!(context, "{prefix}{}: {}", j + 1, truncate_line(line));
+        }
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    async fn setup_test_directory() -> Arc<Context> {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let fs = ctx.fs();
+        fs.create_dir_all("/project/src/nested").await.unwrap();
+        fs.create_dir_all("/project/target/debug").await.unwrap();
+        fs.create_dir_all("/project/.git").await.unwrap();
+        fs.write("/project/.gitignore", "target/\n*.log\n!keep.log\n")
+            .await
+            .unwrap();
+        fs.write("/project/src/main.rs", "fn main() {\n    println!(\"Hello\");\n}\n")
+            .await
+            .unwrap();
+        fs.write(
+            "/project/src/nested/lib.rs",
+            "pub fn hello() {}\n// TODO: hello world\n",
+        )
+        .await
+        .unwrap();
+        fs.write("/project/src/nested/.gitignore", "generated.rs\n")
+            .await
+            .unwrap();
+        fs.write("/project/src/nested/generated.rs", "fn hello() {}\n")
+            .await
+            .unwrap();
+        fs.write("/project/target/debug/out.rs", "hello\n").await.unwrap();
+        fs.write("/project/debug.log", "hello\n").await.unwrap();
+        fs.write("/project/keep.log", "hello\n").await.unwrap();
+        fs.write("/project/.git/HEAD", "hello\n").await.unwrap();
+        fs.write("/project/data.bin", b"hello\0world").await.unwrap();
+        ctx
+    }
+
+    async fn search(ctx: &Context, args: serde_json::Value) -> serde_json::Value {
+        let mut fs_search = serde_json::from_value::<WorkspaceSearch>(args).unwrap();
+        fs_search.validate(ctx).await.unwrap();
+        match fs_search.invoke(ctx, &mut std::io::sink()).await.unwrap().output {
+            OutputKind::Text(text) => serde_json::from_str(&text).unwrap(),
+            _ => panic!("expected text output"),
+        }
+    }
+
+    fn matched_paths(results: &serde_json::Value) -> Vec<&str> {
+        results["matches"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["path"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_fs_search_honors_gitignore() {
+        let ctx = setup_test_directory().await;
+        let results = search(
+            &ctx,
+            serde_json::json!({ "path": "/project", "pattern": "hello", "case_insensitive": true }),
+        )
+        .await;
+        assert_eq!(matched_paths(&results), vec![
+            "/project/keep.log",
+            "/project/src/main.rs",
+            "/project/src/nested/lib.rs",
+            "/project/src/nested/lib.rs",
+        ]);
+        assert_eq!(results["truncated"], false);
+        assert_eq!(results["matches"][0]["line_number"], 1);
+        assert!(results["matches"][0].get("context").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fs_search_filters() {
+        let ctx = setup_test_directory().await;
+        let results = search(
+            &ctx,
+            serde_json::json!({
+                "path": "/project",
+                "pattern": "hello(",
+                "literal": true,
+                "include": ["*.rs"],
+                "exclude": ["src/nested/**"],
+            }),
+        )
+        .await;
+        assert_eq!(matched_paths(&results), Vec::<&str>::new());
+
+        let results = search(
+            &ctx,
+            serde_json::json!({ "path": "/project", "pattern": "fn \\w+\\(", "include": ["**/nested/*.rs"] }),
+        )
+        .await;
+        assert_eq!(matched_paths(&results), vec!["/project/src/nested/lib.rs"]);
+    }
+
+    #[tokio::test]
+    async fn test_fs_search_max_results_and_context() {
+        let ctx = setup_test_directory().await;
+        let results = search(
+            &ctx,
+            serde_json::json!({
+                "path": "/project/src",
+                "pattern": "hello",
+                "case_insensitive": true,
+                "max_results": 1,
+                "context_lines": 1,
+            }),
+        )
+        .await;
+        assert_eq!(results["truncated"], true);
+        assert_eq!(results["matches"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            results["matches"][0]["context"],
+            format!(
+                "{}1: fn main() {{\n{}2:     println!(\"Hello\");\n{}3: }}\n",
+                WorkspaceSearch::CONTEXT_LINE_PREFIX,
+                WorkspaceSearch::MATCHING_LINE_PREFIX,
+                WorkspaceSearch::CONTEXT_LINE_PREFIX
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_search_validate() {
+        let ctx = setup_test_directory().await;
+        for args in [
+            serde_json::json!({ "path": "/project", "pattern": "" }),
+            serde_json::json!({ "path": "/project", "pattern": "(" }),
+            serde_json::json!({ "path": "/missing", "pattern": "hello" }),
+            serde_json::json!({ "path": "/project", "pattern": "hello", "include": ["["] }),
+        ] {
+            let mut fs_search = serde_json::from_value::<WorkspaceSearch>(args.clone()).unwrap();
+            assert!(fs_search.validate(&ctx).await.is_err(), "{args} should be invalid");
+        }
+    }
+}
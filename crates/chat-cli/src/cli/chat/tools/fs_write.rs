@@ -1,5 +1,8 @@
 use std::io::Write;
-use std::path::Path;
+use std::path::{
+    Path,
+    PathBuf,
+};
 use std::sync::LazyLock;
 
 use crossterm::queue;
@@ -10,6 +13,7 @@ use crossterm::style::{
 use eyre::{
     ContextCompat as _,
     Result,
+    WrapErr as _,
     bail,
     eyre,
 };
@@ -33,6 +37,7 @@ use super::{
     sanitize_path_tool_arg,
     supports_truecolor,
 };
+use crate::cli::chat::util::unified_diff;
 use crate::platform::Context;
 
 static SYNTAX_SET: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
@@ -63,6 +68,34 @@ pub enum FsWrite {
     },
     #[serde(rename = "append")]
     Append { path: String, new_str: String },
+    /// Applies changes spanning multiple hunks or files at once, given either as a unified diff
+    /// or as an ordered list of replacements. Relative paths are resolved against `path`.
+    #[serde(rename = "patch")]
+    Patch {
+        path: String,
+        diff: Option<String>,
+        edits: Option<Vec<PatchEdit>>,
+    },
+}
+
+/// A single replacement of a [FsWrite::Patch] command. `old_str` must match exactly once in the
+/// file, taking into account the edits preceding it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatchEdit {
+    pub path: String,
+    pub old_str: String,
+    pub new_str: String,
+}
+
+/// The result of applying a [FsWrite::Patch] command to a single file, computed in memory before
+/// anything is written to disk.
+#[derive(Debug, Clone)]
+struct PlannedChange {
+    path: PathBuf,
+    /// [None] if the file does not exist yet.
+    before: Option<String>,
+    /// [None] if the file is to be deleted.
+    after: Option<String>,
 }
 
 impl FsWrite {
@@ -160,6 +193,26 @@ impl FsWrite {
                 write_to_file(ctx, path, file).await?;
                 Ok(Default::default())
             },
+            FsWrite::Patch { .. } => {
+                let changes = self.plan_patch(ctx)?;
+                for change in &changes {
+                    let invoke_description = match (&change.before, &change.after) {
+                        (None, _) => "Creating: ",
+                        (_, None) => "Deleting: ",
+                        _ => "Updating: ",
+                    };
+                    queue!(
+                        updates,
+                        style::Print(invoke_description),
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(format_path(&cwd, &change.path)),
+                        style::ResetColor,
+                        style::Print("\n"),
+                    )?;
+                }
+                apply_changes(ctx, &changes).await?;
+                Ok(Default::default())
+            },
         }
     }
 
@@ -223,6 +276,56 @@ impl FsWrite {
                 print_diff(updates, &Default::default(), &file, start_line)?;
                 Ok(())
             },
+            FsWrite::Patch { .. } => {
+                for change in self.plan_patch(ctx)? {
+                    let relative_path = format_path(&cwd, &change.path);
+                    let (label, color) = match (&change.before, &change.after) {
+                        (None, _) => ("created", Color::Green),
+                        (_, None) => ("deleted", Color::Red),
+                        _ => ("modified", Color::Yellow),
+                    };
+                    queue!(
+                        updates,
+                        style::SetForegroundColor(Color::Green),
+                        style::Print(&relative_path),
+                        style::SetForegroundColor(color),
+                        style::Print(format!(" ({label})\n")),
+                        style::ResetColor,
+                    )?;
+                    match (change.before.as_deref(), change.after.as_deref()) {
+                        (_, None) => (),
+                        (None, Some(after)) => {
+                            let new = stylize_output_if_able(ctx, &relative_path, after);
+                            print_diff(updates, &Default::default(), &new, 1)?;
+                        },
+                        (Some(before), Some(after)) => {
+                            let before_lines = LinesWithEndings::from(before).collect::<Vec<_>>();
+                            let after_lines = LinesWithEndings::from(after).collect::<Vec<_>>();
+                            let diff = similar::TextDiff::from_lines(before, after);
+                            for group in diff.grouped_ops(3) {
+                                let (Some(first), Some(last)) = (group.first(), group.last()) else {
+                                    continue;
+                                };
+                                let old_range = first.old_range().start..last.old_range().end;
+                                let new_range = first.new_range().start..last.new_range().end;
+                                let old = stylize_output_if_able(
+                                    ctx,
+                                    &relative_path,
+                                    &before_lines[old_range.clone()].concat(),
+                                );
+                                let new = stylize_output_if_able(
+                                    ctx,
+                                    &relative_path,
+                                    &after_lines[new_range.clone()].concat(),
+                                );
+                                print_diff_at(updates, &old, &new, old_range.start + 1, new_range.start + 1)?;
+                            }
+                        },
+                    }
+                    queue!(updates, style::Print("\n"))?;
+                }
+                Ok(())
+            },
         }
     }
 
@@ -247,11 +350,111 @@ impl FsWrite {
                     bail!("Content to append must not be empty")
                 };
             },
+            FsWrite::Patch { path, .. } => {
+                if path.is_empty() {
+                    bail!("Path must not be empty")
+                };
+                // Every hunk and replacement is checked before anything gets written.
+                self.plan_patch(ctx)?;
+            },
         }
 
         Ok(())
     }
 
+    /// Returns the sanitized paths of every file this command will write to, failing if a
+    /// [FsWrite::Patch] command cannot be applied.
+    pub fn paths(&self, ctx: &Context) -> Result<Vec<PathBuf>> {
+        let path = match self {
+            FsWrite::Create { path, .. }
+            | FsWrite::StrReplace { path, .. }
+            | FsWrite::Insert { path, .. }
+            | FsWrite::Append { path, .. } => path,
+            FsWrite::Patch { .. } => {
+                return Ok(self.plan_patch(ctx)?.into_iter().map(|c| c.path).collect());
+            },
+        };
+        Ok(vec![sanitize_path_tool_arg(ctx, path)])
+    }
+
+    /// Computes the resulting contents of every file touched by a [FsWrite::Patch] command
+    /// without writing anything, failing if any hunk or replacement cannot be applied.
+    fn plan_patch(&self, ctx: &Context) -> Result<Vec<PlannedChange>> {
+        let FsWrite::Patch { path, diff, edits } = self else {
+            bail!("not a patch command");
+        };
+        let base = sanitize_path_tool_arg(ctx, path);
+        let resolve = |p: &str| {
+            let p = sanitize_path_tool_arg(ctx, p);
+            if p.is_absolute() { p } else { base.join(p) }
+        };
+
+        let mut changes: Vec<PlannedChange> = Vec::new();
+        match (diff, edits) {
+            (Some(diff), None) => {
+                for file in unified_diff::parse(diff)? {
+                    if let (Some(old), Some(new)) = (&file.old_path, &file.new_path) {
+                        if old != new {
+                            bail!("Renaming files is not supported: {old} -> {new}");
+                        }
+                    }
+                    let path = resolve(file.path());
+                    let change = planned_change_for(ctx, &mut changes, path.clone())?;
+                    change.after = match (&file.old_path, &file.new_path, change.after.take()) {
+                        (None, _, Some(_)) => bail!("Cannot create {}: the file already exists", path.display()),
+                        (None, _, None) => Some(unified_diff::apply("", &file.hunks)?),
+                        (Some(_), _, None) => bail!("Cannot patch {}: the file does not exist", path.display()),
+                        (Some(_), None, Some(content)) => {
+                            // Only delete the file if the diff was made against its current contents.
+                            let remaining = unified_diff::apply(&content, &file.hunks)
+                                .wrap_err_with(|| format!("Failed to apply the diff to {}", path.display()))?;
+                            if !remaining.is_empty() {
+                                bail!(
+                                    "Cannot delete {}: the diff does not remove all of its contents",
+                                    path.display()
+                                );
+                            }
+                            None
+                        },
+                        (Some(_), Some(_), Some(content)) => Some(
+                            unified_diff::apply(&content, &file.hunks)
+                                .wrap_err_with(|| format!("Failed to apply the diff to {}", path.display()))?,
+                        ),
+                    };
+                }
+            },
+            (None, Some(edits)) => {
+                if edits.is_empty() {
+                    bail!("At least one edit must be provided");
+                }
+                for (i, edit) in edits.iter().enumerate() {
+                    let path = resolve(&edit.path);
+                    let change = planned_change_for(ctx, &mut changes, path.clone())?;
+                    let Some(content) = change.after.as_mut() else {
+                        bail!("Cannot edit {}: the file does not exist", path.display());
+                    };
+                    match content.matches(edit.old_str.as_str()).count() {
+                        1 => *content = content.replacen(&edit.old_str, &edit.new_str, 1),
+                        0 => bail!(
+                            "Edit {}: no occurrences of \"{}\" were found in {}",
+                            i + 1,
+                            edit.old_str,
+                            path.display()
+                        ),
+                        x => bail!(
+                            "Edit {}: {x} occurrences of old_str were found in {} when only 1 is expected",
+                            i + 1,
+                            path.display()
+                        ),
+                    }
+                }
+            },
+            _ => bail!("Exactly one of `diff` or `edits` must be provided for the patch command"),
+        }
+
+        Ok(changes)
+    }
+
     fn print_relative_path(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
         let cwd = ctx.env().current_dir()?;
         let path = match self {
@@ -259,6 +462,7 @@ impl FsWrite {
             FsWrite::StrReplace { path, .. } => path,
             FsWrite::Insert { path, .. } => path,
             FsWrite::Append { path, .. } => path,
+            FsWrite::Patch { path, .. } => path,
         };
         let relative_path = format_path(cwd, path);
         queue!(
@@ -301,6 +505,62 @@ async fn write_to_file(ctx: &Context, path: impl AsRef<Path>, mut content: Strin
     Ok(())
 }
 
+/// Returns the pending change for `path`, so that multiple changes to the same file are applied on
+/// top of one another.
+fn planned_change_for<'a>(
+    ctx: &Context,
+    changes: &'a mut Vec<PlannedChange>,
+    path: PathBuf,
+) -> Result<&'a mut PlannedChange> {
+    let i = match changes.iter().position(|c| c.path == path) {
+        Some(i) => i,
+        None => {
+            let before = if ctx.fs().exists(&path) {
+                Some(ctx.fs().read_to_string_sync(&path)?)
+            } else {
+                None
+            };
+            changes.push(PlannedChange {
+                path,
+                after: before.clone(),
+                before,
+            });
+            changes.len() - 1
+        },
+    };
+    Ok(&mut changes[i])
+}
+
+/// Writes every planned change to disk. If any write fails, the changes already written are
+/// reverted so that the patch is applied either entirely or not at all.
+async fn apply_changes(ctx: &Context, changes: &[PlannedChange]) -> Result<()> {
+    async fn write_state(ctx: &Context, path: &Path, contents: Option<&String>) -> Result<()> {
+        match contents {
+            Some(contents) => {
+                if let Some(parent) = path.parent() {
+                    ctx.fs().create_dir_all(parent).await?;
+                }
+                ctx.fs().write(path, contents).await?;
+            },
+            None if ctx.fs().exists(path) => ctx.fs().remove_file(path).await?,
+            None => (),
+        }
+        Ok(())
+    }
+
+    for (i, change) in changes.iter().enumerate() {
+        if let Err(err) = write_state(ctx, &change.path, change.after.as_ref()).await {
+            for applied in changes[..i].iter().rev() {
+                if let Err(rollback_err) = write_state(ctx, &applied.path, applied.before.as_ref()).await {
+                    error!(?rollback_err, path = ?applied.path, "failed to roll back a patched file");
+                }
+            }
+            return Err(err.wrap_err(format!("Failed to write {}", change.path.display())));
+        }
+    }
+    Ok(())
+}
+
 /// Returns a prefix/suffix pair before and after the content dictated by `[start_line, end_line]`
 /// within `content`. The updated start and end lines containing the original context along with
 /// the suffix and prefix are returned.
@@ -365,6 +625,18 @@ fn print_diff(
     old_str: &StylizedFile,
     new_str: &StylizedFile,
     start_line: usize,
+) -> Result<()> {
+    print_diff_at(updates, old_str, new_str, start_line, start_line)
+}
+
+/// Same as [print_diff], except that `old_str` and `new_str` may start at different lines, e.g.
+/// for a hunk following other hunks that changed the line count.
+fn print_diff_at(
+    updates: &mut impl Write,
+    old_str: &StylizedFile,
+    new_str: &StylizedFile,
+    old_start_line: usize,
+    new_start_line: usize,
 ) -> Result<()> {
     let diff = similar::TextDiff::from_lines(&old_str.content, &new_str.content);
 
@@ -372,10 +644,10 @@ fn print_diff(
     let (mut max_old_i, mut max_new_i) = (1, 1);
     for change in diff.iter_all_changes() {
         if let Some(i) = change.old_index() {
-            max_old_i = i + start_line;
+            max_old_i = i + old_start_line;
         }
         if let Some(i) = change.new_index() {
-            max_new_i = i + start_line;
+            max_new_i = i + new_start_line;
         }
     }
     let old_line_num_width = terminal_width_required_for_line_count(max_old_i);
@@ -413,8 +685,8 @@ fn print_diff(
             similar::ChangeTag::Insert => "+",
         };
 
-        let old_i_str = fmt_index(change.old_index(), start_line);
-        let new_i_str = fmt_index(change.new_index(), start_line);
+        let old_i_str = fmt_index(change.old_index(), old_start_line);
+        let new_i_str = fmt_index(change.new_index(), new_start_line);
 
         // Print the gutter and line numbers.
         queue!(updates, style::SetBackgroundColor(gutter_bg_color))?;
@@ -929,6 +1201,118 @@ mod tests {
         assert!(result.is_err(), "Appending to non-existent file should fail");
     }
 
+    #[tokio::test]
+    async fn test_fs_write_tool_patch_diff() {
+        let ctx = setup_test_directory().await;
+        let mut stdout = std::io::stdout();
+
+        let diff = "\
+--- a/test_file.txt
++++ b/test_file.txt
+@@ -1,2 +1,2 @@
+-1: Hello world!
++1: Goodbye world!
+ 2: This is line 2
+@@ -4,1 +4,2 @@
+ 4: Hello world!
++5: New line
+--- /dev/null
++++ b/aaaa1/new_file.txt
+@@ -0,0 +1,1 @@
++created by patch
+--- a/aaaa2/.hidden
++++ /dev/null
+@@ -1 +0,0 @@
+-this is a hidden file
+";
+        let v = serde_json::json!({
+            "path": "/",
+            "command": "patch",
+            "diff": diff,
+        });
+        let mut fw = serde_json::from_value::<FsWrite>(v).unwrap();
+        fw.validate(&ctx).await.unwrap();
+        assert_eq!(fw.paths(&ctx).unwrap().len(), 3);
+        fw.invoke(&ctx, &mut stdout).await.unwrap();
+
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_FILE_PATH).await.unwrap(),
+            "1: Goodbye world!\n2: This is line 2\n3: asdf\n4: Hello world!\n5: New line\n"
+        );
+        assert_eq!(
+            ctx.fs().read_to_string("/aaaa1/new_file.txt").await.unwrap(),
+            "created by patch\n"
+        );
+        assert!(!ctx.fs().exists(TEST_HIDDEN_FILE_PATH));
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_tool_patch_edits() {
+        let ctx = setup_test_directory().await;
+        let mut stdout = std::io::stdout();
+
+        let v = serde_json::json!({
+            "path": "/",
+            "command": "patch",
+            "edits": [
+                { "path": "test_file.txt", "old_str": "1: Hello world!", "new_str": "1: Hi world!" },
+                // Only unique once the first edit has been applied.
+                { "path": "test_file.txt", "old_str": "Hello world!", "new_str": "Bye world!" },
+                { "path": "/aaaa2/.hidden", "old_str": "hidden", "new_str": "visible" },
+            ],
+        });
+        let mut fw = serde_json::from_value::<FsWrite>(v).unwrap();
+        fw.validate(&ctx).await.unwrap();
+        fw.invoke(&ctx, &mut stdout).await.unwrap();
+
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_FILE_PATH).await.unwrap(),
+            "1: Hi world!\n2: This is line 2\n3: asdf\n4: Bye world!\n"
+        );
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_HIDDEN_FILE_PATH).await.unwrap(),
+            "this is a visible file"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_write_tool_patch_is_all_or_nothing() {
+        let ctx = setup_test_directory().await;
+
+        // The second edit does not match, so validation should fail without touching the first file.
+        let v = serde_json::json!({
+            "path": "/",
+            "command": "patch",
+            "edits": [
+                { "path": "/aaaa2/.hidden", "old_str": "hidden", "new_str": "visible" },
+                { "path": TEST_FILE_PATH, "old_str": "Hello world!", "new_str": "Bye world!" },
+            ],
+        });
+        let mut fw = serde_json::from_value::<FsWrite>(v).unwrap();
+        assert!(fw.validate(&ctx).await.is_err());
+        assert_eq!(
+            ctx.fs().read_to_string(TEST_HIDDEN_FILE_PATH).await.unwrap(),
+            "this is a hidden file"
+        );
+
+        // Deleting a file that does not match the removed lines, or only partially, should fail.
+        for hunk in [
+            "@@ -1 +0,0 @@\n-this is a stale file\n",
+            "@@ -1 +1 @@\n-this is a hidden file\n+but not for long\n",
+        ] {
+            let diff = format!("--- a/aaaa2/.hidden\n+++ /dev/null\n{hunk}");
+            let v = serde_json::json!({ "path": "/", "command": "patch", "diff": diff });
+            let mut fw = serde_json::from_value::<FsWrite>(v).unwrap();
+            assert!(fw.validate(&ctx).await.is_err());
+            assert!(ctx.fs().exists(TEST_HIDDEN_FILE_PATH));
+        }
+
+        // Both or neither of diff and edits.
+        let v = serde_json::json!({ "path": "/", "command": "patch" });
+        let mut fw = serde_json::from_value::<FsWrite>(v).unwrap();
+        assert!(fw.validate(&ctx).await.is_err());
+    }
+
     #[test]
     fn test_lines_with_context() {
         let content = "Hello\nWorld!\nhow\nare\nyou\ntoday?";
@@ -0,0 +1,110 @@
+use std::io::Write;
+
+use crossterm::queue;
+use crossterm::style::{
+    self,
+    Color,
+};
+use eyre::{
+    Result,
+    bail,
+};
+use serde::Deserialize;
+
+use super::{
+    InvokeOutput,
+    MAX_TOOL_RESPONSE_SIZE,
+    OutputKind,
+};
+use crate::cli::chat::knowledge::KnowledgeStore;
+use crate::platform::Context;
+
+/// Searches the knowledge bases the user has indexed with `/knowledge add`.
+///
+/// This is a beta feature that can be enabled/disabled via settings:
+/// `q settings chat.enableKnowledge true`
+#[derive(Debug, Clone, Deserialize)]
+pub struct KnowledgeSearch {
+    pub query: String,
+    /// The name of the knowledge base to search, defaulting to all of them.
+    pub knowledge_base: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl KnowledgeSearch {
+    const MAX_RESULTS: usize = 50;
+
+    pub async fn validate(&mut self, _ctx: &Context) -> Result<()> {
+        if self.query.trim().is_empty() {
+            bail!("Search query cannot be empty");
+        }
+        if self.limit == Some(0) {
+            bail!("limit must be greater than 0");
+        }
+        Ok(())
+    }
+
+    pub fn queue_description(&self, updates: &mut impl Write) -> Result<()> {
+        queue!(
+            updates,
+            style::Print("Searching "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(self.knowledge_base.as_deref().unwrap_or("all knowledge bases")),
+            style::ResetColor,
+            style::Print(" for: "),
+            style::SetForegroundColor(Color::Green),
+            style::Print(&self.query),
+            style::ResetColor,
+        )?;
+        Ok(())
+    }
+
+    pub async fn invoke(&self, ctx: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
+        let store = KnowledgeStore::global(ctx).await?;
+        let limit = self
+            .limit
+            .unwrap_or(KnowledgeStore::DEFAULT_RESULTS)
+            .min(Self::MAX_RESULTS);
+        let results = store.search(&self.query, self.knowledge_base.as_deref(), limit).await?;
+
+        queue!(
+            updates,
+            style::Print(format!("Found {} relevant passages\n", results.len())),
+            style::Print("\n"),
+        )?;
+
+        let output = serde_json::to_string(&results)?;
+        if output.len() > MAX_TOOL_RESPONSE_SIZE {
+            bail!(
+                "The search results are {} bytes, which is more than the {MAX_TOOL_RESPONSE_SIZE} bytes this tool can return. Try a lower limit.",
+                output.len()
+            );
+        }
+        Ok(InvokeOutput {
+            output: OutputKind::Text(output),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_validate() {
+        let ctx = Context::builder().with_test_home().await.unwrap().build_fake();
+        let mut tool = serde_json::from_value::<KnowledgeSearch>(serde_json::json!({
+            "query": "how are deployments rolled out",
+            "knowledge_base": "docs",
+        }))
+        .unwrap();
+        assert!(tool.validate(&ctx).await.is_ok());
+
+        tool.limit = Some(0);
+        assert!(tool.validate(&ctx).await.is_err());
+
+        tool.limit = None;
+        tool.query = "  ".to_string();
+        assert!(tool.validate(&ctx).await.is_err());
+    }
+}
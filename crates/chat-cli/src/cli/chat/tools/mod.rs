@@ -1,8 +1,12 @@
 pub mod custom_tool;
+pub mod delegate;
 pub mod execute;
 pub mod fs_read;
+pub mod fs_search;
 pub mod fs_write;
 pub mod gh_issue;
+#[cfg(feature = "knowledge")]
+pub mod knowledge_search;
 pub mod thinking;
 pub mod use_aws;
 
@@ -15,11 +19,20 @@ use std::path::{
 
 use crossterm::style::Stylize;
 use custom_tool::CustomTool;
+use delegate::Delegate;
 use execute::ExecuteCommand;
+use execute::rules::{
+    CommandRules,
+    RuleAction,
+    RuleEvaluation,
+};
 use eyre::Result;
 use fs_read::FsRead;
+use fs_search::WorkspaceSearch;
 use fs_write::FsWrite;
 use gh_issue::GhIssue;
+#[cfg(feature = "knowledge")]
+use knowledge_search::KnowledgeSearch;
 use serde::{
     Deserialize,
     Serialize,
@@ -28,6 +41,7 @@ use thinking::Thinking;
 use use_aws::UseAws;
 
 use super::consts::MAX_TOOL_RESPONSE_SIZE;
+use super::hooks::ToolHookOutcome;
 use super::util::images::RichImageBlocks;
 use crate::platform::Context;
 
@@ -36,12 +50,16 @@ use crate::platform::Context;
 #[derive(Debug, Clone)]
 pub enum Tool {
     FsRead(FsRead),
+    WorkspaceSearch(WorkspaceSearch),
     FsWrite(FsWrite),
     ExecuteCommand(ExecuteCommand),
     UseAws(UseAws),
     Custom(CustomTool),
     GhIssue(GhIssue),
     Thinking(Thinking),
+    #[cfg(feature = "knowledge")]
+    KnowledgeSearch(KnowledgeSearch),
+    Delegate(Delegate),
 }
 
 impl Tool {
@@ -49,6 +67,7 @@ impl Tool {
     pub fn display_name(&self) -> String {
         match self {
             Tool::FsRead(_) => "fs_read",
+            Tool::WorkspaceSearch(_) => "fs_search",
             Tool::FsWrite(_) => "fs_write",
             #[cfg(windows)]
             Tool::ExecuteCommand(_) => "execute_cmd",
@@ -58,6 +77,9 @@ impl Tool {
             Tool::Custom(custom_tool) => &custom_tool.name,
             Tool::GhIssue(_) => "gh_issue",
             Tool::Thinking(_) => "thinking (prerelease)",
+            #[cfg(feature = "knowledge")]
+            Tool::KnowledgeSearch(_) => "knowledge_search",
+            Tool::Delegate(_) => "delegate",
         }
         .to_owned()
     }
@@ -66,12 +88,16 @@ impl Tool {
     pub fn requires_acceptance(&self, _ctx: &Context) -> bool {
         match self {
             Tool::FsRead(_) => false,
+            Tool::WorkspaceSearch(_) => false,
             Tool::FsWrite(_) => true,
             Tool::ExecuteCommand(execute_command) => execute_command.requires_acceptance(),
             Tool::UseAws(use_aws) => use_aws.requires_acceptance(),
             Tool::Custom(_) => true,
             Tool::GhIssue(_) => false,
             Tool::Thinking(_) => false,
+            #[cfg(feature = "knowledge")]
+            Tool::KnowledgeSearch(_) => false,
+            Tool::Delegate(_) => false,
         }
     }
 
@@ -79,12 +105,17 @@ impl Tool {
     pub async fn invoke(&self, context: &Context, updates: &mut impl Write) -> Result<InvokeOutput> {
         match self {
             Tool::FsRead(fs_read) => fs_read.invoke(context, updates).await,
+            Tool::WorkspaceSearch(fs_search) => fs_search.invoke(context, updates).await,
             Tool::FsWrite(fs_write) => fs_write.invoke(context, updates).await,
-            Tool::ExecuteCommand(execute_command) => execute_command.invoke(updates).await,
+            Tool::ExecuteCommand(execute_command) => execute_command.invoke(context, updates).await,
             Tool::UseAws(use_aws) => use_aws.invoke(context, updates).await,
             Tool::Custom(custom_tool) => custom_tool.invoke(context, updates).await,
             Tool::GhIssue(gh_issue) => gh_issue.invoke(updates).await,
             Tool::Thinking(think) => think.invoke(updates).await,
+            #[cfg(feature = "knowledge")]
+            Tool::KnowledgeSearch(knowledge_search) => knowledge_search.invoke(context, updates).await,
+            // Boxed since the child conversation of a delegated task invokes tools itself.
+            Tool::Delegate(delegate) => Box::pin(delegate.invoke(updates)).await,
         }
     }
 
@@ -92,12 +123,16 @@ impl Tool {
     pub async fn queue_description(&self, ctx: &Context, updates: &mut impl Write) -> Result<()> {
         match self {
             Tool::FsRead(fs_read) => fs_read.queue_description(ctx, updates).await,
+            Tool::WorkspaceSearch(fs_search) => fs_search.queue_description(updates),
             Tool::FsWrite(fs_write) => fs_write.queue_description(ctx, updates),
             Tool::ExecuteCommand(execute_command) => execute_command.queue_description(updates),
             Tool::UseAws(use_aws) => use_aws.queue_description(updates),
             Tool::Custom(custom_tool) => custom_tool.queue_description(updates),
             Tool::GhIssue(gh_issue) => gh_issue.queue_description(updates),
             Tool::Thinking(thinking) => thinking.queue_description(updates),
+            #[cfg(feature = "knowledge")]
+            Tool::KnowledgeSearch(knowledge_search) => knowledge_search.queue_description(updates),
+            Tool::Delegate(delegate) => delegate.queue_description(updates),
         }
     }
 
@@ -105,12 +140,16 @@ impl Tool {
     pub async fn validate(&mut self, ctx: &Context) -> Result<()> {
         match self {
             Tool::FsRead(fs_read) => fs_read.validate(ctx).await,
+            Tool::WorkspaceSearch(fs_search) => fs_search.validate(ctx).await,
             Tool::FsWrite(fs_write) => fs_write.validate(ctx).await,
             Tool::ExecuteCommand(execute_command) => execute_command.validate(ctx).await,
             Tool::UseAws(use_aws) => use_aws.validate(ctx).await,
             Tool::Custom(custom_tool) => custom_tool.validate(ctx).await,
             Tool::GhIssue(gh_issue) => gh_issue.validate(ctx).await,
             Tool::Thinking(think) => think.validate(ctx).await,
+            #[cfg(feature = "knowledge")]
+            Tool::KnowledgeSearch(knowledge_search) => knowledge_search.validate(ctx).await,
+            Tool::Delegate(delegate) => delegate.validate(ctx).await,
         }
     }
 }
@@ -128,6 +167,9 @@ pub struct ToolPermissions {
     // We need this field for any stragglers
     pub trust_all: bool,
     pub permissions: HashMap<String, ToolPermission>,
+    /// User defined rules for shell commands. These take precedence over the trust settings
+    /// above.
+    pub command_rules: CommandRules,
 }
 
 impl ToolPermissions {
@@ -135,6 +177,30 @@ impl ToolPermissions {
         Self {
             trust_all: false,
             permissions: HashMap::with_capacity(capacity),
+            command_rules: CommandRules::default(),
+        }
+    }
+
+    /// Evaluates the command rules against `tool`, returning [None] if the tool is not a shell
+    /// command or no rules are defined.
+    pub fn evaluate_rules(&self, tool: &Tool) -> Option<RuleEvaluation<'_>> {
+        match tool {
+            Tool::ExecuteCommand(execute_command) if !self.command_rules.is_empty() => {
+                Some(self.command_rules.evaluate(&execute_command.command))
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether `tool` runs without asking the user: it is allowed by a command rule, trusted, or
+    /// doesn't need approval in the first place.
+    pub fn allows_without_asking(&self, tool_name: &str, tool: &Tool, ctx: &Context) -> bool {
+        match self.evaluate_rules(tool).and_then(|evaluation| evaluation.action) {
+            Some(RuleAction::Allow) => true,
+            Some(RuleAction::Ask | RuleAction::Deny) => false,
+            None => {
+                self.trust_all || (self.has(tool_name) && self.is_trusted(tool_name)) || !tool.requires_acceptance(ctx)
+            },
         }
     }
 
@@ -144,6 +210,19 @@ impl ToolPermissions {
 
     /// Returns a label to describe the permission status for a given tool.
     pub fn display_label(&self, tool_name: &str) -> String {
+        let label = self.trust_label(tool_name);
+        match self.command_rules.rules().len() {
+            n if n > 0 && matches!(tool_name, "execute_bash" | "execute_cmd") => {
+                format!(
+                    "{label} {}",
+                    format!("(+{n} command rules, see /tools rules)").dark_grey()
+                )
+            },
+            _ => label,
+        }
+    }
+
+    fn trust_label(&self, tool_name: &str) -> String {
         if self.has(tool_name) || self.trust_all {
             if self.is_trusted(tool_name) {
                 format!("  {}", "trusted".dark_green().bold())
@@ -185,6 +264,7 @@ impl ToolPermissions {
     fn default_permission_label(&self, tool_name: &str) -> String {
         let label = match tool_name {
             "fs_read" => "trusted".dark_green().bold(),
+            "fs_search" => "trusted".dark_green().bold(),
             "fs_write" => "not trusted".dark_grey(),
             #[cfg(not(windows))]
             "execute_bash" => "trust read-only commands".dark_grey(),
@@ -193,6 +273,8 @@ impl ToolPermissions {
             "use_aws" => "trust read-only commands".dark_grey(),
             "report_issue" => "trusted".dark_green().bold(),
             "thinking" => "trusted (prerelease)".dark_green().bold(),
+            "knowledge_search" => "trusted".dark_green().bold(),
+            "delegate" => "trusted".dark_green().bold(),
             _ if self.trust_all => "trusted".dark_grey().bold(),
             _ => "not trusted".dark_grey(),
         };
@@ -211,6 +293,18 @@ pub struct ToolSpec {
     pub input_schema: InputSchema,
     #[serde(skip_serializing, default = "tool_origin")]
     pub tool_origin: ToolOrigin,
+    /// Hints about the tool's behavior, as reported by the MCP server providing it.
+    #[serde(skip_serializing, default)]
+    pub annotations: Option<ToolAnnotations>,
+}
+
+/// The MCP tool annotations the chat makes use of.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAnnotations {
+    /// Whether the tool leaves its environment unmodified.
+    #[serde(default)]
+    pub read_only_hint: Option<bool>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -264,6 +358,10 @@ pub struct QueuedTool {
     pub name: String,
     pub accepted: bool,
     pub tool: Tool,
+    /// The input of the tool use, after being rewritten by any pre tool use hooks.
+    pub input: serde_json::Value,
+    /// The outcome of the pre tool use hooks.
+    pub pre_hook_outcome: ToolHookOutcome,
 }
 
 /// The schema specification describing a tool's fields.
@@ -354,7 +452,7 @@ fn absolute_to_relative(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> Result
 }
 
 /// Small helper for formatting the path as a relative path, if able.
-fn format_path(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> String {
+pub fn format_path(cwd: impl AsRef<Path>, path: impl AsRef<Path>) -> String {
     absolute_to_relative(cwd, path.as_ref())
         .map(|p| p.to_string_lossy().to_string())
         // If we have three consecutive ".." then it should probably just stay as an absolute path.
@@ -2,6 +2,7 @@ pub mod images;
 pub mod issue;
 pub mod shared_writer;
 pub mod ui;
+pub mod unified_diff;
 
 use std::io::Write;
 use std::time::Duration;
@@ -52,6 +52,60 @@ impl Write for SharedWriter {
     }
 }
 
+/// Writes complete lines to a [SharedWriter], each starting with `prefix`, so that the output of
+/// tasks running concurrently can be told apart. A trailing partial line is written once the
+/// writer is dropped.
+pub struct PrefixedWriter {
+    inner: SharedWriter,
+    prefix: String,
+    line: Vec<u8>,
+}
+
+impl PrefixedWriter {
+    pub fn new(inner: SharedWriter, prefix: impl Into<String>) -> Self {
+        Self {
+            inner,
+            prefix: prefix.into(),
+            line: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        let mut line = Vec::with_capacity(self.prefix.len() + self.line.len());
+        line.extend_from_slice(self.prefix.as_bytes());
+        line.append(&mut self.line);
+        // Written at once so that lines of other writers don't end up in the middle of it.
+        self.inner.write_all(&line)
+    }
+}
+
+impl Write for PrefixedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(end) = rest.iter().position(|b| *b == b'\n') {
+            self.line.extend_from_slice(&rest[..=end]);
+            self.write_line()?;
+            rest = &rest[end + 1..];
+        }
+        self.line.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for PrefixedWriter {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.line.push(b'\n');
+            self.write_line().ok();
+        }
+        self.inner.flush().ok();
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct NullWriter {}
 
@@ -87,3 +141,27 @@ impl Write for TestWriterWithSink {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefixed_writer() {
+        let sink = TestWriterWithSink {
+            sink: Arc::new(Mutex::new(Vec::new())),
+        };
+        let shared = SharedWriter::new(sink.clone());
+        let mut first = PrefixedWriter::new(shared.clone(), "[1] ");
+        let mut second = PrefixedWriter::new(shared, "[2] ");
+        write!(first, "reading").unwrap();
+        writeln!(second, "writing\ndone").unwrap();
+        writeln!(first, " files").unwrap();
+        write!(first, "partial").unwrap();
+        drop(first);
+        assert_eq!(
+            String::from_utf8(sink.get_content()).unwrap(),
+            "[2] writing\n[2] done\n[1] reading files\n[1] partial\n"
+        );
+    }
+}
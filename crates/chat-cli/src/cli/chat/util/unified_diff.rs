@@ -0,0 +1,428 @@
+//! Minimal parser and applier for unified diffs, as produced by `diff -u` or `git diff`.
+
+use eyre::{
+    Result,
+    bail,
+    eyre,
+};
+
+const DEV_NULL: &str = "/dev/null";
+
+/// The changes to a single file contained in a unified diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    /// Path of the file before the change, [None] if the file is being created.
+    pub old_path: Option<String>,
+    /// Path of the file after the change, [None] if the file is being deleted.
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FileDiff {
+    /// The path the changes should be applied to.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// 1-indexed line the hunk is expected to start at in the original file.
+    pub old_start: usize,
+    /// Context and removed lines, i.e. the lines expected in the original file.
+    pub old_lines: Vec<String>,
+    /// Context and added lines, i.e. the lines that replace [Self::old_lines].
+    pub new_lines: Vec<String>,
+    /// Whether the last of [Self::old_lines] is the last line of the file and has no trailing
+    /// newline, as marked by `\ No newline at end of file`.
+    pub old_missing_newline: bool,
+    /// Whether the last of [Self::new_lines] should be written without a trailing newline.
+    pub new_missing_newline: bool,
+}
+
+impl Hunk {
+    /// Records a "\ No newline at end of file" marker following a line of the given kind.
+    fn mark_missing_newline(&mut self, kind: char) {
+        match kind {
+            '-' => self.old_missing_newline = true,
+            '+' => self.new_missing_newline = true,
+            _ => {
+                self.old_missing_newline = true;
+                self.new_missing_newline = true;
+            },
+        }
+    }
+}
+
+/// Parses `diff` into the per file changes it contains.
+pub fn parse(diff: &str) -> Result<Vec<FileDiff>> {
+    let mut files = Vec::new();
+    let mut lines = diff.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some(old_path) = line.strip_prefix("--- ") else {
+            // Skip anything that isn't part of a file section, e.g. `diff --git` or `index` lines.
+            continue;
+        };
+        let new_path = lines
+            .next()
+            .and_then(|l| l.strip_prefix("+++ "))
+            .ok_or_else(|| eyre!("expected a '+++' line after '--- {old_path}'"))?;
+        let mut file = FileDiff {
+            old_path: parse_header_path(old_path, "a/"),
+            new_path: parse_header_path(new_path, "b/"),
+            hunks: Vec::new(),
+        };
+        if file.old_path.is_none() && file.new_path.is_none() {
+            bail!("a file diff must not have /dev/null as both its old and new path");
+        }
+
+        while let Some(header) = lines.next_if(|l| l.starts_with("@@")) {
+            let (old_start, old_len, new_len) = parse_hunk_header(header)?;
+            let mut hunk = Hunk {
+                old_start,
+                old_lines: Vec::new(),
+                new_lines: Vec::new(),
+                old_missing_newline: false,
+                new_missing_newline: false,
+            };
+            // The kind of the previous line, which a "\ No newline at end of file" marker refers to.
+            let mut last_kind = ' ';
+            while hunk.old_lines.len() < old_len || hunk.new_lines.len() < new_len {
+                let Some(line) = lines.next() else {
+                    bail!("unexpected end of diff in hunk '{header}'");
+                };
+                match line.split_at_checked(1) {
+                    Some((" ", rest)) => {
+                        hunk.old_lines.push(rest.to_string());
+                        hunk.new_lines.push(rest.to_string());
+                    },
+                    Some(("-", rest)) => hunk.old_lines.push(rest.to_string()),
+                    Some(("+", rest)) => hunk.new_lines.push(rest.to_string()),
+                    Some(("\\", _)) => {
+                        hunk.mark_missing_newline(last_kind);
+                        continue;
+                    },
+                    // Some tools strip the trailing whitespace of empty context lines.
+                    None => {
+                        hunk.old_lines.push(String::new());
+                        hunk.new_lines.push(String::new());
+                    },
+                    _ => bail!("invalid line in hunk '{header}': {line}"),
+                }
+                last_kind = line.chars().next().unwrap_or(' ');
+            }
+            if hunk.old_lines.len() != old_len || hunk.new_lines.len() != new_len {
+                bail!("the line counts of hunk '{header}' do not match its contents");
+            }
+            while lines.next_if(|l| l.starts_with('\\')).is_some() {
+                hunk.mark_missing_newline(last_kind);
+            }
+            file.hunks.push(hunk);
+        }
+
+        if file.hunks.is_empty() && file.old_path.is_some() && file.new_path.is_some() {
+            bail!("the diff for {} contains no hunks", file.path());
+        }
+        files.push(file);
+    }
+
+    if files.is_empty() {
+        bail!("no file changes were found in the diff");
+    }
+    Ok(files)
+}
+
+/// Applies `hunks` in order to `content`.
+///
+/// Line numbers in hunk headers are treated as hints: each hunk is applied at the occurrence of
+/// its original lines, context and removed lines alike, closest to the line it claims to start at,
+/// after the previous hunk. The line endings of untouched lines are preserved, added lines use the
+/// line ending of the first line of the file.
+pub fn apply(content: &str, hunks: &[Hunk]) -> Result<String> {
+    let mut lines = content
+        .split_inclusive('\n')
+        .map(|line| {
+            let (text, eol) = match line.strip_suffix('\n') {
+                Some(text) => text.strip_suffix('\r').map_or((text, "\n"), |text| (text, "\r\n")),
+                None => (line, ""),
+            };
+            (text.to_string(), eol)
+        })
+        .collect::<Vec<_>>();
+    let eol = lines.first().map_or("\n", |(_, eol)| *eol);
+    let eol = if eol.is_empty() { "\n" } else { eol };
+    // The line ending of the last line of the file.
+    let mut last_eol = lines.last().map_or(eol, |(_, eol)| *eol);
+    // Offset between line numbers in the original file and the file being modified.
+    let mut offset: isize = 0;
+    // Hunks must not overlap, so each one is searched for after the end of the previous.
+    let mut min_index = 0;
+
+    for (i, hunk) in hunks.iter().enumerate() {
+        let expected = (hunk.old_start.saturating_sub(1) as isize + offset).max(0) as usize;
+        let matches_at = |index: usize| {
+            let end = index + hunk.old_lines.len();
+            lines
+                .get(index..end)
+                .is_some_and(|window| window.iter().map(|(text, _)| text).eq(&hunk.old_lines))
+                && (!hunk.old_missing_newline || (end == lines.len() && last_eol.is_empty()))
+        };
+        let index = find_index(matches_at, expected, min_index, lines.len()).ok_or_else(|| {
+            let mismatch = hunk
+                .old_lines
+                .iter()
+                .enumerate()
+                .find(|(j, old)| lines.get(expected + j).is_none_or(|(text, _)| text != *old));
+            match mismatch {
+                Some((j, old)) => eyre!(
+                    "hunk {} (starting at line {}) does not match the contents of the file: expected line {} to be {:?}",
+                    i + 1,
+                    hunk.old_start,
+                    expected + j + 1,
+                    old,
+                ),
+                None => eyre!(
+                    "hunk {} (starting at line {}) expects the file to end without a newline",
+                    i + 1,
+                    hunk.old_start
+                ),
+            }
+        })?;
+
+        let end = index + hunk.old_lines.len();
+        if end == lines.len() {
+            if hunk.new_missing_newline {
+                last_eol = "";
+            } else if hunk.old_missing_newline {
+                last_eol = eol;
+            }
+        } else if hunk.new_missing_newline {
+            bail!(
+                "hunk {} (starting at line {}) removes the final newline but is not at the end of the file",
+                i + 1,
+                hunk.old_start
+            );
+        }
+        lines.splice(index..end, hunk.new_lines.iter().map(|line| (line.clone(), eol)));
+        offset += hunk.new_lines.len() as isize - hunk.old_lines.len() as isize;
+        min_index = index + hunk.new_lines.len();
+    }
+
+    let last = lines.len().saturating_sub(1);
+    Ok(lines
+        .iter()
+        .enumerate()
+        .map(|(i, (text, line_eol))| match (i == last, line_eol.is_empty()) {
+            (true, _) => format!("{text}{last_eol}"),
+            (false, true) => format!("{text}{eol}"),
+            (false, false) => format!("{text}{line_eol}"),
+        })
+        .collect())
+}
+
+/// Returns the index at or after `min_index` closest to `expected` where `matches_at` holds.
+fn find_index(matches_at: impl Fn(usize) -> bool, expected: usize, min_index: usize, len: usize) -> Option<usize> {
+    (min_index..=len.max(min_index))
+        .filter(|i| matches_at(*i))
+        .min_by_key(|i| i.abs_diff(expected))
+}
+
+/// Extracts the path from a `---`/`+++` header, stripping git's `a/` and `b/` prefixes and any
+/// trailing timestamp.
+fn parse_header_path(header: &str, git_prefix: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or_default().trim();
+    if path == DEV_NULL {
+        return None;
+    }
+    Some(path.strip_prefix(git_prefix).unwrap_or(path).to_string())
+}
+
+/// Parses `@@ -l,s +l,s @@`, returning the old start line and the old and new line counts.
+fn parse_hunk_header(header: &str) -> Result<(usize, usize, usize)> {
+    let invalid = || eyre!("invalid hunk header: {header}");
+    let mut ranges = header
+        .trim_start_matches('@')
+        .split("@@")
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let parse_range = |range: Option<&str>, sign: char| -> Result<(usize, usize)> {
+        let range = range.and_then(|r| r.strip_prefix(sign)).ok_or_else(invalid)?;
+        let (start, len) = range.split_once(',').unwrap_or((range, "1"));
+        Ok((
+            start.parse().map_err(|_err| invalid())?,
+            len.parse().map_err(|_err| invalid())?,
+        ))
+    };
+    let (old_start, old_len) = parse_range(ranges.next(), '-')?;
+    let (_, new_len) = parse_range(ranges.next(), '+')?;
+    Ok((old_start, old_len, new_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{}\", a + b);\n}\n";
+
+    #[test]
+    fn test_parse_git_diff() {
+        let diff = "\
+diff --git a/src/main.rs b/src/main.rs
+index 1234567..89abcde 100644
+--- a/src/main.rs
++++ b/src/main.rs
+@@ -1,3 +1,3 @@
+ fn main() {
+-    let a = 1;
++    let a = 10;
+     let b = 2;
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1 @@
++hello
+\\ No newline at end of file
+";
+        let files = parse(diff).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path(), "src/main.rs");
+        assert_eq!(files[0].hunks[0].old_start, 1);
+        assert_eq!(files[0].hunks[0].old_lines, vec![
+            "fn main() {",
+            "    let a = 1;",
+            "    let b = 2;"
+        ]);
+        assert_eq!(files[1].old_path, None);
+        assert_eq!(files[1].path(), "new.txt");
+        assert_eq!(files[1].hunks[0].new_lines, vec!["hello"]);
+    }
+
+    #[test]
+    fn test_parse_invalid_diffs() {
+        assert!(parse("just some text").is_err());
+        assert!(parse("--- a/file\n+++ b/file\n").is_err());
+        assert!(parse("--- a/file\n+++ b/file\n@@ -1,2 +1,2 @@\n line\n").is_err());
+        assert!(parse("--- a/file\n+++ b/file\n@@ invalid @@\n").is_err());
+    }
+
+    #[test]
+    fn test_apply_multiple_hunks() {
+        let diff = "\
+--- a/main.rs
++++ b/main.rs
+@@ -2,1 +2,1 @@
+-    let a = 1;
++    let a = 10;
+@@ -4,1 +4,2 @@
+     println!(\"{}\", a + b);
++    println!(\"done\");
+";
+        let files = parse(diff).unwrap();
+        assert_eq!(
+            apply(ORIGINAL, &files[0].hunks).unwrap(),
+            "fn main() {\n    let a = 10;\n    let b = 2;\n    println!(\"{}\", a + b);\n    println!(\"done\");\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_with_wrong_line_numbers() {
+        let hunks = vec![Hunk {
+            old_start: 40,
+            old_lines: vec!["    let b = 2;".to_string()],
+            new_lines: vec!["    let b = 3;".to_string()],
+            old_missing_newline: false,
+            new_missing_newline: false,
+        }];
+        assert_eq!(
+            apply(ORIGINAL, &hunks).unwrap(),
+            ORIGINAL.replace("let b = 2", "let b = 3")
+        );
+    }
+
+    #[test]
+    fn test_apply_mismatched_hunk() {
+        let hunks = vec![Hunk {
+            old_start: 1,
+            old_lines: vec!["    let c = 3;".to_string()],
+            new_lines: vec![],
+            old_missing_newline: false,
+            new_missing_newline: false,
+        }];
+        assert!(apply(ORIGINAL, &hunks).is_err());
+
+        // Removed lines must match the file just like context lines.
+        let diff = "\
+--- a/main.rs
++++ b/main.rs
+@@ -1,3 +1,2 @@
+ fn main() {
+-    let a = 2;
+     let b = 2;
+";
+        let files = parse(diff).unwrap();
+        let err = apply(ORIGINAL, &files[0].hunks).unwrap_err();
+        assert!(err.to_string().contains("expected line 2 to be"), "{err}");
+    }
+
+    #[test]
+    fn test_apply_preserves_crlf() {
+        let diff = "\
+--- a/main.rs
++++ b/main.rs
+@@ -2,1 +2,2 @@
+-    let a = 1;
++    let a = 10;
++    let c = 3;
+";
+        let files = parse(diff).unwrap();
+        assert_eq!(
+            apply(&ORIGINAL.replace('\n', "\r\n"), &files[0].hunks).unwrap(),
+            "fn main() {\r\n    let a = 10;\r\n    let c = 3;\r\n    let b = 2;\r\n    println!(\"{}\", a + b);\r\n}\r\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_no_newline_at_end_of_file() {
+        let original = "a\nb";
+        let add_newline = "\
+--- a/f
++++ b/f
+@@ -1,2 +1,2 @@
+ a
+-b
+\\ No newline at end of file
++b
+";
+        let hunks = &parse(add_newline).unwrap()[0].hunks;
+        assert_eq!(apply(original, hunks).unwrap(), "a\nb\n");
+        // The marker requires the file to actually end without a newline.
+        assert!(apply("a\nb\n", hunks).is_err());
+
+        let remove_newline = "\
+--- a/f
++++ b/f
+@@ -1,2 +1,2 @@
+ a
+-b
++c
+\\ No newline at end of file
+";
+        let hunks = &parse(remove_newline).unwrap()[0].hunks;
+        assert_eq!(apply("a\nb\n", hunks).unwrap(), "a\nc");
+
+        // Files missing a final newline keep it missing when the diff does not mention it.
+        let unmarked = "\
+--- a/f
++++ b/f
+@@ -1,2 +1,3 @@
+ a
++x
+ b
+";
+        let hunks = &parse(unmarked).unwrap()[0].hunks;
+        assert_eq!(apply(original, hunks).unwrap(), "a\nx\nb");
+    }
+}
@@ -83,8 +83,14 @@ pub struct AddArgs {
     #[arg(long)]
     pub name: String,
     /// The command used to launch the server
+    #[arg(long, required_unless_present = "url", conflicts_with = "url")]
+    pub command: Option<String>,
+    /// The url of a remote server, either http(s):// or ws(s)://
     #[arg(long)]
-    pub command: String,
+    pub url: Option<String>,
+    /// Headers to send to a remote server, in the form 'name: value'
+    #[arg(long = "header", value_parser = parse_header, requires = "url")]
+    pub headers: Vec<(String, String)>,
     /// Where to add the server to.
     #[arg(long, value_enum)]
     pub scope: Option<Scope>,
@@ -116,8 +122,11 @@ impl AddArgs {
         }
 
         let merged_env = self.env.into_iter().flatten().collect::<HashMap<_, _>>();
+        let headers = self.headers.into_iter().collect::<HashMap<_, _>>();
         let tool: CustomToolConfig = serde_json::from_value(serde_json::json!({
-            "command": self.command,
+            "command": self.command.unwrap_or_default(),
+            "url": self.url,
+            "headers": (!headers.is_empty()).then_some(headers),
             "env": merged_env,
             "timeout": self.timeout.unwrap_or(default_timeout()),
         }))?;
@@ -203,7 +212,7 @@ impl ListArgs {
             match cfg_opt {
                 Some(cfg) if !cfg.mcp_servers.is_empty() => {
                     for (name, tool_cfg) in &cfg.mcp_servers {
-                        writeln!(output, "    • {name:<12} {}", tool_cfg.command)?;
+                        writeln!(output, "    • {name:<12} {}", tool_cfg.endpoint())?;
                     }
                 },
                 _ => {
@@ -285,7 +294,10 @@ impl StatusArgs {
                     style::Print("\n─────────────\n"),
                     style::Print(format!("Scope   : {}\n", scope_display(&sc))),
                     style::Print(format!("File    : {}\n", path.display())),
-                    style::Print(format!("Command : {}\n", cfg.command)),
+                    style::Print(match &cfg.url {
+                        Some(url) => format!("Url     : {}\n", url),
+                        None => format!("Command : {}\n", cfg.command),
+                    }),
                     style::Print(format!("Timeout : {} ms\n", cfg.timeout)),
                     style::Print(format!(
                         "Env Vars: {}\n",
@@ -390,6 +402,13 @@ fn parse_env_vars(arg: &str) -> Result<HashMap<String, String>> {
     Ok(vars)
 }
 
+fn parse_header(arg: &str) -> Result<(String, String)> {
+    match arg.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => bail!("Failed to parse header '{}'. Expected 'name: value'", arg),
+    }
+}
+
 async fn load_cfg(ctx: &Context, p: &PathBuf) -> Result<McpServerConfig> {
     Ok(if ctx.fs().exists(p) {
         McpServerConfig::load_from_file(ctx, p).await?
@@ -447,7 +466,9 @@ mod tests {
         // 1. add
         AddArgs {
             name: "local".into(),
-            command: "echo hi".into(),
+            command: Some("echo hi".into()),
+            url: None,
+            headers: vec![],
             env: vec![],
             timeout: None,
             scope: None,
@@ -490,7 +511,9 @@ mod tests {
             ],
             RootSubcommand::Mcp(McpSubcommand::Add(AddArgs {
                 name: "test_server".to_string(),
-                command: "test_command".to_string(),
+                command: Some("test_command".to_string()),
+                url: None,
+                headers: vec![],
                 scope: None,
                 env: vec![
                     [
@@ -506,6 +529,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mcp_subcomman_add_remote() {
+        assert_parse!(
+            [
+                "mcp",
+                "add",
+                "--name",
+                "remote_server",
+                "--url",
+                "https://example.com/mcp",
+                "--header",
+                "Authorization: Bearer token"
+            ],
+            RootSubcommand::Mcp(McpSubcommand::Add(AddArgs {
+                name: "remote_server".to_string(),
+                command: None,
+                url: Some("https://example.com/mcp".to_string()),
+                headers: vec![("Authorization".to_string(), "Bearer token".to_string())],
+                scope: None,
+                env: vec![],
+                timeout: None,
+                force: false,
+            }))
+        );
+    }
+
     #[test]
     fn test_mcp_subcomman_remove_workspace() {
         assert_parse!(
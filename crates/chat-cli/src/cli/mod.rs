@@ -203,8 +203,11 @@ impl Cli {
         let mut database = crate::database::Database::new().await?;
         let telemetry = crate::telemetry::TelemetryThread::new(&env, &mut database).await?;
 
-        // Check for auth on subcommands that require it.
-        if subcommand.requires_auth() && !crate::auth::is_logged_in(&mut database).await {
+        // Check for auth on subcommands that require it. Chat doesn't need it when it is pointed at
+        // an OpenAI-compatible endpoint.
+        let uses_openai_endpoint = matches!(subcommand, RootSubcommand::Chat(_))
+            && crate::api_client::clients::OpenAiConfig::load(&database).is_some();
+        if subcommand.requires_auth() && !uses_openai_endpoint && !crate::auth::is_logged_in(&mut database).await {
             bail!(
                 "You are not logged in, please log in with {}",
                 format!("{CLI_BINARY_NAME} login").bold()
@@ -317,7 +320,10 @@ impl Cli {
 
 #[cfg(test)]
 mod test {
+    use std::path::PathBuf;
+
     use super::*;
+    use crate::cli::chat::OutputFormat;
     use crate::util::CHAT_BINARY_NAME;
     use crate::util::test::assert_parse;
 
@@ -351,12 +357,15 @@ mod test {
             subcommand: Some(RootSubcommand::Chat(ChatArgs {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: None,
                 model: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
             })),
             verbose: 2,
             help_all: false,
@@ -391,12 +400,15 @@ mod test {
             RootSubcommand::Chat(ChatArgs {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: Some("my-profile".to_string()),
                 model: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
             })
         );
     }
@@ -408,12 +420,15 @@ mod test {
             RootSubcommand::Chat(ChatArgs {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: Some("Hello".to_string()),
                 profile: Some("my-profile".to_string()),
                 model: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
             })
         );
     }
@@ -425,12 +440,15 @@ mod test {
             RootSubcommand::Chat(ChatArgs {
                 accept_all: true,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: Some("my-profile".to_string()),
                 model: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
             })
         );
     }
@@ -442,12 +460,15 @@ mod test {
             RootSubcommand::Chat(ChatArgs {
                 accept_all: false,
                 no_interactive: true,
-                resume: true,
+                resume: Some(None),
                 input: None,
                 profile: None,
                 model: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
             })
         );
         assert_parse!(
@@ -455,12 +476,71 @@ mod test {
             RootSubcommand::Chat(ChatArgs {
                 accept_all: false,
                 no_interactive: true,
-                resume: true,
+                resume: Some(None),
                 input: None,
                 profile: None,
                 model: None,
                 trust_all_tools: false,
                 trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_with_resume_id() {
+        assert_parse!(
+            ["chat", "--resume=abc-123"],
+            RootSubcommand::Chat(ChatArgs {
+                accept_all: false,
+                no_interactive: false,
+                resume: Some(Some("abc-123".to_string())),
+                input: None,
+                profile: None,
+                model: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_with_resume_and_input() {
+        assert_parse!(
+            ["chat", "--resume", "fix the build"],
+            RootSubcommand::Chat(ChatArgs {
+                accept_all: false,
+                no_interactive: false,
+                resume: Some(None),
+                input: Some("fix the build".to_string()),
+                profile: None,
+                model: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
+            })
+        );
+        assert_parse!(
+            ["chat", "-r=abc-123", "fix the build"],
+            RootSubcommand::Chat(ChatArgs {
+                accept_all: false,
+                no_interactive: false,
+                resume: Some(Some("abc-123".to_string())),
+                input: Some("fix the build".to_string()),
+                profile: None,
+                model: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
             })
         );
     }
@@ -472,12 +552,15 @@ mod test {
             RootSubcommand::Chat(ChatArgs {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: None,
                 model: None,
                 trust_all_tools: true,
                 trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
             })
         );
     }
@@ -489,12 +572,15 @@ mod test {
             RootSubcommand::Chat(ChatArgs {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: None,
                 model: None,
                 trust_all_tools: false,
                 trust_tools: Some(vec!["".to_string()]),
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
             })
         );
     }
@@ -506,12 +592,72 @@ mod test {
             RootSubcommand::Chat(ChatArgs {
                 accept_all: false,
                 no_interactive: false,
-                resume: false,
+                resume: None,
                 input: None,
                 profile: None,
                 model: None,
                 trust_all_tools: false,
                 trust_tools: Some(vec!["fs_read".to_string(), "fs_write".to_string()]),
+                output_format: OutputFormat::Text,
+                record: None,
+                replay: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_chat_with_record_and_replay() {
+        assert_parse!(
+            ["chat", "--record", "session.jsonl"],
+            RootSubcommand::Chat(ChatArgs {
+                accept_all: false,
+                no_interactive: false,
+                resume: None,
+                input: None,
+                profile: None,
+                model: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: Some(PathBuf::from("session.jsonl")),
+                replay: None,
+            })
+        );
+        assert_parse!(
+            ["chat", "--replay", "bug.jsonl", "--record", "replayed.jsonl"],
+            RootSubcommand::Chat(ChatArgs {
+                accept_all: false,
+                no_interactive: false,
+                resume: None,
+                input: None,
+                profile: None,
+                model: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                output_format: OutputFormat::Text,
+                record: Some(PathBuf::from("replayed.jsonl")),
+                replay: Some(PathBuf::from("bug.jsonl")),
+            })
+        );
+        assert!(Cli::try_parse_from([CHAT_BINARY_NAME, "chat", "--replay", "bug.jsonl", "hello"]).is_err());
+    }
+
+    #[test]
+    fn test_chat_with_output_format() {
+        assert_parse!(
+            ["chat", "--output-format", "stream-json", "hello"],
+            RootSubcommand::Chat(ChatArgs {
+                accept_all: false,
+                no_interactive: false,
+                resume: None,
+                input: Some("hello".to_string()),
+                profile: None,
+                model: None,
+                trust_all_tools: false,
+                trust_tools: None,
+                output_format: OutputFormat::StreamJson,
+                record: None,
+                replay: None,
             })
         );
     }
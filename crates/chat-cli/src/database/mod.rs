@@ -69,7 +69,8 @@ const MIGRATIONS: &[Migration] = migrations![
     "004_state_table",
     "005_auth_table",
     "006_make_state_blob",
-    "007_conversations_table"
+    "007_conversations_table",
+    "008_conversation_history_table"
 ];
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -151,6 +152,22 @@ impl<T> From<PoisonError<T>> for DatabaseError {
     }
 }
 
+/// A past conversation, as listed by `/history`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationSummary {
+    pub conversation_id: String,
+    /// The directory the conversation took place in.
+    pub path: String,
+    /// The first prompt of the conversation, kept even once it has been dropped from the history.
+    pub first_prompt: Option<String>,
+    /// Unix timestamp of when the conversation was first saved.
+    pub created_at: i64,
+    /// Unix timestamp of when the conversation was last saved.
+    pub updated_at: i64,
+    /// Estimated size of the conversation history in tokens.
+    pub token_count: usize,
+}
+
 #[derive(Debug)]
 pub enum Table {
     /// The state table contains persistent application state.
@@ -344,6 +361,81 @@ impl Database {
         self.set_json_entry(Table::Conversations, path, state)
     }
 
+    /// Get a chat conversation from the conversation history given its id.
+    pub fn get_conversation_by_id(&self, conversation_id: &str) -> Result<Option<ConversationState>, DatabaseError> {
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare("SELECT value FROM conversation_history WHERE conversation_id = ?1")?;
+        match stmt.query_row([conversation_id], |row| row.get::<_, String>(0)) {
+            Ok(value) => Ok(Some(serde_json::from_str(&value)?)),
+            Err(Error::QueryReturnedNoRows) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Add or update a chat conversation in the conversation history, keyed by its id.
+    pub fn set_conversation_history(
+        &mut self,
+        path: impl AsRef<Path>,
+        state: &ConversationState,
+    ) -> Result<usize, DatabaseError> {
+        // We would need to encode this to support non utf8 paths.
+        let path = match path.as_ref().to_str() {
+            Some(path) => path,
+            None => return Ok(0),
+        };
+
+        let now = time::OffsetDateTime::now_utc().unix_timestamp();
+        Ok(self.pool.get()?.execute(
+            "INSERT INTO conversation_history
+                (conversation_id, path, first_prompt, created_at, updated_at, token_count, value)
+            VALUES (?1, ?2, ?3, ?4, ?4, ?5, ?6)
+            ON CONFLICT (conversation_id) DO UPDATE SET
+                path = excluded.path,
+                first_prompt = COALESCE(first_prompt, excluded.first_prompt),
+                updated_at = excluded.updated_at,
+                token_count = excluded.token_count,
+                value = excluded.value",
+            params![
+                state.conversation_id(),
+                path,
+                state.first_prompt(),
+                now,
+                state.token_count() as i64,
+                serde_json::to_string(state)?
+            ],
+        )?)
+    }
+
+    /// List past conversations, most recently updated first, optionally only those that took place
+    /// in `path`.
+    pub fn list_conversations(&self, path: Option<&Path>) -> Result<Vec<ConversationSummary>, DatabaseError> {
+        let path = match path.map(|p| p.to_str()) {
+            Some(Some(path)) => Some(path),
+            Some(None) => return Ok(Vec::new()),
+            None => None,
+        };
+
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(
+            "SELECT conversation_id, path, first_prompt, created_at, updated_at, token_count
+            FROM conversation_history
+            WHERE ?1 IS NULL OR path = ?1
+            ORDER BY updated_at DESC, rowid DESC",
+        )?;
+        let rows = stmt.query_map([path], |row| {
+            Ok(ConversationSummary {
+                conversation_id: row.get(0)?,
+                path: row.get(1)?,
+                first_prompt: row.get(2)?,
+                created_at: row.get(3)?,
+                updated_at: row.get(4)?,
+                token_count: row.get::<_, i64>(5)? as usize,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
     pub async fn get_secret(&self, key: &str) -> Result<Option<Secret>, DatabaseError> {
         trace!(key, "getting secret");
         Ok(self.get_entry::<String>(Table::Auth, key)?.map(Into::into))
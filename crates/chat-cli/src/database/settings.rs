@@ -21,6 +21,7 @@ pub enum Setting {
     OldClientId,
     ShareCodeWhispererContent,
     EnabledThinking,
+    EnabledKnowledge,
     SkimCommandKey,
     ChatGreetingEnabled,
     ApiTimeout,
@@ -28,10 +29,14 @@ pub enum Setting {
     ChatEnableNotifications,
     ApiCodeWhispererService,
     ApiQService,
+    ApiOpenAiService,
     McpInitTimeout,
     McpNoInteractiveTimeout,
     McpLoadedBefore,
     ChatDefaultModel,
+    ChatTokenizer,
+    ChatAutoCompactionStrategy,
+    ChatAutoCompactionThreshold,
 }
 
 impl AsRef<str> for Setting {
@@ -41,6 +46,7 @@ impl AsRef<str> for Setting {
             Self::OldClientId => "telemetryClientId",
             Self::ShareCodeWhispererContent => "codeWhisperer.shareCodeWhispererContentWithAWS",
             Self::EnabledThinking => "chat.enableThinking",
+            Self::EnabledKnowledge => "chat.enableKnowledge",
             Self::SkimCommandKey => "chat.skimCommandKey",
             Self::ChatGreetingEnabled => "chat.greeting.enabled",
             Self::ApiTimeout => "api.timeout",
@@ -48,10 +54,14 @@ impl AsRef<str> for Setting {
             Self::ChatEnableNotifications => "chat.enableNotifications",
             Self::ApiCodeWhispererService => "api.codewhisperer.service",
             Self::ApiQService => "api.q.service",
+            Self::ApiOpenAiService => "api.openai.service",
             Self::McpInitTimeout => "mcp.initTimeout",
             Self::McpNoInteractiveTimeout => "mcp.noInteractiveTimeout",
             Self::McpLoadedBefore => "mcp.loadedBefore",
             Self::ChatDefaultModel => "chat.defaultModel",
+            Self::ChatTokenizer => "chat.tokenizer",
+            Self::ChatAutoCompactionStrategy => "chat.autoCompaction.strategy",
+            Self::ChatAutoCompactionThreshold => "chat.autoCompaction.threshold",
         }
     }
 }
@@ -71,6 +81,7 @@ impl TryFrom<&str> for Setting {
             "telemetryClientId" => Ok(Self::OldClientId),
             "codeWhisperer.shareCodeWhispererContentWithAWS" => Ok(Self::ShareCodeWhispererContent),
             "chat.enableThinking" => Ok(Self::EnabledThinking),
+            "chat.enableKnowledge" => Ok(Self::EnabledKnowledge),
             "chat.skimCommandKey" => Ok(Self::SkimCommandKey),
             "chat.greeting.enabled" => Ok(Self::ChatGreetingEnabled),
             "api.timeout" => Ok(Self::ApiTimeout),
@@ -78,10 +89,14 @@ impl TryFrom<&str> for Setting {
             "chat.enableNotifications" => Ok(Self::ChatEnableNotifications),
             "api.codewhisperer.service" => Ok(Self::ApiCodeWhispererService),
             "api.q.service" => Ok(Self::ApiQService),
+            "api.openai.service" => Ok(Self::ApiOpenAiService),
             "mcp.initTimeout" => Ok(Self::McpInitTimeout),
             "mcp.noInteractiveTimeout" => Ok(Self::McpNoInteractiveTimeout),
             "mcp.loadedBefore" => Ok(Self::McpLoadedBefore),
             "chat.defaultModel" => Ok(Self::ChatDefaultModel),
+            "chat.tokenizer" => Ok(Self::ChatTokenizer),
+            "chat.autoCompaction.strategy" => Ok(Self::ChatAutoCompactionStrategy),
+            "chat.autoCompaction.threshold" => Ok(Self::ChatAutoCompactionThreshold),
             _ => Err(DatabaseError::InvalidSetting(value.to_string())),
         }
     }
@@ -0,0 +1,11 @@
+CREATE TABLE conversation_history (
+    conversation_id TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    first_prompt TEXT,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    token_count INTEGER NOT NULL,
+    value TEXT NOT NULL
+);
+
+CREATE INDEX conversation_history_path_updated_at ON conversation_history (path, updated_at);
@@ -16,27 +16,34 @@ use serde::{
     Serialize,
 };
 use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::time;
 use tokio::time::error::Elapsed;
 
 use super::transport::base_protocol::{
+    JsonRpcError,
     JsonRpcMessage,
     JsonRpcNotification,
     JsonRpcRequest,
     JsonRpcVersion,
 };
+use super::transport::http::JsonRpcHttpTransport;
 use super::transport::stdio::JsonRpcStdioTransport;
+use super::transport::websocket::JsonRpcWebSocketTransport;
 use super::transport::{
     self,
     Transport,
     TransportError,
 };
 use super::{
+    ClientRequestError,
+    ClientRequestHandler,
     JsonRpcResponse,
-    Listener as _,
+    Listener,
     LogListener,
     Messenger,
     PaginationSupportedOps,
+    ProgressNotification,
     PromptGet,
     PromptsListResult,
     ResourceTemplatesListResult,
@@ -51,6 +58,8 @@ use crate::util::process::{
 
 pub type ClientInfo = serde_json::Value;
 pub type StdioTransport = JsonRpcStdioTransport;
+pub type HttpTransport = JsonRpcHttpTransport;
+pub type WebSocketTransport = JsonRpcWebSocketTransport;
 
 /// Represents the capabilities of a client in the Model Context Protocol.
 /// This structure is sent to the server during initialization to communicate
@@ -84,6 +93,17 @@ pub struct ClientConfig {
     pub env: Option<HashMap<String, String>>,
 }
 
+/// Configuration for a server that is already running and reachable through a url, as opposed to
+/// one spawned by the client.
+#[derive(Debug, Deserialize)]
+pub struct RemoteClientConfig {
+    pub server_name: String,
+    pub url: String,
+    pub headers: HashMap<String, String>,
+    pub timeout: u64,
+    pub client_info: serde_json::Value,
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Error)]
 pub enum ClientError {
@@ -128,6 +148,9 @@ pub struct Client<T: Transport> {
     client_info: serde_json::Value,
     current_id: Arc<AtomicU64>,
     pub messenger: Option<Box<dyn Messenger>>,
+    /// Answers the requests initiated by the server. Without it, sampling and roots are not
+    /// advertised as client capabilities.
+    pub request_handler: Option<Arc<dyn ClientRequestHandler>>,
     // TODO: move this to tool manager that way all the assets are treated equally
     pub prompt_gets: Arc<SyncRwLock<HashMap<String, PromptGet>>>,
     pub is_prompts_out_of_date: Arc<AtomicBool>,
@@ -145,6 +168,7 @@ impl<T: Transport> Clone for Client<T> {
             client_info: self.client_info.clone(),
             current_id: self.current_id.clone(),
             messenger: None,
+            request_handler: self.request_handler.clone(),
             prompt_gets: self.prompt_gets.clone(),
             is_prompts_out_of_date: self.is_prompts_out_of_date.clone(),
         }
@@ -198,17 +222,13 @@ impl Client<StdioTransport> {
         let server_process_id = Some(Pid::from_u32(server_process_id));
 
         let transport = Arc::new(transport::stdio::JsonRpcStdioTransport::client(child)?);
-        Ok(Self {
+        Ok(Self::with_transport(
             server_name,
             transport,
             timeout,
             server_process_id,
             client_info,
-            current_id: Arc::new(AtomicU64::new(0)),
-            messenger: None,
-            prompt_gets: Arc::new(SyncRwLock::new(HashMap::new())),
-            is_prompts_out_of_date: Arc::new(AtomicBool::new(false)),
-        })
+        ))
     }
 
     fn build_windows_command(bin_path: &str, args: Vec<String>) -> String {
@@ -265,6 +285,34 @@ impl Client<StdioTransport> {
     }
 }
 
+impl Client<HttpTransport> {
+    pub fn from_remote_config(config: RemoteClientConfig) -> Result<Self, ClientError> {
+        let RemoteClientConfig {
+            server_name,
+            url,
+            headers,
+            timeout,
+            client_info,
+        } = config;
+        let transport = Arc::new(JsonRpcHttpTransport::client(&url, headers)?);
+        Ok(Self::with_transport(server_name, transport, timeout, None, client_info))
+    }
+}
+
+impl Client<WebSocketTransport> {
+    pub fn from_remote_config(config: RemoteClientConfig) -> Result<Self, ClientError> {
+        let RemoteClientConfig {
+            server_name,
+            url,
+            headers,
+            timeout,
+            client_info,
+        } = config;
+        let transport = Arc::new(JsonRpcWebSocketTransport::client(&url, headers)?);
+        Ok(Self::with_transport(server_name, transport, timeout, None, client_info))
+    }
+}
+
 impl<T> Drop for Client<T>
 where
     T: Transport,
@@ -282,6 +330,27 @@ impl<T> Client<T>
 where
     T: Transport,
 {
+    fn with_transport(
+        server_name: String,
+        transport: Arc<T>,
+        timeout: u64,
+        server_process_id: Option<Pid>,
+        client_info: serde_json::Value,
+    ) -> Self {
+        Self {
+            server_name,
+            transport,
+            timeout,
+            server_process_id,
+            client_info,
+            current_id: Arc::new(AtomicU64::new(0)),
+            messenger: None,
+            request_handler: None,
+            prompt_gets: Arc::new(SyncRwLock::new(HashMap::new())),
+            is_prompts_out_of_date: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
     /// Exchange of information specified as per https://spec.modelcontextprotocol.io/specification/2024-11-05/basic/lifecycle/#initialization
     ///
     /// Also done are the following:
@@ -311,32 +380,7 @@ where
             }
         });
 
-        let init_params = Some({
-            let client_cap = ClientCapabilities::from(self.client_info.clone());
-            serde_json::json!(client_cap)
-        });
-        let init_resp = self.request("initialize", init_params).await?;
-        if let Err(e) = examine_server_capabilities(&init_resp) {
-            return Err(ClientError::NegotiationError(format!(
-                "Client {} has failed to negotiate server capabilities with server: {:?}",
-                self.server_name, e
-            )));
-        }
-        let cap = {
-            let result = init_resp.result.ok_or(ClientError::NegotiationError(format!(
-                "Server {} init resp is missing result",
-                self.server_name
-            )))?;
-            let cap = result
-                .get("capabilities")
-                .ok_or(ClientError::NegotiationError(format!(
-                    "Server {} init resp result is missing capabilities",
-                    self.server_name
-                )))?
-                .clone();
-            serde_json::from_value::<ServerCapabilities>(cap)?
-        };
-        self.notify("initialized", None).await?;
+        let cap = self.initialize().await?;
 
         // TODO: group this into examine_server_capabilities
         // Prefetch prompts in the background. We should only do this after the server has been
@@ -356,6 +400,13 @@ where
                 fetch_tools_and_notify_with_messenger(&client_ref, messenger_ref.as_ref()).await;
             });
         }
+        if cap.resources.is_some() {
+            let client_ref = (*self).clone();
+            let messenger_ref = self.messenger.as_ref().map(|m| m.duplicate());
+            tokio::spawn(async move {
+                fetch_resources_and_notify_with_messenger(&client_ref, messenger_ref.as_ref()).await;
+            });
+        }
 
         let transport_ref = self.transport.clone();
         let server_name = self.server_name.clone();
@@ -364,13 +415,21 @@ where
 
         let prompts_list_changed_supported = cap.prompts.as_ref().is_some_and(|p| p.get("listChanged").is_some());
         let tools_list_changed_supported = cap.tools.as_ref().is_some_and(|t| t.get("listChanged").is_some());
+        let resources_list_changed_supported = cap.resources.as_ref().is_some_and(|r| r.get("listChanged").is_some());
         tokio::spawn(async move {
             let mut listener = transport_ref.get_listener();
             loop {
                 match listener.recv().await {
                     Ok(msg) => {
                         match msg {
-                            JsonRpcMessage::Request(_req) => {},
+                            JsonRpcMessage::Request(req) => {
+                                // Answering may wait on the user, so it should not hold up the
+                                // listener.
+                                let client_ref = client_ref.clone();
+                                tokio::spawn(async move {
+                                    respond_to_server_request(&client_ref, req).await;
+                                });
+                            },
                             JsonRpcMessage::Notification(notif) => {
                                 let JsonRpcNotification { method, params, .. } = notif;
                                 match method.as_str() {
@@ -420,6 +479,12 @@ where
                                         fetch_tools_and_notify_with_messenger(&client_ref, messenger_ref.as_ref())
                                             .await;
                                     },
+                                    "notifications/resources/list_changed" | "resources/list_changed"
+                                        if resources_list_changed_supported =>
+                                    {
+                                        fetch_resources_and_notify_with_messenger(&client_ref, messenger_ref.as_ref())
+                                            .await;
+                                    },
                                     _ => {},
                                 }
                             },
@@ -447,6 +512,46 @@ where
         Ok(cap)
     }
 
+    /// Performs the initialization handshake, returning the capabilities of the server. This is
+    /// also used to start a new session once a remote server has expired the previous one.
+    async fn initialize(&self) -> Result<ServerCapabilities, ClientError> {
+        let init_params = Some({
+            let mut client_cap = ClientCapabilities::from(self.client_info.clone());
+            if self.request_handler.is_some() {
+                client_cap
+                    .capabilities
+                    .insert("sampling".to_owned(), serde_json::json!({}));
+                client_cap
+                    .capabilities
+                    .insert("roots".to_owned(), serde_json::json!({ "listChanged": true }));
+            }
+            serde_json::json!(client_cap)
+        });
+        let init_resp = self.request("initialize", init_params).await?;
+        if let Err(e) = examine_server_capabilities(&init_resp) {
+            return Err(ClientError::NegotiationError(format!(
+                "Client {} has failed to negotiate server capabilities with server: {:?}",
+                self.server_name, e
+            )));
+        }
+        let cap = {
+            let result = init_resp.result.ok_or(ClientError::NegotiationError(format!(
+                "Server {} init resp is missing result",
+                self.server_name
+            )))?;
+            let cap = result
+                .get("capabilities")
+                .ok_or(ClientError::NegotiationError(format!(
+                    "Server {} init resp result is missing capabilities",
+                    self.server_name
+                )))?
+                .clone();
+            serde_json::from_value::<ServerCapabilities>(cap)?
+        };
+        self.notify("initialized", None).await?;
+        Ok(cap)
+    }
+
     /// Sends a request to the server associated.
     /// This call will yield until a response is received.
     pub async fn request(
@@ -454,9 +559,27 @@ where
         method: &str,
         params: Option<serde_json::Value>,
     ) -> Result<JsonRpcResponse, ClientError> {
-        let send_map_err = |e: Elapsed| (e, method.to_string());
-        let recv_map_err = |e: Elapsed| (e, format!("recv for {method}"));
+        self.request_with_progress(method, params, None).await
+    }
+
+    /// Sends a request to the server associated, asking it to report the progress of the request
+    /// through `progress`. Since a server that reports progress is still working on the request,
+    /// the response timeout restarts every time progress is reported.
+    ///
+    /// If the returned future is dropped before a response is received, e.g. because the user
+    /// interrupted the request or it timed out, the server is notified that it was cancelled.
+    pub async fn request_with_progress(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+        progress: Option<UnboundedSender<ProgressNotification>>,
+    ) -> Result<JsonRpcResponse, ClientError> {
         let mut id = self.get_id();
+        let params = match progress {
+            // Request ids are unique to the client, which is all that is needed of the token.
+            Some(_) => Some(with_progress_token(params, id)),
+            None => params,
+        };
         let request = JsonRpcRequest {
             jsonrpc: JsonRpcVersion::default(),
             id,
@@ -464,27 +587,10 @@ where
             params,
         };
         tracing::trace!(target: "mcp", "To {}:\n{:#?}", self.server_name, request);
-        let msg = JsonRpcMessage::Request(request);
-        time::timeout(Duration::from_millis(self.timeout), self.transport.send(&msg))
-            .await
-            .map_err(send_map_err)??;
+        // The listener needs to be obtained before sending. Remote transports may have received
+        // (and broadcasted) the response by the time send returns.
         let mut listener = self.transport.get_listener();
-        let mut resp = time::timeout(Duration::from_millis(self.timeout), async {
-            // we want to ignore all other messages sent by the server at this point and let the
-            // background loop handle them
-            // We also want to ignore all messages emitted by the server to its stdout that does
-            // not deserialize into a valid JsonRpcMessage (they are not supposed to do this but
-            // too many people complained about this so we are adding this safeguard in)
-            loop {
-                if let Ok(JsonRpcMessage::Response(resp)) = listener.recv().await {
-                    if resp.id == id {
-                        break Ok::<JsonRpcResponse, TransportError>(resp);
-                    }
-                }
-            }
-        })
-        .await
-        .map_err(recv_map_err)??;
+        let mut resp = self.send_and_recv(&mut listener, request, progress.as_ref()).await?;
         // Pagination support: https://spec.modelcontextprotocol.io/specification/2024-11-05/server/utilities/pagination/#pagination-model
         let mut next_cursor = resp.result.as_ref().and_then(|v| v.get("nextCursor"));
         if next_cursor.is_some() {
@@ -537,22 +643,7 @@ where
                             "cursor": next_cursor,
                         })),
                     };
-                    let msg = JsonRpcMessage::Request(next_request);
-                    time::timeout(Duration::from_millis(self.timeout), self.transport.send(&msg))
-                        .await
-                        .map_err(send_map_err)??;
-                    let resp = time::timeout(Duration::from_millis(self.timeout), async {
-                        loop {
-                            if let Ok(JsonRpcMessage::Response(resp)) = listener.recv().await {
-                                if resp.id == id {
-                                    break Ok::<JsonRpcResponse, TransportError>(resp);
-                                }
-                            }
-                        }
-                    })
-                    .await
-                    .map_err(recv_map_err)??;
-                    current_resp = resp;
+                    current_resp = self.send_and_recv(&mut listener, next_request, None).await?;
                     next_cursor = current_resp.result.as_ref().and_then(|v| v.get("nextCursor"));
                 }
                 resp.result = Some({
@@ -566,6 +657,82 @@ where
         Ok(resp)
     }
 
+    /// Sends `request` and waits for its response, forwarding the progress reported for it
+    /// through `progress` in the meantime.
+    async fn send_and_recv(
+        &self,
+        listener: &mut impl Listener,
+        request: JsonRpcRequest,
+        progress: Option<&UnboundedSender<ProgressNotification>>,
+    ) -> Result<JsonRpcResponse, ClientError> {
+        let id = request.id;
+        let method = request.method.clone();
+        let send_map_err = |e: Elapsed| (e, method.clone());
+        let recv_map_err = |e: Elapsed| (e, format!("recv for {method}"));
+        let msg = JsonRpcMessage::Request(request);
+        let sent = time::timeout(Duration::from_millis(self.timeout), self.transport.send(&msg))
+            .await
+            .map_err(send_map_err)?;
+        match sent {
+            // A remote server has forgotten the session, which is started anew before retrying
+            // the request once.
+            Err(TransportError::SessionExpired(session_id)) if method != "initialize" => {
+                tracing::info!(target: "mcp", "Session {session_id} of {} expired, reinitializing", self.server_name);
+                // Boxed since initializing sends a request itself.
+                Box::pin(self.initialize()).await?;
+                time::timeout(Duration::from_millis(self.timeout), self.transport.send(&msg))
+                    .await
+                    .map_err(send_map_err)??;
+            },
+            sent => sent?,
+        }
+        // The initialize request must not be cancelled as per the spec.
+        let mut cancel_guard = CancelOnDrop {
+            transport: self.transport.clone(),
+            request_id: id,
+            armed: method != "initialize",
+        };
+        let progress_token = serde_json::json!(id);
+        let resp = loop {
+            let received = time::timeout(Duration::from_millis(self.timeout), async {
+                // we want to ignore all other messages sent by the server at this point and let
+                // the background loop handle them
+                // We also want to ignore all messages emitted by the server to its stdout that
+                // does not deserialize into a valid JsonRpcMessage (they are not supposed to do
+                // this but too many people complained about this so we are adding this safeguard
+                // in)
+                loop {
+                    match listener.recv().await {
+                        Ok(JsonRpcMessage::Response(resp)) if resp.id == id => break Ok(resp),
+                        Ok(JsonRpcMessage::Notification(notif))
+                            if progress.is_some() && notif.method == "notifications/progress" =>
+                        {
+                            let update = notif
+                                .params
+                                .and_then(|params| serde_json::from_value::<ProgressNotification>(params).ok());
+                            if let Some(update) = update.filter(|update| update.progress_token == progress_token) {
+                                break Err(update);
+                            }
+                        },
+                        _ => {},
+                    }
+                }
+            })
+            .await
+            .map_err(recv_map_err)?;
+            match (received, progress) {
+                (Ok(resp), _) => break resp,
+                (Err(update), Some(progress)) => {
+                    let _ = progress.send(update);
+                },
+                (Err(_), None) => {},
+            }
+        };
+        // The request has completed, there is nothing left to cancel.
+        cancel_guard.armed = false;
+        Ok(resp)
+    }
+
     /// Sends a notification to the server associated.
     /// Notifications are requests that expect no responses.
     pub async fn notify(&self, method: &str, params: Option<serde_json::Value>) -> Result<(), ClientError> {
@@ -681,6 +848,141 @@ where
     }
 }
 
+/// Returns `params` with a `_meta.progressToken` set, asking the server to report progress.
+fn with_progress_token(params: Option<serde_json::Value>, token: u64) -> serde_json::Value {
+    let mut params = match params {
+        Some(serde_json::Value::Object(params)) => params,
+        _ => serde_json::Map::new(),
+    };
+    let meta = params.entry("_meta").or_insert_with(|| serde_json::json!({}));
+    if let Some(meta) = meta.as_object_mut() {
+        meta.insert("progressToken".to_owned(), serde_json::json!(token));
+    }
+    serde_json::Value::Object(params)
+}
+
+/// Tells the server that a request has been abandoned when dropped, e.g. because the request
+/// timed out or the user interrupted it.
+struct CancelOnDrop<T: Transport> {
+    transport: Arc<T>,
+    request_id: u64,
+    armed: bool,
+}
+
+impl<T: Transport> Drop for CancelOnDrop<T> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let transport = self.transport.clone();
+        let msg = JsonRpcMessage::Notification(JsonRpcNotification {
+            jsonrpc: JsonRpcVersion::default(),
+            method: "notifications/cancelled".to_owned(),
+            params: Some(serde_json::json!({
+                "requestId": self.request_id,
+                "reason": "The request was cancelled by the client",
+            })),
+        });
+        handle.spawn(async move {
+            if let Err(e) = transport.send(&msg).await {
+                tracing::error!("Failed to send cancellation: {:?}", e);
+            }
+        });
+    }
+}
+
+/// Answers a request initiated by the server, using the request handler of the client for
+/// sampling and roots.
+async fn respond_to_server_request<T>(client: &Client<T>, req: JsonRpcRequest)
+where
+    T: Transport,
+{
+    let JsonRpcRequest { id, method, params, .. } = req;
+    let result = match (client.request_handler.as_ref(), method.as_str()) {
+        (_, "ping") => Ok(serde_json::json!({})),
+        (Some(handler), "sampling/createMessage") => match serde_json::from_value(params.unwrap_or_default()) {
+            Ok(params) => handler
+                .create_message(&client.server_name, params)
+                .await
+                .map(|result| serde_json::json!(result)),
+            Err(e) => Err(ClientRequestError::InvalidParams(e.to_string())),
+        },
+        (Some(handler), "roots/list") => handler
+            .list_roots(&client.server_name)
+            .await
+            .map(|result| serde_json::json!(result)),
+        _ => {
+            tracing::warn!("Received unsupported request {method} from {}", client.server_name);
+            Err(ClientRequestError::MethodNotFound(method.clone()))
+        },
+    };
+    let resp = match result {
+        Ok(result) => JsonRpcResponse {
+            id,
+            result: Some(result),
+            ..Default::default()
+        },
+        Err(e) => JsonRpcResponse {
+            id,
+            error: Some(JsonRpcError {
+                code: e.code(),
+                message: e.to_string(),
+                data: None,
+            }),
+            ..Default::default()
+        },
+    };
+    if let Err(e) = client.transport.send(&JsonRpcMessage::Response(resp)).await {
+        tracing::error!("Failed to respond to {method} from {}: {:?}", client.server_name, e);
+    }
+}
+
+/// Fetches both the resources and the resource templates offered by the server. Servers are not
+/// required to support templates, in which case the messenger is sent the resulting error.
+#[allow(clippy::borrowed_box)]
+async fn fetch_resources_and_notify_with_messenger<T>(client: &Client<T>, messenger: Option<&Box<dyn Messenger>>)
+where
+    T: Transport,
+{
+    let resources_list_result = fetch_list::<T, ResourcesListResult>(client, "resources/list").await;
+    let resource_templates_list_result =
+        fetch_list::<T, ResourceTemplatesListResult>(client, "resources/templates/list").await;
+    if let Some(messenger) = messenger {
+        let _ = messenger
+            .send_resources_list_result(resources_list_result)
+            .await
+            .map_err(|e| tracing::error!("Failed to send resource list result through messenger {:?}", e));
+        let _ = messenger
+            .send_resource_templates_list_result(resource_templates_list_result)
+            .await
+            .map_err(|e| tracing::error!("Failed to send resource template list result through messenger {:?}", e));
+    }
+}
+
+async fn fetch_list<T, R>(client: &Client<T>, method: &str) -> eyre::Result<R>
+where
+    T: Transport,
+    R: serde::de::DeserializeOwned,
+{
+    let resp = client.request(method, None).await?;
+    if let Some(error) = resp.error {
+        eyre::bail!("{method} failed for {}: {:?}", client.server_name, error);
+    }
+    let Some(result) = resp.result else {
+        eyre::bail!("{method} response from {} is missing result", client.server_name);
+    };
+    serde_json::from_value::<R>(result).map_err(|e| {
+        eyre::eyre!(
+            "Failed to deserialize {method} result from {}: {:?}",
+            client.server_name,
+            e
+        )
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
@@ -978,6 +1280,253 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_with_progress_token() {
+        assert_eq!(
+            with_progress_token(None, 3),
+            serde_json::json!({ "_meta": { "progressToken": 3 } })
+        );
+        assert_eq!(
+            with_progress_token(
+                Some(serde_json::json!({ "name": "search", "_meta": { "other": true } })),
+                4
+            ),
+            serde_json::json!({ "name": "search", "_meta": { "other": true, "progressToken": 4 } })
+        );
+    }
+
+    /// Answers a request with `result`, echoing its id.
+    fn respond_with(result: Value) -> impl Fn(&mockito::Request) -> Vec<u8> {
+        move |req| {
+            let req = serde_json::from_slice::<Value>(req.body().unwrap()).unwrap();
+            serde_json::json!({ "jsonrpc": "2.0", "id": req["id"], "result": result })
+                .to_string()
+                .into_bytes()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_http_session_expired() {
+        use mockito::Matcher;
+
+        let mut server = mockito::Server::new_async().await;
+        let mut inits = Vec::new();
+        for session_id in ["first", "second"] {
+            let init = server
+                .mock("POST", "/")
+                .match_body(Matcher::PartialJson(serde_json::json!({ "method": "initialize" })))
+                .with_header("content-type", "application/json")
+                .with_header("mcp-session-id", session_id)
+                .with_body_from_request(respond_with(serde_json::json!({ "capabilities": {} })))
+                .expect(1)
+                .create_async()
+                .await;
+            inits.push(init);
+        }
+        let initialized = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                serde_json::json!({ "method": "notifications/initialized" }),
+            ))
+            .with_status(202)
+            .expect(2)
+            .create_async()
+            .await;
+        let expired = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({ "method": "tools/call" })))
+            .match_header("mcp-session-id", "first")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
+        let call = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({ "method": "tools/call" })))
+            .match_header("mcp-session-id", "second")
+            .with_header("content-type", "application/json")
+            .with_body_from_request(respond_with(serde_json::json!({ "content": [] })))
+            .expect(1)
+            .create_async()
+            .await;
+        let _stream = server.mock("GET", "/").with_status(405).create_async().await;
+
+        let client = Client::<HttpTransport>::from_remote_config(RemoteClientConfig {
+            server_name: "remote".to_string(),
+            url: server.url(),
+            headers: HashMap::new(),
+            timeout: 5000,
+            client_info: serde_json::json!({ "name": "TestClient", "version": "1.0.0" }),
+        })
+        .unwrap();
+        client.init().await.unwrap();
+
+        // The server no longer knows the first session, so a new one is started for the retry.
+        let resp = client.request("tools/call", None).await.unwrap();
+        assert_eq!(resp.result, Some(serde_json::json!({ "content": [] })));
+        for init in inits {
+            init.assert_async().await;
+        }
+        initialized.assert_async().await;
+        expired.assert_async().await;
+        call.assert_async().await;
+    }
+
+    /// In memory transport, handing the messages sent by the client to the test, which plays the
+    /// part of the server through `server`.
+    #[derive(Debug)]
+    struct FakeTransport {
+        sent: tokio::sync::mpsc::UnboundedSender<JsonRpcMessage>,
+        server: tokio::sync::broadcast::Sender<JsonRpcMessage>,
+        log: tokio::sync::broadcast::Sender<String>,
+    }
+
+    struct FakeListener(tokio::sync::broadcast::Receiver<JsonRpcMessage>);
+
+    #[async_trait::async_trait]
+    impl Listener for FakeListener {
+        async fn recv(&mut self) -> Result<JsonRpcMessage, TransportError> {
+            Ok(self.0.recv().await?)
+        }
+    }
+
+    struct FakeLogListener(tokio::sync::broadcast::Receiver<String>);
+
+    #[async_trait::async_trait]
+    impl LogListener for FakeLogListener {
+        async fn recv(&mut self) -> Result<String, TransportError> {
+            Ok(self.0.recv().await?)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Transport for FakeTransport {
+        async fn send(&self, msg: &JsonRpcMessage) -> Result<(), TransportError> {
+            self.sent
+                .send(msg.clone())
+                .map_err(|e| TransportError::Custom(e.to_string()))
+        }
+
+        fn get_listener(&self) -> impl Listener {
+            FakeListener(self.server.subscribe())
+        }
+
+        async fn shutdown(&self) -> Result<(), TransportError> {
+            Ok(())
+        }
+
+        fn get_log_listener(&self) -> impl LogListener {
+            FakeLogListener(self.log.subscribe())
+        }
+    }
+
+    /// Returns a client over a [FakeTransport], along with the messages it sends and the sender
+    /// of the messages it receives.
+    fn fake_client(
+        timeout: u64,
+    ) -> (
+        Client<FakeTransport>,
+        tokio::sync::mpsc::UnboundedReceiver<JsonRpcMessage>,
+        tokio::sync::broadcast::Sender<JsonRpcMessage>,
+    ) {
+        let (sent, sent_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (server, _) = tokio::sync::broadcast::channel(16);
+        let (log, _) = tokio::sync::broadcast::channel(16);
+        let transport = Arc::new(FakeTransport {
+            sent,
+            server: server.clone(),
+            log,
+        });
+        let client = Client::with_transport(
+            "fake".to_string(),
+            transport,
+            timeout,
+            None,
+            serde_json::json!({ "name": "TestClient", "version": "1.0.0" }),
+        );
+        (client, sent_rx, server)
+    }
+
+    fn progress_notification(token: Value, progress: f64) -> JsonRpcMessage {
+        JsonRpcMessage::Notification(JsonRpcNotification {
+            jsonrpc: JsonRpcVersion::default(),
+            method: "notifications/progress".to_owned(),
+            params: Some(serde_json::json!({ "progressToken": token, "progress": progress })),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_request_with_progress() {
+        let (client, mut sent, server) = fake_client(300);
+        let (progress_tx, mut progress_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let Some(JsonRpcMessage::Request(req)) = sent.recv().await else {
+                panic!("expected a request");
+            };
+            let token = req.params.unwrap()["_meta"]["progressToken"].clone();
+            assert_eq!(token, serde_json::json!(req.id));
+            // Progress of other requests is ignored.
+            server.send(progress_notification(serde_json::json!(42), 0.5)).unwrap();
+            // Reporting progress keeps the request alive well past its timeout.
+            for progress in 1..=3 {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                server
+                    .send(progress_notification(token.clone(), progress as f64))
+                    .unwrap();
+            }
+            server
+                .send(JsonRpcMessage::Response(JsonRpcResponse {
+                    jsonrpc: JsonRpcVersion::default(),
+                    id: req.id,
+                    result: Some(serde_json::json!({ "content": [] })),
+                    error: None,
+                }))
+                .unwrap();
+        });
+
+        let resp = client
+            .request_with_progress("tools/call", None, Some(progress_tx))
+            .await
+            .unwrap();
+        assert_eq!(resp.result, Some(serde_json::json!({ "content": [] })));
+        let mut reported = Vec::new();
+        while let Ok(update) = progress_rx.try_recv() {
+            reported.push(update.progress);
+        }
+        assert_eq!(reported, vec![1.0, 2.0, 3.0]);
+    }
+
+    #[tokio::test]
+    async fn test_request_cancelled_on_drop() {
+        let (client, mut sent, _server) = fake_client(5000);
+
+        // The server never answers, and the caller gives up.
+        let _ = tokio::time::timeout(Duration::from_millis(100), client.request("tools/call", None)).await;
+        let Some(JsonRpcMessage::Request(req)) = sent.recv().await else {
+            panic!("expected a request");
+        };
+        let Some(JsonRpcMessage::Notification(notif)) = sent.recv().await else {
+            panic!("expected a notification");
+        };
+        assert_eq!(notif.method, "notifications/cancelled");
+        assert_eq!(notif.params.unwrap()["requestId"], serde_json::json!(req.id));
+    }
+
+    #[tokio::test]
+    async fn test_initialize_not_cancelled() {
+        let (client, mut sent, _server) = fake_client(100);
+
+        // The server never answers the handshake, which times out.
+        assert!(client.initialize().await.is_err());
+        let Some(JsonRpcMessage::Request(req)) = sent.recv().await else {
+            panic!("expected a request");
+        };
+        assert_eq!(req.method, "initialize");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(sent.try_recv().is_err());
+    }
+
     #[cfg(windows)]
     mod windows_command_tests {
         use super::*;
@@ -84,6 +84,55 @@ pub struct ResourceTemplatesListResult {
     pub next_cursor: Option<String>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// A resource listed by a server in the result of `resources/list`
+pub struct ResourceInfo {
+    /// URI that identifies the resource, to be used with `resources/read`
+    pub uri: String,
+    /// Human-readable name of the resource
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// A parameterized resource listed by a server in the result of `resources/templates/list`
+pub struct ResourceTemplateInfo {
+    /// RFC 6570 URI template from which resource URIs can be constructed
+    pub uri_template: String,
+    /// Human-readable name of the template
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// `result` field in [JsonRpcResponse] from a `resources/read` request
+pub struct ResourceReadResult {
+    pub contents: Vec<ResourceReadContents>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// Contents of a resource read. Exactly one of `text` and `blob` (base64-encoded) is expected to
+/// be present.
+pub struct ResourceReadContents {
+    pub uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 /// Result of prompt listing query
@@ -207,6 +256,69 @@ impl std::fmt::Display for MessageContent {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// `params` field in a `notifications/progress` notification, sent by a server working on a
+/// request that was made with a progress token
+pub struct ProgressNotification {
+    /// The token sent with the request
+    pub progress_token: serde_json::Value,
+    pub progress: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// `params` field in a `sampling/createMessage` request sent by a server to ask the host LLM for
+/// a completion. Model preferences and sampling parameters are not supported and are ignored.
+pub struct CreateMessageParams {
+    pub messages: Vec<SamplingMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// A message of the conversation a server asks the host LLM to complete
+pub struct SamplingMessage {
+    pub role: Role,
+    pub content: MessageContent,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// `result` field in the response to a `sampling/createMessage` request
+pub struct CreateMessageResult {
+    pub role: Role,
+    pub content: MessageContent,
+    /// The name of the model that generated the message
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// A directory the client exposes to servers, as listed in the response to `roots/list`
+pub struct Root {
+    /// A `file://` URI of the directory
+    pub uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+/// `result` field in the response to a `roots/list` request
+pub struct ListRootsResult {
+    pub roots: Vec<Root>,
+}
+
 /// Resource contents
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
@@ -0,0 +1,51 @@
+use thiserror::Error;
+
+use super::error::ErrorCode;
+use super::{
+    CreateMessageParams,
+    CreateMessageResult,
+    ListRootsResult,
+};
+
+/// An interface through which the consumer of a client answers the requests initiated by the
+/// server, i.e. the sampling and roots capabilities of the client. These capabilities are only
+/// advertised to servers when a handler has been assigned to the client.
+#[async_trait::async_trait]
+pub trait ClientRequestHandler: std::fmt::Debug + Send + Sync + 'static {
+    /// Answers a `sampling/createMessage` request, in which the server named `server_name` asks
+    /// the host LLM to complete a conversation
+    async fn create_message(
+        &self,
+        server_name: &str,
+        params: CreateMessageParams,
+    ) -> Result<CreateMessageResult, ClientRequestError>;
+
+    /// Answers a `roots/list` request with the directories the server named `server_name` may
+    /// operate on
+    async fn list_roots(&self, server_name: &str) -> Result<ListRootsResult, ClientRequestError>;
+}
+
+#[derive(Clone, Debug, Error)]
+pub enum ClientRequestError {
+    /// The user declined the request
+    #[error("{0}")]
+    Rejected(String),
+    #[error("{0}")]
+    InvalidParams(String),
+    #[error("Method not found: {0}")]
+    MethodNotFound(String),
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl ClientRequestError {
+    /// The JSON-RPC error code the server is sent. Rejections use -1 as suggested by the spec.
+    pub fn code(&self) -> i32 {
+        match self {
+            ClientRequestError::Rejected(_) => -1,
+            ClientRequestError::InvalidParams(_) => ErrorCode::InvalidParams.into(),
+            ClientRequestError::MethodNotFound(_) => ErrorCode::MethodNotFound.into(),
+            ClientRequestError::Custom(_) => ErrorCode::InternalError.into(),
+        }
+    }
+}
@@ -0,0 +1,522 @@
+//! Client side of the Streamable HTTP transport.
+//! Referencing https://modelcontextprotocol.io/specification/2025-03-26/basic/transports#streamable-http
+//!
+//! Every message is POSTed to a single endpoint. The server either answers with a plain json body,
+//! a `text/event-stream` carrying one or more messages, or `202 Accepted` for notifications and
+//! responses. Server initiated messages arrive over a long lived GET stream which is reopened (and
+//! resumed with `Last-Event-ID`) whenever it drops.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+
+use reqwest::header::{
+    ACCEPT,
+    CONTENT_TYPE,
+    HeaderMap,
+    HeaderName,
+    HeaderValue,
+};
+use reqwest::{
+    Client as HttpClient,
+    Response,
+    StatusCode,
+};
+use tokio::sync::{
+    RwLock,
+    broadcast,
+};
+
+use super::base_protocol::JsonRpcMessage;
+use super::{
+    Listener,
+    LogListener,
+    MAX_RECONNECT_ATTEMPTS,
+    MCP_SESSION_ID_HEADER,
+    Transport,
+    TransportError,
+    reconnect_delay,
+};
+
+const EVENT_STREAM_MIME: &str = "text/event-stream";
+const JSON_MIME: &str = "application/json";
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+#[derive(Debug)]
+struct HttpShared {
+    url: String,
+    headers: HeaderMap,
+    http_client: HttpClient,
+    session_id: RwLock<Option<String>>,
+    last_event_id: RwLock<Option<String>>,
+    tx: broadcast::Sender<Result<JsonRpcMessage, TransportError>>,
+    log_tx: broadcast::Sender<String>,
+    is_listening: AtomicBool,
+    is_shutdown: AtomicBool,
+}
+
+#[derive(Debug)]
+pub struct JsonRpcHttpTransport {
+    shared: Arc<HttpShared>,
+    receiver: broadcast::Receiver<Result<JsonRpcMessage, TransportError>>,
+    log_receiver: broadcast::Receiver<String>,
+}
+
+impl JsonRpcHttpTransport {
+    pub fn client(url: &str, headers: HashMap<String, String>) -> Result<Self, TransportError> {
+        let url = url::Url::parse(url).map_err(|e| TransportError::Custom(format!("Invalid url {url}: {e}")))?;
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| TransportError::Custom(format!("Invalid header name {name}: {e}")))?;
+            let value = HeaderValue::from_str(&value)
+                .map_err(|e| TransportError::Custom(format!("Invalid value for header {name}: {e}")))?;
+            header_map.insert(name, value);
+        }
+        let http_client = crate::request::new_client().map_err(|e| TransportError::Custom(e.to_string()))?;
+        let (tx, receiver) = broadcast::channel::<Result<JsonRpcMessage, TransportError>>(100);
+        let (log_tx, log_receiver) = broadcast::channel::<String>(100);
+        Ok(Self {
+            shared: Arc::new(HttpShared {
+                url: url.to_string(),
+                headers: header_map,
+                http_client,
+                session_id: RwLock::new(None),
+                last_event_id: RwLock::new(None),
+                tx,
+                log_tx,
+                is_listening: AtomicBool::new(false),
+                is_shutdown: AtomicBool::new(false),
+            }),
+            receiver,
+            log_receiver,
+        })
+    }
+
+    /// The session id assigned by the server during initialization, if any.
+    #[allow(dead_code)]
+    pub async fn session_id(&self) -> Option<String> {
+        self.shared.session_id.read().await.clone()
+    }
+
+    async fn post(&self, msg: &JsonRpcMessage) -> Result<Response, TransportError> {
+        let body = serde_json::to_vec(msg)?;
+        let mut attempt = 0;
+        loop {
+            let mut req = self
+                .shared
+                .http_client
+                .post(&self.shared.url)
+                .headers(self.shared.headers.clone())
+                .header(ACCEPT, format!("{JSON_MIME}, {EVENT_STREAM_MIME}"))
+                .header(CONTENT_TYPE, JSON_MIME)
+                .body(body.clone());
+            if let Some(session_id) = self.shared.session_id.read().await.as_ref() {
+                req = req.header(MCP_SESSION_ID_HEADER, session_id);
+            }
+            match req.send().await {
+                Ok(resp) => break Ok(resp),
+                // Only failures to connect are retried since we know for certain that the server
+                // has not seen the message.
+                Err(e) if e.is_connect() && attempt < MAX_RECONNECT_ATTEMPTS => {
+                    let _ = self
+                        .shared
+                        .log_tx
+                        .send(format!("Failed to connect to {}: {e}. Retrying.", self.shared.url));
+                    tokio::time::sleep(reconnect_delay(attempt)).await;
+                    attempt += 1;
+                },
+                Err(e) => break Err(e.into()),
+            }
+        }
+    }
+
+    /// Spawns the task that keeps the server-to-client event stream open.
+    fn spawn_event_listener(&self) {
+        if self.shared.is_listening.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let shared = self.shared.clone();
+        tokio::spawn(async move {
+            let mut attempt = 0;
+            while !shared.is_shutdown.load(Ordering::Acquire) {
+                let mut req = shared
+                    .http_client
+                    .get(&shared.url)
+                    .headers(shared.headers.clone())
+                    .header(ACCEPT, EVENT_STREAM_MIME);
+                if let Some(session_id) = shared.session_id.read().await.as_ref() {
+                    req = req.header(MCP_SESSION_ID_HEADER, session_id);
+                }
+                if let Some(last_event_id) = shared.last_event_id.read().await.as_ref() {
+                    req = req.header(LAST_EVENT_ID_HEADER, last_event_id);
+                }
+                match req.send().await {
+                    // Servers are not obligated to offer a standalone stream.
+                    Ok(resp) if resp.status() == StatusCode::METHOD_NOT_ALLOWED => break,
+                    Ok(resp) if resp.status() == StatusCode::NOT_FOUND => {
+                        let _ = shared.log_tx.send("Session no longer recognized by server".to_string());
+                        break;
+                    },
+                    Ok(resp) if resp.status().is_success() => {
+                        attempt = 0;
+                        if let Err(e) = read_event_stream(&shared, resp).await {
+                            let _ = shared.log_tx.send(format!("Event stream interrupted: {e}"));
+                        }
+                    },
+                    Ok(resp) => {
+                        let _ = shared
+                            .log_tx
+                            .send(format!("Event stream request failed with status {}", resp.status()));
+                    },
+                    Err(e) => {
+                        let _ = shared.log_tx.send(format!("Event stream request failed: {e}"));
+                    },
+                }
+                if attempt >= MAX_RECONNECT_ATTEMPTS {
+                    let _ = shared
+                        .log_tx
+                        .send(format!("Giving up on event stream after {attempt} attempts"));
+                    break;
+                }
+                tokio::time::sleep(reconnect_delay(attempt)).await;
+                attempt += 1;
+            }
+            shared.is_listening.store(false, Ordering::Release);
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for JsonRpcHttpTransport {
+    async fn send(&self, msg: &JsonRpcMessage) -> Result<(), TransportError> {
+        if self.shared.is_shutdown.load(Ordering::Acquire) {
+            return Err(TransportError::Connection("Transport has been shut down".to_string()));
+        }
+        let resp = self.post(msg).await?;
+        let status = resp.status();
+        if let Some(session_id) = resp.headers().get(MCP_SESSION_ID_HEADER).and_then(|v| v.to_str().ok()) {
+            let mut lock = self.shared.session_id.write().await;
+            if lock.as_deref() != Some(session_id) {
+                lock.replace(session_id.to_string());
+            }
+        }
+        if status == StatusCode::NOT_FOUND {
+            if let Some(expired) = self.shared.session_id.write().await.take() {
+                // The server has terminated the session. The client starts a new one by
+                // initializing again.
+                return Err(TransportError::SessionExpired(expired));
+            }
+        }
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(TransportError::Connection(format!(
+                "Server responded with status {status}: {body}"
+            )));
+        }
+        let is_initialized_notif =
+            matches!(msg, JsonRpcMessage::Notification(notif) if notif.method == "notifications/initialized");
+        if is_initialized_notif {
+            self.spawn_event_listener();
+        }
+        if status == StatusCode::ACCEPTED {
+            return Ok(());
+        }
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        if content_type.starts_with(EVENT_STREAM_MIME) {
+            // Responses to a request may take a while to arrive. We do not want to hold up the
+            // sender for it since the caller is listening on the broadcast channel.
+            let shared = self.shared.clone();
+            tokio::spawn(async move {
+                if let Err(e) = read_event_stream(&shared, resp).await {
+                    let _ = shared.tx.send(Err(e));
+                }
+            });
+        } else if content_type.starts_with(JSON_MIME) {
+            let body = resp.bytes().await?;
+            if !body.is_empty() {
+                for msg in parse_messages(&body)? {
+                    let _ = self.shared.tx.send(Ok(msg));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_listener(&self) -> impl Listener {
+        HttpListener {
+            receiver: self.receiver.resubscribe(),
+        }
+    }
+
+    async fn shutdown(&self) -> Result<(), TransportError> {
+        if self.shared.is_shutdown.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        // Explicitly terminating the session is a courtesy to the server, which is why a failure
+        // here is not surfaced.
+        if let Some(session_id) = self.shared.session_id.write().await.take() {
+            let _ = self
+                .shared
+                .http_client
+                .delete(&self.shared.url)
+                .headers(self.shared.headers.clone())
+                .header(MCP_SESSION_ID_HEADER, session_id)
+                .send()
+                .await;
+        }
+        Ok(())
+    }
+
+    fn get_log_listener(&self) -> impl LogListener {
+        HttpLogListener {
+            receiver: self.log_receiver.resubscribe(),
+        }
+    }
+}
+
+/// Consumes a `text/event-stream` body, forwarding every message found to the listeners.
+async fn read_event_stream(shared: &HttpShared, mut resp: Response) -> Result<(), TransportError> {
+    let mut parser = SseParser::default();
+    while let Some(chunk) = resp.chunk().await? {
+        for event in parser.feed(&chunk) {
+            if let Some(id) = event.id {
+                shared.last_event_id.write().await.replace(id);
+            }
+            if event.event.as_deref().is_some_and(|e| e != "message") || event.data.is_empty() {
+                continue;
+            }
+            match parse_messages(event.data.as_bytes()) {
+                Ok(msgs) => {
+                    for msg in msgs {
+                        let _ = shared.tx.send(Ok(msg));
+                    }
+                },
+                Err(e) => {
+                    let _ = shared.tx.send(Err(e));
+                },
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A payload may either be a single message or a batch of them.
+fn parse_messages(body: &[u8]) -> Result<Vec<JsonRpcMessage>, TransportError> {
+    match serde_json::from_slice::<JsonRpcMessage>(body) {
+        Ok(msg) => Ok(vec![msg]),
+        Err(e) => serde_json::from_slice::<Vec<JsonRpcMessage>>(body).map_err(|_err| e.into()),
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Incremental parser for server sent events.
+/// Referencing https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    current: SseEvent,
+    has_data: bool,
+}
+
+impl SseParser {
+    /// Feeds a chunk of the stream, returning the events that have been completed by it.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let mut line = self.buffer.drain(..=pos).collect::<Vec<_>>();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            let line = String::from_utf8_lossy(&line);
+            if line.is_empty() {
+                if self.has_data || self.current.id.is_some() {
+                    events.push(std::mem::take(&mut self.current));
+                } else {
+                    self.current = SseEvent::default();
+                }
+                self.has_data = false;
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line.as_ref(), ""),
+            };
+            match field {
+                "data" => {
+                    if self.has_data {
+                        self.current.data.push('\n');
+                    }
+                    self.current.data.push_str(value);
+                    self.has_data = true;
+                },
+                "event" => self.current.event = Some(value.to_string()),
+                "id" => self.current.id = Some(value.to_string()),
+                _ => {},
+            }
+        }
+        events
+    }
+}
+
+pub struct HttpListener {
+    pub receiver: broadcast::Receiver<Result<JsonRpcMessage, TransportError>>,
+}
+
+#[async_trait::async_trait]
+impl Listener for HttpListener {
+    async fn recv(&mut self) -> Result<JsonRpcMessage, TransportError> {
+        self.receiver.recv().await?
+    }
+}
+
+pub struct HttpLogListener {
+    pub receiver: broadcast::Receiver<String>,
+}
+
+#[async_trait::async_trait]
+impl LogListener for HttpLogListener {
+    async fn recv(&mut self) -> Result<String, TransportError> {
+        Ok(self.receiver.recv().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+    use serde_json::json;
+
+    use super::*;
+
+    fn request(id: u64, method: &str) -> JsonRpcMessage {
+        serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sse_parser() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b": keep alive\n\nid: 1\nda").is_empty());
+        let events = parser.feed(b"ta: {\"a\":\r\ndata: 1}\n\nevent: ping\ndata:\n\n");
+        assert_eq!(events, vec![
+            SseEvent {
+                id: Some("1".to_string()),
+                event: None,
+                data: "{\"a\":\n1}".to_string(),
+            },
+            SseEvent {
+                id: None,
+                event: Some("ping".to_string()),
+                data: String::new(),
+            }
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_http_transport_session_and_responses() {
+        let mut server = mockito::Server::new_async().await;
+        let init = server
+            .mock("POST", "/mcp")
+            .match_body(Matcher::PartialJson(json!({ "method": "initialize" })))
+            .match_header("x-api-key", "secret")
+            .with_status(200)
+            .with_header("content-type", JSON_MIME)
+            .with_header(MCP_SESSION_ID_HEADER, "session-1")
+            .with_body(json!({ "jsonrpc": "2.0", "id": 0, "result": {} }).to_string())
+            .create_async()
+            .await;
+        let streamed = server
+            .mock("POST", "/mcp")
+            .match_body(Matcher::PartialJson(json!({ "method": "tools/list" })))
+            .match_header(MCP_SESSION_ID_HEADER, "session-1")
+            .with_status(200)
+            .with_header("content-type", EVENT_STREAM_MIME)
+            .with_body(concat!(
+                "event: message\n",
+                "data: {\"jsonrpc\":\"2.0\",\"method\":\"notifications/message\"}\n\n",
+                "event: message\n",
+                "data: {\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"tools\":[]}}\n\n",
+            ))
+            .create_async()
+            .await;
+        let terminate = server
+            .mock("DELETE", "/mcp")
+            .match_header(MCP_SESSION_ID_HEADER, "session-1")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let headers = HashMap::from([("x-api-key".to_string(), "secret".to_string())]);
+        let transport = JsonRpcHttpTransport::client(&format!("{}/mcp", server.url()), headers).unwrap();
+        let mut listener = transport.get_listener();
+
+        transport.send(&request(0, "initialize")).await.unwrap();
+        assert_eq!(listener.recv().await.unwrap().id(), Some(0));
+        assert_eq!(transport.session_id().await.as_deref(), Some("session-1"));
+
+        transport.send(&request(1, "tools/list")).await.unwrap();
+        assert!(matches!(
+            listener.recv().await.unwrap(),
+            JsonRpcMessage::Notification(_)
+        ));
+        assert_eq!(listener.recv().await.unwrap().id(), Some(1));
+
+        transport.shutdown().await.unwrap();
+        assert!(transport.send(&request(2, "tools/list")).await.is_err());
+
+        init.assert_async().await;
+        streamed.assert_async().await;
+        terminate.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_http_transport_session_expired() {
+        let mut server = mockito::Server::new_async().await;
+        let _init = server
+            .mock("POST", "/")
+            .match_header(MCP_SESSION_ID_HEADER, Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", JSON_MIME)
+            .with_header(MCP_SESSION_ID_HEADER, "stale")
+            .with_body(json!({ "jsonrpc": "2.0", "id": 0, "result": {} }).to_string())
+            .create_async()
+            .await;
+        let _expired = server
+            .mock("POST", "/")
+            .match_header(MCP_SESSION_ID_HEADER, "stale")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let transport = JsonRpcHttpTransport::client(&server.url(), HashMap::new()).unwrap();
+        transport.send(&request(0, "initialize")).await.unwrap();
+        let err = transport.send(&request(1, "tools/list")).await.unwrap_err();
+        assert!(matches!(err, TransportError::SessionExpired(id) if id == "stale"));
+        // The stale session is dropped so that the next initialize starts a new one.
+        assert!(transport.session_id().await.is_none());
+        transport.send(&request(2, "initialize")).await.unwrap();
+        assert_eq!(transport.session_id().await.as_deref(), Some("stale"));
+    }
+}
@@ -1,18 +1,39 @@
 pub mod base_protocol;
+pub mod http;
 pub mod stdio;
+pub mod websocket;
 
 use std::fmt::Debug;
+use std::time::Duration;
 
 pub use base_protocol::*;
 pub use stdio::*;
 use thiserror::Error;
 
+/// Header used by remote transports to carry the session id assigned by the server.
+/// See https://modelcontextprotocol.io/specification/2025-03-26/basic/transports#session-management
+pub const MCP_SESSION_ID_HEADER: &str = "mcp-session-id";
+/// Maximum number of consecutive attempts made to re-establish a dropped remote connection.
+pub const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_BASE_DELAY_MS: u64 = 200;
+const RECONNECT_MAX_DELAY_MS: u64 = 5_000;
+
+/// Exponential backoff used between reconnect attempts of remote transports.
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    let delay = RECONNECT_BASE_DELAY_MS.saturating_mul(2_u64.saturating_pow(attempt));
+    Duration::from_millis(delay.min(RECONNECT_MAX_DELAY_MS))
+}
+
 #[derive(Clone, Debug, Error)]
 pub enum TransportError {
     #[error("Serialization error: {0}")]
     Serialization(String),
     #[error("IO error: {0}")]
     Stdio(String),
+    #[error("Connection error: {0}")]
+    Connection(String),
+    #[error("Session {0} has expired")]
+    SessionExpired(String),
     #[error("{0}")]
     Custom(String),
     #[error(transparent)]
@@ -31,6 +52,18 @@ impl From<std::io::Error> for TransportError {
     }
 }
 
+impl From<reqwest::Error> for TransportError {
+    fn from(err: reqwest::Error) -> Self {
+        TransportError::Connection(err.to_string())
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for TransportError {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        TransportError::Connection(err.to_string())
+    }
+}
+
 #[allow(dead_code)]
 #[async_trait::async_trait]
 pub trait Transport: Send + Sync + Debug + 'static {
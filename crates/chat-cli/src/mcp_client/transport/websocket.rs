@@ -0,0 +1,369 @@
+//! Client side of a websocket transport. Each json rpc message is carried in a single text frame.
+//!
+//! When the connection drops the transport reconnects with backoff, presenting the session id
+//! handed out by the server on the first handshake so that the server may resume the session.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+
+use futures::stream::{
+    SplitSink,
+    SplitStream,
+};
+use futures::{
+    SinkExt,
+    StreamExt,
+};
+use tokio::net::TcpStream;
+use tokio::sync::{
+    Mutex,
+    RwLock,
+    broadcast,
+};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{
+    HeaderName,
+    HeaderValue,
+};
+use tokio_tungstenite::{
+    MaybeTlsStream,
+    WebSocketStream,
+};
+
+use super::base_protocol::JsonRpcMessage;
+use super::{
+    Listener,
+    LogListener,
+    MAX_RECONNECT_ATTEMPTS,
+    MCP_SESSION_ID_HEADER,
+    Transport,
+    TransportError,
+    reconnect_delay,
+};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type WsSink = SplitSink<WsStream, Message>;
+
+const MCP_SUBPROTOCOL: &str = "mcp";
+
+#[derive(Debug)]
+struct WebSocketShared {
+    url: String,
+    headers: HashMap<String, String>,
+    session_id: RwLock<Option<String>>,
+    sink: Mutex<Option<WsSink>>,
+    tx: broadcast::Sender<Result<JsonRpcMessage, TransportError>>,
+    log_tx: broadcast::Sender<String>,
+    is_started: AtomicBool,
+    is_shutdown: AtomicBool,
+}
+
+impl WebSocketShared {
+    /// Performs the handshake, handing back both halves of the connection.
+    async fn connect(&self) -> Result<(WsSink, SplitStream<WsStream>), TransportError> {
+        let mut request = self.url.as_str().into_client_request()?;
+        let headers = request.headers_mut();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| TransportError::Custom(format!("Invalid header name {name}: {e}")))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| TransportError::Custom(format!("Invalid value for header {name}: {e}")))?;
+            headers.insert(name, value);
+        }
+        headers.insert("sec-websocket-protocol", HeaderValue::from_static(MCP_SUBPROTOCOL));
+        if let Some(session_id) = self.session_id.read().await.as_ref() {
+            let value = HeaderValue::from_str(session_id)
+                .map_err(|e| TransportError::Custom(format!("Invalid session id {session_id}: {e}")))?;
+            headers.insert(MCP_SESSION_ID_HEADER, value);
+        }
+        let (stream, resp) = tokio_tungstenite::connect_async(request).await?;
+        if let Some(session_id) = resp.headers().get(MCP_SESSION_ID_HEADER).and_then(|v| v.to_str().ok()) {
+            self.session_id.write().await.replace(session_id.to_string());
+        }
+        Ok(stream.split())
+    }
+
+    async fn reconnect(&self) -> Option<SplitStream<WsStream>> {
+        self.sink.lock().await.take();
+        for attempt in 0..MAX_RECONNECT_ATTEMPTS {
+            if self.is_shutdown.load(Ordering::Acquire) {
+                return None;
+            }
+            tokio::time::sleep(reconnect_delay(attempt)).await;
+            match self.connect().await {
+                Ok((sink, stream)) => {
+                    self.sink.lock().await.replace(sink);
+                    let _ = self.log_tx.send(format!("Reconnected to {}", self.url));
+                    return Some(stream);
+                },
+                Err(e) => {
+                    let _ = self
+                        .log_tx
+                        .send(format!("Reconnect attempt {} to {} failed: {e}", attempt + 1, self.url));
+                },
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonRpcWebSocketTransport {
+    shared: Arc<WebSocketShared>,
+    receiver: broadcast::Receiver<Result<JsonRpcMessage, TransportError>>,
+    log_receiver: broadcast::Receiver<String>,
+}
+
+impl JsonRpcWebSocketTransport {
+    /// Creates the transport. The connection itself is established lazily with the first message
+    /// sent, which allows the handshake to happen as part of the client's initialization.
+    pub fn client(url: &str, headers: HashMap<String, String>) -> Result<Self, TransportError> {
+        let parsed = url::Url::parse(url).map_err(|e| TransportError::Custom(format!("Invalid url {url}: {e}")))?;
+        if !matches!(parsed.scheme(), "ws" | "wss") {
+            return Err(TransportError::Custom(format!(
+                "Invalid url {url}: websocket urls must use ws or wss"
+            )));
+        }
+        let (tx, receiver) = broadcast::channel::<Result<JsonRpcMessage, TransportError>>(100);
+        let (log_tx, log_receiver) = broadcast::channel::<String>(100);
+        let shared = Arc::new(WebSocketShared {
+            url: url.to_string(),
+            headers,
+            session_id: RwLock::new(None),
+            sink: Mutex::new(None),
+            tx,
+            log_tx,
+            is_started: AtomicBool::new(false),
+            is_shutdown: AtomicBool::new(false),
+        });
+        Ok(Self {
+            shared,
+            receiver,
+            log_receiver,
+        })
+    }
+
+    /// The session id assigned by the server during the handshake, if any.
+    #[allow(dead_code)]
+    pub async fn session_id(&self) -> Option<String> {
+        self.shared.session_id.read().await.clone()
+    }
+
+    fn spawn_reader(shared: Arc<WebSocketShared>, mut stream: SplitStream<WsStream>) {
+        tokio::spawn(async move {
+            loop {
+                while let Some(frame) = stream.next().await {
+                    let payload = match frame {
+                        Ok(Message::Text(text)) => text.as_bytes().to_vec(),
+                        Ok(Message::Binary(bytes)) => bytes.to_vec(),
+                        Ok(Message::Close(_)) => break,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            let _ = shared.log_tx.send(format!("Error reading from {}: {e}", shared.url));
+                            break;
+                        },
+                    };
+                    match serde_json::from_slice::<JsonRpcMessage>(&payload) {
+                        Ok(msg) => {
+                            let _ = shared.tx.send(Ok(msg));
+                        },
+                        Err(e) => {
+                            let _ = shared.tx.send(Err(e.into()));
+                        },
+                    }
+                }
+                if shared.is_shutdown.load(Ordering::Acquire) {
+                    break;
+                }
+                let _ = shared.log_tx.send(format!("Connection to {} dropped", shared.url));
+                match shared.reconnect().await {
+                    Some(new_stream) => stream = new_stream,
+                    None => break,
+                }
+            }
+            shared.sink.lock().await.take();
+            let _ = shared.tx.send(Err(TransportError::Connection(format!(
+                "Connection to {} closed",
+                shared.url
+            ))));
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for JsonRpcWebSocketTransport {
+    async fn send(&self, msg: &JsonRpcMessage) -> Result<(), TransportError> {
+        let serialized = serde_json::to_string(msg)?;
+        let mut sink = self.shared.sink.lock().await;
+        if sink.is_none() && !self.shared.is_started.swap(true, Ordering::AcqRel) {
+            match self.shared.connect().await {
+                Ok((new_sink, stream)) => {
+                    sink.replace(new_sink);
+                    Self::spawn_reader(self.shared.clone(), stream);
+                },
+                Err(e) => {
+                    self.shared.is_started.store(false, Ordering::Release);
+                    return Err(e);
+                },
+            }
+        }
+        let Some(sink) = sink.as_mut() else {
+            return Err(TransportError::Connection(format!(
+                "Not connected to {}",
+                self.shared.url
+            )));
+        };
+        sink.send(Message::text(serialized)).await?;
+        Ok(())
+    }
+
+    fn get_listener(&self) -> impl Listener {
+        WebSocketListener {
+            receiver: self.receiver.resubscribe(),
+        }
+    }
+
+    async fn shutdown(&self) -> Result<(), TransportError> {
+        self.shared.is_shutdown.store(true, Ordering::Release);
+        if let Some(mut sink) = self.shared.sink.lock().await.take() {
+            sink.send(Message::Close(None)).await?;
+            sink.close().await?;
+        }
+        Ok(())
+    }
+
+    fn get_log_listener(&self) -> impl LogListener {
+        WebSocketLogListener {
+            receiver: self.log_receiver.resubscribe(),
+        }
+    }
+}
+
+pub struct WebSocketListener {
+    pub receiver: broadcast::Receiver<Result<JsonRpcMessage, TransportError>>,
+}
+
+#[async_trait::async_trait]
+impl Listener for WebSocketListener {
+    async fn recv(&mut self) -> Result<JsonRpcMessage, TransportError> {
+        self.receiver.recv().await?
+    }
+}
+
+pub struct WebSocketLogListener {
+    pub receiver: broadcast::Receiver<String>,
+}
+
+#[async_trait::async_trait]
+impl LogListener for WebSocketLogListener {
+    async fn recv(&mut self) -> Result<String, TransportError> {
+        Ok(self.receiver.recv().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use serde_json::json;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{
+        Request,
+        Response,
+    };
+
+    use super::*;
+
+    fn request(id: u64) -> JsonRpcMessage {
+        serde_json::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "ping",
+        }))
+        .unwrap()
+    }
+
+    /// Stand in for a remote server. It answers every request with an empty result and drops the
+    /// first connection after its first reply to exercise the reconnect path. The session ids
+    /// presented by the client on each handshake are recorded.
+    async fn spawn_server() -> (String, Arc<std::sync::Mutex<Vec<Option<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let seen_sessions = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen_sessions_clone = seen_sessions.clone();
+        let connection_count = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let seen_sessions = seen_sessions_clone.clone();
+                let connection_no = connection_count.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let callback = |req: &Request, mut resp: Response| {
+                        let session = req
+                            .headers()
+                            .get(MCP_SESSION_ID_HEADER)
+                            .and_then(|v| v.to_str().ok())
+                            .map(str::to_string);
+                        seen_sessions.lock().unwrap().push(session);
+                        resp.headers_mut()
+                            .insert(MCP_SESSION_ID_HEADER, HeaderValue::from_static("ws-session"));
+                        resp.headers_mut()
+                            .insert("sec-websocket-protocol", HeaderValue::from_static(MCP_SUBPROTOCOL));
+                        Ok(resp)
+                    };
+                    let mut ws = tokio_tungstenite::accept_hdr_async(stream, callback).await.unwrap();
+                    while let Some(Ok(Message::Text(text))) = ws.next().await {
+                        let msg = serde_json::from_str::<JsonRpcMessage>(text.as_str()).unwrap();
+                        let resp = json!({ "jsonrpc": "2.0", "id": msg.id(), "result": {} });
+                        ws.send(Message::text(resp.to_string())).await.unwrap();
+                        if connection_no == 0 {
+                            let _ = ws.close(None).await;
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        (url, seen_sessions)
+    }
+
+    #[tokio::test]
+    async fn test_websocket_transport_reconnects_with_session() {
+        let (url, seen_sessions) = spawn_server().await;
+        let transport = JsonRpcWebSocketTransport::client(&url, HashMap::new()).unwrap();
+        assert!(transport.session_id().await.is_none());
+
+        let mut listener = transport.get_listener();
+        transport.send(&request(1)).await.unwrap();
+        assert_eq!(listener.recv().await.unwrap().id(), Some(1));
+        assert_eq!(transport.session_id().await.as_deref(), Some("ws-session"));
+
+        // The server closes the first connection after replying. Wait for the transport to come
+        // back before sending again.
+        let mut sent = false;
+        for _ in 0..50 {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            if seen_sessions.lock().unwrap().len() == 2 && transport.send(&request(2)).await.is_ok() {
+                sent = true;
+                break;
+            }
+        }
+        assert!(sent, "transport failed to reconnect");
+        assert_eq!(listener.recv().await.unwrap().id(), Some(2));
+        assert_eq!(*seen_sessions.lock().unwrap(), vec![
+            None,
+            Some("ws-session".to_string())
+        ]);
+
+        transport.shutdown().await.unwrap();
+        assert!(transport.send(&request(3)).await.is_err());
+    }
+
+    #[test]
+    fn test_websocket_transport_rejects_http_url() {
+        assert!(JsonRpcWebSocketTransport::client("http://localhost:1234", HashMap::new()).is_err());
+    }
+}
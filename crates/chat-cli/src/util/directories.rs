@@ -139,6 +139,19 @@ pub fn chat_profiles_dir(ctx: &Context) -> Result<PathBuf> {
     Ok(home_dir(ctx)?.join(".aws").join("amazonq").join("profiles"))
 }
 
+/// The directory containing the knowledge bases added with `/knowledge` in `q chat`.
+#[cfg(feature = "knowledge")]
+pub fn chat_knowledge_bases_dir(ctx: &Context) -> Result<PathBuf> {
+    Ok(home_dir(ctx)?.join(".aws").join("amazonq").join("knowledge_bases"))
+}
+
+/// The directory containing the file snapshots taken before `q chat` edits a file, with a
+/// subdirectory per conversation in which they are named after the SHA-256 digest of their
+/// contents.
+pub fn chat_checkpoints_dir(ctx: &Context) -> Result<PathBuf> {
+    Ok(home_dir(ctx)?.join(".aws").join("amazonq").join("checkpoints"))
+}
+
 /// The path to the fig settings file
 pub fn settings_path() -> Result<PathBuf> {
     Ok(fig_data_dir()?.join("settings.json"))
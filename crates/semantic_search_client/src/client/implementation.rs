@@ -1,4 +1,7 @@
-use std::collections::HashMap;
+use std::collections::{
+    HashMap,
+    HashSet,
+};
 use std::fs;
 use std::path::{
     Path,
@@ -9,8 +12,10 @@ use std::sync::{
     Mutex,
 };
 
+use chrono::Utc;
 use serde_json::Value;
 
+use crate::client::search_filter::PointFilter;
 use crate::client::semantic_context::SemanticContext;
 use crate::client::{
     embedder_factory,
@@ -30,8 +35,12 @@ use crate::types::{
     ContextId,
     ContextMap,
     DataPoint,
+    FileState,
     MemoryContext,
     ProgressStatus,
+    RefreshSummary,
+    SearchFilter,
+    SearchMode,
     SearchResults,
 };
 
@@ -305,7 +314,9 @@ impl SemanticSearchClient {
         }
 
         // Process the file
-        let items = process_file(file_path)?;
+        let state = utils::file_state(file_path)?;
+        let items = Self::process_source_file(file_path)?;
+        let path_str = file_path.to_string_lossy().to_string();
 
         // Notify progress: Indexing
         if let Some(ref callback) = progress_callback {
@@ -326,7 +337,8 @@ impl SemanticSearchClient {
             name,
             description,
             persistent,
-            Some(file_path.to_string_lossy().to_string()),
+            Some(path_str.clone()),
+            HashMap::from([(path_str, state)]),
             semantic_context,
         )?;
 
@@ -374,7 +386,7 @@ impl SemanticSearchClient {
         let file_count = Self::count_files_in_directory(dir_path, &progress_callback)?;
 
         // Process files
-        let items = Self::process_directory_files(dir_path, file_count, &progress_callback)?;
+        let (items, files) = Self::process_directory_files(dir_path, file_count, &progress_callback)?;
 
         // Create and populate semantic context
         let semantic_context = self.create_semantic_context(&context_dir, &items, &progress_callback)?;
@@ -386,12 +398,171 @@ impl SemanticSearchClient {
             description,
             persistent,
             Some(dir_path.to_string_lossy().to_string()),
+            files,
             semantic_context,
         )?;
 
         Ok(id)
     }
 
+    /// Refresh a persistent context from its source path, only re-indexing the files whose
+    /// content changed since the context was created or last refreshed
+    ///
+    /// Files are first compared by modification time, then by content hash. The data points of
+    /// changed and deleted files are removed and the new ones are added to the existing index.
+    ///
+    /// # Arguments
+    ///
+    /// * `context_id` - ID of the context to refresh
+    /// * `progress_callback` - Optional callback for progress updates
+    ///
+    /// # Returns
+    ///
+    /// The number of files added, updated, removed and left unchanged
+    pub fn refresh_context<F>(&mut self, context_id: &str, progress_callback: Option<F>) -> Result<RefreshSummary>
+    where
+        F: Fn(ProgressStatus) + Send + 'static,
+    {
+        let context_meta = self
+            .persistent_contexts
+            .get(context_id)
+            .cloned()
+            .ok_or_else(|| SemanticSearchError::ContextNotFound(context_id.to_string()))?;
+        let source_path = context_meta.source_path.as_ref().ok_or_else(|| {
+            SemanticSearchError::InvalidArgument(format!("Context {} was not created from a path", context_id))
+        })?;
+        let source_path = Path::new(source_path);
+        if !source_path.exists() {
+            return Err(SemanticSearchError::InvalidPath(format!(
+                "Path does not exist: {}",
+                source_path.display()
+            )));
+        }
+
+        self.load_persistent_context(context_id)?;
+        let context = self
+            .volatile_contexts
+            .get(context_id)
+            .cloned()
+            .ok_or_else(|| SemanticSearchError::ContextNotFound(context_id.to_string()))?;
+
+        // Notify progress: Starting
+        if let Some(ref callback) = progress_callback {
+            callback(ProgressStatus::CountingFiles);
+        }
+
+        let source_files = Self::source_files(source_path);
+
+        // Notify progress: Starting indexing
+        if let Some(ref callback) = progress_callback {
+            callback(ProgressStatus::StartingIndexing(source_files.len()));
+        }
+
+        let mut summary = RefreshSummary::default();
+        let mut files = HashMap::new();
+        let mut unchanged = HashSet::new();
+        let mut items = Vec::new();
+
+        for (i, path) in source_files.iter().enumerate() {
+            let path_str = path.to_string_lossy().to_string();
+            let previous = context_meta.files.get(&path_str);
+
+            // Only hash the files whose modification time changed
+            let state = match (previous, utils::file_modified(path)) {
+                (Some(previous), Ok(modified)) if previous.modified == modified => Ok(previous.clone()),
+                _ => utils::file_state(path),
+            };
+
+            match state {
+                Ok(state) if previous.is_some_and(|previous| previous.hash == state.hash) => {
+                    summary.unchanged += 1;
+                    unchanged.insert(path_str.clone());
+                    files.insert(path_str, state);
+                },
+                Ok(state) => {
+                    // Skip files that fail to process
+                    if let Ok(mut file_items) = Self::process_source_file(path) {
+                        items.append(&mut file_items);
+                        if previous.is_some() {
+                            summary.updated += 1;
+                        } else {
+                            summary.added += 1;
+                        }
+                        files.insert(path_str, state);
+                    }
+                },
+                Err(_) => {},
+            }
+
+            // Update progress
+            if let Some(ref callback) = progress_callback {
+                callback(ProgressStatus::Indexing(i + 1, source_files.len()));
+            }
+        }
+
+        summary.removed = context_meta
+            .files
+            .keys()
+            .filter(|path| !files.contains_key(*path))
+            .count();
+
+        // Notify progress: Creating semantic context
+        if let Some(ref callback) = progress_callback {
+            callback(ProgressStatus::CreatingSemanticContext);
+        }
+
+        // Number the new data points after those already in the context, and generate their
+        // embeddings before locking the context so searches aren't blocked meanwhile
+        let first_id = context
+            .lock()
+            .map_err(|e| SemanticSearchError::OperationFailed(format!("Failed to acquire lock on context: {}", e)))?
+            .next_data_point_id();
+        let data_points = self.process_items_to_data_points(&items, first_id, &progress_callback)?;
+
+        // Notify progress: Building index
+        if let Some(ref callback) = progress_callback {
+            callback(ProgressStatus::BuildingIndex);
+        }
+
+        let mut context_guard = context
+            .lock()
+            .map_err(|e| SemanticSearchError::OperationFailed(format!("Failed to acquire lock on context: {}", e)))?;
+
+        // Drop every data point that doesn't belong to an unchanged file, which also covers
+        // contexts created before file states were recorded
+        context_guard.remove_data_points(|point| {
+            !point
+                .payload
+                .get("path")
+                .and_then(|p| p.as_str())
+                .is_some_and(|path| unchanged.contains(path))
+        })?;
+        context_guard.add_data_points(data_points)?;
+
+        // Notify progress: Finalizing
+        if let Some(ref callback) = progress_callback {
+            callback(ProgressStatus::Finalizing);
+        }
+
+        context_guard.save()?;
+        let item_count = context_guard.get_data_points().len();
+        drop(context_guard);
+
+        if let Some(context_meta) = self.persistent_contexts.get_mut(context_id) {
+            context_meta.files = files;
+            context_meta.item_count = item_count;
+            context_meta.updated_at = Utc::now();
+        }
+        self.save_contexts_metadata()?;
+
+        // Notify progress: Complete
+        if let Some(ref callback) = progress_callback {
+            callback(ProgressStatus::Complete);
+        }
+
+        Ok(summary)
+    }
+
     /// Create a context directory
     fn create_context_directory(&self, id: &str, persistent: bool) -> Result<PathBuf> {
         utils::create_context_directory(&self.base_dir, id, persistent)
@@ -410,7 +581,7 @@ impl SemanticSearchClient {
         dir_path: &Path,
         file_count: usize,
         progress_callback: &Option<F>,
-    ) -> Result<Vec<Value>>
+    ) -> Result<(Vec<Value>, HashMap<String, FileState>)>
     where
         F: Fn(ProgressStatus) + Send + 'static,
     {
@@ -422,29 +593,18 @@ impl SemanticSearchClient {
         // Process all files in the directory with progress updates
         let mut processed_files = 0;
         let mut items = Vec::new();
+        let mut files = HashMap::new();
 
-        for entry in walkdir::WalkDir::new(dir_path)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.file_type().is_file())
-        {
-            let path = entry.path();
-
-            // Skip hidden files
-            if path
-                .file_name()
-                .and_then(|n| n.to_str())
-                .is_some_and(|s| s.starts_with('.'))
-            {
+        for path in Self::source_files(dir_path) {
+            // Process the file, skipping files that fail to process
+            let Ok(state) = utils::file_state(&path) else {
                 continue;
-            }
-
-            // Process the file
-            match process_file(path) {
+            };
+            match Self::process_source_file(&path) {
                 Ok(mut file_items) => items.append(&mut file_items),
-                Err(_) => continue, // Skip files that fail to process
+                Err(_) => continue,
             }
+            files.insert(path.to_string_lossy().to_string(), state);
 
             processed_files += 1;
 
@@ -454,6 +614,40 @@ impl SemanticSearchClient {
             }
         }
 
+        Ok((items, files))
+    }
+
+    /// Get the files to index from a path, skipping hidden files
+    fn source_files(path: &Path) -> Vec<PathBuf> {
+        if path.is_file() {
+            return vec![path.to_path_buf()];
+        }
+
+        walkdir::WalkDir::new(path)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| !e.file_name().to_str().is_some_and(|s| s.starts_with('.')))
+            .map(|e| e.into_path())
+            .collect()
+    }
+
+    /// Process a file into items, making sure every item records the path it came from so that
+    /// its data points can be removed when the file changes
+    fn process_source_file(path: &Path) -> Result<Vec<Value>> {
+        let path_str = Value::String(path.to_string_lossy().to_string());
+        let items = process_file(path)?
+            .into_iter()
+            .map(|item| {
+                let mut map = match item {
+                    Value::Object(map) => map,
+                    other => serde_json::Map::from_iter([("text".to_string(), other)]),
+                };
+                map.entry("path").or_insert_with(|| path_str.clone());
+                Value::Object(map)
+            })
+            .collect();
         Ok(items)
     }
 
@@ -473,10 +667,10 @@ impl SemanticSearchClient {
         }
 
         // Create a new semantic context
-        let mut semantic_context = SemanticContext::new(context_dir.join("data.json"))?;
+        let mut semantic_context = SemanticContext::new(context_dir.join("data.bin"))?;
 
         // Process items to data points
-        let data_points = self.process_items_to_data_points(items, progress_callback)?;
+        let data_points = self.process_items_to_data_points(items, 0, progress_callback)?;
 
         // Notify progress: Building index
         if let Some(ref callback) = progress_callback {
@@ -489,7 +683,13 @@ impl SemanticSearchClient {
         Ok(semantic_context)
     }
 
-    fn process_items_to_data_points<F>(&self, items: &[Value], progress_callback: &Option<F>) -> Result<Vec<DataPoint>>
+    /// Create the data points of `items`, with ids starting at `first_id`
+    fn process_items_to_data_points<F>(
+        &self,
+        items: &[Value],
+        first_id: usize,
+        progress_callback: &Option<F>,
+    ) -> Result<Vec<DataPoint>>
     where
         F: Fn(ProgressStatus) + Send + 'static,
     {
@@ -506,7 +706,7 @@ impl SemanticSearchClient {
             }
 
             // Create a data point from the item
-            let data_point = self.create_data_point_from_item(item, i)?;
+            let data_point = self.create_data_point_from_item(item, first_id + i)?;
             data_points.push(data_point);
         }
 
@@ -514,6 +714,7 @@ impl SemanticSearchClient {
     }
 
     /// Save and store context
+    #[allow(clippy::too_many_arguments)]
     fn save_and_store_context(
         &mut self,
         id: &str,
@@ -521,6 +722,7 @@ impl SemanticSearchClient {
         description: &str,
         persistent: bool,
         source_path: Option<String>,
+        files: HashMap<String, FileState>,
         semantic_context: SemanticContext,
     ) -> Result<()> {
         // Notify progress: Finalizing (90% progress point)
@@ -532,7 +734,8 @@ impl SemanticSearchClient {
         }
 
         // Create the context metadata
-        let context = MemoryContext::new(id.to_string(), name, description, persistent, source_path, item_count);
+        let mut context = MemoryContext::new(id.to_string(), name, description, persistent, source_path, item_count);
+        context.files = files;
 
         // Store the context
         if persistent {
@@ -565,7 +768,11 @@ impl SemanticSearchClient {
         let mut payload = HashMap::new();
         payload.insert("text".to_string(), Value::String(text.to_string()));
 
-        Ok(DataPoint { id, payload, vector })
+        Ok(DataPoint {
+            id,
+            payload,
+            vector: vector.into(),
+        })
     }
 
     /// Create a data point from a JSON item
@@ -594,7 +801,11 @@ impl SemanticSearchClient {
             map
         };
 
-        Ok(DataPoint { id, payload, vector })
+        Ok(DataPoint {
+            id,
+            payload,
+            vector: vector.into(),
+        })
     }
 
     /// Add a context from text
@@ -636,7 +847,7 @@ impl SemanticSearchClient {
         let context_dir = self.create_context_directory(&context_id, is_persistent)?;
 
         // Create a new semantic context
-        let mut semantic_context = SemanticContext::new(context_dir.join("data.json"))?;
+        let mut semantic_context = SemanticContext::new(context_dir.join("data.bin"))?;
 
         // Create a data point from the text
         let data_point = self.create_data_point_from_text(text, 0)?;
@@ -656,6 +867,7 @@ impl SemanticSearchClient {
             context_description,
             is_persistent,
             None,
+            HashMap::new(),
             semantic_context,
         )?;
 
@@ -706,6 +918,62 @@ impl SemanticSearchClient {
     ///
     /// A vector of (context_id, results) pairs
     pub fn search_all(&self, query_text: &str, result_limit: Option<usize>) -> Result<Vec<(ContextId, SearchResults)>> {
+        self.search_all_with_mode(query_text, result_limit, config::get_config().search_mode)
+    }
+
+    /// Search across all contexts with a specific search mode
+    ///
+    /// # Arguments
+    ///
+    /// * `query_text` - Search query
+    /// * `result_limit` - Maximum number of results to return per context (if None, uses
+    ///   default_results from config)
+    /// * `mode` - How the query is matched against the contexts
+    ///
+    /// # Returns
+    ///
+    /// A vector of (context_id, results) pairs
+    pub fn search_all_with_mode(
+        &self,
+        query_text: &str,
+        result_limit: Option<usize>,
+        mode: SearchMode,
+    ) -> Result<Vec<(ContextId, SearchResults)>> {
+        self.search_all_matching(query_text, result_limit, mode, &SearchFilter::default())
+    }
+
+    /// Search across all contexts among the data points matching a filter
+    ///
+    /// The filter is applied while searching, so each context still returns up to `result_limit`
+    /// matching results.
+    ///
+    /// # Arguments
+    ///
+    /// * `query_text` - Search query
+    /// * `result_limit` - Maximum number of results to return per context (if None, uses
+    ///   default_results from config)
+    /// * `filter` - Which data points can be returned
+    ///
+    /// # Returns
+    ///
+    /// A vector of (context_id, results) pairs
+    pub fn search_all_filtered(
+        &self,
+        query_text: &str,
+        result_limit: Option<usize>,
+        filter: &SearchFilter,
+    ) -> Result<Vec<(ContextId, SearchResults)>> {
+        self.search_all_matching(query_text, result_limit, config::get_config().search_mode, filter)
+    }
+
+    /// Search across all contexts with a search mode, among the data points matching a filter
+    fn search_all_matching(
+        &self,
+        query_text: &str,
+        result_limit: Option<usize>,
+        mode: SearchMode,
+        filter: &SearchFilter,
+    ) -> Result<Vec<(ContextId, SearchResults)>> {
         // Validate inputs
         if query_text.is_empty() {
             return Err(SemanticSearchError::InvalidArgument(
@@ -727,7 +995,15 @@ impl SemanticSearchClient {
                 SemanticSearchError::OperationFailed(format!("Failed to acquire lock on context: {}", e))
             })?;
 
-            match context_guard.search(&query_vector, effective_limit) {
+            let filter = PointFilter::new(filter, self.context_root(context_id))?;
+            match Self::search_semantic_context(
+                &context_guard,
+                &query_vector,
+                query_text,
+                effective_limit,
+                mode,
+                &filter,
+            ) {
                 Ok(results) => {
                     if !results.is_empty() {
                         all_results.push((context_id.clone(), results));
@@ -773,6 +1049,73 @@ impl SemanticSearchClient {
         context_id: &str,
         query_text: &str,
         result_limit: Option<usize>,
+    ) -> Result<SearchResults> {
+        self.search_context_with_mode(context_id, query_text, result_limit, config::get_config().search_mode)
+    }
+
+    /// Search in a specific context with a specific search mode
+    ///
+    /// # Arguments
+    ///
+    /// * `context_id` - ID of the context to search in
+    /// * `query_text` - Search query
+    /// * `result_limit` - Maximum number of results to return (if None, uses default_results from
+    ///   config)
+    /// * `mode` - How the query is matched against the context
+    ///
+    /// # Returns
+    ///
+    /// A vector of search results
+    pub fn search_context_with_mode(
+        &self,
+        context_id: &str,
+        query_text: &str,
+        result_limit: Option<usize>,
+        mode: SearchMode,
+    ) -> Result<SearchResults> {
+        self.search_context_matching(context_id, query_text, result_limit, mode, &SearchFilter::default())
+    }
+
+    /// Search in a specific context among the data points matching a filter
+    ///
+    /// The filter is applied while searching, so up to `result_limit` matching results are
+    /// returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `context_id` - ID of the context to search in
+    /// * `query_text` - Search query
+    /// * `result_limit` - Maximum number of results to return (if None, uses default_results from
+    ///   config)
+    /// * `filter` - Which data points can be returned
+    ///
+    /// # Returns
+    ///
+    /// A vector of search results
+    pub fn search_context_filtered(
+        &self,
+        context_id: &str,
+        query_text: &str,
+        result_limit: Option<usize>,
+        filter: &SearchFilter,
+    ) -> Result<SearchResults> {
+        self.search_context_matching(
+            context_id,
+            query_text,
+            result_limit,
+            config::get_config().search_mode,
+            filter,
+        )
+    }
+
+    /// Search in a specific context with a search mode, among the data points matching a filter
+    fn search_context_matching(
+        &self,
+        context_id: &str,
+        query_text: &str,
+        result_limit: Option<usize>,
+        mode: SearchMode,
+        filter: &SearchFilter,
     ) -> Result<SearchResults> {
         // Validate inputs
         if context_id.is_empty() {
@@ -802,7 +1145,42 @@ impl SemanticSearchClient {
             .lock()
             .map_err(|e| SemanticSearchError::OperationFailed(format!("Failed to acquire lock on context: {}", e)))?;
 
-        context_guard.search(&query_vector, effective_limit)
+        let filter = PointFilter::new(filter, self.context_root(context_id))?;
+        Self::search_semantic_context(
+            &context_guard,
+            &query_vector,
+            query_text,
+            effective_limit,
+            mode,
+            &filter,
+        )
+    }
+
+    /// Search a semantic context with a search mode, among the data points matching a filter
+    fn search_semantic_context(
+        context: &SemanticContext,
+        query_vector: &[f32],
+        query_text: &str,
+        limit: usize,
+        mode: SearchMode,
+        filter: &PointFilter<'_>,
+    ) -> Result<SearchResults> {
+        let is_match = |point: &DataPoint| filter.matches(point);
+        match mode {
+            SearchMode::Dense => context.search_filtered(query_vector, limit, is_match),
+            SearchMode::Hybrid(fusion) => {
+                context.search_hybrid_filtered(query_vector, query_text, limit, fusion, is_match)
+            },
+        }
+    }
+
+    /// Get the source directory of a context created from a path, which search filters match
+    /// paths relative to
+    fn context_root(&self, context_id: &str) -> Option<&Path> {
+        self.persistent_contexts
+            .get(context_id)
+            .and_then(|context| context.source_path.as_deref())
+            .map(Path::new)
     }
 
     /// Get all contexts
@@ -855,8 +1233,7 @@ impl SemanticSearchClient {
             .map_err(|e| SemanticSearchError::OperationFailed(format!("Failed to acquire lock on context: {}", e)))?;
 
         // Save the data to the persistent directory
-        let data_path = persistent_dir.join("data.json");
-        utils::save_json_to_file(&data_path, context_guard.get_data_points())?;
+        context_guard.save_to(&persistent_dir.join("data.bin"))?;
 
         // Create the context metadata
         let context_meta = MemoryContext::new(
@@ -1028,7 +1405,7 @@ impl SemanticSearchClient {
         }
 
         // Create a new semantic context
-        let semantic_context = SemanticContext::new(context_dir.join("data.json"))?;
+        let semantic_context = SemanticContext::new(context_dir.join("data.bin"))?;
 
         // Store the semantic context
         self.volatile_contexts
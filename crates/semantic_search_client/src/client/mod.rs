@@ -2,8 +2,12 @@
 pub mod embedder_factory;
 /// Client implementation for semantic search operations
 mod implementation;
+/// Matching of data points against search filters
+mod search_filter;
 /// Semantic context implementation for search operations
 pub mod semantic_context;
+/// Binary on-disk format of semantic contexts
+mod storage;
 /// Utility functions for semantic search operations
 pub mod utils;
 
@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use globset::{
+    GlobBuilder,
+    GlobSet,
+    GlobSetBuilder,
+};
+
+use crate::error::{
+    Result,
+    SemanticSearchError,
+};
+use crate::types::{
+    DataPoint,
+    SearchFilter,
+};
+
+/// A search filter compiled for the data points of a context
+pub struct PointFilter<'a> {
+    filter: &'a SearchFilter,
+    include_paths: Option<GlobSet>,
+    exclude_paths: Option<GlobSet>,
+    file_types: Vec<String>,
+    /// Source directory of the context, which paths are matched relative to
+    root: Option<&'a Path>,
+}
+
+impl<'a> PointFilter<'a> {
+    /// Compile a search filter
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The search filter
+    /// * `root` - Source directory of the context, if it was created from a path
+    ///
+    /// # Returns
+    ///
+    /// The compiled filter, or an error if a path pattern is invalid
+    pub fn new(filter: &'a SearchFilter, root: Option<&'a Path>) -> Result<Self> {
+        Ok(Self {
+            filter,
+            include_paths: glob_set(&filter.include_paths)?,
+            exclude_paths: glob_set(&filter.exclude_paths)?,
+            // File types are stored in payloads by their debug representation
+            file_types: filter
+                .file_types
+                .iter()
+                .map(|file_type| format!("{:?}", file_type))
+                .collect(),
+            root,
+        })
+    }
+
+    /// Check if a data point matches the filter
+    pub fn matches(&self, point: &DataPoint) -> bool {
+        if self.filter.is_empty() {
+            return true;
+        }
+
+        if self.include_paths.is_some() || self.exclude_paths.is_some() {
+            let Some(path) = point.payload.get("path").and_then(|v| v.as_str()).map(Path::new) else {
+                return self.include_paths.is_none();
+            };
+            let relative_path = self.root.and_then(|root| path.strip_prefix(root).ok());
+            let is_match = |globs: &GlobSet| globs.is_match(path) || relative_path.is_some_and(|p| globs.is_match(p));
+
+            if self.include_paths.as_ref().is_some_and(|globs| !is_match(globs))
+                || self.exclude_paths.as_ref().is_some_and(is_match)
+            {
+                return false;
+            }
+        }
+
+        if !self.file_types.is_empty() {
+            let file_type = point.payload.get("file_type").and_then(|v| v.as_str());
+            if !file_type.is_some_and(|file_type| self.file_types.iter().any(|t| t == file_type)) {
+                return false;
+            }
+        }
+
+        self.filter
+            .payload
+            .iter()
+            .all(|predicate| predicate.matches(&point.payload))
+    }
+}
+
+/// Compile path patterns, where `*` doesn't match path separators but `**` does
+fn glob_set(patterns: &[String]) -> Result<Option<GlobSet>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| SemanticSearchError::InvalidArgument(format!("Invalid path pattern {}: {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| SemanticSearchError::InvalidArgument(format!("Invalid path patterns: {}", e)))
+}
@@ -1,32 +1,60 @@
-use std::fs::{
-    self,
-    File,
+use std::collections::{
+    HashMap,
+    HashSet,
 };
-use std::io::{
-    BufReader,
-    BufWriter,
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
 };
-use std::path::PathBuf;
 
-use crate::error::Result;
-use crate::index::VectorIndex;
+use hnsw_rs::prelude::{
+    DistCosine,
+    Distance,
+};
+use tracing::warn;
+
+use crate::client::storage;
+use crate::error::{
+    Result,
+    SemanticSearchError,
+};
+use crate::index::{
+    Bm25Index,
+    VectorIndex,
+};
 use crate::types::{
     DataPoint,
+    FusionMethod,
     SearchResult,
 };
 
+/// Minimum size of the candidate list of index searches
+const MIN_EF_SEARCH: usize = 100;
+
+/// How many candidates hybrid searches fetch from each index for every requested result
+const HYBRID_CANDIDATES_PER_RESULT: usize = 4;
+
 /// A semantic context containing data points and a vector index
 pub struct SemanticContext {
     /// The data points stored in the index
     pub(crate) data_points: Vec<DataPoint>,
+    /// Positions of removed data points, which stay in the index until it is rebuilt since HNSW
+    /// graphs don't support deletion
+    removed: HashSet<usize>,
     /// The vector index for fast approximate nearest neighbor search
     index: Option<VectorIndex>,
+    /// The inverted index over the text of the data points for lexical search
+    lexical_index: Bm25Index,
     /// Path to save/load the data points
     data_path: PathBuf,
 }
 
 impl SemanticContext {
     /// Create a new semantic context
+    ///
+    /// Data points saved in the legacy JSON format, either at `data_path` or next to it with a
+    /// `.json` extension, are migrated to the binary format.
     pub fn new(data_path: PathBuf) -> Result<Self> {
         // Create the directory if it doesn't exist
         if let Some(parent) = data_path.parent() {
@@ -36,37 +64,135 @@ impl SemanticContext {
         // Create a new instance
         let mut context = Self {
             data_points: Vec::new(),
+            removed: HashSet::new(),
             index: None,
+            lexical_index: Bm25Index::new(),
             data_path: data_path.clone(),
         };
 
         // Load data points if the file exists
-        if data_path.exists() {
-            let file = File::open(&data_path)?;
-            let reader = BufReader::new(file);
-            context.data_points = serde_json::from_reader(reader)?;
-        }
+        let legacy_path = data_path.with_extension("json");
+        let source_path = if data_path.exists() {
+            &data_path
+        } else if legacy_path.exists() {
+            &legacy_path
+        } else {
+            return Ok(context);
+        };
+        let stored = storage::read_data_points(source_path)?;
+        context.data_points = stored.data_points;
+        context.removed = stored.removed;
 
-        // If we have data points, rebuild the index
-        if !context.data_points.is_empty() {
-            context.rebuild_index()?;
+        if stored.legacy {
+            // Migrate to the binary format, which also saves the index
+            if !context.data_points.is_empty() {
+                context.rebuild_index()?;
+            }
+            context.save()?;
+            if source_path != &data_path {
+                fs::remove_file(&legacy_path)?;
+            }
+        } else if !context.data_points.is_empty() {
+            // Load the saved indexes, falling back to rebuilding them
+            match context.load_index() {
+                Ok(index) => {
+                    context.index = Some(index);
+                    match context.load_lexical_index() {
+                        Ok(lexical_index) => context.lexical_index = lexical_index,
+                        Err(e) => {
+                            warn!("Rebuilding the lexical index of {}: {}", data_path.display(), e);
+                            context.rebuild_lexical_index();
+                        },
+                    }
+                },
+                Err(e) => {
+                    warn!("Rebuilding the index of {}: {}", data_path.display(), e);
+                    context.rebuild_index()?;
+                },
+            }
         }
 
         Ok(context)
     }
 
-    /// Save data points to disk
+    /// Save the data points and the index to disk
     pub fn save(&self) -> Result<()> {
-        // Save the data points as JSON
-        let file = File::create(&self.data_path)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, &self.data_points)?;
+        self.save_to(&self.data_path)
+    }
+
+    /// Save the data points and the index to a specific path
+    ///
+    /// The indexes are saved next to the data points as `{stem}.hnsw.graph`, `{stem}.hnsw.data`
+    /// and `{stem}.bm25`.
+    pub fn save_to(&self, data_path: &Path) -> Result<()> {
+        let (dir, basename) = Self::index_location(data_path);
+
+        // The data and vector index files are replaced by renaming, since the old ones may still be
+        // memory-mapped
+        storage::write_data_points(data_path, &self.data_points, &self.removed)?;
+        match self.index.as_ref().filter(|index| !index.is_empty()) {
+            Some(index) => {
+                index.save(dir, &basename)?;
+                self.lexical_index.save(dir, &basename)?;
+            },
+            None => {
+                VectorIndex::remove_files(dir, &basename)?;
+                Bm25Index::remove_files(dir, &basename)?;
+            },
+        }
 
         Ok(())
     }
 
-    /// Rebuild the index from the current data points
+    /// Load the index saved next to the data points
+    fn load_index(&self) -> Result<VectorIndex> {
+        let (dir, basename) = Self::index_location(&self.data_path);
+        let index = VectorIndex::load(dir, &basename)?;
+        if index.len() != self.data_points.len() {
+            return Err(SemanticSearchError::OperationFailed(format!(
+                "The index has {} elements instead of {}",
+                index.len(),
+                self.data_points.len()
+            )));
+        }
+        Ok(index)
+    }
+
+    /// Load the lexical index saved next to the data points
+    fn load_lexical_index(&self) -> Result<Bm25Index> {
+        let (dir, basename) = Self::index_location(&self.data_path);
+        let lexical_index = Bm25Index::load(dir, &basename)?;
+        let expected = self.data_points.len() - self.removed.len();
+        if lexical_index.len() != expected {
+            return Err(SemanticSearchError::OperationFailed(format!(
+                "The lexical index has {} documents instead of {}",
+                lexical_index.len(),
+                expected
+            )));
+        }
+        Ok(lexical_index)
+    }
+
+    /// Get the directory and base name of the index files saved next to a data file
+    fn index_location(data_path: &Path) -> (&Path, String) {
+        let dir = data_path.parent().unwrap_or(Path::new("."));
+        let basename = data_path
+            .file_stem()
+            .map_or_else(|| "data".to_string(), |stem| stem.to_string_lossy().to_string());
+        (dir, basename)
+    }
+
+    /// Rebuild the index from the current data points, dropping the removed ones
     pub fn rebuild_index(&mut self) -> Result<()> {
+        if !self.removed.is_empty() {
+            let removed = std::mem::take(&mut self.removed);
+            let mut position = 0;
+            self.data_points.retain(|_| {
+                position += 1;
+                !removed.contains(&(position - 1))
+            });
+        }
+
         // Create a new index with the current data points
         let index = VectorIndex::new(self.data_points.len().max(100));
 
@@ -77,10 +203,26 @@ impl SemanticContext {
 
         // Set the new index
         self.index = Some(index);
+        self.rebuild_lexical_index();
 
         Ok(())
     }
 
+    /// Rebuild the lexical index from the current data points, skipping the removed ones
+    fn rebuild_lexical_index(&mut self) {
+        self.lexical_index = Bm25Index::new();
+        for (i, point) in self.data_points.iter().enumerate() {
+            if !self.removed.contains(&i) {
+                self.lexical_index.insert(i, Self::point_text(point));
+            }
+        }
+    }
+
+    /// Get the text of a data point used for lexical search
+    fn point_text(point: &DataPoint) -> &str {
+        point.payload.get("text").and_then(|v| v.as_str()).unwrap_or_default()
+    }
+
     /// Add data points to the context
     pub fn add_data_points(&mut self, data_points: Vec<DataPoint>) -> Result<usize> {
         // Store the count before extending the data points
@@ -101,6 +243,38 @@ impl SemanticContext {
         Ok(count)
     }
 
+    /// Remove the data points matching a predicate from the context
+    ///
+    /// The points are skipped by searches right away, and only dropped from the index once it is
+    /// rebuilt, which happens when most of it has been removed.
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Returns `true` for the data points to remove
+    ///
+    /// # Returns
+    ///
+    /// The number of removed data points
+    pub fn remove_data_points(&mut self, predicate: impl Fn(&DataPoint) -> bool) -> Result<usize> {
+        let positions = self
+            .data_points
+            .iter()
+            .enumerate()
+            .filter(|(i, point)| !self.removed.contains(i) && predicate(point))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        self.removed.extend(&positions);
+        for i in &positions {
+            self.lexical_index.remove(*i, Self::point_text(&self.data_points[*i]));
+        }
+
+        if self.removed.len() * 2 > self.data_points.len() {
+            self.rebuild_index()?;
+        }
+
+        Ok(positions.len())
+    }
+
     /// Update the index with data points in a specific range
     pub fn update_index_by_range(&mut self, start_idx: usize, end_idx: usize) -> Result<()> {
         // If we don't have an index yet, or if the index is small and we're adding many points,
@@ -113,9 +287,10 @@ impl SemanticContext {
         // Get the existing index
         let index = self.index.as_ref().unwrap();
 
-        // Add only the points in the specified range to the index
+        // Add only the points in the specified range to the indexes
         for i in start_idx..end_idx {
             index.insert(&self.data_points[i].vector, i);
+            self.lexical_index.insert(i, Self::point_text(&self.data_points[i]));
         }
 
         Ok(())
@@ -123,13 +298,38 @@ impl SemanticContext {
 
     /// Search for similar items to the given vector
     pub fn search(&self, query_vector: &[f32], limit: usize) -> Result<Vec<SearchResult>> {
+        self.search_filtered(query_vector, limit, |_| true)
+    }
+
+    /// Search for similar items to the given vector among the data points matching a filter
+    ///
+    /// The filter is applied while searching the index, so the results aren't cut short by data
+    /// points that don't match.
+    ///
+    /// # Arguments
+    ///
+    /// * `query_vector` - The embedding of the query
+    /// * `limit` - Maximum number of results to return
+    /// * `filter` - Returns `false` for the data points to skip
+    ///
+    /// # Returns
+    ///
+    /// A vector of search results, best match first
+    pub fn search_filtered(
+        &self,
+        query_vector: &[f32],
+        limit: usize,
+        filter: impl Fn(&DataPoint) -> bool,
+    ) -> Result<Vec<SearchResult>> {
         let index = match &self.index {
             Some(idx) => idx,
             None => return Ok(Vec::new()), // Return empty results if no index
         };
 
-        // Search for the nearest neighbors
-        let results = index.search(query_vector, limit, 100);
+        // Search for the nearest neighbors, skipping removed data points
+        let results = index.search_filtered(query_vector, limit, Self::ef_search(limit), |id| {
+            self.is_match(id, &filter)
+        });
 
         // Convert the results to our SearchResult type
         let search_results = results
@@ -143,8 +343,153 @@ impl SemanticContext {
         Ok(search_results)
     }
 
+    /// Search with both the vector index and the BM25 index, merging their results
+    ///
+    /// Every result gets both its cosine distance to the query and its BM25 score, even when it
+    /// was only found by one of the indexes.
+    ///
+    /// # Arguments
+    ///
+    /// * `query_vector` - The embedding of the query
+    /// * `query_text` - The text of the query
+    /// * `limit` - Maximum number of results to return
+    /// * `fusion` - How the results of both indexes are merged
+    ///
+    /// # Returns
+    ///
+    /// A vector of search results, best match first
+    pub fn search_hybrid(
+        &self,
+        query_vector: &[f32],
+        query_text: &str,
+        limit: usize,
+        fusion: FusionMethod,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_hybrid_filtered(query_vector, query_text, limit, fusion, |_| true)
+    }
+
+    /// Search with both the vector index and the BM25 index among the data points matching a
+    /// filter, merging their results
+    ///
+    /// # Arguments
+    ///
+    /// * `query_vector` - The embedding of the query
+    /// * `query_text` - The text of the query
+    /// * `limit` - Maximum number of results to return
+    /// * `fusion` - How the results of both indexes are merged
+    /// * `filter` - Returns `false` for the data points to skip
+    ///
+    /// # Returns
+    ///
+    /// A vector of search results, best match first
+    pub fn search_hybrid_filtered(
+        &self,
+        query_vector: &[f32],
+        query_text: &str,
+        limit: usize,
+        fusion: FusionMethod,
+        filter: impl Fn(&DataPoint) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        let index = match &self.index {
+            Some(idx) => idx,
+            None => return Ok(Vec::new()), // Return empty results if no index
+        };
+
+        let candidates = limit.saturating_mul(HYBRID_CANDIDATES_PER_RESULT);
+        let is_match = |id: usize| self.is_match(id, &filter);
+        let dense = index.search_filtered(query_vector, candidates, Self::ef_search(candidates), is_match);
+        let lexical = self.lexical_index.search(query_text, candidates, is_match);
+
+        // Score every candidate with both signals
+        let dense_ranks = dense
+            .iter()
+            .enumerate()
+            .map(|(rank, (id, _))| (*id, rank + 1))
+            .collect::<HashMap<_, _>>();
+        let lexical_ranks = lexical
+            .iter()
+            .enumerate()
+            .map(|(rank, (id, _))| (*id, rank + 1))
+            .collect::<HashMap<_, _>>();
+        let scored = dense
+            .iter()
+            .map(|(id, distance)| (*id, *distance, self.lexical_index.score(*id, query_text)))
+            .chain(
+                lexical
+                    .iter()
+                    .filter(|(id, _)| !dense_ranks.contains_key(id))
+                    .map(|(id, score)| {
+                        (
+                            *id,
+                            DistCosine.eval(query_vector, &self.data_points[*id].vector),
+                            *score,
+                        )
+                    }),
+            )
+            .collect::<Vec<_>>();
+
+        let best_lexical_score = scored.iter().map(|(_, _, score)| *score).fold(0.0, f32::max);
+        let fused_score = |id: usize, distance: f32, lexical_score: f32| match fusion {
+            FusionMethod::ReciprocalRank { k } => {
+                let rank_score = |rank: Option<&usize>| rank.map_or(0.0, |rank| 1.0 / (k + *rank as f32));
+                // Normalized by the score of a result ranked first by both indexes
+                (rank_score(dense_ranks.get(&id)) + rank_score(lexical_ranks.get(&id))) * (k + 1.0) / 2.0
+            },
+            FusionMethod::Weighted { dense_weight } => {
+                let dense_weight = dense_weight.clamp(0.0, 1.0);
+                let lexical_score = if best_lexical_score > 0.0 {
+                    lexical_score / best_lexical_score
+                } else {
+                    0.0
+                };
+                dense_weight * (1.0 - distance).clamp(0.0, 1.0) + (1.0 - dense_weight) * lexical_score
+            },
+        };
+        let mut fused = scored
+            .into_iter()
+            .map(|(id, distance, lexical_score)| {
+                (id, distance, lexical_score, fused_score(id, distance, lexical_score))
+            })
+            .collect::<Vec<_>>();
+        fused.sort_by(|(a_id, _, _, a), (b_id, _, _, b)| b.total_cmp(a).then(a_id.cmp(b_id)));
+
+        let search_results = fused
+            .into_iter()
+            .take(limit)
+            .map(|(id, distance, lexical_score, score)| SearchResult {
+                point: self.data_points[id].clone(),
+                distance: 1.0 - score,
+                dense_distance: Some(distance),
+                lexical_score: Some(lexical_score),
+            })
+            .collect();
+
+        Ok(search_results)
+    }
+
+    /// Check if a data point wasn't removed and matches a filter
+    fn is_match(&self, id: usize, filter: impl Fn(&DataPoint) -> bool) -> bool {
+        !self.removed.contains(&id) && filter(&self.data_points[id])
+    }
+
+    /// Get the size of the candidate list of index searches, which grows with the number of
+    /// requested results so that filtered searches fetch enough matching candidates
+    fn ef_search(limit: usize) -> usize {
+        MIN_EF_SEARCH.max(limit.saturating_mul(2))
+    }
+
+    /// Get the id following the highest data point id in the context, including removed ones
+    pub fn next_data_point_id(&self) -> usize {
+        self.data_points.iter().map(|point| point.id + 1).max().unwrap_or(0)
+    }
+
     /// Get the data points for serialization
-    pub fn get_data_points(&self) -> &Vec<DataPoint> {
-        &self.data_points
+    pub fn get_data_points(&self) -> Vec<&DataPoint> {
+        self.data_points
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !self.removed.contains(i))
+            .map(|(_, point)| point)
+            .collect()
     }
 }
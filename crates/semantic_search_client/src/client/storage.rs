@@ -0,0 +1,233 @@
+use std::borrow::Cow;
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    BufWriter,
+    Write,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use serde_json::Value;
+
+use crate::error::{
+    Result,
+    SemanticSearchError,
+};
+use crate::types::{
+    DataPoint,
+    Vector,
+};
+
+/// Magic bytes at the start of a binary context file
+const MAGIC: &[u8; 4] = b"QSSC";
+
+/// Version of the binary format written by [write_data_points]
+const FORMAT_VERSION: u32 = 1;
+
+/// Size of the header: magic, format version, vector dimension and number of data points
+const HEADER_LEN: usize = 20;
+
+/// Everything but the vectors of the data points, stored as JSON after the vectors
+#[derive(Serialize, Deserialize)]
+struct Metadata<'a> {
+    points: Vec<PointMetadata<'a>>,
+    removed: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PointMetadata<'a> {
+    id: usize,
+    payload: Cow<'a, HashMap<String, Value>>,
+}
+
+/// Data points read from a context file
+pub struct StoredDataPoints {
+    /// The data points, including removed ones
+    pub data_points: Vec<DataPoint>,
+    /// Positions of the removed data points
+    pub removed: HashSet<usize>,
+    /// Whether the file used the legacy JSON format
+    pub legacy: bool,
+}
+
+/// Write data points to a binary context file
+///
+/// The file starts with a fixed size header, followed by the vectors as contiguous little-endian
+/// `f32`s so they can be memory-mapped, followed by the payloads as JSON. The file is written to
+/// a temporary path first so a failed write doesn't corrupt the existing file.
+///
+/// # Arguments
+///
+/// * `path` - Path of the context file
+/// * `data_points` - The data points to write, including removed ones
+/// * `removed` - Positions of the removed data points
+///
+/// # Returns
+///
+/// Result indicating success or failure
+pub fn write_data_points(path: &Path, data_points: &[DataPoint], removed: &HashSet<usize>) -> Result<()> {
+    let dimension = data_points.first().map_or(0, |point| point.vector.len());
+    if data_points.iter().any(|point| point.vector.len() != dimension) {
+        return Err(SemanticSearchError::InvalidArgument(
+            "All data points of a context must have the same vector dimension".to_string(),
+        ));
+    }
+
+    let temp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp_path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&(dimension as u32).to_le_bytes())?;
+    writer.write_all(&(data_points.len() as u64).to_le_bytes())?;
+    for point in data_points {
+        for value in point.vector.iter() {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    let mut removed = removed.iter().copied().collect::<Vec<_>>();
+    removed.sort_unstable();
+    let metadata = Metadata {
+        points: data_points
+            .iter()
+            .map(|point| PointMetadata {
+                id: point.id,
+                payload: Cow::Borrowed(&point.payload),
+            })
+            .collect(),
+        removed,
+    };
+    serde_json::to_writer(&mut writer, &metadata)?;
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    fs::rename(temp_path, path)?;
+    Ok(())
+}
+
+/// Read data points from a context file, in either the binary or the legacy JSON format
+///
+/// The vectors of binary files are served from a memory map of the file, which is kept alive by
+/// the data points.
+///
+/// # Arguments
+///
+/// * `path` - Path of the context file
+///
+/// # Returns
+///
+/// The data points and the positions of the removed ones
+pub fn read_data_points(path: &Path) -> Result<StoredDataPoints> {
+    let file = File::open(path)?;
+    // SAFETY: the context files are only written by this crate, which replaces them by renaming
+    // instead of modifying them in place, so the mapped contents never change.
+    let bytes = Arc::new(unsafe { Mmap::map(&file)? });
+
+    if !bytes.starts_with(MAGIC) {
+        return Ok(StoredDataPoints {
+            data_points: serde_json::from_slice(&bytes)?,
+            removed: HashSet::new(),
+            legacy: true,
+        });
+    }
+
+    let invalid = |msg: &str| SemanticSearchError::SerializationError(format!("{}: {}", msg, path.display()));
+    if bytes.len() < HEADER_LEN {
+        return Err(invalid("Truncated context file"));
+    }
+    let read_u32 =
+        |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+    let version = read_u32(4);
+    if version > FORMAT_VERSION {
+        return Err(SemanticSearchError::SerializationError(format!(
+            "Unsupported context format version {} (expected at most {}): {}",
+            version,
+            FORMAT_VERSION,
+            path.display()
+        )));
+    }
+    let dimension = read_u32(8) as usize;
+    let mut count = [0; 8];
+    count.copy_from_slice(&bytes[12..HEADER_LEN]);
+    let count = u64::from_le_bytes(count) as usize;
+
+    let vectors_end = count
+        .checked_mul(dimension)
+        .and_then(|len| len.checked_mul(4))
+        .and_then(|len| len.checked_add(HEADER_LEN))
+        .filter(|end| *end <= bytes.len())
+        .ok_or_else(|| invalid("Truncated context file"))?;
+    let metadata: Metadata<'static> = serde_json::from_slice(&bytes[vectors_end..])?;
+    if metadata.points.len() != count {
+        return Err(invalid("Mismatched number of data points in context file"));
+    }
+
+    let data_points = metadata
+        .points
+        .into_iter()
+        .enumerate()
+        .map(|(i, point)| {
+            let offset = HEADER_LEN + i * dimension * 4;
+            let vector = Vector::mapped(Arc::clone(&bytes), offset, dimension).unwrap_or_else(|| {
+                bytes[offset..offset + dimension * 4]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                    .collect::<Vec<_>>()
+                    .into()
+            });
+            DataPoint {
+                id: point.id,
+                payload: point.payload.into_owned(),
+                vector,
+            }
+        })
+        .collect();
+
+    Ok(StoredDataPoints {
+        data_points,
+        removed: metadata.removed.into_iter().filter(|i| *i < count).collect(),
+        legacy: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_mapped_vectors() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("data.bin");
+        let data_points = (0..3)
+            .map(|i| DataPoint {
+                id: i,
+                payload: HashMap::new(),
+                vector: vec![i as f32; 8].into(),
+            })
+            .collect::<Vec<_>>();
+        write_data_points(&path, &data_points, &HashSet::from([1])).unwrap();
+
+        let stored = read_data_points(&path).unwrap();
+        assert!(!stored.legacy);
+        assert_eq!(stored.removed, HashSet::from([1]));
+        for (i, point) in stored.data_points.iter().enumerate() {
+            assert_eq!(*point.vector, [i as f32; 8]);
+            assert_eq!(point.vector.is_mapped(), cfg!(target_endian = "little"));
+        }
+
+        // Replacing the file leaves the vectors mapped from the previous one untouched
+        write_data_points(&path, &[], &HashSet::new()).unwrap();
+        assert_eq!(*stored.data_points[2].vector, [2.0; 8]);
+    }
+}
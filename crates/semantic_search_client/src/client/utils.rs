@@ -4,10 +4,21 @@ use std::path::{
     PathBuf,
 };
 
+use chrono::{
+    DateTime,
+    Utc,
+};
+use sha2::{
+    Digest,
+    Sha256,
+};
 use uuid::Uuid;
 
 use crate::error::Result;
-use crate::types::ProgressStatus;
+use crate::types::{
+    FileState,
+    ProgressStatus,
+};
 
 /// Create a context directory based on persistence setting
 ///
@@ -88,6 +99,34 @@ where
     Ok(file_count)
 }
 
+/// Get the last modification time of a file
+///
+/// # Arguments
+///
+/// * `path` - Path to the file
+///
+/// # Returns
+///
+/// The last modification time
+pub fn file_modified(path: &Path) -> Result<DateTime<Utc>> {
+    Ok(fs::metadata(path)?.modified()?.into())
+}
+
+/// Get the current state of a file by hashing its content
+///
+/// # Arguments
+///
+/// * `path` - Path to the file
+///
+/// # Returns
+///
+/// The content hash and last modification time of the file
+pub fn file_state(path: &Path) -> Result<FileState> {
+    let modified = file_modified(path)?;
+    let hash = format!("{:x}", Sha256::digest(fs::read(path)?));
+    Ok(FileState { hash, modified })
+}
+
 /// Save JSON data to a file
 ///
 /// # Arguments
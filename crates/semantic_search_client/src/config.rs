@@ -16,6 +16,8 @@ use serde::{
     Serialize,
 };
 
+use crate::types::SearchMode;
+
 /// Main configuration structure for the semantic search client.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SemanticSearchConfig {
@@ -36,6 +38,10 @@ pub struct SemanticSearchConfig {
 
     /// Base directory for storing persistent contexts
     pub base_dir: PathBuf,
+
+    /// How searches match queries against contexts, unless specified per search
+    #[serde(default)]
+    pub search_mode: SearchMode,
 }
 
 impl Default for SemanticSearchConfig {
@@ -47,6 +53,7 @@ impl Default for SemanticSearchConfig {
             model_name: "all-MiniLM-L6-v2".to_string(),
             timeout: 30000, // 30 seconds
             base_dir: get_default_base_dir(),
+            search_mode: SearchMode::default(),
         }
     }
 }
@@ -282,6 +289,7 @@ mod tests {
             model_name: "different-model".to_string(),
             timeout: 30000,
             base_dir: temp_dir.path().to_path_buf(),
+            search_mode: Default::default(),
         };
 
         // Update the config
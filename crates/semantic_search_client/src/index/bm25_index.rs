@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    BufReader,
+    BufWriter,
+    Write,
+};
+use std::path::Path;
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+use crate::error::Result;
+
+/// Term frequency saturation parameter
+const K1: f32 = 1.2;
+
+/// Document length normalization parameter
+const B: f32 = 0.75;
+
+/// Inverted index for lexical search with Okapi BM25 scoring
+///
+/// Identifiers are indexed both whole and split into their words, so that `get_data_points`
+/// matches queries for `get_data_points` as well as for `data points`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Bm25Index {
+    /// The IDs of the documents containing each term, in increasing order, with the term frequency
+    postings: HashMap<String, Vec<(usize, u32)>>,
+    /// The number of terms of each document by ID, [None] for IDs that were skipped or removed
+    doc_lengths: Vec<Option<u32>>,
+    /// The number of terms of all documents
+    total_length: u64,
+    /// The number of documents
+    documents: usize,
+}
+
+impl Bm25Index {
+    /// Create a new empty BM25 index
+    ///
+    /// # Returns
+    ///
+    /// A new Bm25Index instance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a document into the index
+    ///
+    /// IDs must be inserted in increasing order, as the positions of the data points of a context.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID associated with the document
+    /// * `text` - The text of the document
+    pub fn insert(&mut self, id: usize, text: &str) {
+        let tokens = tokenize(text);
+
+        let mut frequencies = HashMap::<String, u32>::new();
+        for token in &tokens {
+            *frequencies.entry(token.clone()).or_default() += 1;
+        }
+        for (term, frequency) in frequencies {
+            self.postings.entry(term).or_default().push((id, frequency));
+        }
+
+        if self.doc_lengths.len() <= id {
+            self.doc_lengths.resize(id + 1, None);
+        }
+        self.doc_lengths[id] = Some(tokens.len() as u32);
+        self.total_length += tokens.len() as u64;
+        self.documents += 1;
+    }
+
+    /// Remove a document from the index, so that it no longer affects the scores of the others
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID associated with the document
+    /// * `text` - The text the document was inserted with
+    pub fn remove(&mut self, id: usize, text: &str) {
+        let Some(length) = self.doc_lengths.get_mut(id).and_then(Option::take) else {
+            return;
+        };
+        for term in query_terms(text) {
+            let Some(postings) = self.postings.get_mut(&term) else {
+                continue;
+            };
+            if let Ok(i) = postings.binary_search_by_key(&id, |(id, _)| *id) {
+                postings.remove(i);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        self.total_length -= length as u64;
+        self.documents -= 1;
+    }
+
+    /// Load an index previously saved with [Bm25Index::save]
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory containing the index file
+    /// * `basename` - Base name of the index file
+    ///
+    /// # Returns
+    ///
+    /// The loaded Bm25Index instance
+    pub fn load(dir: &Path, basename: &str) -> Result<Self> {
+        let file = File::open(Self::path(dir, basename))?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    /// Save the index to `{basename}.bm25`
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory to save the index file to
+    /// * `basename` - Base name of the index file
+    ///
+    /// # Returns
+    ///
+    /// Result indicating success or failure
+    pub fn save(&self, dir: &Path, basename: &str) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(Self::path(dir, basename))?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Remove the file of a saved index, if any
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory containing the index file
+    /// * `basename` - Base name of the index file
+    ///
+    /// # Returns
+    ///
+    /// Result indicating success or failure
+    pub fn remove_files(dir: &Path, basename: &str) -> Result<()> {
+        let path = Self::path(dir, basename);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn path(dir: &Path, basename: &str) -> std::path::PathBuf {
+        dir.join(format!("{}.bm25", basename))
+    }
+
+    /// Search for the documents that best match a query
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query text
+    /// * `limit` - Maximum number of results to return
+    /// * `filter` - Returns `false` for the IDs to skip
+    ///
+    /// # Returns
+    ///
+    /// A vector of (id, score) pairs, best match first
+    pub fn search(&self, query: &str, limit: usize, filter: impl Fn(usize) -> bool) -> Vec<(usize, f32)> {
+        let mut scores = HashMap::<usize, f32>::new();
+        for term in query_terms(query) {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+            let idf = self.idf(postings.len());
+            for (id, frequency) in postings {
+                if filter(*id) {
+                    *scores.entry(*id).or_default() += idf * self.term_score(*id, *frequency);
+                }
+            }
+        }
+
+        let mut results = scores.into_iter().collect::<Vec<_>>();
+        results.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(a_id.cmp(b_id)));
+        results.truncate(limit);
+        results
+    }
+
+    /// Score a single document against a query
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the document
+    /// * `query` - The query text
+    ///
+    /// # Returns
+    ///
+    /// The BM25 score of the document, zero if it doesn't contain any query term
+    pub fn score(&self, id: usize, query: &str) -> f32 {
+        query_terms(query)
+            .into_iter()
+            .filter_map(|term| {
+                let postings = self.postings.get(&term)?;
+                let i = postings.binary_search_by_key(&id, |(id, _)| *id).ok()?;
+                Some(self.idf(postings.len()) * self.term_score(id, postings[i].1))
+            })
+            .sum()
+    }
+
+    /// Get the number of documents in the index
+    ///
+    /// # Returns
+    ///
+    /// The number of documents in the index
+    pub fn len(&self) -> usize {
+        self.documents
+    }
+
+    /// Check if the index is empty
+    ///
+    /// # Returns
+    ///
+    /// `true` if the index is empty, `false` otherwise
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn idf(&self, document_frequency: usize) -> f32 {
+        let documents = self.len() as f32;
+        let document_frequency = document_frequency as f32;
+        ((documents - document_frequency + 0.5) / (document_frequency + 0.5)).ln_1p()
+    }
+
+    fn term_score(&self, id: usize, frequency: u32) -> f32 {
+        let average_length = (self.total_length as f32 / self.len().max(1) as f32).max(1.0);
+        let length_ratio = self.doc_lengths[id].unwrap_or_default() as f32 / average_length;
+        let frequency = frequency as f32;
+        frequency * (K1 + 1.0) / (frequency + K1 * (1.0 - B + B * length_ratio))
+    }
+}
+
+/// The distinct terms of a query
+fn query_terms(query: &str) -> Vec<String> {
+    let mut terms = tokenize(query);
+    terms.sort_unstable();
+    terms.dedup();
+    terms
+}
+
+/// Split text into lowercase terms, adding the words of `snake_case` and `camelCase` identifiers
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in text
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+    {
+        let parts = identifier_words(word);
+        if parts.len() > 1 {
+            tokens.extend(parts);
+        }
+        let word = word.trim_matches('_');
+        if !word.is_empty() {
+            tokens.push(word.to_lowercase());
+        }
+    }
+    tokens
+}
+
+/// Split an identifier into its lowercase words, e.g. `parseHTTPResponse` into `parse`, `http`
+/// and `response`
+fn identifier_words(identifier: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    for segment in identifier.split('_').filter(|segment| !segment.is_empty()) {
+        let chars = segment.chars().collect::<Vec<_>>();
+        let mut start = 0;
+        for i in 1..chars.len() {
+            let boundary = chars[i].is_uppercase()
+                && (chars[i - 1].is_lowercase()
+                    || (chars[i - 1].is_uppercase() && chars.get(i + 1).is_some_and(|c| c.is_lowercase())));
+            if boundary {
+                words.push(chars[start..i].iter().collect::<String>().to_lowercase());
+                start = i;
+            }
+        }
+        words.push(chars[start..].iter().collect::<String>().to_lowercase());
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_identifiers() {
+        assert_eq!(identifier_words("parseHTTPResponse"), vec!["parse", "http", "response"]);
+        assert_eq!(identifier_words("get_data_points"), vec!["get", "data", "points"]);
+        assert_eq!(tokenize("fn get_data_points()"), vec![
+            "fn",
+            "get",
+            "data",
+            "points",
+            "get_data_points"
+        ]);
+        assert_eq!(tokenize("Hello, world!"), vec!["hello", "world"]);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut index = Bm25Index::new();
+        index.insert(0, "The quick brown fox jumps over the lazy dog");
+        index.insert(1, "fn rebuild_index(&mut self) -> Result<()>");
+        index.insert(2, "Rebuilding the index from scratch is slow");
+
+        // Exact identifiers rank first
+        let results = index.search("rebuild_index", 10, |_| true);
+        assert_eq!(results[0].0, 1);
+        assert_eq!(results.len(), 2);
+        assert_eq!(index.score(1, "rebuild_index"), results[0].1);
+
+        // Filtered documents are skipped
+        let results = index.search("rebuild_index", 10, |id| id != 1);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, 2);
+
+        assert!(index.search("missing", 10, |_| true).is_empty());
+        assert_eq!(index.score(0, "missing"), 0.0);
+    }
+
+    #[test]
+    fn test_removed_documents_do_not_affect_scores() {
+        let mut index = Bm25Index::new();
+        index.insert(0, "fn rebuild_index(&mut self)");
+        index.insert(2, "Rebuilding the index from scratch is slow");
+
+        let mut with_removed = Bm25Index::new();
+        with_removed.insert(0, "fn rebuild_index(&mut self)");
+        with_removed.insert(1, "index index index of an index");
+        with_removed.insert(2, "Rebuilding the index from scratch is slow");
+        with_removed.remove(1, "index index index of an index");
+
+        assert_eq!(with_removed.len(), 2);
+        assert_eq!(
+            with_removed.search("index", 10, |_| true),
+            index.search("index", 10, |_| true)
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut index = Bm25Index::new();
+        index.insert(0, "fn rebuild_index(&mut self)");
+        index.insert(1, "Rebuilding the index from scratch is slow");
+        index.save(temp_dir.path(), "index").unwrap();
+
+        let loaded = Bm25Index::load(temp_dir.path(), "index").unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(
+            loaded.search("rebuild_index", 10, |_| true),
+            index.search("rebuild_index", 10, |_| true)
+        );
+
+        Bm25Index::remove_files(temp_dir.path(), "index").unwrap();
+        assert!(Bm25Index::load(temp_dir.path(), "index").is_err());
+    }
+}
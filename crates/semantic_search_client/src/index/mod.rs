@@ -1,3 +1,5 @@
+mod bm25_index;
 mod vector_index;
 
+pub use bm25_index::Bm25Index;
 pub use vector_index::VectorIndex;
@@ -1,19 +1,65 @@
+use std::fs;
+use std::path::Path;
+use std::ptr::NonNull;
+
+use hnsw_rs::api::AnnT;
 use hnsw_rs::hnsw::Hnsw;
+use hnsw_rs::hnswio::{
+    HnswIo,
+    ReloadOptions,
+};
 use hnsw_rs::prelude::DistCosine;
 use tracing::{
     debug,
     info,
 };
 
+use crate::error::{
+    Result,
+    SemanticSearchError,
+};
+
 /// Vector index for fast approximate nearest neighbor search
 pub struct VectorIndex {
     /// The HNSW index
+    ///
+    /// Declared before `_loader` so that it's dropped first, since a reloaded graph borrows its
+    /// vectors from the loader.
     index: Hnsw<'static, f32, DistCosine>,
+    /// The loader the index was reloaded with, if any
+    _loader: Option<IndexLoader>,
     /// Counter to track the number of elements
     count: std::sync::atomic::AtomicUsize,
 }
 
+/// Heap allocated [HnswIo] that a reloaded graph borrows its memory-mapped vectors from
+///
+/// The allocation is owned through a pointer rather than a `Box` so that moving the owner doesn't
+/// invalidate the references held by the graph.
+struct IndexLoader(NonNull<HnswIo>);
+
+impl IndexLoader {
+    fn new(loader: HnswIo) -> Self {
+        Self(NonNull::from(Box::leak(Box::new(loader))))
+    }
+}
+
+impl Drop for IndexLoader {
+    fn drop(&mut self) {
+        // SAFETY: the pointer was created from a `Box` in `IndexLoader::new` and is only freed here
+        drop(unsafe { Box::from_raw(self.0.as_ptr()) });
+    }
+}
+
+// SAFETY: the loader is only accessed while loading the graph, and `HnswIo` is itself `Send` and
+// `Sync`
+unsafe impl Send for IndexLoader {}
+unsafe impl Sync for IndexLoader {}
+
 impl VectorIndex {
+    /// Extensions of the files of a saved index
+    const EXTENSIONS: [&str; 2] = ["hnsw.graph", "hnsw.data"];
+
     /// Create a new empty vector index
     ///
     /// # Arguments
@@ -37,10 +83,103 @@ impl VectorIndex {
         debug!("Vector index created successfully");
         Self {
             index,
+            _loader: None,
             count: std::sync::atomic::AtomicUsize::new(0),
         }
     }
 
+    /// Load an index previously saved with [VectorIndex::save]
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory containing the index files
+    /// * `basename` - Base name of the index files
+    ///
+    /// # Returns
+    ///
+    /// The loaded VectorIndex instance
+    pub fn load(dir: &Path, basename: &str) -> Result<Self> {
+        info!("Loading vector index {} from {}", basename, dir.display());
+
+        // The vectors are memory-mapped instead of being copied to the heap, so the graph borrows
+        // them from the loader, which is kept alive next to it.
+        let loader = IndexLoader::new(HnswIo::new_with_options(dir, basename, ReloadOptions::new(true)));
+        // SAFETY: the loader is never moved or accessed again, and is only freed after the index is
+        // dropped.
+        let io: &'static mut HnswIo = unsafe { &mut *loader.0.as_ptr() };
+        let index = io
+            .load_hnsw::<f32, DistCosine>()
+            .map_err(|e| SemanticSearchError::OperationFailed(format!("Failed to load vector index: {}", e)))?;
+
+        let count = index.get_nb_point();
+        debug!("Vector index loaded with {} elements", count);
+        Ok(Self {
+            index,
+            _loader: Some(loader),
+            count: std::sync::atomic::AtomicUsize::new(count),
+        })
+    }
+
+    /// Save the index graph and vectors to `{basename}.hnsw.graph` and `{basename}.hnsw.data`
+    ///
+    /// The files are written under a temporary name and then renamed over the existing ones, which
+    /// may still be memory-mapped by a reloaded index.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory to save the index files to
+    /// * `basename` - Base name of the index files
+    ///
+    /// # Returns
+    ///
+    /// Result indicating success or failure
+    pub fn save(&self, dir: &Path, basename: &str) -> Result<()> {
+        // Reloaded graphs never overwrite existing files, and save under a random name instead
+        let temp_basename = format!("{}.tmp", basename);
+        Self::remove_files(dir, &temp_basename)?;
+
+        let saved_basename = self
+            .index
+            .file_dump(dir, &temp_basename)
+            .map_err(|e| SemanticSearchError::OperationFailed(format!("Failed to save vector index: {}", e)))?;
+        if saved_basename != temp_basename {
+            Self::remove_files(dir, &saved_basename)?;
+            return Err(SemanticSearchError::OperationFailed(format!(
+                "Vector index was saved as {} instead of {}",
+                saved_basename, temp_basename
+            )));
+        }
+
+        for extension in Self::EXTENSIONS {
+            fs::rename(
+                dir.join(format!("{}.{}", temp_basename, extension)),
+                dir.join(format!("{}.{}", basename, extension)),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the files of a saved index, if any
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Directory containing the index files
+    /// * `basename` - Base name of the index files
+    ///
+    /// # Returns
+    ///
+    /// Result indicating success or failure
+    pub fn remove_files(dir: &Path, basename: &str) -> Result<()> {
+        for extension in Self::EXTENSIONS {
+            let path = dir.join(format!("{}.{}", basename, extension));
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Insert a vector into the index
     ///
     /// # Arguments
@@ -72,6 +211,44 @@ impl VectorIndex {
             .collect()
     }
 
+    /// Search for the nearest neighbors matching a filter
+    ///
+    /// The filter is applied while traversing the graph, which keeps exploring until it finds
+    /// `ef_search` matching neighbors, so filtering out most of the index still returns up to
+    /// `limit` results.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query vector
+    /// * `limit` - Maximum number of results to return
+    /// * `ef_search` - Size of the dynamic candidate list for search
+    /// * `filter` - Returns `false` for the IDs to skip
+    ///
+    /// # Returns
+    ///
+    /// A vector of (id, distance) pairs
+    pub fn search_filtered(
+        &self,
+        query: &[f32],
+        limit: usize,
+        ef_search: usize,
+        filter: impl Fn(usize) -> bool,
+    ) -> Vec<(usize, f32)> {
+        let hnsw_filter = |id: &usize| filter(*id);
+        // The entry point of the graph can be returned without being filtered, so fetch one extra
+        // result in case it has to be skipped
+        let results = self
+            .index
+            .search_filter(query, limit + 1, ef_search, Some(&hnsw_filter));
+
+        results
+            .into_iter()
+            .filter(|neighbor| filter(neighbor.d_id))
+            .take(limit)
+            .map(|neighbor| (neighbor.d_id, neighbor.distance))
+            .collect()
+    }
+
     /// Get the number of elements in the index
     ///
     /// # Returns
@@ -30,8 +30,15 @@ pub use error::{
 };
 pub use types::{
     DataPoint,
+    FileState,
     FileType,
+    FusionMethod,
     MemoryContext,
+    PayloadPredicate,
     ProgressStatus,
+    RefreshSummary,
+    SearchFilter,
+    SearchMode,
     SearchResult,
+    Vector,
 };
@@ -0,0 +1,308 @@
+use tree_sitter::{
+    Language,
+    Node,
+    Parser,
+};
+
+use crate::config;
+
+/// A chunk of source code split along the structure of the language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeChunk {
+    /// The source text of the chunk
+    pub text: String,
+    /// Qualified name of the symbol the chunk belongs to, e.g. `Parser::parse`
+    pub symbol: Option<String>,
+    /// Kind of the symbol, e.g. `function` or `class`, or `code` for code outside any definition
+    pub kind: &'static str,
+    /// First line of the chunk, starting at 1
+    pub start_line: usize,
+    /// Last line of the chunk, inclusive
+    pub end_line: usize,
+}
+
+/// How to find the definitions of a language
+struct Grammar {
+    language: Language,
+    /// Node kinds of the definitions, with the kind of symbol they define
+    definitions: &'static [(&'static str, &'static str)],
+    /// Separator between the names of nested symbols
+    separator: &'static str,
+}
+
+const RUST_DEFINITIONS: &[(&str, &str)] = &[
+    ("function_item", "function"),
+    ("function_signature_item", "function"),
+    ("impl_item", "impl"),
+    ("struct_item", "struct"),
+    ("enum_item", "enum"),
+    ("union_item", "union"),
+    ("trait_item", "trait"),
+    ("mod_item", "module"),
+    ("macro_definition", "macro"),
+];
+
+const PYTHON_DEFINITIONS: &[(&str, &str)] = &[("function_definition", "function"), ("class_definition", "class")];
+
+const TYPESCRIPT_DEFINITIONS: &[(&str, &str)] = &[
+    ("function_declaration", "function"),
+    ("generator_function_declaration", "function"),
+    ("class_declaration", "class"),
+    ("abstract_class_declaration", "class"),
+    ("interface_declaration", "interface"),
+    ("enum_declaration", "enum"),
+    ("type_alias_declaration", "type"),
+    ("method_definition", "method"),
+    ("internal_module", "namespace"),
+    ("module", "module"),
+];
+
+const GO_DEFINITIONS: &[(&str, &str)] = &[
+    ("function_declaration", "function"),
+    ("method_declaration", "method"),
+    ("type_declaration", "type"),
+];
+
+const JAVA_DEFINITIONS: &[(&str, &str)] = &[
+    ("class_declaration", "class"),
+    ("interface_declaration", "interface"),
+    ("enum_declaration", "enum"),
+    ("record_declaration", "record"),
+    ("annotation_type_declaration", "annotation"),
+    ("method_declaration", "method"),
+    ("constructor_declaration", "constructor"),
+];
+
+/// Get the grammar for a file extension
+fn grammar(extension: &str) -> Option<Grammar> {
+    let (language, definitions, separator) = match extension {
+        "rs" => (tree_sitter_rust::LANGUAGE.into(), RUST_DEFINITIONS, "::"),
+        "py" => (tree_sitter_python::LANGUAGE.into(), PYTHON_DEFINITIONS, "."),
+        "ts" => (
+            tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            TYPESCRIPT_DEFINITIONS,
+            ".",
+        ),
+        // The TSX grammar also parses JavaScript
+        "tsx" | "js" | "jsx" => (tree_sitter_typescript::LANGUAGE_TSX.into(), TYPESCRIPT_DEFINITIONS, "."),
+        "go" => (tree_sitter_go::LANGUAGE.into(), GO_DEFINITIONS, "."),
+        "java" => (tree_sitter_java::LANGUAGE.into(), JAVA_DEFINITIONS, "."),
+        _ => return None,
+    };
+    Some(Grammar {
+        language,
+        definitions,
+        separator,
+    })
+}
+
+/// Chunk source code along the definitions of its language
+///
+/// Each definition (function, impl block, class, ...) becomes a chunk along with the comments and
+/// attributes right before it, and the code between definitions is grouped into `code` chunks.
+/// Definitions longer than the chunk size are split into their nested definitions, or into
+/// groups of lines when they don't have any.
+///
+/// # Arguments
+///
+/// * `text` - The source code to chunk
+/// * `extension` - The file extension, which determines the language
+/// * `chunk_size` - Optional maximum number of words per chunk (if None, uses config value)
+///
+/// # Returns
+///
+/// The chunks, or `None` if the language isn't supported or the code couldn't be parsed
+pub fn chunk_code(text: &str, extension: &str, chunk_size: Option<usize>) -> Option<Vec<CodeChunk>> {
+    let grammar = grammar(extension)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar.language).ok()?;
+    let tree = parser.parse(text, None)?;
+
+    let mut chunker = Chunker {
+        source: text,
+        grammar: &grammar,
+        chunk_size: chunk_size.unwrap_or_else(|| config::get_config().chunk_size).max(1),
+        line_starts: std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect(),
+        chunks: Vec::new(),
+    };
+    chunker.chunk_children(tree.root_node(), 0, text.len(), &Scope {
+        symbol: None,
+        kind: "code",
+    });
+
+    Some(chunker.chunks)
+}
+
+/// The definition enclosing the code being chunked
+struct Scope {
+    symbol: Option<String>,
+    kind: &'static str,
+}
+
+struct Chunker<'a> {
+    source: &'a str,
+    grammar: &'a Grammar,
+    chunk_size: usize,
+    /// Byte offset of the start of each line
+    line_starts: Vec<usize>,
+    chunks: Vec<CodeChunk>,
+}
+
+impl Chunker<'_> {
+    /// Chunk the byte range of a node, made of its children and the code around them
+    fn chunk_children(&mut self, parent: Node<'_>, start: usize, end: usize, scope: &Scope) {
+        let mut cursor = parent.walk();
+        let children = parent.named_children(&mut cursor).collect::<Vec<_>>();
+
+        let mut code_start = start;
+        for (i, child) in children.iter().enumerate() {
+            let Some(kind) = self.definition_kind(*child) else {
+                continue;
+            };
+
+            // Keep the comments and attributes of the definition with it
+            let mut first = i;
+            while first > 0 && is_attached(children[first - 1]) && children[first - 1].start_byte() >= code_start {
+                first -= 1;
+            }
+            let definition_start = children[first].start_byte();
+
+            self.push_lines(code_start, definition_start, scope.symbol.clone(), scope.kind);
+            self.chunk_definition(*child, definition_start, kind, scope);
+            code_start = child.end_byte();
+        }
+        self.push_lines(code_start, end, scope.symbol.clone(), scope.kind);
+    }
+
+    /// Chunk a definition, starting at its first comment or attribute
+    fn chunk_definition(&mut self, node: Node<'_>, start: usize, kind: &'static str, scope: &Scope) {
+        let definition = unwrap_definition(node);
+        let symbol = match (&scope.symbol, self.symbol_name(definition)) {
+            (Some(scope), Some(name)) => Some(format!("{}{}{}", scope, self.grammar.separator, name)),
+            (scope, name) => name.or_else(|| scope.clone()),
+        };
+        let end = node.end_byte();
+
+        if word_count(&self.source[start..end]) <= self.chunk_size {
+            self.push_chunk(start, end, symbol, kind);
+        } else if let Some(body) = definition
+            .child_by_field_name("body")
+            .filter(|body| body.named_child_count() > 0)
+        {
+            self.chunk_children(body, start, end, &Scope { symbol, kind });
+        } else {
+            self.push_lines(start, end, symbol, kind);
+        }
+    }
+
+    /// Get the kind of symbol defined by a node, if it's a definition
+    fn definition_kind(&self, node: Node<'_>) -> Option<&'static str> {
+        let node = unwrap_definition(node);
+        self.grammar
+            .definitions
+            .iter()
+            .find(|(node_kind, _)| *node_kind == node.kind())
+            .map(|(_, kind)| *kind)
+    }
+
+    /// Get the name of the symbol defined by a node
+    fn symbol_name(&self, node: Node<'_>) -> Option<String> {
+        let text = |node: Node<'_>| self.source[node.byte_range()].to_string();
+        match node.kind() {
+            // Name impl blocks after their type, without its generic parameters
+            "impl_item" => {
+                let ty = node.child_by_field_name("type")?;
+                let ty = match ty.kind() {
+                    "generic_type" => ty.child_by_field_name("type").unwrap_or(ty),
+                    _ => ty,
+                };
+                Some(text(ty))
+            },
+            "type_declaration" => {
+                let mut cursor = node.walk();
+                let spec = node
+                    .named_children(&mut cursor)
+                    .find(|child| child.kind() == "type_spec")?;
+                Some(text(spec.child_by_field_name("name")?))
+            },
+            _ => {
+                let name = text(node.child_by_field_name("name")?);
+                // Qualify Go methods with the type of their receiver
+                let receiver = node
+                    .child_by_field_name("receiver")
+                    .and_then(|receiver| receiver.named_child(0))
+                    .and_then(|parameter| parameter.child_by_field_name("type"))
+                    .map(text);
+                match receiver {
+                    Some(receiver) => {
+                        let receiver = receiver.trim_start_matches('*');
+                        let receiver = receiver.split('[').next().unwrap_or(receiver);
+                        Some(format!("{}.{}", receiver, name))
+                    },
+                    None => Some(name),
+                }
+            },
+        }
+    }
+
+    /// Push the code of a byte range as chunks of whole lines of at most `chunk_size` words
+    fn push_lines(&mut self, start: usize, end: usize, symbol: Option<String>, kind: &'static str) {
+        let mut chunk_start = start;
+        let mut chunk_words = 0;
+        let mut line_start = start;
+        for line in self.source[start..end].split_inclusive('\n') {
+            let words = word_count(line);
+            if chunk_words > 0 && chunk_words + words > self.chunk_size {
+                self.push_chunk(chunk_start, line_start, symbol.clone(), kind);
+                chunk_start = line_start;
+                chunk_words = 0;
+            }
+            chunk_words += words;
+            line_start += line.len();
+        }
+        self.push_chunk(chunk_start, end, symbol, kind);
+    }
+
+    /// Push the code of a byte range as a chunk, unless it's blank
+    fn push_chunk(&mut self, start: usize, end: usize, symbol: Option<String>, kind: &'static str) {
+        let untrimmed = &self.source[start..end];
+        let text = untrimmed.trim();
+        if text.is_empty() {
+            return;
+        }
+        let start = start + untrimmed.len() - untrimmed.trim_start().len();
+        self.chunks.push(CodeChunk {
+            text: text.to_string(),
+            symbol,
+            kind,
+            start_line: self.line(start),
+            end_line: self.line(start + text.len() - 1),
+        });
+    }
+
+    /// Get the line of a byte offset, starting at 1
+    fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|start| *start <= offset)
+    }
+}
+
+/// Get the definition wrapped by an export statement or decorators
+fn unwrap_definition(node: Node<'_>) -> Node<'_> {
+    let field = match node.kind() {
+        "decorated_definition" => "definition",
+        "export_statement" => "declaration",
+        _ => return node,
+    };
+    node.child_by_field_name(field).unwrap_or(node)
+}
+
+/// Check if a node is a comment or attribute that belongs to the definition after it
+fn is_attached(node: Node<'_>) -> bool {
+    node.kind().contains("comment") || node.kind() == "attribute_item"
+}
+
+fn word_count(text: &str) -> usize {
+    text.split_whitespace().count()
+}
@@ -7,6 +7,7 @@ use crate::error::{
     Result,
     SemanticSearchError,
 };
+use crate::processing::code_chunker::chunk_code;
 use crate::processing::text_chunker::chunk_text;
 use crate::types::FileType;
 
@@ -67,8 +68,17 @@ pub fn process_file(path: &Path) -> Result<Vec<Value>> {
     match file_type {
         FileType::Text | FileType::Markdown | FileType::Code => {
             // For text-based files, chunk the content and create multiple data points
-            // Use the configured chunk size and overlap
-            let chunks = chunk_text(&content, None, None);
+            // Code is chunked along its definitions when the language is supported, otherwise use
+            // the configured chunk size and overlap
+            let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+            let code_chunks = match file_type {
+                FileType::Code => chunk_code(&content, extension, None),
+                _ => None,
+            };
+            let chunks = match &code_chunks {
+                Some(code_chunks) => code_chunks.iter().map(|chunk| chunk.text.clone()).collect(),
+                None => chunk_text(&content, None, None),
+            };
             let path_str = path.to_string_lossy().to_string();
             let file_type_str = format!("{:?}", file_type);
 
@@ -95,6 +105,16 @@ pub fn process_file(path: &Path) -> Result<Vec<Value>> {
                     );
                 }
 
+                // For code chunked along its definitions, add the symbol and line range
+                if let Some(code_chunk) = code_chunks.as_ref().map(|code_chunks| &code_chunks[i]) {
+                    if let Some(symbol) = &code_chunk.symbol {
+                        metadata.insert("symbol".to_string(), Value::String(symbol.clone()));
+                    }
+                    metadata.insert("kind".to_string(), Value::String(code_chunk.kind.to_string()));
+                    metadata.insert("start_line".to_string(), Value::Number(code_chunk.start_line.into()));
+                    metadata.insert("end_line".to_string(), Value::Number(code_chunk.end_line.into()));
+                }
+
                 results.push(Value::Object(metadata));
             }
 
@@ -1,8 +1,14 @@
+/// Syntax-aware chunking of source code along functions, classes and other definitions
+pub mod code_chunker;
 /// File processing utilities for handling different file types and extracting content
 pub mod file_processor;
 /// Text chunking utilities for breaking down text into manageable pieces for embedding
 pub mod text_chunker;
 
+pub use code_chunker::{
+    CodeChunk,
+    chunk_code,
+};
 pub use file_processor::{
     get_file_type,
     process_directory,
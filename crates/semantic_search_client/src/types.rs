@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
 use std::sync::{
     Arc,
     Mutex,
@@ -8,9 +10,12 @@ use chrono::{
     DateTime,
     Utc,
 };
+use memmap2::Mmap;
 use serde::{
     Deserialize,
+    Deserializer,
     Serialize,
+    Serializer,
 };
 
 use crate::client::SemanticContext;
@@ -50,6 +55,10 @@ pub struct MemoryContext {
 
     /// Number of items in the context
     pub item_count: usize,
+
+    /// State of every indexed file by path, used to only re-index changed files when refreshing
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub files: HashMap<String, FileState>,
 }
 
 impl MemoryContext {
@@ -72,10 +81,37 @@ impl MemoryContext {
             source_path,
             persistent,
             item_count,
+            files: HashMap::new(),
         }
     }
 }
 
+/// The state of an indexed file when it was last processed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileState {
+    /// SHA-256 hash of the file content, as a hex string
+    pub hash: String,
+
+    /// Last modification time of the file
+    pub modified: DateTime<Utc>,
+}
+
+/// The outcome of refreshing a context
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RefreshSummary {
+    /// Number of files indexed for the first time
+    pub added: usize,
+
+    /// Number of files re-indexed because their content changed
+    pub updated: usize,
+
+    /// Number of files removed from the context
+    pub removed: usize,
+
+    /// Number of files left untouched
+    pub unchanged: usize,
+}
+
 /// A data point in the semantic index
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DataPoint {
@@ -86,7 +122,79 @@ pub struct DataPoint {
     pub payload: HashMap<String, serde_json::Value>,
 
     /// Vector representation of the data point
-    pub vector: Vec<f32>,
+    pub vector: Vector,
+}
+
+/// The embedding vector of a [DataPoint]
+///
+/// The vectors of data points loaded from a context file are served from a memory map of the
+/// file instead of being copied to the heap.
+#[derive(Clone)]
+pub struct Vector(VectorData);
+
+#[derive(Clone)]
+enum VectorData {
+    Owned(Vec<f32>),
+    Mapped { map: Arc<Mmap>, offset: usize, len: usize },
+}
+
+impl Vector {
+    /// Serve a vector of `len` values from a memory map, starting at byte `offset`
+    ///
+    /// Returns [None] if the values are out of bounds, aren't aligned, or aren't in the byte order
+    /// of the platform, in which case they have to be copied instead.
+    pub(crate) fn mapped(map: Arc<Mmap>, offset: usize, len: usize) -> Option<Self> {
+        let bytes = map.get(offset..offset.checked_add(len.checked_mul(size_of::<f32>())?)?)?;
+        if cfg!(target_endian = "big") || bytes.as_ptr().align_offset(align_of::<f32>()) != 0 {
+            return None;
+        }
+        Some(Self(VectorData::Mapped { map, offset, len }))
+    }
+
+    /// Check if the vector is served from a memory map
+    #[cfg(test)]
+    pub(crate) fn is_mapped(&self) -> bool {
+        matches!(self.0, VectorData::Mapped { .. })
+    }
+}
+
+impl Deref for Vector {
+    type Target = [f32];
+
+    fn deref(&self) -> &[f32] {
+        match &self.0 {
+            VectorData::Owned(vector) => vector,
+            // SAFETY: the bounds, alignment and byte order of the values were checked by
+            // `Vector::mapped`, and every bit pattern is a valid f32
+            VectorData::Mapped { map, offset, len } => unsafe {
+                std::slice::from_raw_parts(map[*offset..].as_ptr().cast::<f32>(), *len)
+            },
+        }
+    }
+}
+
+impl From<Vec<f32>> for Vector {
+    fn from(vector: Vec<f32>) -> Self {
+        Self(VectorData::Owned(vector))
+    }
+}
+
+impl fmt::Debug for Vector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for Vector {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.deref().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Vector {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<f32>::deserialize(deserializer).map(Self::from)
+    }
 }
 
 /// A search result from the semantic index
@@ -96,13 +204,28 @@ pub struct SearchResult {
     pub point: DataPoint,
 
     /// Distance/similarity score (lower is better)
+    ///
+    /// For hybrid searches, this is one minus the fused score normalized to `[0, 1]`.
     pub distance: f32,
+
+    /// Cosine distance between the query and the data point embeddings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dense_distance: Option<f32>,
+
+    /// BM25 score of the data point text for the query, only set by hybrid searches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lexical_score: Option<f32>,
 }
 
 impl SearchResult {
     /// Create a new search result
     pub fn new(point: DataPoint, distance: f32) -> Self {
-        Self { point, distance }
+        Self {
+            point,
+            distance,
+            dense_distance: Some(distance),
+            lexical_score: None,
+        }
     }
 
     /// Get the text content of this result
@@ -111,6 +234,90 @@ impl SearchResult {
     }
 }
 
+/// How a query is matched against the data points of a context
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchMode {
+    /// Nearest neighbors of the query embedding
+    #[default]
+    Dense,
+    /// Nearest neighbors of the query embedding merged with the BM25 matches of the query text,
+    /// which works better for queries containing identifiers
+    Hybrid(FusionMethod),
+}
+
+/// How the dense and lexical results of a hybrid search are merged
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FusionMethod {
+    /// Reciprocal rank fusion, scoring each result `1 / (k + rank)` in every ranking it appears in
+    ReciprocalRank {
+        /// Smoothing constant, higher values reduce the advantage of the top ranks
+        k: f32,
+    },
+    /// Weighted sum of the dense similarity and of the BM25 score normalized by the best one
+    Weighted {
+        /// Weight of the dense similarity between 0 and 1, the BM25 score gets the rest
+        dense_weight: f32,
+    },
+}
+
+impl Default for FusionMethod {
+    fn default() -> Self {
+        Self::ReciprocalRank { k: 60.0 }
+    }
+}
+
+/// Restricts the data points a search can return
+///
+/// Path patterns are globs such as `src/**/*.rs`, matched against the path of each data point
+/// relative to the source directory of its context, or against its full path. Only persistent
+/// contexts keep their source directory, so patterns starting with `**/` are needed to match the
+/// paths of volatile ones. The default filter matches every data point.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilter {
+    /// Patterns of which the path of results must match at least one, any path if empty
+    pub include_paths: Vec<String>,
+    /// Patterns the path of results must not match
+    pub exclude_paths: Vec<String>,
+    /// File types of results, any file type if empty
+    pub file_types: Vec<FileType>,
+    /// Predicates the payload of results must all satisfy
+    pub payload: Vec<PayloadPredicate>,
+}
+
+impl SearchFilter {
+    /// Check if the filter matches every data point
+    pub fn is_empty(&self) -> bool {
+        self.include_paths.is_empty()
+            && self.exclude_paths.is_empty()
+            && self.file_types.is_empty()
+            && self.payload.is_empty()
+    }
+}
+
+/// Predicate on a payload value of a data point
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadPredicate {
+    /// The payload has the key
+    Exists(String),
+    /// The payload has the key with the value
+    Equals(String, serde_json::Value),
+    /// The payload doesn't have the key, or with another value
+    NotEquals(String, serde_json::Value),
+}
+
+impl PayloadPredicate {
+    /// Check if a payload satisfies the predicate
+    pub fn matches(&self, payload: &HashMap<String, serde_json::Value>) -> bool {
+        match self {
+            Self::Exists(key) => payload.contains_key(key),
+            Self::Equals(key, value) => payload.get(key) == Some(value),
+            Self::NotEquals(key, value) => payload.get(key) != Some(value),
+        }
+    }
+}
+
 /// File type for processing
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
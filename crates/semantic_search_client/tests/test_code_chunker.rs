@@ -0,0 +1,191 @@
+use semantic_search_client::processing::code_chunker::{
+    CodeChunk,
+    chunk_code,
+};
+
+/// The symbol, kind and line range of each chunk
+fn outline(chunks: &[CodeChunk]) -> Vec<(Option<&str>, &str, usize, usize)> {
+    chunks
+        .iter()
+        .map(|chunk| (chunk.symbol.as_deref(), chunk.kind, chunk.start_line, chunk.end_line))
+        .collect()
+}
+
+#[test]
+fn test_chunk_rust() {
+    let code = r#"use std::fmt;
+
+/// A point in the plane
+#[derive(Debug)]
+pub struct Point {
+    x: f32,
+    y: f32,
+}
+
+impl Point {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+}
+
+fn main() {
+    println!("{:?}", Point::new(1.0, 2.0));
+}
+"#;
+    let chunks = chunk_code(code, "rs", Some(100)).unwrap();
+    assert_eq!(outline(&chunks), vec![
+        (None, "code", 1, 1),
+        (Some("Point"), "struct", 3, 8),
+        (Some("Point"), "impl", 10, 14),
+        (Some("main"), "function", 16, 18),
+    ]);
+    assert!(chunks[1].text.starts_with("/// A point in the plane\n#[derive(Debug)]"));
+    assert!(chunks[3].text.ends_with('}'));
+
+    // Definitions that are too long are split into their nested definitions
+    let chunks = chunk_code(code, "rs", Some(16)).unwrap();
+    assert_eq!(outline(&chunks)[2..], [
+        (Some("Point"), "impl", 10, 10),
+        (Some("Point::new"), "function", 11, 13),
+        (Some("Point"), "impl", 14, 14),
+        (Some("main"), "function", 16, 18),
+    ]);
+}
+
+#[test]
+fn test_chunk_long_function() {
+    let mut code = "fn long() {\n".to_string();
+    for i in 0..10 {
+        code.push_str(&format!("    let a{} = {};\n", i, i));
+    }
+    code.push_str("}\n");
+
+    // Without nested definitions, long definitions are split into lines
+    let chunks = chunk_code(&code, "rs", Some(10)).unwrap();
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|chunk| chunk.symbol.as_deref() == Some("long")));
+    assert!(chunks.iter().all(|chunk| chunk.text.split_whitespace().count() <= 10));
+    assert_eq!(chunks[0].start_line, 1);
+    assert_eq!(chunks.last().unwrap().end_line, 12);
+    assert!(chunks.windows(2).all(|pair| pair[0].end_line < pair[1].start_line));
+}
+
+#[test]
+fn test_chunk_python() {
+    let code = r#"import os
+
+
+class Greeter:
+    def __init__(self, name):
+        self.name = name
+
+    @staticmethod
+    def greet(name):
+        return f"Hello, {name}"
+
+
+def main():
+    print(Greeter.greet(os.getlogin()))
+"#;
+    let chunks = chunk_code(code, "py", Some(100)).unwrap();
+    assert_eq!(outline(&chunks), vec![
+        (None, "code", 1, 1),
+        (Some("Greeter"), "class", 4, 10),
+        (Some("main"), "function", 13, 14),
+    ]);
+
+    let chunks = chunk_code(code, "py", Some(10)).unwrap();
+    assert_eq!(outline(&chunks)[1..], [
+        (Some("Greeter"), "class", 4, 4),
+        (Some("Greeter.__init__"), "function", 5, 6),
+        (Some("Greeter.greet"), "function", 8, 10),
+        (Some("main"), "function", 13, 14),
+    ]);
+}
+
+#[test]
+fn test_chunk_typescript() {
+    let code = r#"import { readFile } from "fs";
+
+export interface Options {
+  path: string;
+}
+
+export class Loader {
+  load(options: Options): string {
+    return readFile(options.path);
+  }
+}
+
+function helper() {}
+"#;
+    let chunks = chunk_code(code, "ts", Some(100)).unwrap();
+    assert_eq!(outline(&chunks), vec![
+        (None, "code", 1, 1),
+        (Some("Options"), "interface", 3, 5),
+        (Some("Loader"), "class", 7, 11),
+        (Some("helper"), "function", 13, 13),
+    ]);
+    assert!(chunks[2].text.starts_with("export class Loader"));
+}
+
+#[test]
+fn test_chunk_go() {
+    let code = r#"package main
+
+type Server struct {
+	addr string
+}
+
+func (s *Server) Start() error {
+	return nil
+}
+
+func main() {}
+"#;
+    let chunks = chunk_code(code, "go", Some(100)).unwrap();
+    assert_eq!(outline(&chunks), vec![
+        (None, "code", 1, 1),
+        (Some("Server"), "type", 3, 5),
+        (Some("Server.Start"), "method", 7, 9),
+        (Some("main"), "function", 11, 11),
+    ]);
+}
+
+#[test]
+fn test_chunk_java() {
+    let code = r#"package example;
+
+public class Counter {
+    private int count;
+
+    public Counter() {
+        count = 0;
+    }
+
+    /** Increments the counter */
+    public void increment() {
+        count++;
+    }
+}
+"#;
+    let chunks = chunk_code(code, "java", Some(100)).unwrap();
+    assert_eq!(outline(&chunks), vec![
+        (None, "code", 1, 1),
+        (Some("Counter"), "class", 3, 14)
+    ]);
+
+    let chunks = chunk_code(code, "java", Some(12)).unwrap();
+    assert_eq!(outline(&chunks)[1..], [
+        (Some("Counter"), "class", 3, 4),
+        (Some("Counter.Counter"), "constructor", 6, 8),
+        (Some("Counter.increment"), "method", 10, 13),
+        (Some("Counter"), "class", 14, 14),
+    ]);
+}
+
+#[test]
+fn test_unsupported_language() {
+    assert!(chunk_code("puts 'hello'", "rb", Some(100)).is_none());
+    assert_eq!(chunk_code("", "rs", Some(100)), Some(Vec::new()));
+}
@@ -70,6 +70,45 @@ fn test_process_markdown_file() {
     fs::remove_dir_all(temp_dir).unwrap_or(());
 }
 
+#[test]
+fn test_process_code_file() {
+    // Create a temporary directory for the test
+    let temp_dir = env::temp_dir().join("memory_bank_test_process_code");
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    // Initialize config
+    config::init_config(&temp_dir).unwrap();
+
+    // Create a test Rust file and a file in a language without a code chunker
+    let test_file = temp_dir.join("test.rs");
+    fs::write(
+        &test_file,
+        "use std::io;\n\nfn read() -> io::Result<()> {\n    Ok(())\n}\n",
+    )
+    .unwrap();
+    let fallback_file = temp_dir.join("test.rb");
+    fs::write(&fallback_file, "def read\n  nil\nend\n").unwrap();
+
+    // Code is chunked along its definitions, with their symbol and line range
+    let items = process_file(&test_file).unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1]["symbol"], "read");
+    assert_eq!(items[1]["kind"], "function");
+    assert_eq!(items[1]["start_line"], 3);
+    assert_eq!(items[1]["end_line"], 5);
+    assert_eq!(items[1]["language"], "rs");
+    assert!(items[0].get("symbol").is_none());
+
+    // Other languages fall back to the word chunker
+    let items = process_file(&fallback_file).unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["text"], "def read nil end");
+    assert!(items[0].get("kind").is_none());
+
+    // Clean up
+    fs::remove_dir_all(temp_dir).unwrap_or(());
+}
+
 #[test]
 fn test_process_nonexistent_file() {
     // Create a temporary directory for the test
@@ -0,0 +1,47 @@
+use std::fs;
+
+use semantic_search_client::embedding::EmbeddingType;
+use semantic_search_client::types::ProgressStatus;
+use semantic_search_client::{
+    FusionMethod,
+    SearchMode,
+    SemanticSearchClient,
+};
+use tempfile::TempDir;
+
+#[test]
+fn test_search_with_mode() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("index.rs"), "pub fn rebuild_index(&mut self) {}\n").unwrap();
+    fs::write(
+        source_dir.join("notes.md"),
+        "Indexes are rebuilt when most points are removed",
+    )
+    .unwrap();
+
+    let mut client =
+        SemanticSearchClient::with_embedding_type(temp_dir.path().join("semantic_search"), EmbeddingType::BM25)
+            .unwrap();
+    let id = client
+        .add_context_from_path(&source_dir, "Code", "Code", false, None::<fn(ProgressStatus)>)
+        .unwrap();
+
+    // Dense searches only report the embedding distance
+    let results = client.search_context(&id, "rebuild_index", Some(2)).unwrap();
+    assert!(results.iter().all(|result| result.lexical_score.is_none()));
+
+    let mode = SearchMode::Hybrid(FusionMethod::default());
+    let results = client
+        .search_context_with_mode(&id, "rebuild_index", Some(2), mode)
+        .unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].text().unwrap().contains("rebuild_index"));
+    assert!(results[0].lexical_score.unwrap() > 0.0);
+    assert!(results[0].dense_distance.is_some());
+
+    let results = client.search_all_with_mode("rebuild_index", Some(1), mode).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].1.len(), 1);
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::time::{
+    Duration,
+    SystemTime,
+};
+
+use semantic_search_client::embedding::EmbeddingType;
+use semantic_search_client::types::ProgressStatus;
+use semantic_search_client::{
+    RefreshSummary,
+    SemanticSearchClient,
+};
+use tempfile::TempDir;
+
+#[test]
+fn test_refresh_context() {
+    let temp_dir = TempDir::new().unwrap();
+    let base_dir = temp_dir.path().join("semantic_search");
+    let source_dir = temp_dir.path().join("source");
+    fs::create_dir_all(&source_dir).unwrap();
+    fs::write(source_dir.join("apples.txt"), "Apples grow on trees in orchards").unwrap();
+    fs::write(source_dir.join("bananas.txt"), "Bananas are yellow and curved").unwrap();
+    fs::write(
+        source_dir.join("cherries.md"),
+        "# Cherries\n\nCherries are small and red",
+    )
+    .unwrap();
+
+    let mut client = SemanticSearchClient::with_embedding_type(&base_dir, EmbeddingType::BM25).unwrap();
+    let id = client
+        .add_context_from_path(&source_dir, "Fruits", "Fruit notes", true, None::<fn(ProgressStatus)>)
+        .unwrap();
+    assert_eq!(client.get_contexts()[0].files.len(), 3);
+
+    // Nothing changed
+    let summary = client.refresh_context(&id, None::<fn(ProgressStatus)>).unwrap();
+    assert_eq!(summary, RefreshSummary {
+        unchanged: 3,
+        ..Default::default()
+    });
+
+    // Touching a file without changing its content doesn't re-index it
+    fs::File::options()
+        .write(true)
+        .open(source_dir.join("apples.txt"))
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+    fs::write(source_dir.join("bananas.txt"), "Plantains are starchy and cooked").unwrap();
+    fs::File::options()
+        .write(true)
+        .open(source_dir.join("bananas.txt"))
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+    fs::remove_file(source_dir.join("cherries.md")).unwrap();
+    fs::write(source_dir.join("dates.txt"), "Dates are sweet and grow on palms").unwrap();
+
+    let summary = client.refresh_context(&id, None::<fn(ProgressStatus)>).unwrap();
+    assert_eq!(summary, RefreshSummary {
+        added: 1,
+        updated: 1,
+        removed: 1,
+        unchanged: 1,
+    });
+
+    let paths = |client: &SemanticSearchClient, query: &str| {
+        client
+            .search_context(&id, query, Some(10))
+            .unwrap()
+            .into_iter()
+            .map(|result| {
+                let path = result.point.payload["path"].as_str().unwrap().to_string();
+                (path, result.text().unwrap_or_default().to_string())
+            })
+            .collect::<Vec<_>>()
+    };
+    let results = paths(&client, "plantains");
+    assert_eq!(results.len(), 3);
+    assert!(results.iter().any(|(_, text)| text.contains("Plantains")));
+    assert!(!results.iter().any(|(_, text)| text.contains("Bananas")));
+    assert!(!results.iter().any(|(path, _)| path.ends_with("cherries.md")));
+
+    // The new data points are numbered after the existing ones rather than reusing their ids
+    for result in client.search_context(&id, "plantains", Some(10)).unwrap() {
+        let path = result.point.payload["path"].as_str().unwrap();
+        assert_eq!(result.point.id >= 3, !path.ends_with("apples.txt"), "{path}");
+    }
+
+    let context = &client.get_contexts()[0];
+    assert_eq!(context.item_count, 3);
+    assert_eq!(context.files.len(), 3);
+
+    // The refreshed context and file states are persisted
+    drop(client);
+    let mut client = SemanticSearchClient::with_embedding_type(&base_dir, EmbeddingType::BM25).unwrap();
+    assert_eq!(paths(&client, "dates").len(), 3);
+    let summary = client.refresh_context(&id, None::<fn(ProgressStatus)>).unwrap();
+    assert_eq!(summary, RefreshSummary {
+        unchanged: 3,
+        ..Default::default()
+    });
+
+    // Contexts that weren't created from a path can't be refreshed
+    let text_id = client
+        .add_context_from_text("Some fruit facts", "Facts", "Fruit facts", true)
+        .unwrap();
+    assert!(client.refresh_context(&text_id, None::<fn(ProgressStatus)>).is_err());
+}
@@ -0,0 +1,111 @@
+use std::fs;
+
+use semantic_search_client::embedding::EmbeddingType;
+use semantic_search_client::types::ProgressStatus;
+use semantic_search_client::{
+    FileType,
+    PayloadPredicate,
+    SearchFilter,
+    SearchResult,
+    SemanticSearchClient,
+};
+use serde_json::Value;
+use tempfile::TempDir;
+
+/// The file names of search results
+fn file_names(results: &[SearchResult]) -> Vec<String> {
+    let mut names = results
+        .iter()
+        .map(|result| {
+            let path = result.point.payload["path"].as_str().unwrap();
+            path.rsplit(['/', '\\']).next().unwrap().to_string()
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[test]
+fn test_search_filter() {
+    let temp_dir = TempDir::new().unwrap();
+    let source_dir = temp_dir.path().join("source");
+    fs::create_dir_all(source_dir.join("src")).unwrap();
+    fs::create_dir_all(source_dir.join("tests")).unwrap();
+    fs::write(
+        source_dir.join("src/parser.rs"),
+        "pub fn parse() {}\n\npub fn tokenize() {}\n",
+    )
+    .unwrap();
+    fs::write(source_dir.join("src/lexer.py"), "def lex():\n    pass\n").unwrap();
+    fs::write(source_dir.join("tests/parser_test.rs"), "fn test_parse() {}\n").unwrap();
+    fs::write(source_dir.join("README.md"), "# Parser\n\nParses the input").unwrap();
+
+    let mut client =
+        SemanticSearchClient::with_embedding_type(temp_dir.path().join("semantic_search"), EmbeddingType::BM25)
+            .unwrap();
+    let id = client
+        .add_context_from_path(&source_dir, "Parser", "Parser", true, None::<fn(ProgressStatus)>)
+        .unwrap();
+    let search = |filter: &SearchFilter| client.search_context_filtered(&id, "parse", Some(10), filter).unwrap();
+
+    assert_eq!(file_names(&search(&SearchFilter::default())), vec![
+        "README.md",
+        "lexer.py",
+        "parser.rs",
+        "parser_test.rs"
+    ]);
+
+    // Paths are matched relative to the source directory
+    let results = search(&SearchFilter {
+        include_paths: vec!["src/**/*.rs".to_string()],
+        ..Default::default()
+    });
+    assert_eq!(file_names(&results), vec!["parser.rs"]);
+    assert_eq!(results.len(), 2);
+
+    let results = search(&SearchFilter {
+        exclude_paths: vec!["tests/**".to_string(), "*.md".to_string()],
+        ..Default::default()
+    });
+    assert_eq!(file_names(&results), vec!["lexer.py", "parser.rs"]);
+
+    let results = search(&SearchFilter {
+        file_types: vec![FileType::Markdown],
+        ..Default::default()
+    });
+    assert_eq!(file_names(&results), vec!["README.md"]);
+
+    let results = search(&SearchFilter {
+        payload: vec![
+            PayloadPredicate::Equals("kind".to_string(), Value::String("function".to_string())),
+            PayloadPredicate::NotEquals("symbol".to_string(), Value::String("tokenize".to_string())),
+        ],
+        ..Default::default()
+    });
+    assert_eq!(results.len(), 3);
+    assert!(
+        results
+            .iter()
+            .all(|result| result.point.payload["symbol"] != "tokenize")
+    );
+
+    let results = client
+        .search_all_filtered("parse", Some(10), &SearchFilter {
+            include_paths: vec!["**/tests/*.rs".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(file_names(&results[0].1), vec!["parser_test.rs"]);
+
+    // Invalid patterns are rejected
+    assert!(
+        client
+            .search_context_filtered(&id, "parse", Some(10), &SearchFilter {
+                include_paths: vec!["src/[".to_string()],
+                ..Default::default()
+            })
+            .is_err()
+    );
+}
@@ -5,7 +5,10 @@ use std::{
 };
 
 use semantic_search_client::client::SemanticContext;
-use semantic_search_client::types::DataPoint;
+use semantic_search_client::types::{
+    DataPoint,
+    FusionMethod,
+};
 use serde_json::Value;
 
 #[test]
@@ -14,7 +17,7 @@ fn test_semantic_context_creation() {
     let temp_dir = env::temp_dir().join("memory_bank_test_semantic_context");
     fs::create_dir_all(&temp_dir).unwrap();
 
-    let data_path = temp_dir.join("data.json");
+    let data_path = temp_dir.join("data.bin");
 
     // Create a new semantic context
     let semantic_context = SemanticContext::new(data_path).unwrap();
@@ -32,7 +35,7 @@ fn test_add_data_points() {
     let temp_dir = env::temp_dir().join("memory_bank_test_add_data");
     fs::create_dir_all(&temp_dir).unwrap();
 
-    let data_path = temp_dir.join("data.json");
+    let data_path = temp_dir.join("data.bin");
 
     // Create a new semantic context
     let mut semantic_context = SemanticContext::new(data_path.clone()).unwrap();
@@ -54,7 +57,7 @@ fn test_add_data_points() {
     data_points.push(DataPoint {
         id: 0,
         payload: payload1,
-        vector: vector1,
+        vector: vector1.into(),
     });
 
     // Second data point
@@ -71,7 +74,7 @@ fn test_add_data_points() {
     data_points.push(DataPoint {
         id: 1,
         payload: payload2,
-        vector: vector2,
+        vector: vector2.into(),
     });
 
     // Add the data points to the context
@@ -98,3 +101,299 @@ fn test_add_data_points() {
     // Clean up
     fs::remove_dir_all(temp_dir).unwrap_or(());
 }
+
+#[test]
+fn test_remove_data_points() {
+    // Create a temporary directory for the test
+    let temp_dir = env::temp_dir().join("memory_bank_test_remove_data");
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    let data_path = temp_dir.join("data.bin");
+
+    // Create a new semantic context with one data point per source
+    let mut semantic_context = SemanticContext::new(data_path.clone()).unwrap();
+    let data_points = ["test1.txt", "test2.txt", "test3.txt"]
+        .into_iter()
+        .enumerate()
+        .map(|(i, source)| {
+            let mut vector = vec![0.1; 384];
+            vector[i] = 1.0;
+            DataPoint {
+                id: i,
+                payload: HashMap::from([("source".to_string(), Value::String(source.to_string()))]),
+                vector: vector.into(),
+            }
+        })
+        .collect();
+    semantic_context.add_data_points(data_points).unwrap();
+
+    let source = |point: &DataPoint| point.payload["source"].as_str().unwrap().to_string();
+
+    // Removed data points are skipped by searches before the index is rebuilt
+    let removed = semantic_context
+        .remove_data_points(|point| source(point) == "test2.txt")
+        .unwrap();
+    assert_eq!(removed, 1);
+    assert_eq!(semantic_context.get_data_points().len(), 2);
+
+    let results = semantic_context.search(&vec![0.1; 384], 3).unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| source(&result.point) != "test2.txt"));
+
+    // Only the remaining data points are saved
+    semantic_context.save().unwrap();
+    let loaded_context = SemanticContext::new(data_path).unwrap();
+    assert_eq!(loaded_context.get_data_points().len(), 2);
+
+    // Removing most of the context rebuilds the index without the removed data points
+    semantic_context
+        .remove_data_points(|point| source(point) == "test3.txt")
+        .unwrap();
+    let results = semantic_context.search(&vec![0.1; 384], 3).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(source(&results[0].point), "test1.txt");
+
+    // Clean up
+    fs::remove_dir_all(temp_dir).unwrap_or(());
+}
+
+#[test]
+fn test_saved_index_is_reloaded() {
+    // Create a temporary directory for the test
+    let temp_dir = env::temp_dir().join("memory_bank_test_saved_index");
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    let data_path = temp_dir.join("data.bin");
+
+    // Save a context with an index
+    let mut semantic_context = SemanticContext::new(data_path.clone()).unwrap();
+    let data_points = (0..3)
+        .map(|i| {
+            let mut vector = vec![0.1; 384];
+            vector[i] = 1.0;
+            DataPoint {
+                id: i,
+                payload: HashMap::from([("text".to_string(), Value::String(format!("point {}", i)))]),
+                vector: vector.into(),
+            }
+        })
+        .collect();
+    semantic_context.add_data_points(data_points).unwrap();
+    semantic_context.remove_data_points(|point| point.id == 1).unwrap();
+    semantic_context.save().unwrap();
+    assert!(temp_dir.join("data.hnsw.graph").exists());
+    assert!(temp_dir.join("data.hnsw.data").exists());
+    assert!(temp_dir.join("data.bm25").exists());
+
+    // The reloaded context keeps its vectors and removed data points
+    let loaded_context = SemanticContext::new(data_path.clone()).unwrap();
+    let points = loaded_context.get_data_points();
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[1].vector[2], 1.0);
+
+    let mut query = vec![0.1; 384];
+    query[2] = 1.0;
+    let results = loaded_context.search(&query, 3).unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].point.id, 2);
+
+    // The lexical index is reloaded without the removed data point
+    let results = loaded_context
+        .search_hybrid(&query, "point 2", 3, FusionMethod::default())
+        .unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].point.id, 2);
+    assert!(results[0].lexical_score.unwrap() > results[1].lexical_score.unwrap());
+
+    // Saving a reloaded context replaces its index files
+    loaded_context.save().unwrap();
+    let index_files = fs::read_dir(&temp_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().contains(".hnsw."))
+        .count();
+    assert_eq!(index_files, 2);
+
+    // Missing indexes are rebuilt
+    fs::remove_file(temp_dir.join("data.bm25")).unwrap();
+    let rebuilt_context = SemanticContext::new(data_path.clone()).unwrap();
+    let results = rebuilt_context
+        .search_hybrid(&query, "point 2", 3, FusionMethod::default())
+        .unwrap();
+    assert_eq!(results[0].point.id, 2);
+    fs::remove_file(temp_dir.join("data.hnsw.graph")).unwrap();
+    let rebuilt_context = SemanticContext::new(data_path).unwrap();
+    assert_eq!(rebuilt_context.search(&query, 3).unwrap()[0].point.id, 2);
+
+    // Clean up
+    fs::remove_dir_all(temp_dir).unwrap_or(());
+}
+
+#[test]
+fn test_legacy_json_is_migrated() {
+    // Create a temporary directory for the test
+    let temp_dir = env::temp_dir().join("memory_bank_test_legacy_json");
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    // Write data points in the legacy JSON format
+    let data_points = (0..2)
+        .map(|i| DataPoint {
+            id: i,
+            payload: HashMap::from([("text".to_string(), Value::String(format!("point {}", i)))]),
+            vector: vec![0.1 * (i + 1) as f32; 384].into(),
+        })
+        .collect::<Vec<_>>();
+    fs::write(temp_dir.join("data.json"), serde_json::to_vec(&data_points).unwrap()).unwrap();
+
+    let data_path = temp_dir.join("data.bin");
+    let semantic_context = SemanticContext::new(data_path.clone()).unwrap();
+    assert_eq!(semantic_context.get_data_points().len(), 2);
+    assert_eq!(semantic_context.search(&vec![0.1; 384], 2).unwrap().len(), 2);
+
+    // The context is converted to the binary format
+    assert!(!temp_dir.join("data.json").exists());
+    assert!(data_path.exists());
+    assert!(temp_dir.join("data.hnsw.graph").exists());
+    assert_eq!(SemanticContext::new(data_path).unwrap().get_data_points().len(), 2);
+
+    // Clean up
+    fs::remove_dir_all(temp_dir).unwrap_or(());
+}
+
+#[test]
+fn test_unsupported_format_version() {
+    // Create a temporary directory for the test
+    let temp_dir = env::temp_dir().join("memory_bank_test_format_version");
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    let data_path = temp_dir.join("data.bin");
+    SemanticContext::new(data_path.clone()).unwrap().save().unwrap();
+
+    // Bump the format version in the header
+    let mut bytes = fs::read(&data_path).unwrap();
+    bytes[4..8].copy_from_slice(&99u32.to_le_bytes());
+    fs::write(&data_path, bytes).unwrap();
+    assert!(SemanticContext::new(data_path).is_err());
+
+    // Clean up
+    fs::remove_dir_all(temp_dir).unwrap_or(());
+}
+
+#[test]
+fn test_hybrid_search() {
+    // Create a temporary directory for the test
+    let temp_dir = env::temp_dir().join("memory_bank_test_hybrid_search");
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    let mut semantic_context = SemanticContext::new(temp_dir.join("data.bin")).unwrap();
+    let texts = [
+        "How vectors are inserted into the graph",
+        "Searching the nearest neighbors of a query",
+        "fn rebuild_lexical_index(&mut self)",
+        "Removing data points from a context",
+    ];
+    let data_points = texts
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| {
+            let mut vector = vec![0.1; 384];
+            vector[i] = 1.0;
+            DataPoint {
+                id: i,
+                payload: HashMap::from([("text".to_string(), Value::String(text.to_string()))]),
+                vector: vector.into(),
+            }
+        })
+        .collect();
+    semantic_context.add_data_points(data_points).unwrap();
+    semantic_context.remove_data_points(|point| point.id == 3).unwrap();
+
+    // The query embedding is closest to the first data point, but the identifier only matches the
+    // third one
+    let mut query = vec![0.1; 384];
+    query[0] = 1.0;
+    assert_eq!(semantic_context.search(&query, 1).unwrap()[0].point.id, 0);
+
+    for fusion in [FusionMethod::ReciprocalRank { k: 60.0 }, FusionMethod::Weighted {
+        dense_weight: 0.3,
+    }] {
+        let results = semantic_context
+            .search_hybrid(&query, "rebuild_lexical_index", 4, fusion)
+            .unwrap();
+        assert_eq!(results.len(), 3, "{:?}", fusion);
+        assert_eq!(results[0].point.id, 2, "{:?}", fusion);
+        assert!(results[0].lexical_score.unwrap() > 0.0);
+        assert!(results[0].dense_distance.unwrap() > results[1].dense_distance.unwrap());
+        assert!(results.windows(2).all(|pair| pair[0].distance <= pair[1].distance));
+        assert!(results.iter().all(|result| result.point.id != 3));
+    }
+
+    // Without any lexical match, the dense ranking is kept
+    let results = semantic_context
+        .search_hybrid(&query, "unrelated", 3, FusionMethod::default())
+        .unwrap();
+    assert_eq!(results[0].point.id, 0);
+    assert_eq!(results[0].lexical_score, Some(0.0));
+
+    // Clean up
+    fs::remove_dir_all(temp_dir).unwrap_or(());
+}
+
+#[test]
+fn test_search_filtered() {
+    // Create a temporary directory for the test
+    let temp_dir = env::temp_dir().join("memory_bank_test_search_filtered");
+    fs::create_dir_all(&temp_dir).unwrap();
+
+    // Only one data point out of 50 is a test, and all of them are far from the query
+    let mut semantic_context = SemanticContext::new(temp_dir.join("data.bin")).unwrap();
+    let data_points = (0..500)
+        .map(|i| {
+            let is_test = i % 50 == 0;
+            let vector = (0..384)
+                .map(|j| {
+                    let value = ((i * 7919 + j * 104729) % 1000) as f32 / 1000.0;
+                    if is_test { value - 1.0 } else { value }
+                })
+                .collect::<Vec<_>>();
+            DataPoint {
+                id: i,
+                payload: HashMap::from([("test".to_string(), Value::Bool(is_test))]),
+                vector: vector.into(),
+            }
+        })
+        .collect();
+    semantic_context.add_data_points(data_points).unwrap();
+    semantic_context.remove_data_points(|point| point.id == 0).unwrap();
+
+    let is_test = |point: &DataPoint| point.payload["test"] == Value::Bool(true);
+    let query = vec![1.0; 384];
+    assert!(
+        !semantic_context
+            .search(&query, 5)
+            .unwrap()
+            .iter()
+            .any(|r| is_test(&r.point))
+    );
+
+    // The filter is applied during the search, so it still returns the requested number of results
+    let results = semantic_context.search_filtered(&query, 5, is_test).unwrap();
+    assert_eq!(results.len(), 5);
+    assert!(
+        results
+            .iter()
+            .all(|result| is_test(&result.point) && result.point.id != 0)
+    );
+
+    let results = semantic_context
+        .search_hybrid_filtered(&query, "anything", 5, FusionMethod::default(), is_test)
+        .unwrap();
+    assert_eq!(results.len(), 5);
+    assert!(
+        results
+            .iter()
+            .all(|result| is_test(&result.point) && result.point.id != 0)
+    );
+
+    // Clean up
+    fs::remove_dir_all(temp_dir).unwrap_or(());
+}
@@ -53,3 +53,48 @@ fn test_search() {
         assert!(results[0].0 <= 2);
     }
 }
+
+#[test]
+fn test_save_and_load() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+
+    let index = VectorIndex::new(384);
+    for i in 0..10 {
+        let mut vector = vec![0.1; 384];
+        vector[i] = 1.0;
+        index.insert(&vector, i);
+    }
+    index.save(temp_dir.path(), "index").unwrap();
+
+    let loaded = VectorIndex::load(temp_dir.path(), "index").unwrap();
+    assert_eq!(loaded.len(), 10);
+
+    let mut query = vec![0.1; 384];
+    query[7] = 1.0;
+    assert_eq!(loaded.search(&query, 1, 100)[0].0, 7);
+
+    // A reloaded index, whose vectors are memory-mapped, can be extended and saved again under the
+    // same name
+    let mut vector = vec![0.1; 384];
+    vector[20] = 1.0;
+    loaded.insert(&vector, 10);
+    loaded.save(temp_dir.path(), "index").unwrap();
+    // The searches of the index still use the vectors mapped from the replaced files
+    assert_eq!(loaded.search(&query, 1, 100)[0].0, 7);
+    drop(loaded);
+    let mut files = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files, vec!["index.hnsw.data", "index.hnsw.graph"]);
+
+    let reloaded = VectorIndex::load(temp_dir.path(), "index").unwrap();
+    assert_eq!(reloaded.len(), 11);
+    assert_eq!(reloaded.search(&query, 1, 100)[0].0, 7);
+    assert_eq!(reloaded.search(&vector, 1, 100)[0].0, 10);
+    drop(reloaded);
+
+    VectorIndex::remove_files(temp_dir.path(), "index").unwrap();
+    assert!(VectorIndex::load(temp_dir.path(), "index").is_err());
+}